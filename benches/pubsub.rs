@@ -0,0 +1,101 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+// Intra-process publish/subscribe through cyclone: the latency of a write until
+// the sample is taken, and the shared memory loan path. The loan benchmark is
+// skipped unless iox-roudi is running. Run with `cargo bench --bench pubsub`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use cdds_derive::{Topic, TopicFixedSize};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cyclonedds_rs::*;
+
+#[derive(Serialize, Deserialize, Topic, Default)]
+struct Frame {
+    #[topic_key]
+    camera: u32,
+    payload: Vec<u8>,
+}
+
+// only written through loans, which need the shm feature
+#[cfg_attr(not(cyclone_shm), allow(dead_code))]
+#[repr(C)]
+#[derive(Serialize, Deserialize, TopicFixedSize, Default)]
+struct Pose {
+    #[topic_key]
+    id: u64,
+    position: [f64; 3],
+    orientation: [f64; 4],
+}
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn round_trip(c: &mut Criterion) {
+    let participant = DdsParticipant::create(None, None, None).unwrap();
+    let topic = Frame::create_topic(&participant, Some("bench_round_trip"), None, None).unwrap();
+    let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+    let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+    let mut samples = reader.sample_buffer(1);
+    let poll = BusyPoll::new();
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut group = c.benchmark_group("round_trip");
+    for size in [64usize, 4 * 1024, 64 * 1024] {
+        let sample = Arc::new(Frame { camera: 1, payload: vec![0x5a; size] });
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &sample, |b, sample| {
+            b.iter(|| {
+                writer.write(sample.clone()).unwrap();
+                reader.poll_take(&mut samples, poll, TIMEOUT).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+#[cfg(cyclone_shm)]
+fn loan(c: &mut Criterion) {
+    CycloneConfig::new().with_shared_memory(true).set_env();
+    let participant = DdsParticipant::create(None, None, None).unwrap();
+    let topic = Pose::create_topic(&participant, Some("bench_loan"), None, None).unwrap();
+    let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+    let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+    let mut samples = reader.sample_buffer(1);
+    let poll = BusyPoll::new();
+    std::thread::sleep(Duration::from_millis(200));
+
+    if writer.loan().is_err() {
+        eprintln!("skipping the loan benchmark, shared memory is not available");
+        return;
+    }
+
+    c.bench_function("loan_round_trip", |b| {
+        b.iter(|| {
+            let mut loaned = writer.loan().unwrap();
+            unsafe { loaned.as_mut_ptr().unwrap().write(Pose::default()) };
+            writer.return_loan(loaned.assume_init()).unwrap();
+            reader.poll_take(&mut samples, poll, TIMEOUT).unwrap()
+        })
+    });
+}
+
+#[cfg(cyclone_shm)]
+criterion_group!(benches, round_trip, loan);
+#[cfg(not(cyclone_shm))]
+criterion_group!(benches, round_trip);
+criterion_main!(benches);
@@ -0,0 +1,100 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+// Serialization, deserialization and keyhash computation of samples through
+// the serdata operations of a sertype, without a domain. Run with
+// `cargo bench --bench serdes --features fuzzing`.
+
+use std::sync::Arc;
+
+use cdds_derive::Topic;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cyclonedds_rs::fuzz_support::FuzzSertype;
+use cyclonedds_rs::*;
+
+#[derive(Serialize, Deserialize, Topic, Default, Clone)]
+struct Frame {
+    #[topic_key]
+    camera: u32,
+    sequence: u64,
+    label: String,
+    payload: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Topic, Default)]
+struct Named {
+    #[topic_key]
+    name: String,
+    #[topic_key]
+    index: u32,
+    value: f64,
+}
+
+const PAYLOAD_SIZES: [usize; 3] = [64, 4 * 1024, 64 * 1024];
+
+fn frame(size: usize) -> Frame {
+    Frame {
+        camera: 1,
+        sequence: 42,
+        label: "front".to_owned(),
+        payload: vec![0x5a; size],
+    }
+}
+
+fn serialize(c: &mut Criterion) {
+    let sertype = FuzzSertype::<Frame>::new();
+    let mut group = c.benchmark_group("serialize");
+    for size in PAYLOAD_SIZES {
+        let sample = Sample::from(Arc::new(frame(size)));
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &sample, |b, sample| {
+            b.iter(|| sertype.serialize(black_box(sample)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn deserialize(c: &mut Criterion) {
+    let sertype = FuzzSertype::<Frame>::new();
+    let mut buffer = SampleBuffer::<Frame>::new(1);
+    let mut group = c.benchmark_group("deserialize");
+    for size in PAYLOAD_SIZES {
+        let serialized = sertype.serialize(&Sample::from(Arc::new(frame(size)))).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &serialized, |b, serialized| {
+            b.iter(|| assert!(sertype.receive(black_box(serialized), &mut buffer)))
+        });
+    }
+    group.finish();
+}
+
+fn keyhash(c: &mut Criterion) {
+    let mut group = c.benchmark_group("keyhash");
+    // a key that fits the keyhash and one that needs md5
+    let fixed = frame(64);
+    group.bench_function("fixed_key", |b| b.iter(|| black_box(&fixed).hash(0)));
+    let named = Named {
+        name: "left front wheel speed sensor".to_owned(),
+        index: 3,
+        value: 1.0,
+    };
+    group.bench_function("string_key", |b| b.iter(|| black_box(&named).hash(0)));
+    group.bench_function("string_key_cdr", |b| b.iter(|| black_box(&named).key_cdr()));
+    group.finish();
+}
+
+criterion_group!(benches, serialize, deserialize, keyhash);
+criterion_main!(benches);
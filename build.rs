@@ -0,0 +1,112 @@
+// Detect what the installed cyclone was built with, so that the struct fields
+// that only exist in some builds are only used when they are there.
+//
+// `cyclone_shm` is set when the `shm` feature is enabled and cyclone has the
+// iceoryx fields, `cyclone_type_discovery` likewise for the `type-discovery`
+// feature. When no cyclone headers can be found the features are trusted.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// The first cyclone releases with iceoryx and with type discovery
+const SHM_SINCE: (u32, u32) = (0, 9);
+const TYPE_DISCOVERY_SINCE: (u32, u32) = (0, 10);
+
+fn include_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(home) = env::var_os("CYCLONEDDS_HOME") {
+        dirs.push(PathBuf::from(home).join("include"));
+    }
+    if let Some(prefixes) = env::var_os("CMAKE_PREFIX_PATH") {
+        dirs.extend(env::split_paths(&prefixes).map(|p| p.join("include")));
+    }
+    dirs.push(PathBuf::from("/usr/local/include"));
+    dirs.push(PathBuf::from("/usr/include"));
+    dirs
+}
+
+fn read_header(name: &str) -> Option<String> {
+    include_dirs()
+        .into_iter()
+        .map(|dir| dir.join("dds").join(name))
+        .find(|path| path.exists())
+        .and_then(|path| {
+            println!("cargo:rerun-if-changed={}", path.display());
+            fs::read_to_string(path).ok()
+        })
+}
+
+// "0.10.2" from `#define DDS_VERSION "0.10.2"`
+fn parse_version(header: &str) -> Option<(u32, u32)> {
+    let line = header
+        .lines()
+        .find(|line| line.trim_start().starts_with("#define DDS_VERSION "))?;
+    let quoted = line.split('"').nth(1)?;
+    let mut parts = quoted.split('.').map(|p| p.parse::<u32>().ok());
+    Some((parts.next()??, parts.next()??))
+}
+
+fn defines(header: &str, name: &str) -> bool {
+    header.lines().any(|line| {
+        let mut words = line.split_whitespace();
+        words.next() == Some("#define") && words.next() == Some(name)
+    })
+}
+
+// Whether cyclone has `feature`, judged by features.h when cyclone ships one
+// and by the version otherwise
+fn detected(
+    features: Option<&str>,
+    version: Option<(u32, u32)>,
+    define: &str,
+    since: (u32, u32),
+) -> Option<bool> {
+    match (features, version) {
+        (Some(features), _) => Some(defines(features, define)),
+        (None, Some(version)) => Some(version >= since),
+        (None, None) => None,
+    }
+}
+
+fn enable(cfg: &str, feature: &str, detected: Option<bool>) {
+    println!("cargo:rustc-check-cfg=cfg({})", cfg);
+    let requested = env::var_os(format!(
+        "CARGO_FEATURE_{}",
+        feature.to_uppercase().replace('-', "_")
+    ))
+    .is_some();
+    match (requested, detected) {
+        (true, Some(false)) => println!(
+            "cargo:warning=the installed cyclonedds is built without {}, the `{}` feature is disabled",
+            feature, feature
+        ),
+        (true, _) => println!("cargo:rustc-cfg={}", cfg),
+        (false, _) => {}
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=CYCLONEDDS_HOME");
+    println!("cargo:rerun-if-env-changed=CMAKE_PREFIX_PATH");
+
+    let features = read_header("features.h");
+    let version = read_header("version.h").as_deref().and_then(parse_version);
+
+    enable(
+        "cyclone_shm",
+        "shm",
+        detected(features.as_deref(), version, "DDS_HAS_SHM", SHM_SINCE),
+    );
+    enable(
+        "cyclone_type_discovery",
+        "type-discovery",
+        detected(
+            features.as_deref(),
+            version,
+            "DDS_HAS_TYPE_DISCOVERY",
+            TYPE_DISCOVERY_SINCE,
+        ),
+    );
+}
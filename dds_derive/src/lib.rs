@@ -37,12 +37,21 @@ pub fn derive_topic(item: TokenStream) -> TokenStream {
 fn derive_topic_impl(item: TokenStream, is_fixed_size: bool) -> TokenStream {
     let topic_struct = parse_macro_input!(item as syn::ItemStruct);
 
+    if is_fixed_size {
+        if let Err(e) = check_fixed_layout(&topic_struct) {
+            return e.to_compile_error().into();
+        }
+    }
+
     let mut ts = build_key_holder_struct(&topic_struct);
     let ts2 = create_keyhash_functions(&topic_struct, is_fixed_size);
     let ts3 = create_topic_functions(&topic_struct);
 
     ts.extend(ts2);
     ts.extend(ts3);
+    if is_fixed_size {
+        ts.extend(create_layout_assertions(&topic_struct));
+    }
   
     //println!("KEYHOLDER:{:?}",ts.clone().to_string());
     ts
@@ -152,9 +161,10 @@ fn build_key_holder_struct(item : &syn::ItemStruct) -> TokenStream {
 fn create_keyhash_functions(item : &syn::ItemStruct, is_fixed_size: bool) -> TokenStream {
     let topic_key_ident = &item.ident;
     let topic_key_holder_ident =  quote::format_ident!("{}KeyHolder_",&item.ident);
+    let key_fields = create_key_fields(item);
 
     let ts = quote!{
-        impl TopicType for #topic_key_ident {
+        impl cyclonedds_rs::TopicType for #topic_key_ident {
             /// return the cdr encoding for the key. The encoded string includes the four byte
             /// encapsulation string.
             fn key_cdr(&self) -> Vec<u8> {
@@ -167,6 +177,7 @@ fn create_keyhash_functions(item : &syn::ItemStruct, is_fixed_size: bool) -> Tok
             fn is_fixed_size() -> bool {
                 #is_fixed_size
             }
+
             
             fn has_key() -> bool {
                 if std::mem::size_of::<#topic_key_holder_ident>() > 0 {
@@ -179,6 +190,10 @@ fn create_keyhash_functions(item : &syn::ItemStruct, is_fixed_size: bool) -> Tok
             fn force_md5_keyhash() -> bool {
                  #topic_key_holder_ident::is_variable_length()
             }
+
+            fn key_fields() -> Vec<String> {
+                #key_fields
+            }
         }
     };
 
@@ -186,6 +201,119 @@ fn create_keyhash_functions(item : &syn::ItemStruct, is_fixed_size: bool) -> Tok
     
 }
 
+// The names of the key fields. A nested struct contributes its own key fields
+// below its name, or itself if it has none.
+fn create_key_fields(item : &syn::ItemStruct) -> proc_macro2::TokenStream {
+    let mut pushes = Vec::new();
+    for field in item.fields.iter().filter(|field| is_key(field)) {
+        let name = field.ident.as_ref().unwrap().to_string();
+        let ty = &field.ty;
+        let nested = matches!(ty, syn::Type::Path(_)) && !is_primitive(field) && !is_key_enum(field);
+        if nested {
+            pushes.push(quote!{
+                let nested = <#ty as cyclonedds_rs::TopicType>::key_fields();
+                if nested.is_empty() {
+                    fields.push(#name.to_owned());
+                } else {
+                    fields.extend(nested.into_iter().map(|nested| format!("{}.{}", #name, nested)));
+                }
+            });
+        } else {
+            pushes.push(quote!{ fields.push(#name.to_owned()); });
+        }
+    }
+    quote!{
+        #[allow(unused_mut)]
+        let mut fields = Vec::new();
+        #(#pushes)*
+        fields
+    }
+}
+
+/// Samples of fixed size types are loaned over iceoryx and used in place by the
+/// readers, possibly in another process. The layout must be the same for every
+/// build and the sample must not point to memory of the writer.
+fn check_fixed_layout(item : &syn::ItemStruct) -> Result<(), syn::Error> {
+    if !is_repr_c(item) {
+        return Err(syn::Error::new_spanned(
+            &item.ident,
+            "TopicFixedSize types are shared in place and need #[repr(C)]",
+        ));
+    }
+    for field in &item.fields {
+        if let Some(ty) = find_pointer_type(&field.ty) {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "TopicFixedSize types are shared in place, this type points to memory of the writer",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_repr_c(item : &syn::ItemStruct) -> bool {
+    item.attrs.iter().filter(|attr| attr.path.is_ident("repr")).any(|attr| {
+        if let Ok(syn::Meta::List(list)) = attr.parse_meta() {
+            list.nested.iter().any(|nested| {
+                matches!(nested, syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("C"))
+            })
+        } else {
+            false
+        }
+    })
+}
+
+// The first type in `ty` that owns or borrows memory outside of the sample
+fn find_pointer_type(ty : &syn::Type) -> Option<&syn::Type> {
+    const POINTER_TYPES: &[&str] = &[
+        "String", "Vec", "VecDeque", "Box", "Rc", "Arc", "HashMap", "HashSet", "BTreeMap",
+        "BTreeSet", "Cow", "CString", "PathBuf",
+    ];
+    match ty {
+        syn::Type::Reference(_) | syn::Type::Ptr(_) | syn::Type::Slice(_) | syn::Type::TraitObject(_) => Some(ty),
+        syn::Type::Array(array) => find_pointer_type(&array.elem),
+        syn::Type::Tuple(tuple) => tuple.elems.iter().find_map(find_pointer_type),
+        syn::Type::Paren(paren) => find_pointer_type(&paren.elem),
+        syn::Type::Group(group) => find_pointer_type(&group.elem),
+        syn::Type::Path(type_path) => {
+            let last = type_path.path.segments.last()?;
+            if POINTER_TYPES.iter().any(|name| last.ident == name) {
+                return Some(ty);
+            }
+            if let syn::PathArguments::AngleBracketed(args) = &last.arguments {
+                args.args.iter().find_map(|arg| match arg {
+                    syn::GenericArgument::Type(ty) => find_pointer_type(ty),
+                    _ => None,
+                })
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// Checked when the type is compiled, the size is recorded for iceoryx as a u32.
+// Padding would hand uninitialized bytes of the writer to the readers.
+fn create_layout_assertions(item : &syn::ItemStruct) -> TokenStream {
+    let topic_ident = &item.ident;
+    let field_types = item.fields.iter().map(|field| &field.ty);
+    let ts = quote! {
+        const _: () = {
+            assert!(std::mem::size_of::<#topic_ident>() > 0, "TopicFixedSize types cannot be zero sized");
+            assert!(
+                0 #(+ std::mem::size_of::<#field_types>())* == std::mem::size_of::<#topic_ident>(),
+                "TopicFixedSize types cannot have padding, reorder the fields or add explicit padding fields"
+            );
+            assert!(
+                std::mem::size_of::<#topic_ident>() <= u32::MAX as usize,
+                "TopicFixedSize types must be smaller than 4GiB"
+            );
+        };
+    };
+    ts.into()
+}
+
 fn create_topic_functions(item : &syn::ItemStruct) -> TokenStream {
     let topic_key_ident = &item.ident;
 
@@ -201,12 +329,12 @@ fn create_topic_functions(item : &syn::ItemStruct) -> TokenStream {
             /// * `maybe_listener` - A listener to use on this topic. The listener is optional
             ///
             pub fn create_topic_with_name(
-                participant: &DdsParticipant,
+                participant: &cyclonedds_rs::DdsParticipant,
                 name: &str,
-                maybe_qos: Option<DdsQos>,
-                maybe_listener: Option<DdsListener>,
-            ) -> Result<DdsTopic::<Self>, DDSError> {
-                DdsTopic::<Self>::create(participant,name, maybe_qos,maybe_listener)
+                maybe_qos: Option<cyclonedds_rs::DdsQos>,
+                maybe_listener: Option<cyclonedds_rs::DdsListener>,
+            ) -> Result<cyclonedds_rs::DdsTopic<Self>, cyclonedds_rs::DDSError> {
+                cyclonedds_rs::DdsTopic::<Self>::create(participant,name, maybe_qos,maybe_listener)
             }
 
             /// Create a topic of this Type using the default topic name. The default topic
@@ -219,13 +347,13 @@ fn create_topic_functions(item : &syn::ItemStruct) -> TokenStream {
             /// * `maybe_listener` - A listener to use on this topic. The listener is optional
             ///
             pub fn create_topic(
-                participant: &DdsParticipant,
+                participant: &cyclonedds_rs::DdsParticipant,
                 maybe_topic_prefix: Option<&str>,
-                maybe_qos: Option<DdsQos>,
-                maybe_listener: Option<DdsListener>,
-            ) -> Result<DdsTopic::<Self>, DDSError> {
-                let name = #topic_key_ident::topic_name(maybe_topic_prefix);
-                DdsTopic::<Self>::create(participant,&name, maybe_qos,maybe_listener)
+                maybe_qos: Option<cyclonedds_rs::DdsQos>,
+                maybe_listener: Option<cyclonedds_rs::DdsListener>,
+            ) -> Result<cyclonedds_rs::DdsTopic<Self>, cyclonedds_rs::DDSError> {
+                let name = <#topic_key_ident as cyclonedds_rs::TopicType>::topic_name(maybe_topic_prefix);
+                cyclonedds_rs::DdsTopic::<Self>::create(participant,&name, maybe_qos,maybe_listener)
             }
 
             /// Create a sample buffer for storing an array of samples
@@ -233,8 +361,8 @@ fn create_topic_functions(item : &syn::ItemStruct) -> TokenStream {
             /// samples. Multiple samples are useful when you have one or more
             /// keys in your topic structure. Each value of the key will result in
             /// the storage of another sample.
-            pub fn create_sample_buffer(len: usize) -> SampleBuffer<#topic_key_ident> {
-                SampleBuffer::new(len)
+            pub fn create_sample_buffer(len: usize) -> cyclonedds_rs::SampleBuffer<#topic_key_ident> {
+                cyclonedds_rs::SampleBuffer::new(len)
             }
         }
     };
@@ -0,0 +1,40 @@
+#![no_main]
+
+// Samples kept as CDR for borrowed reads, see BorrowedTopicType
+
+use cdds_derive::Topic;
+use cyclonedds_rs::fuzz_support::FuzzSertype;
+use cyclonedds_rs::*;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Serialize, Deserialize, Topic, Default)]
+struct Frame {
+    #[topic_key]
+    id: u32,
+    label: String,
+    payload: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct FrameRef<'a> {
+    id: u32,
+    #[allow(dead_code)]
+    label: &'a str,
+    #[allow(dead_code)]
+    payload: &'a [u8],
+}
+
+impl BorrowedTopicType for Frame {
+    type Borrowed<'a> = FrameRef<'a>;
+
+    fn borrowed_key_cdr(sample: &FrameRef<'_>) -> Vec<u8> {
+        cdr::serialize::<_, _, cdr::CdrBe>(&sample.id, cdr::Infinite).unwrap()
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    thread_local! {
+        static FRAME: FuzzSertype<Frame> = FuzzSertype::new_borrowed();
+    }
+    FRAME.with(|t| t.run(data));
+});
@@ -0,0 +1,56 @@
+#![no_main]
+
+// Samples of keyed and keyless types with the field kinds of a typical topic,
+// deserialized the way they arrive from the network
+
+use cdds_derive::Topic;
+use cyclonedds_rs::fuzz_support::FuzzSertype;
+use cyclonedds_rs::*;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Serialize, Deserialize, Topic, Default)]
+struct Position {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+enum Mode {
+    #[default]
+    Idle,
+    Moving(u8),
+    Fault { code: u32, reason: String },
+}
+
+#[derive(Serialize, Deserialize, Topic, Default)]
+struct Vehicle {
+    #[topic_key]
+    id: u32,
+    #[topic_key]
+    name: String,
+    position: Position,
+    mode: Mode,
+    waypoints: Vec<Position>,
+    tags: Vec<String>,
+    image: Vec<u8>,
+    fuel: f32,
+}
+
+#[derive(Serialize, Deserialize, Topic, Default, Clone, Copy)]
+struct Fixed {
+    #[topic_key]
+    id: u64,
+    values: [i32; 8],
+}
+
+fuzz_target!(|data: &[u8]| {
+    thread_local! {
+        static VEHICLE: FuzzSertype<Vehicle> = FuzzSertype::new();
+        static POSITION: FuzzSertype<Position> = FuzzSertype::new();
+        static FIXED: FuzzSertype<Fixed> = FuzzSertype::new();
+    }
+    VEHICLE.with(|t| t.run(data));
+    POSITION.with(|t| t.run(data));
+    FIXED.with(|t| t.run(data));
+});
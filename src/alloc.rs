@@ -14,7 +14,51 @@
     limitations under the License.
 */
 
-pub use cyclonedds_sys::dds_alloc;
-pub use cyclonedds_sys::dds_free_op_t_DDS_FREE_ALL as DDS_FREE_ALL;
-pub use cyclonedds_sys::dds_sample_free;
+//! Where the memory of samples comes from.
+//!
+//! * Received samples are read into a [`SampleBuffer`]. The buffers of
+//!   [`DdsReader::sample_buffer`](crate::DdsReader::sample_buffer) take their
+//!   [`Sample`]s from the [`SamplePool`] of the reader and give them back when
+//!   dropped, so a reader in steady state reads without allocating.
+//!   [`SamplePool::preallocate`] fills the pool up front.
+//! * The value of a received sample is a [`SampleStorage`]: owned, or a
+//!   [`LoanedPtr`] into the memory of cyclone, such as the iceoryx chunk it
+//!   was received in. Either can outlive the buffer.
+//! * [`LoanedSamples`] are the samples of a read or take loaned from the
+//!   reader cache itself, see
+//!   [`DdsReader::take_loan`](crate::DdsReader::take_loan).
+//! * With the `shm` feature, writers of fixed size types loan a [`Loaned`]
+//!   sample from iceoryx, which is published without a copy, see
+//!   [`DdsWriter::loan`](crate::DdsWriter::loan).
 
+use cyclonedds_sys::{dds_free_op_t, dds_topic_descriptor_t, size_t};
+use std::ffi::c_void;
+
+// The raw allocation functions stay for one release. `#[deprecated]` has no
+// effect on a `pub use`, so they are wrapped.
+
+#[deprecated(since = "0.7.0", note = "samples are allocated through SamplePool and Loaned")]
+pub const DDS_FREE_ALL: dds_free_op_t = cyclonedds_sys::dds_free_op_t_DDS_FREE_ALL;
+
+/// # Safety
+/// As `dds_alloc` of cyclone
+#[deprecated(since = "0.7.0", note = "samples are allocated through SamplePool and Loaned")]
+pub unsafe fn dds_alloc(size: size_t) -> *mut c_void {
+    cyclonedds_sys::dds_alloc(size)
+}
+
+/// # Safety
+/// As `dds_sample_free` of cyclone
+#[deprecated(since = "0.7.0", note = "samples are allocated through SamplePool and Loaned")]
+pub unsafe fn dds_sample_free(
+    sample: *mut c_void,
+    desc: *const dds_topic_descriptor_t,
+    op: dds_free_op_t,
+) {
+    cyclonedds_sys::dds_sample_free(sample, desc, op)
+}
+
+pub use crate::dds_reader::LoanedSamples;
+#[cfg(cyclone_shm)]
+pub use crate::dds_writer::Loaned;
+pub use crate::serdes::{LoanedPtr, Sample, SampleBuffer, SamplePool, SampleStorage};
@@ -0,0 +1,138 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! A waker slot shared by a future and the cyclone thread that wakes it,
+//! without a lock. The same protocol as `AtomicWaker` of the futures crate:
+//! the slot is only accessed by whoever moves the state out of `WAITING`.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::Waker;
+
+const WAITING: usize = 0;
+const REGISTERING: usize = 0b01;
+const WAKING: usize = 0b10;
+
+pub(crate) struct AtomicWaker {
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// the waker is only accessed by the owner of the state
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+}
+
+impl AtomicWaker {
+    /// Wake `waker` on the next call to `wake`. If a `wake` races with the
+    /// registration, `waker` is woken right away.
+    pub(crate) fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => {
+                unsafe {
+                    let slot = &mut *self.waker.get();
+                    if !slot.as_ref().is_some_and(|w| w.will_wake(waker)) {
+                        *slot = Some(waker.clone());
+                    }
+                }
+                if let Err(state) =
+                    self.state
+                        .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire)
+                {
+                    // woken while registering, we still own the slot
+                    debug_assert_eq!(state, REGISTERING | WAKING);
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+            // a wake is in progress
+            Err(WAKING) => waker.wake_by_ref(),
+            // registered concurrently, which a single waiting task never does
+            Err(_) => {}
+        }
+    }
+
+    /// Wake the registered waker, if any
+    pub(crate) fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            // the registering side or another wake sees the flag
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    #[derive(Default)]
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_atomic_waker() {
+        let flag = Arc::new(Flag::default());
+        let waker = Waker::from(flag.clone());
+        let slot = Arc::new(AtomicWaker::default());
+
+        // nothing registered yet
+        slot.wake();
+        assert!(!flag.0.load(Ordering::SeqCst));
+
+        slot.register(&waker);
+        let other = slot.clone();
+        std::thread::spawn(move || other.wake()).join().unwrap();
+        assert!(flag.0.load(Ordering::SeqCst));
+
+        // a waker is woken once
+        flag.0.store(false, Ordering::SeqCst);
+        slot.wake();
+        assert!(!flag.0.load(Ordering::SeqCst));
+    }
+}
@@ -14,19 +14,856 @@
     limitations under the License.
 */
 
-use cyclonedds_sys::{DdsEntity};
+use cyclonedds_sys::{dds_entity_t, dds_guid_t, dds_instance_handle_t, dds_return_t, size_t, DdsEntity};
+use crate::dds_api::DdsStatus;
+use crate::dds_domain::DdsDomain;
+use crate::dds_listener::DdsListener;
+use crate::error::{DDSError, InvalidName, NameProblem};
+use std::convert::{TryFrom, TryInto};
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 
 /// An entity on which you can attach a DdsWriter
 pub trait DdsWritable {
     fn entity(&self) -> &DdsEntity;
+
+    /// `DDSError::UseAfterClose` once the entity is closed
+    fn check_valid(&self) -> Result<(), DDSError> {
+        Ok(())
+    }
+
+    /// Held by the writers created on the entity
+    #[doc(hidden)]
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        None
+    }
 }
 
 /// An entity on which you can attach a DdsReader
 pub trait DdsReadable {
     fn entity(&self) -> &DdsEntity;
+
+    /// `DDSError::UseAfterClose` once the entity is closed
+    fn check_valid(&self) -> Result<(), DDSError> {
+        Ok(())
+    }
+
+    /// Held by the readers created on the entity
+    #[doc(hidden)]
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        None
+    }
 }
 
 pub trait Entity {
     fn entity(&self) -> &DdsEntity;
+
+    /// `DDSError::UseAfterClose` once the entity is closed. The other
+    /// functions check this before using the entity.
+    fn check_valid(&self) -> Result<(), DDSError> {
+        Ok(())
+    }
+
+    /// The GUID of the entity. This is the same GUID that is seen in discovery
+    /// data and on the wire.
+    fn guid(&self) -> Result<Guid, DDSError> {
+        self.check_valid()?;
+        let mut guid = dds_guid_t { v: [0; 16] };
+        unsafe {
+            let ret = cyclonedds_sys::dds_get_guid(self.entity().entity(), &mut guid);
+            if ret == 0 {
+                Ok(Guid(guid.v))
+            } else {
+                Err(DDSError::from_retcode("dds_get_guid", ret))
+            }
+        }
+    }
+
+    /// The instance handle of the entity. Matches the `publication_handle` in
+    /// the sample info of received samples and the handles returned in
+    /// matched endpoint lists.
+    fn instance_handle(&self) -> Result<dds_instance_handle_t, DDSError> {
+        self.check_valid()?;
+        let mut handle: dds_instance_handle_t = 0;
+        unsafe {
+            let ret = cyclonedds_sys::dds_get_instance_handle(self.entity().entity(), &mut handle);
+            if ret == 0 {
+                Ok(handle)
+            } else {
+                Err(DDSError::from_retcode("dds_get_instance_handle", ret))
+            }
+        }
+    }
+
+    /// Enable an entity that was created disabled because the entity factory
+    /// QoS of its parent has autoenable turned off. Enabling an entity that is
+    /// already enabled has no effect.
+    fn enable(&self) -> Result<(), DDSError> {
+        self.check_valid()?;
+        unsafe {
+            let ret = cyclonedds_sys::dds_enable(self.entity().entity());
+            if ret == 0 {
+                Ok(())
+            } else {
+                Err(DDSError::from_retcode("dds_enable", ret))
+            }
+        }
+    }
+
+    /// The parent of this entity. The parent of a participant is its domain.
+    fn parent(&self) -> Result<DdsEntity, DDSError> {
+        self.check_valid()?;
+        unsafe {
+            let p = cyclonedds_sys::dds_get_parent(self.entity().entity());
+            if p > 0 {
+                Ok(DdsEntity::new(p))
+            } else {
+                Err(DDSError::from_retcode("dds_get_parent", p))
+            }
+        }
+    }
+
+    /// The participant this entity belongs to
+    fn participant(&self) -> Result<DdsEntity, DDSError> {
+        self.check_valid()?;
+        unsafe {
+            let p = cyclonedds_sys::dds_get_participant(self.entity().entity());
+            if p > 0 {
+                Ok(DdsEntity::new(p))
+            } else {
+                Err(DDSError::from_retcode("dds_get_participant", p))
+            }
+        }
+    }
+
+    /// The children of this entity, for example the readers of a subscriber.
+    fn children(&self) -> Result<Vec<DdsEntity>, DDSError> {
+        self.check_valid()?;
+        entity_list("dds_get_children", |buf, size| unsafe {
+            cyclonedds_sys::dds_get_children(self.entity().entity(), buf, size)
+        })
+    }
+
+    /// Enable the statuses in `mask`. Only enabled statuses trigger the entity
+    /// as a condition of a waitset.
+    fn set_status_mask(&self, mask: DdsStatus) -> Result<(), DDSError> {
+        self.check_valid()?;
+        let ret = unsafe { cyclonedds_sys::dds_set_status_mask(self.entity().entity(), mask.into()) };
+        if ret < 0 {
+            Err(DDSError::from_retcode("dds_set_status_mask", ret))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The enabled statuses
+    fn get_status_mask(&self) -> Result<DdsStatus, DDSError> {
+        self.check_valid()?;
+        status_op("dds_get_status_mask", |status| unsafe {
+            cyclonedds_sys::dds_get_status_mask(self.entity().entity(), status)
+        })
+    }
+
+    /// The statuses that changed since they were last read or taken, without
+    /// resetting them
+    fn get_status_changes(&self) -> Result<DdsStatus, DDSError> {
+        self.check_valid()?;
+        status_op("dds_get_status_changes", |status| unsafe {
+            cyclonedds_sys::dds_get_status_changes(self.entity().entity(), status)
+        })
+    }
+
+    /// The statuses in `mask` that changed, without resetting them
+    fn read_status(&self, mask: DdsStatus) -> Result<DdsStatus, DDSError> {
+        self.check_valid()?;
+        status_op("dds_read_status", |status| unsafe {
+            cyclonedds_sys::dds_read_status(self.entity().entity(), status, mask.into())
+        })
+    }
+
+    /// The statuses in `mask` that changed, resetting them
+    fn take_status(&self, mask: DdsStatus) -> Result<DdsStatus, DDSError> {
+        self.check_valid()?;
+        status_op("dds_take_status", |status| unsafe {
+            cyclonedds_sys::dds_take_status(self.entity().entity(), status, mask.into())
+        })
+    }
+
+    /// Whether the entity is triggered as a condition: one of its enabled
+    /// statuses changed, or the condition of a read condition or guard
+    /// condition holds.
+    fn triggered(&self) -> Result<bool, DDSError> {
+        self.check_valid()?;
+        let ret = unsafe { cyclonedds_sys::dds_triggered(self.entity().entity()) };
+        if ret < 0 {
+            Err(DDSError::from_retcode("dds_triggered", ret))
+        } else {
+            Ok(ret > 0)
+        }
+    }
+}
+
+// Get a status word of an entity from a cyclone function
+fn status_op<F>(operation: &'static str, f: F) -> Result<DdsStatus, DDSError>
+where
+    F: FnOnce(&mut u32) -> dds_return_t,
+{
+    let mut status = 0u32;
+    let ret = f(&mut status);
+    if ret < 0 {
+        Err(DDSError::from_retcode(operation, ret))
+    } else {
+        Ok(DdsStatus::from(status))
+    }
+}
+
+/// The validity flag of an entity shared by several handles. Once the entity
+/// is closed, the handles fail with `DDSError::UseAfterClose` instead of
+/// passing the id of the deleted entity to cyclone, which may reuse it.
+#[derive(Debug, Default)]
+pub(crate) struct Validity(AtomicBool);
+
+impl Validity {
+    pub(crate) fn check(&self) -> Result<(), DDSError> {
+        if self.is_valid() {
+            Ok(())
+        } else {
+            Err(DDSError::UseAfterClose)
+        }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        !self.0.load(Ordering::SeqCst)
+    }
+
+    /// Mark the entity closed. Fails if it was closed before.
+    pub(crate) fn invalidate(&self) -> Result<(), DDSError> {
+        if self.0.swap(true, Ordering::SeqCst) {
+            Err(DDSError::UseAfterClose)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A cyclone entity shared by the clones of an entity type. The entity is
+/// deleted once the last clone is dropped, and the last clone is only
+/// dropped after the entities created under it: each entity holds its
+/// parent. The listener, topic or domain the entity uses are released after
+/// the entity is deleted.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct EntityWrapper(Arc<Wrapped>);
+
+struct Wrapped {
+    entity: DdsEntity,
+    // for the messages of failed deletes
+    kind: &'static str,
+    // cleared once the entity is closed
+    validity: Validity,
+    // participants found with lookup belong to someone else
+    owned: bool,
+    // the listener it was created with, or the one that replaced it
+    listener: Mutex<Option<DdsListener>>,
+    _uses: Uses,
+    // dropped last, the parent is deleted after its children
+    parent: Option<EntityWrapper>,
+}
+
+/// What an entity needs until it is deleted, besides its parent
+pub(crate) enum Uses {
+    Nothing,
+    /// The topic of a reader or writer
+    Topic(EntityWrapper),
+    /// The explicitly created domain of a participant
+    Domain(DdsDomain),
+}
+
+impl EntityWrapper {
+    pub(crate) fn new(
+        kind: &'static str,
+        entity: DdsEntity,
+        parent: Option<EntityWrapper>,
+        listener: Option<DdsListener>,
+        uses: Uses,
+    ) -> Self {
+        EntityWrapper(Arc::new(Wrapped {
+            entity,
+            kind,
+            validity: Validity::default(),
+            owned: true,
+            listener: Mutex::new(listener),
+            _uses: uses,
+            parent,
+        }))
+    }
+
+    /// An entity that is not deleted when the last clone is dropped
+    pub(crate) fn unowned(kind: &'static str, entity: DdsEntity) -> Self {
+        EntityWrapper(Arc::new(Wrapped {
+            entity,
+            kind,
+            validity: Validity::default(),
+            owned: false,
+            listener: Mutex::new(None),
+            _uses: Uses::Nothing,
+            parent: None,
+        }))
+    }
+
+    pub(crate) fn entity(&self) -> &DdsEntity {
+        &self.0.entity
+    }
+
+    pub(crate) fn domain(&self) -> Option<&DdsDomain> {
+        match &self.0._uses {
+            Uses::Domain(domain) => Some(domain),
+            _ => None,
+        }
+    }
+
+    /// `DDSError::UseAfterClose` once the entity or one of its ancestors is
+    /// closed, which deletes the entity
+    pub(crate) fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
+    }
+
+    /// Replace the listener of the entity, for the entity and its clones. The
+    /// previous listener is kept until cyclone no longer calls it.
+    pub(crate) fn set_listener(&self, listener: DdsListener) -> Result<(), DDSError> {
+        self.check_valid()?;
+        // held while setting, so the stored listener is the one cyclone calls
+        let mut current = self.0.listener.lock().unwrap_or_else(PoisonError::into_inner);
+        let ret = unsafe { cyclonedds_sys::dds_set_listener(self.0.entity.entity(), (&listener).into()) };
+        if ret == 0 {
+            *current = Some(listener);
+            Ok(())
+        } else {
+            Err(DDSError::from_retcode("dds_set_listener", ret).with_entity_kind(self.0.kind))
+        }
+    }
+
+    /// true if the entity itself was closed
+    pub(crate) fn is_closed(&self) -> bool {
+        !self.0.validity.is_valid()
+    }
+
+    /// Mark the entity closed, for the entity and its clones. The caller
+    /// deletes it.
+    pub(crate) fn invalidate(&self) -> Result<(), DDSError> {
+        self.0.check_valid()?;
+        self.0.validity.invalidate()
+    }
+
+    /// Delete the entity and its children now, instead of when the last clone
+    /// is dropped
+    pub(crate) fn close(&self) -> Result<(), DDSError> {
+        self.invalidate()?;
+        let ret = unsafe { cyclonedds_sys::dds_delete(self.0.entity.entity()) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(DDSError::from_retcode("dds_delete", ret).with_entity_kind(self.0.kind))
+        }
+    }
+}
+
+impl Wrapped {
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.validity.check()?;
+        if let Some(parent) = &self.parent {
+            parent.check_valid()?;
+        }
+        // the topic goes with the participant, whatever the parent is
+        match &self._uses {
+            Uses::Topic(topic) => topic.check_valid(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Wrapped {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn drop(&mut self) {
+        // deleted by close, or with a closed ancestor. The id may belong to a
+        // new entity.
+        if !self.owned || self.check_valid().is_err() {
+            return;
+        }
+        let ret = unsafe { cyclonedds_sys::dds_delete(self.entity.entity()) };
+        // the entity may have been deleted with its domain
+        if ret != 0 && DDSError::from(ret) != DDSError::AlreadyDeleted {
+            crate::dds_log::drop_failed(self.kind, &DDSError::from_retcode("dds_delete", ret));
+        }
+    }
+}
+
+/// Handles returned by the navigation functions can be used
+/// to navigate further.
+impl Entity for DdsEntity {
+    fn entity(&self) -> &DdsEntity {
+        self
+    }
+}
+
+// Lengths and sample counts between Rust and cyclone. size_t is 32 bit on
+// armv7 and the length of an iovec is a ULONG on Windows, so lengths are
+// converted to whatever cyclone uses instead of casting to a fixed width.
+const _: () = assert!(std::mem::size_of::<size_t>() >= std::mem::size_of::<u32>());
+const _: () = assert!(std::mem::size_of::<usize>() <= std::mem::size_of::<u64>());
+const _: () = assert!(std::mem::size_of::<dds_return_t>() == std::mem::size_of::<i32>());
+
+/// The integer types cyclone uses for lengths
+pub(crate) trait CLen: TryFrom<usize> {
+    const MAX: Self;
+}
+
+impl CLen for u32 {
+    const MAX: Self = u32::MAX;
+}
+
+impl CLen for u64 {
+    const MAX: Self = u64::MAX;
+}
+
+impl CLen for usize {
+    const MAX: Self = usize::MAX;
+}
+
+/// A length for cyclone, a size_t or the length of an iovec. Saturates, as
+/// this is called from the sertype callbacks where a panic would unwind into
+/// cyclone.
+pub(crate) fn c_len<L: CLen>(len: usize) -> L {
+    L::try_from(len).unwrap_or(L::MAX)
+}
+
+/// A length from cyclone, saturating like [`c_len`]
+pub(crate) fn rust_len<L: TryInto<usize>>(len: L) -> usize {
+    len.try_into().unwrap_or(usize::MAX)
+}
+
+/// The maximum number of samples of a read or take into `len` buffers
+pub(crate) fn max_samples(len: usize) -> u32 {
+    u32::try_from(len).unwrap_or(u32::MAX)
+}
+
+/// The number of samples of a successful read or take
+pub(crate) fn sample_count(ret: dds_return_t) -> usize {
+    usize::try_from(ret).unwrap_or(0)
+}
+
+/// The initial size of the buffers of listeners that empty their reader, see
+/// [`drain`]
+pub(crate) const DRAIN_BATCH: usize = 16;
+
+/// Empty a reader whose samples do not go into a [`SampleBuffer`](crate::SampleBuffer).
+/// `take` takes up to [`DRAIN_BATCH`] samples and returns how many it took, it
+/// is called until it takes fewer. Typed readers grow their buffer instead,
+/// with `DdsReader::take_all_from_entity`.
+pub(crate) fn drain<F>(mut take: F) -> Result<(), DDSError>
+where
+    F: FnMut() -> Result<usize, DDSError>,
+{
+    loop {
+        if take()? < DRAIN_BATCH {
+            return Ok(());
+        }
+    }
+}
+
+/// Collect a list of entities from a cyclone function that takes a buffer and its
+/// size and returns the number of entities available.
+pub(crate) fn entity_list<F>(operation: &'static str, f: F) -> Result<Vec<DdsEntity>, DDSError>
+where
+    F: Fn(*mut dds_entity_t, size_t) -> dds_return_t,
+{
+    let mut handles: Vec<dds_entity_t> = Vec::new();
+    loop {
+        // cyclone wants a null pointer when asking for the count only
+        let n = f(
+            if handles.is_empty() {
+                std::ptr::null_mut()
+            } else {
+                handles.as_mut_ptr()
+            },
+            c_len(handles.len()),
+        );
+        if n < 0 {
+            return Err(DDSError::from_retcode(operation, n));
+        }
+        // entities may be created while we look, retry until they fit
+        if sample_count(n) <= handles.len() {
+            return Ok(handles[..sample_count(n)]
+                .iter()
+                .map(|h| unsafe { DdsEntity::new(*h) })
+                .collect());
+        }
+        handles.resize(n as usize, 0);
+    }
+}
+
+/// The longest topic or partition name we hand to cyclone
+pub(crate) const MAX_NAME_LEN: usize = 1024;
+
+fn check_name(kind: &'static str, name: &str, wildcards: bool) -> Result<CString, DDSError> {
+    let problem = if name.len() > MAX_NAME_LEN {
+        Some(NameProblem::TooLong(MAX_NAME_LEN))
+    } else {
+        name.char_indices().find_map(|(i, c)| match c {
+            '\0' => Some(NameProblem::Nul(i)),
+            '*' | '?' if !wildcards => Some(NameProblem::Wildcard(c, i)),
+            c if !c.is_ascii_graphic() && c != ' ' => Some(NameProblem::NotPrintable(c, i)),
+            _ => None,
+        })
+    };
+    match problem {
+        Some(problem) => Err(InvalidName::new(kind, name, problem).into()),
+        // no NUL, checked above
+        None => Ok(CString::new(name).unwrap()),
+    }
+}
+
+/// The C string of a topic name, which must be non-empty printable ASCII
+/// without wildcards
+pub(crate) fn topic_name(name: &str) -> Result<CString, DDSError> {
+    if name.is_empty() {
+        return Err(InvalidName::new("topic", name, NameProblem::Empty).into());
+    }
+    check_name("topic", name, false)
+}
+
+/// The C string of a type name, which must be non-empty printable ASCII
+pub(crate) fn type_name(name: &str) -> Result<CString, DDSError> {
+    if name.is_empty() {
+        return Err(InvalidName::new("type", name, NameProblem::Empty).into());
+    }
+    check_name("type", name, false)
+}
+
+/// The C string of a partition name. Partitions may be empty (the default
+/// partition) and may hold the wildcards `*` and `?`.
+pub(crate) fn partition_name(name: &str) -> Result<CString, DDSError> {
+    check_name("partition", name, true)
+}
+
+/// The GUID of a DDS entity. Displayed in the standard
+/// 8-4-4-4-12 hexadecimal form.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// The GUID prefix, identifying the participant
+    pub fn prefix(&self) -> [u8; 12] {
+        let mut prefix = [0; 12];
+        prefix.copy_from_slice(&self.0[..12]);
+        prefix
+    }
+}
+
+impl From<[u8; 16]> for Guid {
+    fn from(v: [u8; 16]) -> Self {
+        Guid(v)
+    }
+}
+
+impl std::fmt::Display for Guid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// A set of bit flags with the API shared by the flag types of the crate, like
+/// [`DdsStatus`]. Every flag has a constant and a name: `parse` reads names
+/// separated by commas and `Display` writes them, `Debug` shows the constants.
+/// An alias is a name for several flags that `parse` accepts.
+macro_rules! bit_flags {
+    (
+        $(#[$meta:meta])*
+        pub struct $flags:ident(u32) {
+            $($flag:ident = $bits:expr, $name:literal;)*
+        }
+        $(aliases { $($alias:literal => $aliased:expr;)* })?
+    ) => {
+        $(#[$meta])*
+        #[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct $flags(u32);
+
+        impl $flags {
+            $(pub const $flag: Self = Self($bits);)*
+
+            // the flags with their constants and names
+            const FLAGS: &'static [(Self, &'static str, &'static str)] =
+                &[$((Self::$flag, stringify!($flag), $name),)*];
+
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+
+            /// All the flags
+            pub const fn all() -> Self {
+                Self(0 $(| ($bits))*)
+            }
+
+            pub const fn from_bits(bits: u32) -> Self {
+                Self(bits)
+            }
+
+            pub const fn bits(&self) -> u32 {
+                self.0
+            }
+
+            pub const fn is_empty(&self) -> bool {
+                self.0 == 0
+            }
+
+            /// Whether all the flags of `other` are set
+            pub const fn contains(&self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// Whether any of the flags of `other` is set
+            pub const fn intersects(&self, other: Self) -> bool {
+                self.0 & other.0 != 0
+            }
+
+            pub fn insert(&mut self, other: Self) {
+                self.0 |= other.0;
+            }
+
+            pub fn remove(&mut self, other: Self) {
+                self.0 &= !other.0;
+            }
+
+            /// The flags that are set, one at a time
+            pub fn iter(&self) -> impl Iterator<Item = Self> {
+                let this = *self;
+                Self::FLAGS
+                    .iter()
+                    .map(|(flag, _, _)| *flag)
+                    .filter(move |flag| this.contains(*flag))
+            }
+
+            /// Parse a comma separated list of names, for example the output
+            /// of `Display`. Case and whitespace around the names are ignored.
+            pub fn parse(names: &str) -> Result<Self, $crate::error::DDSError> {
+                let mut flags = Self::empty();
+                for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    flags |= Self::named(name).ok_or($crate::error::DDSError::BadParameter)?;
+                }
+                Ok(flags)
+            }
+
+            fn named(name: &str) -> Option<Self> {
+                $($(
+                    if name.eq_ignore_ascii_case($alias) {
+                        return Some($aliased);
+                    }
+                )*)?
+                Self::FLAGS
+                    .iter()
+                    .find(|(_, _, flag_name)| flag_name.eq_ignore_ascii_case(name))
+                    .map(|(flag, _, _)| *flag)
+            }
+        }
+
+        impl ::std::ops::BitOr for $flags {
+            type Output = Self;
+            fn bitor(self, other: Self) -> Self {
+                Self(self.0 | other.0)
+            }
+        }
+
+        impl ::std::ops::BitOrAssign for $flags {
+            fn bitor_assign(&mut self, other: Self) {
+                self.0 |= other.0;
+            }
+        }
+
+        impl ::std::ops::BitAnd for $flags {
+            type Output = Self;
+            fn bitand(self, other: Self) -> Self {
+                Self(self.0 & other.0)
+            }
+        }
+
+        impl ::std::ops::BitAndAssign for $flags {
+            fn bitand_assign(&mut self, other: Self) {
+                self.0 &= other.0;
+            }
+        }
+
+        impl ::std::ops::Sub for $flags {
+            type Output = Self;
+            fn sub(self, other: Self) -> Self {
+                Self(self.0 & !other.0)
+            }
+        }
+
+        impl ::std::iter::FromIterator<$flags> for $flags {
+            fn from_iter<I: IntoIterator<Item = $flags>>(iter: I) -> Self {
+                iter.into_iter().fold(Self::empty(), |all, flag| all | flag)
+            }
+        }
+
+        /// The constants of the flags that are set, like `Flags(A | B)`. Bits
+        /// that are no flag are shown in hex.
+        impl ::std::fmt::Debug for $flags {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                let mut names: Vec<String> = Self::FLAGS
+                    .iter()
+                    .filter(|(flag, _, _)| self.contains(*flag))
+                    .map(|(_, constant, _)| (*constant).to_owned())
+                    .collect();
+                let unknown = (*self - Self::all()).0;
+                if unknown != 0 {
+                    names.push(format!("{:#x}", unknown));
+                }
+                if names.is_empty() {
+                    write!(f, "{}(empty)", stringify!($flags))
+                } else {
+                    write!(f, "{}({})", stringify!($flags), names.join(" | "))
+                }
+            }
+        }
+
+        /// The names of the flags that are set, separated by commas
+        impl ::std::fmt::Display for $flags {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                let names: Vec<&str> = Self::FLAGS
+                    .iter()
+                    .filter(|(flag, _, _)| self.contains(*flag))
+                    .map(|(_, _, name)| *name)
+                    .collect();
+                write!(f, "{}", names.join(","))
+            }
+        }
+
+        impl From<u32> for $flags {
+            fn from(bits: u32) -> Self {
+                Self(bits)
+            }
+        }
+
+        impl From<$flags> for u32 {
+            fn from(flags: $flags) -> Self {
+                flags.0
+            }
+        }
+    };
+}
+
+pub(crate) use bit_flags;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DdsParticipant;
+
+    #[test]
+    fn test_len_conversions() {
+        let len: size_t = c_len(4096);
+        assert_eq!(rust_len(len), 4096);
+        assert_eq!(max_samples(usize::MAX), u32::MAX);
+        assert_eq!(sample_count(-1), 0);
+        assert_eq!(sample_count(3), 3);
+    }
+
+    #[test]
+    fn test_name_validation() {
+        assert!(topic_name("rt/chatter").is_ok());
+        assert!(partition_name("").is_ok());
+        assert!(partition_name("sensors/*").is_ok());
+
+        let problem = |err: DDSError| {
+            assert_eq!(err, DDSError::BadParameter);
+            std::error::Error::source(&err)
+                .and_then(|e| e.downcast_ref::<InvalidName>())
+                .map(|e| e.problem())
+        };
+        assert_eq!(problem(topic_name("").unwrap_err()), Some(NameProblem::Empty));
+        assert_eq!(problem(topic_name("a\0b").unwrap_err()), Some(NameProblem::Nul(1)));
+        assert_eq!(
+            problem(topic_name("a*").unwrap_err()),
+            Some(NameProblem::Wildcard('*', 1))
+        );
+        assert_eq!(
+            problem(partition_name("caf\u{e9}").unwrap_err()),
+            Some(NameProblem::NotPrintable('\u{e9}', 3))
+        );
+        assert_eq!(
+            problem(topic_name(&"x".repeat(MAX_NAME_LEN + 1)).unwrap_err()),
+            Some(NameProblem::TooLong(MAX_NAME_LEN))
+        );
+        assert!(topic_name("a\nb")
+            .unwrap_err()
+            .to_string()
+            .contains("not a printable ASCII character"));
+    }
+
+    #[test]
+    fn test_guid_display() {
+        let guid = Guid([
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+            0x0f, 0x10,
+        ]);
+        assert_eq!(guid.to_string(), "01020304-0506-0708-090a-0b0c0d0e0f10");
+    }
+
+    #[test]
+    fn test_participant_guid() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let guid = participant.guid().unwrap();
+        assert_ne!(guid, Guid::default());
+    }
+
+    #[test]
+    fn test_instance_handle() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let other = DdsParticipant::create(None, None, None).unwrap();
+        let handle = participant.instance_handle().unwrap();
+        assert_ne!(handle, 0);
+        assert_ne!(handle, other.instance_handle().unwrap());
+    }
+
+    #[test]
+    fn test_create_disabled_and_enable() {
+        let mut qos = crate::DdsQos::create().unwrap();
+        qos.set_entity_factory(false);
+        let participant = DdsParticipant::create(None, Some(qos), None).unwrap();
+        let subscriber = crate::DdsSubscriber::create(&participant, None, None).unwrap();
+        assert!(subscriber.enable().is_ok());
+        assert!(participant.enable().is_ok());
+    }
+
+    #[test]
+    fn test_navigation() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let subscriber = crate::DdsSubscriber::create(&participant, None, None).unwrap();
+        let publisher = crate::DdsPublisher::create(&participant, None, None).unwrap();
+
+        let me = participant.guid().unwrap();
+        assert_eq!(Entity::participant(&subscriber).unwrap().guid().unwrap(), me);
+        assert_eq!(publisher.parent().unwrap().guid().unwrap(), me);
+
+        let children: Vec<Guid> = participant
+            .children()
+            .unwrap()
+            .iter()
+            .map(|c| c.guid().unwrap())
+            .collect();
+        assert!(children.contains(&subscriber.guid().unwrap()));
+        assert!(children.contains(&publisher.guid().unwrap()));
+    }
 }
 
@@ -14,10 +14,12 @@
     limitations under the License.
 */
 
-use std::convert::From;
+//! Status masks and the status ids of cyclone. The statuses of an entity are
+//! read and enabled through [`Entity`], for example
+//! [`Entity::get_status_changes`].
 
-use crate::common::Entity;
-pub use cyclonedds_sys::dds_error::DDSError;
+use crate::common::{bit_flags, Entity};
+pub use crate::error::DDSError;
 use cyclonedds_sys::DdsEntity;
 
 //use crate::dds_writer::DdsWriter;
@@ -41,65 +43,61 @@ pub use cyclonedds_sys::dds_status_id_DDS_SUBSCRIPTION_MATCHED_STATUS_ID as DDS_
 pub use cyclonedds_sys::State;
 pub use cyclonedds_sys::StateMask;
 
-#[derive(Default)]
-pub struct DdsStatus(u32);
+bit_flags! {
+    /// A set of statuses, used as the status mask of an entity and for the
+    /// statuses that changed. The statuses combine like bit flags:
+    /// `DdsStatus::PUBLICATION_MATCHED | DdsStatus::LIVELINESS_LOST`.
+    pub struct DdsStatus(u32) {
+        INCONSISTENT_TOPIC = 1 << DDS_INCONSISTENT_TOPIC_STATUS_ID, "inconsistent_topic";
+        OFFERED_DEADLINE_MISSED = 1 << DDS_OFFERED_DEADLINE_MISSED_STATUS_ID, "offered_deadline_missed";
+        REQUESTED_DEADLINE_MISSED = 1 << DDS_REQUESTED_DEADLINE_MISSED_STATUS_ID, "requested_deadline_missed";
+        OFFERED_INCOMPATIBLE_QOS = 1 << DDS_OFFERED_INCOMPATIBLE_QOS_STATUS_ID, "offered_incompatible_qos";
+        REQUESTED_INCOMPATIBLE_QOS = 1 << DDS_REQUESTED_INCOMPATIBLE_QOS_STATUS_ID, "requested_incompatible_qos";
+        SAMPLE_LOST = 1 << DDS_SAMPLE_LOST_STATUS_ID, "sample_lost";
+        SAMPLE_REJECTED = 1 << DDS_SAMPLE_REJECTED_STATUS_ID, "sample_rejected";
+        DATA_ON_READERS = 1 << DDS_DATA_ON_READERS_STATUS_ID, "data_on_readers";
+        DATA_AVAILABLE = 1 << DDS_DATA_AVAILABLE_STATUS_ID, "data_available";
+        LIVELINESS_LOST = 1 << DDS_LIVELINESS_LOST_STATUS_ID, "liveliness_lost";
+        LIVELINESS_CHANGED = 1 << DDS_LIVELINESS_CHANGED_STATUS_ID, "liveliness_changed";
+        PUBLICATION_MATCHED = 1 << DDS_PUBLICATION_MATCHED_STATUS_ID, "publication_matched";
+        SUBSCRIPTION_MATCHED = 1 << DDS_SUBSCRIPTION_MATCHED_STATUS_ID, "subscription_matched";
+    }
+}
 
 impl DdsStatus {
+    /// Set a status by its id, `DDS_..._STATUS_ID`
     pub fn set(mut self, id: dds_status_id) -> Self {
         let mask = 1 << id;
         self.0 |= mask;
         self
     }
 
+    /// Whether the status with the id `DDS_..._STATUS_ID` is set
     pub fn is_set(&self, id: dds_status_id) -> bool {
         let mask = 1 << id;
         mask & self.0 != 0
     }
 }
 
-
-
-impl From<DdsStatus> for u32 {
-    fn from(status: DdsStatus) -> Self {
-        status.0
-    }
-}
-
+#[deprecated(note = "use Entity::set_status_mask")]
 pub fn dds_set_status_mask(entity: &DdsEntity, status_mask: DdsStatus) -> Result<(), DDSError> {
-    unsafe {
-        let err = cyclonedds_sys::dds_set_status_mask(entity.entity(), status_mask.into());
-
-        if err < 0 {
-            Err(DDSError::from(err))
-        } else {
-            Ok(())
-        }
-    }
+    entity.set_status_mask(status_mask)
 }
 
+#[deprecated(note = "use Entity::get_status_changes")]
 pub fn dds_get_status_changes(entity: &DdsEntity) -> Result<DdsStatus, DDSError> {
-    unsafe {
-        let mut status = DdsStatus::default();
-        let err = cyclonedds_sys::dds_get_status_changes(entity.entity(), &mut status.0);
-
-        if err < 0 {
-            Err(DDSError::from(err))
-        } else {
-            Ok(status)
-        }
-    }
+    entity.get_status_changes()
+}
+
+/// Read and reset the status flags in `status_mask`
+#[deprecated(note = "use Entity::take_status")]
+pub fn dds_take_status(entity: &DdsEntity, status_mask: DdsStatus) -> Result<DdsStatus, DDSError> {
+    entity.take_status(status_mask)
 }
 
+#[deprecated(note = "use Entity::triggered, which reports whether the entity is triggered")]
 pub fn dds_triggered(entity: &dyn Entity) -> Result<(), DDSError> {
-    unsafe {
-        let err = cyclonedds_sys::dds_triggered(entity.entity().entity());
-
-        if err < 0 {
-            Err(DDSError::from(err))
-        } else {
-            Ok(())
-        }
-    }
+    entity.triggered().map(|_| ())
 }
 
 #[cfg(test)]
@@ -119,4 +117,39 @@ mod dds_qos_tests {
         assert_eq!(true, status.is_set(DDS_SUBSCRIPTION_MATCHED_STATUS_ID));
         assert_eq!(false, status.is_set(DDS_SAMPLE_REJECTED_STATUS_ID));
     }
+
+    #[test]
+    fn test_dds_status_flags() {
+        let status = DdsStatus::INCONSISTENT_TOPIC | DdsStatus::SUBSCRIPTION_MATCHED;
+        assert_eq!(
+            status,
+            DdsStatus::default()
+                .set(DDS_INCONSISTENT_TOPIC_STATUS_ID)
+                .set(DDS_SUBSCRIPTION_MATCHED_STATUS_ID)
+        );
+        assert!(status.contains(DdsStatus::SUBSCRIPTION_MATCHED));
+        assert!(!status.contains(DdsStatus::SUBSCRIPTION_MATCHED | DdsStatus::SAMPLE_LOST));
+        assert!(status.intersects(DdsStatus::SUBSCRIPTION_MATCHED | DdsStatus::SAMPLE_LOST));
+        assert_eq!(status - DdsStatus::INCONSISTENT_TOPIC, DdsStatus::SUBSCRIPTION_MATCHED);
+        assert_eq!(
+            status.iter().collect::<Vec<_>>(),
+            vec![DdsStatus::INCONSISTENT_TOPIC, DdsStatus::SUBSCRIPTION_MATCHED]
+        );
+        assert_eq!(status.iter().collect::<DdsStatus>(), status);
+        assert_eq!(DdsStatus::all().iter().count(), 13);
+
+        assert_eq!(format!("{:?}", status), "DdsStatus(INCONSISTENT_TOPIC | SUBSCRIPTION_MATCHED)");
+        assert_eq!(format!("{:?}", DdsStatus::empty()), "DdsStatus(empty)");
+        assert_eq!(format!("{:?}", DdsStatus::from(1 << 31)), "DdsStatus(0x80000000)");
+        assert_eq!(status.to_string(), "inconsistent_topic,subscription_matched");
+        assert_eq!(DdsStatus::parse("Inconsistent_Topic, subscription_matched"), Ok(status));
+        assert_eq!(DdsStatus::parse("nonsense"), Err(DDSError::BadParameter));
+
+        let mut status = DdsStatus::empty();
+        status.insert(DdsStatus::DATA_AVAILABLE);
+        status |= DdsStatus::DATA_ON_READERS;
+        status.remove(DdsStatus::DATA_AVAILABLE);
+        assert_eq!(status, DdsStatus::DATA_ON_READERS);
+        assert!(!status.is_empty());
+    }
 }
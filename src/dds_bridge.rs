@@ -0,0 +1,434 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Forward topics from one domain into another, for example from a robot
+//! internal domain to a fleet domain. Samples are forwarded in their serialized
+//! form, they are never deserialized. Disposes and unregisters are forwarded as
+//! well. The bridge works in one direction, create a second bridge to forward
+//! the other way.
+//!
+//! A keyed topic needs a [`DynamicType`] describing at least the key fields, so
+//! that the bridge can tell instances apart. Keyless topics only need the type
+//! name.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! let inside = DdsParticipant::create(Some(0), None, None).unwrap();
+//! let outside = DdsParticipant::create(Some(1), None, None).unwrap();
+//! let pose = DynamicType::from_idl("module robot { struct Pose { @key long id; double x; double y; }; };").unwrap();
+//! let bridge = DomainBridgeBuilder::new()
+//!     .with_topic(BridgedTopic::new("status", "robot::Status"))
+//!     .with_topic(BridgedTopic::keyed("pose", pose).renamed("robot1/pose"))
+//!     .create(&inside, &outside)
+//!     .unwrap();
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use cyclonedds_sys::*;
+
+use crate::common::{drain, max_samples, sample_count, EntityWrapper, Uses, DRAIN_BATCH};
+use crate::dds_dynamic::DynamicType;
+use crate::error::DDSError;
+use crate::serdes_raw::{create_topic, KeyCodec, RawSerData};
+use crate::{DdsListener, DdsParticipant, DdsQos, Entity};
+
+// the status info of disposes and unregisters on the wire
+const STATUSINFO_DISPOSE: u32 = 1;
+const STATUSINFO_UNREGISTER: u32 = 2;
+
+/// A topic forwarded by a [`DomainBridge`]
+#[derive(Clone)]
+pub struct BridgedTopic {
+    name: String,
+    type_name: String,
+    key: Option<Arc<DynamicType>>,
+    rename: Option<String>,
+    topic_qos: Option<DdsQos>,
+    reader_qos: Option<DdsQos>,
+    writer_qos: Option<DdsQos>,
+}
+
+impl BridgedTopic {
+    /// A keyless topic
+    pub fn new(name: &str, type_name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            type_name: type_name.to_owned(),
+            key: None,
+            rename: None,
+            topic_qos: None,
+            reader_qos: None,
+            writer_qos: None,
+        }
+    }
+
+    /// A keyed topic. The type name is the name of the dynamic type, which
+    /// must describe the fields up to the last key field.
+    pub fn keyed(name: &str, dynamic_type: DynamicType) -> Self {
+        let mut topic = Self::new(name, dynamic_type.name());
+        topic.key = Some(Arc::new(dynamic_type));
+        topic
+    }
+
+    /// Publish under another name in the destination domain
+    pub fn renamed(mut self, name: &str) -> Self {
+        self.rename = Some(name.to_owned());
+        self
+    }
+
+    /// The topic QoS in both domains
+    pub fn with_topic_qos(mut self, qos: DdsQos) -> Self {
+        self.topic_qos = Some(qos);
+        self
+    }
+
+    /// The QoS of the reader in the source domain
+    pub fn with_reader_qos(mut self, qos: DdsQos) -> Self {
+        self.reader_qos = Some(qos);
+        self
+    }
+
+    /// The QoS of the writer in the destination domain
+    pub fn with_writer_qos(mut self, qos: DdsQos) -> Self {
+        self.writer_qos = Some(qos);
+        self
+    }
+
+    /// The name of the topic in the destination domain
+    pub fn destination_name(&self) -> &str {
+        self.rename.as_deref().unwrap_or(&self.name)
+    }
+}
+
+pub struct DomainBridgeBuilder {
+    topics: Vec<BridgedTopic>,
+}
+
+impl DomainBridgeBuilder {
+    pub fn new() -> Self {
+        Self { topics: Vec::new() }
+    }
+
+    pub fn with_topic(mut self, topic: BridgedTopic) -> Self {
+        self.topics.push(topic);
+        self
+    }
+
+    /// Start forwarding from the domain of `from` into the domain of `to`
+    pub fn create(self, from: &DdsParticipant, to: &DdsParticipant) -> Result<DomainBridge, DDSError> {
+        DomainBridge::create(from, to, self.topics)
+    }
+}
+
+impl Default for DomainBridgeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counters of a [`DomainBridge`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BridgeStats {
+    /// Samples, disposes and unregisters written into the destination domain
+    pub forwarded: u64,
+    /// Samples that could not be written into the destination domain
+    pub failed: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    forwarded: AtomicU64,
+    failed: AtomicU64,
+}
+
+// A reader in the source domain and the writer it forwards to, each with its
+// topic and participant
+struct Route {
+    // dropped first so that the listener no longer uses the writer
+    _reader: EntityWrapper,
+    _writer: EntityWrapper,
+}
+
+/// Forwards topics between domains until dropped
+pub struct DomainBridge {
+    _routes: Vec<Route>,
+    counters: Arc<Counters>,
+}
+
+impl DomainBridge {
+    fn create(
+        from: &DdsParticipant,
+        to: &DdsParticipant,
+        topics: Vec<BridgedTopic>,
+    ) -> Result<Self, DDSError> {
+        // forwarding a topic into itself would loop forever
+        if from.domain_id()? == to.domain_id()?
+            && topics.iter().any(|t| t.destination_name() == t.name)
+        {
+            return Err(DDSError::BadParameter);
+        }
+
+        let counters = Arc::new(Counters::default());
+        let mut routes = Vec::with_capacity(topics.len());
+        for topic in topics {
+            routes.push(Self::route(from, to, topic, counters.clone())?);
+        }
+        Ok(Self {
+            _routes: routes,
+            counters,
+        })
+    }
+
+    fn route(
+        from: &DdsParticipant,
+        to: &DdsParticipant,
+        topic: BridgedTopic,
+        counters: Arc<Counters>,
+    ) -> Result<Route, DDSError> {
+        let key_codec = || topic.key.clone().map(|k| k as Arc<dyn KeyCodec>);
+        let source = create_topic(
+            Entity::entity(from),
+            &topic.name,
+            &topic.type_name,
+            key_codec(),
+            topic.topic_qos.clone(),
+            None,
+        )?;
+        let source = EntityWrapper::new("topic", source, Some(from.keep_alive()), None, Uses::Nothing);
+        let destination = create_topic(
+            Entity::entity(to),
+            topic.destination_name(),
+            &topic.type_name,
+            key_codec(),
+            topic.topic_qos.clone(),
+            None,
+        )?;
+        let destination = EntityWrapper::new("topic", destination, Some(to.keep_alive()), None, Uses::Nothing);
+
+        unsafe {
+            let w = dds_create_writer(
+                Entity::entity(to).entity(),
+                destination.entity().entity(),
+                topic.writer_qos.map_or(std::ptr::null(), |q| q.into()),
+                std::ptr::null(),
+            );
+            if w < 0 {
+                return Err(DDSError::from_retcode("dds_create_writer", w).with_entity_kind("writer"));
+            }
+            let writer = EntityWrapper::new(
+                "writer",
+                DdsEntity::new(w),
+                Some(to.keep_alive()),
+                None,
+                Uses::Topic(destination),
+            );
+
+            let listener = DdsListener::new()
+                .on_data_available(move |reader| match forward(&reader, w) {
+                    Ok(n) => {
+                        counters.forwarded.fetch_add(n, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        counters.failed.fetch_add(1, Ordering::Relaxed);
+                        crate::dds_log::warn_failed(format_args!("cannot forward sample"), &e);
+                    }
+                })
+                .hook();
+
+            let r = dds_create_reader(
+                Entity::entity(from).entity(),
+                source.entity().entity(),
+                topic.reader_qos.map_or(std::ptr::null(), |q| q.into()),
+                (&listener).into(),
+            );
+            // on failure the writer and the topics are deleted when dropped
+            if r < 0 {
+                return Err(DDSError::from_retcode("dds_create_reader", r).with_entity_kind("reader"));
+            }
+            Ok(Route {
+                _reader: EntityWrapper::new(
+                    "reader",
+                    DdsEntity::new(r),
+                    Some(from.keep_alive()),
+                    Some(listener),
+                    Uses::Topic(source),
+                ),
+                _writer: writer,
+            })
+        }
+    }
+
+    pub fn stats(&self) -> BridgeStats {
+        BridgeStats {
+            forwarded: self.counters.forwarded.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Take everything from the reader and write it with the writer. Returns the
+// number of samples forwarded.
+fn forward(reader: &DdsEntity, writer: dds_entity_t) -> Result<u64, DDSError> {
+    let mut sertype: *const ddsi_sertype = std::ptr::null();
+    let ret = unsafe { dds_get_entity_sertype(writer, &mut sertype) };
+    if ret < 0 {
+        return Err(DDSError::from_retcode("dds_get_entity_sertype", ret).with_entity_kind("writer"));
+    }
+
+    let mut forwarded = 0;
+    let mut result = Ok(());
+    drain(|| {
+        let mut serdata: [*mut ddsi_serdata; DRAIN_BATCH] = [std::ptr::null_mut(); DRAIN_BATCH];
+        let mut infos = [dds_sample_info_t::default(); DRAIN_BATCH];
+        let n = unsafe {
+            dds_takecdr(reader.entity(), serdata.as_mut_ptr(), max_samples(DRAIN_BATCH), infos.as_mut_ptr(), 0)
+        };
+        if n < 0 {
+            return Err(DDSError::from_retcode("dds_takecdr", n).with_entity_kind("reader"));
+        }
+
+        for (d, info) in serdata[..sample_count(n)].iter().zip(infos.iter()) {
+            unsafe {
+                let statusinfo = if info.valid_data {
+                    Some((**d).statusinfo)
+                } else if info.instance_state == dds_instance_state_DDS_IST_NOT_ALIVE_DISPOSED {
+                    Some(STATUSINFO_DISPOSE)
+                } else if info.instance_state == dds_instance_state_DDS_IST_NOT_ALIVE_NO_WRITERS {
+                    Some(STATUSINFO_UNREGISTER)
+                } else {
+                    None
+                };
+                if let (Some(statusinfo), Ok(())) = (statusinfo, &result) {
+                    let copy = RawSerData::from_serdata(*d).copy_for(sertype);
+                    (*copy).statusinfo = statusinfo;
+                    (*copy).timestamp.v = info.source_timestamp;
+                    // the reference of the copy is handed over
+                    let ret = dds_forwardcdr(writer, copy);
+                    if ret < 0 {
+                        result = Err(DDSError::from_retcode("dds_forwardcdr", ret).with_entity_kind("writer"));
+                    } else {
+                        forwarded += 1;
+                    }
+                }
+                ddsi_serdata_removeref(*d);
+            }
+        }
+        // samples taken after a failed forward are dropped
+        Ok(sample_count(n))
+    })?;
+    result.map(|()| forwarded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{wait_for_sample, LoopbackDomain};
+    use crate::{DdsTopic, DynamicData, DynamicKind, DynamicTopic, DynamicWriter, SampleBuffer, TopicType};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Telemetry {
+        #[topic_key]
+        id: u32,
+        speed: f64,
+        note: String,
+    }
+
+    fn telemetry_type() -> DynamicType {
+        DynamicType::new(Telemetry::typename().to_str().unwrap())
+            .with_key_field("id", DynamicKind::UInt32)
+            .with_field("speed", DynamicKind::Float64)
+            .with_field("note", DynamicKind::String)
+    }
+
+    #[test]
+    fn test_same_topic_same_domain() {
+        let domain = LoopbackDomain::create().unwrap();
+        let bridge = DomainBridgeBuilder::new()
+            .with_topic(BridgedTopic::new("loop", "T"))
+            .create(domain.participant(), domain.participant());
+        assert_eq!(bridge.err(), Some(DDSError::BadParameter));
+    }
+
+    #[test]
+    fn test_dropped_bridge_deletes_its_topics() {
+        use crate::{DdsFoundTopic, FindScope};
+
+        let a = LoopbackDomain::create().unwrap();
+        let b = LoopbackDomain::create().unwrap();
+        let find = |participant: &DdsParticipant, name: &str| {
+            DdsFoundTopic::find(participant, name, FindScope::Participant, Duration::from_millis(0))
+                .unwrap()
+                .is_some()
+        };
+
+        let bridge = DomainBridgeBuilder::new()
+            .with_topic(BridgedTopic::keyed("cleanup", telemetry_type()).renamed("fleet/cleanup"))
+            .create(a.participant(), b.participant())
+            .unwrap();
+        assert!(find(a.participant(), "cleanup"));
+        assert!(find(b.participant(), "fleet/cleanup"));
+
+        drop(bridge);
+        assert!(!find(a.participant(), "cleanup"));
+        assert!(!find(b.participant(), "fleet/cleanup"));
+    }
+
+    #[test]
+    fn test_bridge() {
+        let a = LoopbackDomain::create().unwrap();
+        let b = LoopbackDomain::create().unwrap();
+        let bridge = DomainBridgeBuilder::new()
+            .with_topic(BridgedTopic::keyed("telemetry", telemetry_type()).renamed("fleet/telemetry"))
+            .create(a.participant(), b.participant())
+            .unwrap();
+
+        let topic = DynamicTopic::create(a.participant(), "telemetry", telemetry_type(), None, None).unwrap();
+        let mut writer = DynamicWriter::create(a.participant(), &topic, None, None).unwrap();
+        let reader = b
+            .reader(DdsTopic::<Telemetry>::create(b.participant(), "fleet/telemetry", None, None).unwrap())
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let data = DynamicData::default()
+            .with_field("id", 3u32)
+            .with_field("speed", 1.5)
+            .with_field("note", "ok");
+        writer.write(&data).unwrap();
+        let received = wait_for_sample(&reader, Duration::from_secs(1), |_| true);
+        assert_eq!(
+            received,
+            Some(Telemetry {
+                id: 3,
+                speed: 1.5,
+                note: "ok".to_owned()
+            })
+        );
+
+        writer.dispose(&DynamicData::default().with_field("id", 3u32)).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        // only the forwarded dispose is left, which take_now refuses as no data
+        let mut buf = SampleBuffer::<Telemetry>::new(4);
+        let n = reader.take_all_into(&mut buf).unwrap();
+        assert!(n > 0);
+        assert!((0..n).all(|i| !buf.is_valid_sample(i)));
+        assert_eq!(bridge.stats(), BridgeStats { forwarded: 2, failed: 0 });
+    }
+}
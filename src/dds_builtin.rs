@@ -0,0 +1,529 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Typed readers for the builtin discovery topics (DCPSParticipant, DCPSTopic,
+//! DCPSPublication and DCPSSubscription).
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! let participant = DdsParticipant::create(None, None, None).unwrap();
+//! let reader = DdsBuiltinReader::<PublicationBuiltinTopicData>::create(&participant, None, None).unwrap();
+//! for sample in reader.take(16).unwrap() {
+//!     println!("Writer on {} of type {}", sample.data.topic_name, sample.data.type_name);
+//! }
+//! ```
+
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_void};
+use std::sync::{Arc, Mutex};
+
+use cyclonedds_sys::*;
+use crate::common::{drain, DRAIN_BATCH};
+use crate::error::DDSError;
+
+use crate::{
+    dds_listener::DdsListener,
+    dds_qos::{DdsQos, QosPolicies},
+    DdsReadable, Entity, Guid,
+};
+
+/// Data types of the builtin topics
+pub trait BuiltinTopicData: Sized {
+    /// The pseudo topic handle of the builtin topic
+    fn builtin_topic() -> dds_entity_t;
+    /// Decode a sample loaned from cyclone.
+    /// # Safety
+    /// The pointer must point to a sample of the builtin topic.
+    unsafe fn from_loaned(sample: *const c_void) -> Self;
+}
+
+/// Data about a discovered participant
+pub struct ParticipantBuiltinTopicData {
+    pub key: Guid,
+    pub qos: Option<DdsQos>,
+}
+
+/// Data about a discovered topic. Only available if cyclone is built
+/// with topic discovery enabled.
+pub struct TopicBuiltinTopicData {
+    pub key: Guid,
+    pub topic_name: String,
+    pub type_name: String,
+    pub qos: Option<DdsQos>,
+}
+
+/// Data about a discovered reader or writer.
+pub struct EndpointBuiltinTopicData {
+    pub key: Guid,
+    pub participant_key: Guid,
+    pub participant_instance_handle: dds_instance_handle_t,
+    pub topic_name: String,
+    pub type_name: String,
+    pub qos: Option<DdsQos>,
+}
+
+/// Data about a discovered writer
+pub struct PublicationBuiltinTopicData(pub EndpointBuiltinTopicData);
+
+/// Data about a discovered reader
+pub struct SubscriptionBuiltinTopicData(pub EndpointBuiltinTopicData);
+
+impl std::ops::Deref for PublicationBuiltinTopicData {
+    type Target = EndpointBuiltinTopicData;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for SubscriptionBuiltinTopicData {
+    type Target = EndpointBuiltinTopicData;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Copy a string owned by cyclone. Null strings (as in samples that only carry
+/// the key) become empty strings.
+pub(crate) unsafe fn string_from_ptr(p: *const c_char) -> String {
+    if p.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(p).to_string_lossy().into_owned()
+    }
+}
+
+impl BuiltinTopicData for ParticipantBuiltinTopicData {
+    fn builtin_topic() -> dds_entity_t {
+        unsafe { builtin_entity::BUILTIN_TOPIC_DCPSPARTICIPANT_ENTITY.entity() }
+    }
+
+    unsafe fn from_loaned(sample: *const c_void) -> Self {
+        let sample = &*(sample as *const dds_builtintopic_participant);
+        Self {
+            key: Guid(sample.key.v),
+            qos: DdsQos::from_raw_copy(sample.qos),
+        }
+    }
+}
+
+impl BuiltinTopicData for TopicBuiltinTopicData {
+    fn builtin_topic() -> dds_entity_t {
+        unsafe { builtin_entity::BUILTIN_TOPIC_DCPSTOPIC_ENTITY.entity() }
+    }
+
+    unsafe fn from_loaned(sample: *const c_void) -> Self {
+        let sample = &*(sample as *const dds_builtintopic_topic);
+        Self {
+            key: Guid(sample.key.d),
+            topic_name: string_from_ptr(sample.topic_name),
+            type_name: string_from_ptr(sample.type_name),
+            qos: DdsQos::from_raw_copy(sample.qos),
+        }
+    }
+}
+
+impl EndpointBuiltinTopicData {
+    /// The Qos policies of the remote endpoint, to diagnose incompatibilities
+    pub fn policies(&self) -> Option<QosPolicies> {
+        self.qos.as_ref().map(|q| q.policies())
+    }
+
+    pub(crate) unsafe fn from_loaned(sample: *const c_void) -> Self {
+        let sample = &*(sample as *const dds_builtintopic_endpoint);
+        Self {
+            key: Guid(sample.key.v),
+            participant_key: Guid(sample.participant_key.v),
+            participant_instance_handle: sample.participant_instance_handle,
+            topic_name: string_from_ptr(sample.topic_name),
+            type_name: string_from_ptr(sample.type_name),
+            qos: DdsQos::from_raw_copy(sample.qos),
+        }
+    }
+}
+
+impl BuiltinTopicData for PublicationBuiltinTopicData {
+    fn builtin_topic() -> dds_entity_t {
+        unsafe { builtin_entity::BUILTIN_TOPIC_DCPSPUBLICATION_ENTITY.entity() }
+    }
+
+    unsafe fn from_loaned(sample: *const c_void) -> Self {
+        Self(EndpointBuiltinTopicData::from_loaned(sample))
+    }
+}
+
+impl BuiltinTopicData for SubscriptionBuiltinTopicData {
+    fn builtin_topic() -> dds_entity_t {
+        unsafe { builtin_entity::BUILTIN_TOPIC_DCPSSUBSCRIPTION_ENTITY.entity() }
+    }
+
+    unsafe fn from_loaned(sample: *const c_void) -> Self {
+        Self(EndpointBuiltinTopicData::from_loaned(sample))
+    }
+}
+
+/// A sample read from a builtin topic.
+pub struct BuiltinSample<D> {
+    pub data: D,
+    /// false if only the key of the data is valid. This is the case when
+    /// the instance was disposed or lost all its writers.
+    pub valid_data: bool,
+    /// false if the discovered entity has been deleted or lost
+    pub alive: bool,
+}
+
+/// A reader on one of the builtin topics. Creating a reader for
+/// `TopicBuiltinTopicData` fails with `DDSError::Unsupported` if cyclone
+/// was built without topic discovery.
+pub struct DdsBuiltinReader<D: BuiltinTopicData> {
+    entity: DdsEntity,
+    _listener: Option<DdsListener>,
+    _phantom: PhantomData<D>,
+}
+
+impl<D> DdsBuiltinReader<D>
+where
+    D: BuiltinTopicData,
+{
+    pub fn create(
+        entity: &dyn DdsReadable,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<Self, DDSError> {
+        unsafe {
+            let r = dds_create_reader(
+                entity.entity().entity(),
+                D::builtin_topic(),
+                maybe_qos.map_or(std::ptr::null(), |q| q.into()),
+                maybe_listener
+                    .as_ref()
+                    .map_or(std::ptr::null(), |l| l.into()),
+            );
+            if r > 0 {
+                Ok(Self {
+                    entity: DdsEntity::new(r),
+                    _listener: maybe_listener,
+                    _phantom: PhantomData,
+                })
+            } else {
+                Err(DDSError::from_retcode("dds_create_reader", r).with_entity_kind("builtin reader"))
+            }
+        }
+    }
+
+    /// Take up to `max` samples
+    pub fn take(&self, max: usize) -> Result<Vec<BuiltinSample<D>>, DDSError> {
+        Self::read_or_take(&self.entity, max, true)
+    }
+
+    /// Read up to `max` samples
+    pub fn read(&self, max: usize) -> Result<Vec<BuiltinSample<D>>, DDSError> {
+        Self::read_or_take(&self.entity, max, false)
+    }
+
+    pub(crate) fn read_or_take(
+        entity: &DdsEntity,
+        max: usize,
+        take: bool,
+    ) -> Result<Vec<BuiltinSample<D>>, DDSError> {
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        // null pointers make cyclone loan the samples
+        let mut samples: Vec<*mut c_void> = vec![std::ptr::null_mut(); max];
+        let mut infos = vec![dds_sample_info_t::default(); max];
+
+        unsafe {
+            let n = if take {
+                dds_take(entity.entity(), samples.as_mut_ptr(), infos.as_mut_ptr(), max as size_t, max as u32)
+            } else {
+                dds_read(entity.entity(), samples.as_mut_ptr(), infos.as_mut_ptr(), max as size_t, max as u32)
+            };
+
+            if n < 0 {
+                return Err(DDSError::from_retcode(if take { "dds_take" } else { "dds_read" }, n).with_entity_kind("builtin reader"));
+            }
+
+            let decoded = samples[..n as usize]
+                .iter()
+                .zip(infos.iter())
+                .map(|(sample, info)| BuiltinSample {
+                    data: D::from_loaned(*sample),
+                    valid_data: info.valid_data,
+                    alive: info.instance_state == dds_instance_state_DDS_IST_ALIVE,
+                })
+                .collect();
+
+            if n > 0 {
+                dds_return_loan(entity.entity(), samples.as_mut_ptr(), n);
+            }
+            Ok(decoded)
+        }
+    }
+}
+
+impl<D> Entity for DdsBuiltinReader<D>
+where
+    D: BuiltinTopicData,
+{
+    fn entity(&self) -> &DdsEntity {
+        &self.entity
+    }
+}
+
+impl<D> Drop for DdsBuiltinReader<D>
+where
+    D: BuiltinTopicData,
+{
+    fn drop(&mut self) {
+        unsafe {
+            let _ret: DDSError = cyclonedds_sys::dds_delete(self.entity.entity()).into();
+        }
+    }
+}
+
+/// The kind of a discovered endpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndpointKind {
+    Writer,
+    Reader,
+}
+
+type ParticipantCallback = Box<dyn FnMut(&ParticipantBuiltinTopicData) + Send + 'static>;
+type ParticipantLostCallback = Box<dyn FnMut(&Guid) + Send + 'static>;
+type EndpointCallback = Box<dyn FnMut(EndpointKind, &EndpointBuiltinTopicData) + Send + 'static>;
+type EndpointLostCallback = Box<dyn FnMut(EndpointKind, &Guid) + Send + 'static>;
+
+/// Discovery callbacks registered on the ParticipantBuilder
+#[derive(Default)]
+pub(crate) struct DiscoveryCallbacks {
+    pub on_participant_discovered: Option<ParticipantCallback>,
+    pub on_participant_lost: Option<ParticipantLostCallback>,
+    pub on_endpoint_discovered: Option<EndpointCallback>,
+    pub on_endpoint_lost: Option<EndpointLostCallback>,
+}
+
+impl DiscoveryCallbacks {
+    pub fn is_empty(&self) -> bool {
+        self.on_participant_discovered.is_none()
+            && self.on_participant_lost.is_none()
+            && self.on_endpoint_discovered.is_none()
+            && self.on_endpoint_lost.is_none()
+    }
+}
+
+/// Builtin readers created by a participant to deliver discovery callbacks.
+pub(crate) struct DiscoveryReaders {
+    readers: Vec<DdsEntity>,
+    // the listeners own the callbacks and must outlive the readers
+    _listeners: Vec<DdsListener>,
+}
+
+impl DiscoveryReaders {
+    pub fn create(participant: &DdsEntity, callbacks: DiscoveryCallbacks) -> Result<Self, DDSError> {
+        let mut discovery = DiscoveryReaders {
+            readers: Vec::new(),
+            _listeners: Vec::new(),
+        };
+
+        let DiscoveryCallbacks {
+            on_participant_discovered,
+            on_participant_lost,
+            on_endpoint_discovered,
+            on_endpoint_lost,
+        } = callbacks;
+
+        if on_participant_discovered.is_some() || on_participant_lost.is_some() {
+            let mut discovered = on_participant_discovered;
+            let mut lost = on_participant_lost;
+            discovery.add_reader::<ParticipantBuiltinTopicData, _>(participant, move |sample| {
+                if sample.valid_data && sample.alive {
+                    if let Some(cb) = &mut discovered {
+                        cb(&sample.data);
+                    }
+                } else if !sample.alive {
+                    if let Some(cb) = &mut lost {
+                        cb(&sample.data.key);
+                    }
+                }
+            })?;
+        }
+
+        if on_endpoint_discovered.is_some() || on_endpoint_lost.is_some() {
+            // shared by the publication and subscription readers
+            let callbacks = Arc::new(Mutex::new((on_endpoint_discovered, on_endpoint_lost)));
+            let writer_callbacks = callbacks.clone();
+            discovery.add_reader::<PublicationBuiltinTopicData, _>(participant, move |sample| {
+                dispatch_endpoint(&writer_callbacks, EndpointKind::Writer, sample.valid_data, sample.alive, &sample.data)
+            })?;
+            discovery.add_reader::<SubscriptionBuiltinTopicData, _>(participant, move |sample| {
+                dispatch_endpoint(&callbacks, EndpointKind::Reader, sample.valid_data, sample.alive, &sample.data)
+            })?;
+        }
+
+        Ok(discovery)
+    }
+
+    fn add_reader<D, F>(&mut self, participant: &DdsEntity, mut on_sample: F) -> Result<(), DDSError>
+    where
+        D: BuiltinTopicData,
+        F: FnMut(BuiltinSample<D>) + Send + 'static,
+    {
+        let listener = DdsListener::new()
+            .on_data_available(move |entity| {
+                let _ = drain(|| {
+                    let samples = DdsBuiltinReader::<D>::read_or_take(&entity, DRAIN_BATCH, true)?;
+                    let n = samples.len();
+                    for sample in samples {
+                        on_sample(sample);
+                    }
+                    Ok(n)
+                });
+            })
+            .hook();
+
+        unsafe {
+            let r = dds_create_reader(
+                participant.entity(),
+                D::builtin_topic(),
+                std::ptr::null(),
+                (&listener).into(),
+            );
+            if r > 0 {
+                self.readers.push(DdsEntity::new(r));
+                self._listeners.push(listener);
+                Ok(())
+            } else {
+                Err(DDSError::from_retcode("dds_create_reader", r).with_entity_kind("builtin reader"))
+            }
+        }
+    }
+}
+
+fn dispatch_endpoint(
+    callbacks: &Mutex<(Option<EndpointCallback>, Option<EndpointLostCallback>)>,
+    kind: EndpointKind,
+    valid_data: bool,
+    alive: bool,
+    data: &EndpointBuiltinTopicData,
+) {
+    let mut callbacks = callbacks.lock().unwrap();
+    if valid_data && alive {
+        if let Some(cb) = &mut callbacks.0 {
+            cb(kind, data);
+        }
+    } else if !alive {
+        if let Some(cb) = &mut callbacks.1 {
+            cb(kind, &data.key);
+        }
+    }
+}
+
+impl Drop for DiscoveryReaders {
+    fn drop(&mut self) {
+        for reader in &self.readers {
+            unsafe {
+                let _ret: DDSError = cyclonedds_sys::dds_delete(reader.entity()).into();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsParticipant, DdsPublisher, DdsWriter, TopicType};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Topic, Debug, PartialEq, Default)]
+    struct BuiltinTestTopic {
+        #[topic_key]
+        id: u32,
+    }
+
+    #[test]
+    fn test_publication_discovered() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let reader =
+            DdsBuiltinReader::<PublicationBuiltinTopicData>::create(&participant, None, None).unwrap();
+
+        let topic = BuiltinTestTopic::create_topic(&participant, Some("builtin"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let _writer = DdsWriter::create(&publisher, topic, None, None).unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let samples = reader.take(32).unwrap();
+        let sample = samples
+            .iter()
+            .find(|s| {
+                s.valid_data && s.data.topic_name == BuiltinTestTopic::topic_name(Some("builtin"))
+            })
+            .expect("writer not discovered");
+        assert_eq!(
+            sample.data.type_name,
+            BuiltinTestTopic::typename().to_str().unwrap()
+        );
+        let policies = sample.data.policies().expect("no qos");
+        assert!(policies.reliability.is_some());
+    }
+
+    #[test]
+    fn test_participant_discovered() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let reader =
+            DdsBuiltinReader::<ParticipantBuiltinTopicData>::create(&participant, None, None).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        // at least our own participant
+        let guid = participant.guid().unwrap();
+        assert!(reader
+            .read(32)
+            .unwrap()
+            .iter()
+            .any(|s| s.data.key == guid));
+    }
+
+    #[test]
+    fn test_discovery_callbacks() {
+        let writers = Arc::new(Mutex::new(Vec::new()));
+        let writers_cb = writers.clone();
+        let participants = Arc::new(Mutex::new(0));
+        let participants_cb = participants.clone();
+
+        let participant = crate::ParticipantBuilder::new()
+            .on_participant_discovered(move |_| *participants_cb.lock().unwrap() += 1)
+            .on_endpoint_discovered(move |kind, data| {
+                if kind == EndpointKind::Writer {
+                    writers_cb.lock().unwrap().push(data.topic_name.clone());
+                }
+            })
+            .create()
+            .unwrap();
+
+        let topic = BuiltinTestTopic::create_topic(&participant, Some("discovery"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let _writer = DdsWriter::create(&publisher, topic, None, None).unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        assert!(*participants.lock().unwrap() >= 1);
+        assert!(writers
+            .lock()
+            .unwrap()
+            .contains(&BuiltinTestTopic::topic_name(Some("discovery"))));
+    }
+}
@@ -0,0 +1,339 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! A cache of the latest sample of every instance of a keyed topic. The cache
+//! takes the samples from its reader as they arrive, instances are removed when
+//! they are disposed or when they have no writers left.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example<T: TopicType + Clone + Send + Sync + 'static>(participant: &DdsParticipant, topic: DdsTopic<T>, key: &T) {
+//! let cache = KeyedCache::create(participant, topic, None).unwrap();
+//! cache.on_change(|event: &CacheEvent<T>| {
+//!     if let CacheEvent::Disposed(_last) = event {
+//!         println!("an instance is gone");
+//!     }
+//! });
+//! if let Some(latest) = cache.get(key) {
+//!     // use the latest value for the key
+//! }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use cyclonedds_sys::*;
+
+use crate::common::DRAIN_BATCH;
+use crate::error::DDSError;
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::{DdsListener, DdsQos, DdsReadable, DdsReader, DdsTopic, Entity};
+
+/// A change of the cache
+#[derive(Debug)]
+pub enum CacheEvent<T> {
+    /// First sample of an instance
+    Inserted(Arc<T>),
+    /// New sample of a known instance
+    Updated(Arc<T>),
+    /// The instance was disposed, with the last sample of the instance
+    Disposed(Arc<T>),
+    /// The instance has no writers left, with the last sample of the instance
+    Unregistered(Arc<T>),
+}
+
+struct Entry<T> {
+    key: Vec<u8>,
+    value: Arc<T>,
+}
+
+struct CacheState<T> {
+    instances: HashMap<dds_instance_handle_t, Entry<T>>,
+    // serialized key to instance handle
+    keys: HashMap<Vec<u8>, dds_instance_handle_t>,
+}
+
+impl<T: TopicType> CacheState<T> {
+    fn update(&mut self, handle: dds_instance_handle_t, value: Arc<T>) -> CacheEvent<T> {
+        match self.instances.get_mut(&handle) {
+            Some(entry) => {
+                entry.value = value.clone();
+                CacheEvent::Updated(value)
+            }
+            None => {
+                let key = value.key_cdr();
+                self.keys.insert(key.clone(), handle);
+                self.instances.insert(
+                    handle,
+                    Entry {
+                        key,
+                        value: value.clone(),
+                    },
+                );
+                CacheEvent::Inserted(value)
+            }
+        }
+    }
+
+    fn remove(&mut self, handle: dds_instance_handle_t) -> Option<Arc<T>> {
+        let entry = self.instances.remove(&handle)?;
+        self.keys.remove(&entry.key);
+        Some(entry.value)
+    }
+}
+
+type ChangeCallback<T> = Box<dyn FnMut(&CacheEvent<T>) + Send + 'static>;
+
+struct Shared<T> {
+    state: Mutex<CacheState<T>>,
+    callbacks: Mutex<Vec<ChangeCallback<T>>>,
+}
+
+/// Latest sample per instance of a topic
+pub struct KeyedCache<T: TopicType> {
+    reader: DdsReader<T>,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> KeyedCache<T>
+where
+    T: TopicType + Clone + Send + Sync + 'static,
+{
+    /// Create the reader of the cache. The reader should keep at least the last
+    /// sample of every instance, which is the default.
+    pub fn create(
+        entity: &dyn DdsReadable,
+        topic: DdsTopic<T>,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<Self, DDSError> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(CacheState {
+                instances: HashMap::new(),
+                keys: HashMap::new(),
+            }),
+            callbacks: Mutex::new(Vec::new()),
+        });
+
+        let listener_shared = shared.clone();
+        let mut buffer = SampleBuffer::<T>::new(DRAIN_BATCH);
+        let listener = DdsListener::new()
+            .on_data_available(move |reader| {
+                let events = take_all(&reader, &mut buffer, &listener_shared.state);
+                if !events.is_empty() {
+                    let mut callbacks = listener_shared.callbacks.lock().unwrap();
+                    for event in &events {
+                        for callback in callbacks.iter_mut() {
+                            callback(event);
+                        }
+                    }
+                }
+            })
+            .hook();
+
+        let reader = DdsReader::create(entity, topic, maybe_qos, Some(listener))?;
+        Ok(Self { reader, shared })
+    }
+
+    /// Call `callback` for every change of the cache. The callback runs on a
+    /// cyclonedds thread after the cache has been updated.
+    pub fn on_change<F>(&self, callback: F)
+    where
+        F: FnMut(&CacheEvent<T>) + Send + 'static,
+    {
+        self.shared.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+
+    /// The latest sample of the instance with the same key as `key`. Only the
+    /// key fields of `key` are used.
+    pub fn get(&self, key: &T) -> Option<Arc<T>> {
+        let state = self.shared.state.lock().unwrap();
+        let handle = state.keys.get(&key.key_cdr())?;
+        state.instances.get(handle).map(|e| e.value.clone())
+    }
+
+    /// The latest sample of the instance with the given handle
+    pub fn get_instance(&self, handle: dds_instance_handle_t) -> Option<Arc<T>> {
+        let state = self.shared.state.lock().unwrap();
+        state.instances.get(&handle).map(|e| e.value.clone())
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        self.shared.state.lock().unwrap().keys.contains_key(&key.key_cdr())
+    }
+
+    /// The number of live instances
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The latest sample of every live instance, in no particular order
+    pub fn values(&self) -> Vec<Arc<T>> {
+        self.filter(|_| true)
+    }
+
+    /// The instance handles with the latest sample of each instance
+    pub fn instances(&self) -> Vec<(dds_instance_handle_t, Arc<T>)> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .instances
+            .iter()
+            .map(|(handle, e)| (*handle, e.value.clone()))
+            .collect()
+    }
+
+    /// The latest samples for which `predicate` is true
+    pub fn filter<F: Fn(&T) -> bool>(&self, predicate: F) -> Vec<Arc<T>> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .instances
+            .values()
+            .filter(|e| predicate(&e.value))
+            .map(|e| e.value.clone())
+            .collect()
+    }
+
+    /// Any latest sample for which `predicate` is true
+    pub fn find<F: Fn(&T) -> bool>(&self, predicate: F) -> Option<Arc<T>> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .instances
+            .values()
+            .find(|e| predicate(&e.value))
+            .map(|e| e.value.clone())
+    }
+
+    pub fn reader(&self) -> &DdsReader<T> {
+        &self.reader
+    }
+}
+
+impl<T> Entity for KeyedCache<T>
+where
+    T: TopicType,
+{
+    fn entity(&self) -> &DdsEntity {
+        self.reader.entity()
+    }
+}
+
+// Take everything from the reader into the cache and return the changes
+fn take_all<T>(
+    reader: &DdsEntity,
+    buffer: &mut SampleBuffer<T>,
+    state: &Mutex<CacheState<T>>,
+) -> Vec<CacheEvent<T>>
+where
+    T: TopicType + Clone,
+{
+    let mut events = Vec::new();
+    let n = match DdsReader::take_all_from_entity(reader, buffer, 0) {
+        Ok(n) => n,
+        Err(_) => return events,
+    };
+    let mut state = state.lock().unwrap();
+
+    // the instance state is the state at the time of the take, apply the
+    // samples first and the removals after
+    let mut removed = Vec::new();
+    for i in 0..n {
+        let info = &buffer.sample_info[i];
+        if info.valid_data {
+            if let Some(sample) = buffer.get(i).try_deref() {
+                events.push(state.update(info.instance_handle, Arc::new(sample.clone())));
+            }
+        }
+        if info.instance_state != dds_instance_state_DDS_IST_ALIVE
+            && !removed.iter().any(|(h, _)| *h == info.instance_handle)
+        {
+            removed.push((info.instance_handle, info.instance_state));
+        }
+    }
+    for (handle, instance_state) in removed {
+        if let Some(last) = state.remove(handle) {
+            events.push(if instance_state == dds_instance_state_DDS_IST_NOT_ALIVE_DISPOSED {
+                CacheEvent::Disposed(last)
+            } else {
+                CacheEvent::Unregistered(last)
+            });
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsParticipant, DdsWriter};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Position {
+        #[topic_key]
+        id: u32,
+        x: f64,
+    }
+
+    #[test]
+    fn test_cache() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = Position::create_topic(&participant, Some("cache"), None, None).unwrap();
+        let cache = KeyedCache::create(&participant, topic.clone(), None).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        cache.on_change(move |event: &CacheEvent<Position>| {
+            let event = match event {
+                CacheEvent::Inserted(p) => ("inserted", p.id),
+                CacheEvent::Updated(p) => ("updated", p.id),
+                CacheEvent::Disposed(p) => ("disposed", p.id),
+                CacheEvent::Unregistered(p) => ("unregistered", p.id),
+            };
+            sink.lock().unwrap().push(event);
+        });
+
+        let mut writer = DdsWriter::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(Position { id: 1, x: 1.0 })).unwrap();
+        writer.write(Arc::new(Position { id: 2, x: 2.0 })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(Position { id: 1, x: 3.0 })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(cache.len(), 2);
+        let key = Position { id: 1, x: 0.0 };
+        assert_eq!(cache.get(&key).unwrap().x, 3.0);
+        assert_eq!(cache.find(|p| p.x > 1.5 && p.x < 2.5).unwrap().id, 2);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![("inserted", 1), ("inserted", 2), ("updated", 1)]
+        );
+
+        // the writer disposes its instances when it is deleted
+        drop(writer);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(cache.is_empty());
+        assert!(!cache.contains(&key));
+        let mut removed: Vec<_> = events.lock().unwrap()[3..].to_vec();
+        removed.sort_unstable();
+        assert_eq!(removed, vec![("disposed", 1), ("disposed", 2)]);
+    }
+}
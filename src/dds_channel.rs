@@ -0,0 +1,307 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Receive the samples of a reader through a channel. The listener of the
+//! reader takes the samples as they arrive and hands them to a [`SampleSink`].
+//!
+//! [`channel_reader`] uses the channel of this crate, which works with any
+//! executor: its futures are woken from the cyclone thread that calls the
+//! listener. With the `tokio`, `async-std` or `smol` features the senders of the
+//! channels of those runtimes are sinks as well, to be used with [`sink_reader`].
+//!
+//! Like a KEEP_LAST history, a full channel does not block the listener. The
+//! channel of this crate drops the oldest sample, the bounded channels of the
+//! runtimes drop the newest.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # async fn example<T: TopicType + Clone + Send + Sync + 'static>(participant: &DdsParticipant, topic: DdsTopic<T>) {
+//! let (_reader, mut samples) = channel_reader(participant, topic, None, 64).unwrap();
+//! while let Some(sample) = samples.recv().await {
+//!     // use the sample
+//! }
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::common::DRAIN_BATCH;
+use crate::error::DDSError;
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::{DdsListener, DdsQos, DdsReadable, DdsReader, DdsTopic};
+
+/// Where the listener of a channel reader puts the samples
+pub trait SampleSink<T>: Send + 'static {
+    /// Hand over a sample, false once nobody receives anymore
+    fn send_sample(&mut self, sample: Arc<T>) -> bool;
+}
+
+struct ChannelState<T> {
+    queue: VecDeque<Arc<T>>,
+    capacity: usize,
+    dropped: u64,
+    waker: Option<Waker>,
+    sender_gone: bool,
+    receiver_gone: bool,
+}
+
+/// The sending half of a sample channel, used by the listener
+pub struct SampleSender<T> {
+    state: Arc<Mutex<ChannelState<T>>>,
+}
+
+/// The receiving half of a sample channel
+pub struct SampleReceiver<T> {
+    state: Arc<Mutex<ChannelState<T>>>,
+}
+
+/// A channel that keeps at most `capacity` samples, the oldest sample is
+/// dropped to make room for a new one
+pub fn sample_channel<T>(capacity: usize) -> (SampleSender<T>, SampleReceiver<T>) {
+    let state = Arc::new(Mutex::new(ChannelState {
+        queue: VecDeque::with_capacity(capacity),
+        capacity: capacity.max(1),
+        dropped: 0,
+        waker: None,
+        sender_gone: false,
+        receiver_gone: false,
+    }));
+    (
+        SampleSender {
+            state: state.clone(),
+        },
+        SampleReceiver { state },
+    )
+}
+
+impl<T: Send + Sync + 'static> SampleSink<T> for SampleSender<T> {
+    fn send_sample(&mut self, sample: Arc<T>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.receiver_gone {
+            return false;
+        }
+        if state.queue.len() == state.capacity {
+            state.queue.pop_front();
+            state.dropped += 1;
+        }
+        state.queue.push_back(sample);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+impl<T> Drop for SampleSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.sender_gone = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> SampleReceiver<T> {
+    /// Wait for the next sample, `None` once the sender is gone and every
+    /// sample has been received. Only one task should wait at a time.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    /// The next sample, without waiting
+    pub fn try_recv(&mut self) -> Option<Arc<T>> {
+        self.state.lock().unwrap().queue.pop_front()
+    }
+
+    /// Samples dropped because the channel was full
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+impl<T> Drop for SampleReceiver<T> {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().receiver_gone = true;
+    }
+}
+
+/// The future returned by [`SampleReceiver::recv`]
+pub struct Recv<'a, T> {
+    receiver: &'a mut SampleReceiver<T>,
+}
+
+impl<'a, T> Future for Recv<'a, T> {
+    type Output = Option<Arc<T>>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.receiver.state.lock().unwrap();
+        if let Some(sample) = state.queue.pop_front() {
+            Poll::Ready(Some(sample))
+        } else if state.sender_gone {
+            Poll::Ready(None)
+        } else {
+            state.waker = Some(ctx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: Send + Sync + 'static> SampleSink<T> for tokio::sync::mpsc::Sender<Arc<T>> {
+    fn send_sample(&mut self, sample: Arc<T>) -> bool {
+        !matches!(
+            self.try_send(sample),
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_))
+        )
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: Send + Sync + 'static> SampleSink<T> for tokio::sync::mpsc::UnboundedSender<Arc<T>> {
+    fn send_sample(&mut self, sample: Arc<T>) -> bool {
+        self.send(sample).is_ok()
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl<T: Send + Sync + 'static> SampleSink<T> for async_std::channel::Sender<Arc<T>> {
+    fn send_sample(&mut self, sample: Arc<T>) -> bool {
+        !matches!(
+            self.try_send(sample),
+            Err(async_std::channel::TrySendError::Closed(_))
+        )
+    }
+}
+
+#[cfg(feature = "smol")]
+impl<T: Send + Sync + 'static> SampleSink<T> for smol::channel::Sender<Arc<T>> {
+    fn send_sample(&mut self, sample: Arc<T>) -> bool {
+        !matches!(
+            self.try_send(sample),
+            Err(smol::channel::TrySendError::Closed(_))
+        )
+    }
+}
+
+/// Create a reader that sends its samples into a channel of this crate
+pub fn channel_reader<T>(
+    entity: &dyn DdsReadable,
+    topic: DdsTopic<T>,
+    maybe_qos: Option<DdsQos>,
+    capacity: usize,
+) -> Result<(DdsReader<T>, SampleReceiver<T>), DDSError>
+where
+    T: TopicType + Clone + Send + Sync + 'static,
+{
+    let (sender, receiver) = sample_channel(capacity);
+    let reader = sink_reader(entity, topic, maybe_qos, sender)?;
+    Ok((reader, receiver))
+}
+
+/// Create a reader that sends its samples into `sink`. Samples are still taken
+/// once the receiving side is gone, but are discarded.
+pub fn sink_reader<T, S>(
+    entity: &dyn DdsReadable,
+    topic: DdsTopic<T>,
+    maybe_qos: Option<DdsQos>,
+    mut sink: S,
+) -> Result<DdsReader<T>, DDSError>
+where
+    T: TopicType + Clone + Send + Sync + 'static,
+    S: SampleSink<T>,
+{
+    let mut buffer = SampleBuffer::<T>::new(DRAIN_BATCH);
+    let mut open = true;
+    let listener = DdsListener::new()
+        .on_data_available(move |reader| {
+            let n = DdsReader::take_all_from_entity(&reader, &mut buffer, 0).unwrap_or(0);
+            for i in 0..n {
+                if !open || !buffer.sample_info[i].valid_data {
+                    continue;
+                }
+                if let Some(sample) = buffer.get(i).try_deref() {
+                    open = sink.send_sample(Arc::new(sample.clone()));
+                }
+            }
+        })
+        .hook();
+    DdsReader::create(entity, topic, maybe_qos, Some(listener))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{block_on, LoopbackDomain};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Counter {
+        #[topic_key]
+        id: u32,
+        count: u32,
+    }
+
+    #[test]
+    fn test_channel_overflow() {
+        let (mut sender, mut receiver) = sample_channel::<u32>(2);
+        for i in 0..3 {
+            assert!(sender.send_sample(Arc::new(i)));
+        }
+        assert_eq!(receiver.dropped(), 1);
+        assert_eq!(receiver.try_recv(), Some(Arc::new(1)));
+        drop(sender);
+        assert_eq!(block_on(receiver.recv()), Some(Arc::new(2)));
+        assert_eq!(block_on(receiver.recv()), None);
+    }
+
+    #[test]
+    fn test_channel_reader() {
+        let domain = LoopbackDomain::create().unwrap();
+        let topic = domain.topic::<Counter>("channel").unwrap();
+        let mut writer = domain.writer(topic.clone()).unwrap();
+        let (_reader, mut receiver) = channel_reader(domain.participant(), topic, None, 8).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        // woken by the listener thread, no runtime involved
+        let sample = Counter { id: 1, count: 42 };
+        writer.write(Arc::new(sample.clone())).unwrap();
+        assert_eq!(block_on(receiver.recv()).as_deref(), Some(&sample));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_sink() {
+        let domain = LoopbackDomain::create().unwrap();
+        let topic = domain.topic::<Counter>("tokio_channel").unwrap();
+        let mut writer = domain.writer(topic.clone()).unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(8);
+        let _reader = sink_reader(domain.participant(), topic, None, sender).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        writer.write(Arc::new(Counter { id: 2, count: 1 })).unwrap();
+        let received = tokio::runtime::Runtime::new().unwrap().block_on(async {
+            tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await
+        });
+        assert_eq!(received.unwrap().map(|s| s.count), Some(1));
+    }
+}
@@ -0,0 +1,155 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Coherent sets: updates that readers see all together or not at all.
+//!
+//! On the publishing side [`DdsPublisher::begin_coherent`] (or
+//! [`DdsWriter::begin_coherent`] for a single writer) starts a set, every write
+//! until the returned [`CoherentSet`] is ended or dropped belongs to it. On the
+//! subscribing side [`DdsSubscriber::begin_access`] groups reads over the readers
+//! of the subscriber, so the samples of a set are taken together.
+//!
+//! Both sides need a presentation QoS with coherent access, with the `GROUP`
+//! access scope for sets spanning several topics.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! use std::sync::Arc;
+//! # fn example<A: TopicType, B: TopicType>(publisher: &DdsPublisher, a: &mut DdsWriter<A>, b: &mut DdsWriter<B>, sa: Arc<A>, sb: Arc<B>) {
+//! let set = publisher.begin_coherent().unwrap();
+//! a.write(sa).unwrap();
+//! b.write(sb).unwrap();
+//! // readers receive both samples or neither
+//! set.end().unwrap();
+//! # }
+//! ```
+
+use cyclonedds_sys::*;
+
+use crate::error::DDSError;
+use crate::Entity;
+
+/// A coherent set or a coherent access in progress. Dropping it ends the set,
+/// [`CoherentSet::end`] does the same and returns a failure.
+pub struct CoherentSet {
+    entity: DdsEntity,
+    entity_kind: &'static str,
+    ended: bool,
+}
+
+impl CoherentSet {
+    pub(crate) fn begin(entity: &dyn Entity, entity_kind: &'static str) -> Result<Self, DDSError> {
+        entity.check_valid()?;
+        let ret = unsafe { dds_begin_coherent(entity.entity().entity()) };
+        if ret == 0 {
+            Ok(Self {
+                entity: entity.entity().clone(),
+                entity_kind,
+                ended: false,
+            })
+        } else {
+            Err(DDSError::from_retcode("dds_begin_coherent", ret).with_entity_kind(entity_kind))
+        }
+    }
+
+    /// End the set, the writes of the set are published together
+    pub fn end(mut self) -> Result<(), DDSError> {
+        self.ended = true;
+        self.end_coherent()
+    }
+
+    fn end_coherent(&self) -> Result<(), DDSError> {
+        let ret = unsafe { dds_end_coherent(self.entity.entity()) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(DDSError::from_retcode("dds_end_coherent", ret).with_entity_kind(self.entity_kind))
+        }
+    }
+}
+
+impl Drop for CoherentSet {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        // the entity may have been deleted while the set was open
+        match self.end_coherent() {
+            Err(err) if err != DDSError::AlreadyDeleted => crate::dds_log::warn_failed(
+                format_args!("cannot end coherent set of {} on drop", self.entity_kind),
+                &err,
+            ),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_support::LoopbackDomain;
+    use crate::{DdsPublisher, DdsQos, DdsReader, DdsSubscriber, DdsWriter, SampleBuffer};
+    use cdds_derive::Topic;
+    use cyclonedds_sys::dds_presentation_access_scope_kind;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Pose {
+        #[topic_key]
+        id: u32,
+        x: f64,
+    }
+
+    fn group_qos() -> DdsQos {
+        let mut qos = DdsQos::create().unwrap();
+        qos.set_presentation(dds_presentation_access_scope_kind::DDS_PRESENTATION_GROUP, true, false);
+        qos
+    }
+
+    #[test]
+    fn test_coherent_set() {
+        let domain = LoopbackDomain::create().unwrap();
+        let topic = Pose::create_topic(domain.participant(), Some("coherent"), None, None).unwrap();
+        let publisher = DdsPublisher::create(domain.participant(), Some(group_qos()), None).unwrap();
+        let subscriber = DdsSubscriber::create(domain.participant(), Some(group_qos()), None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut buffer = SampleBuffer::<Pose>::new(4);
+        let set = publisher.begin_coherent().unwrap();
+        writer.write(Arc::new(Pose { id: 1, x: 1.0 })).unwrap();
+        writer.write(Arc::new(Pose { id: 2, x: 2.0 })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        // nothing is visible before the set is complete
+        assert!(reader.take_now(&mut buffer).is_err());
+        set.end().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let access = subscriber.begin_access().unwrap();
+        assert_eq!(reader.take_now(&mut buffer).unwrap(), 2);
+        drop(access);
+
+        // a set is also ended when dropped
+        {
+            let _set = writer.begin_coherent().unwrap();
+            writer.write(Arc::new(Pose { id: 3, x: 3.0 })).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(reader.take_now(&mut buffer).unwrap(), 1);
+    }
+}
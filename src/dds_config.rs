@@ -0,0 +1,455 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Typed builder for the CycloneDDS XML configuration. Use this instead of
+//! hand writing the XML passed in CYCLONEDDS_URI or to `DdsDomain::create`.
+//! # Example
+//! ```
+//! use cyclonedds_rs::CycloneConfig;
+//! let config = CycloneConfig::new()
+//!     .with_domain_id(3)
+//!     .with_interface("lo")
+//!     .with_peer("127.0.0.1")
+//!     .with_multicast(false)
+//!     .with_shared_memory(false);
+//! let xml = config.to_xml();
+//! assert!(xml.contains("<Domain id=\"3\">"));
+//! ```
+
+use crate::dds_domain::DdsDomain;
+use std::path::{Path, PathBuf};
+pub use cyclonedds_sys::DdsDomainId;
+pub use crate::error::DDSError;
+
+/// Verbosity levels for cyclone tracing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceVerbosity {
+    None,
+    Severe,
+    Warning,
+    Info,
+    Config,
+    Fine,
+    Finer,
+    Finest,
+}
+
+impl TraceVerbosity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TraceVerbosity::None => "none",
+            TraceVerbosity::Severe => "severe",
+            TraceVerbosity::Warning => "warning",
+            TraceVerbosity::Info => "info",
+            TraceVerbosity::Config => "config",
+            TraceVerbosity::Fine => "fine",
+            TraceVerbosity::Finer => "finer",
+            TraceVerbosity::Finest => "finest",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CycloneConfig {
+    domain_id: Option<DdsDomainId>,
+    interfaces: Vec<String>,
+    peers: Vec<String>,
+    allow_multicast: Option<bool>,
+    participant_index: Option<String>,
+    max_auto_participant_index: Option<u32>,
+    spdp_interval: Option<std::time::Duration>,
+    shared_memory: Option<bool>,
+    shared_memory_log_level: Option<String>,
+    trace_verbosity: Option<TraceVerbosity>,
+    trace_categories: Vec<String>,
+    trace_output_file: Option<String>,
+}
+
+impl CycloneConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The domain this configuration applies to. If not set, the configuration
+    /// applies to any domain.
+    pub fn with_domain_id(mut self, domain_id: DdsDomainId) -> Self {
+        self.domain_id = Some(domain_id);
+        self
+    }
+
+    /// Add a network interface (name or address) to use
+    pub fn with_interface(mut self, interface: &str) -> Self {
+        self.interfaces.push(interface.to_owned());
+        self
+    }
+
+    /// Add a unicast peer for discovery
+    pub fn with_peer(mut self, address: &str) -> Self {
+        self.peers.push(address.to_owned());
+        self
+    }
+
+    pub fn with_multicast(mut self, allow: bool) -> Self {
+        self.allow_multicast = Some(allow);
+        self
+    }
+
+    /// The participant index. Use "auto", "none" or a number.
+    pub fn with_participant_index(mut self, index: &str) -> Self {
+        self.participant_index = Some(index.to_owned());
+        self
+    }
+
+    pub fn with_max_auto_participant_index(mut self, max: u32) -> Self {
+        self.max_auto_participant_index = Some(max);
+        self
+    }
+
+    /// The interval between participant discovery (SPDP) messages
+    pub fn with_spdp_interval(mut self, interval: std::time::Duration) -> Self {
+        self.spdp_interval = Some(interval);
+        self
+    }
+
+    /// Enable or disable shared memory (iceoryx) transport
+    pub fn with_shared_memory(mut self, enable: bool) -> Self {
+        self.shared_memory = Some(enable);
+        self
+    }
+
+    pub fn with_shared_memory_log_level(mut self, level: &str) -> Self {
+        self.shared_memory_log_level = Some(level.to_owned());
+        self
+    }
+
+    pub fn with_trace_verbosity(mut self, verbosity: TraceVerbosity) -> Self {
+        self.trace_verbosity = Some(verbosity);
+        self
+    }
+
+    /// Add a trace category, for example "discovery" or "trace"
+    pub fn with_trace_category(mut self, category: &str) -> Self {
+        self.trace_categories.push(category.to_owned());
+        self
+    }
+
+    /// Add a set of trace categories
+    pub fn with_trace_categories(mut self, categories: crate::dds_log::TraceCategories) -> Self {
+        if categories != crate::dds_log::TraceCategories::default() {
+            self.trace_categories.push(categories.to_string());
+        }
+        self
+    }
+
+    /// File to write the trace to. "stdout" and "stderr" are also accepted.
+    pub fn with_trace_output_file(mut self, file: &str) -> Self {
+        self.trace_output_file = Some(file.to_owned());
+        self
+    }
+
+    /// Render the configuration as CycloneDDS XML
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n<CycloneDDS xmlns=\"https://cdds.io/config\">\n",
+        );
+        let domain_id = self
+            .domain_id
+            .map_or_else(|| String::from("any"), |id| id.to_string());
+        xml.push_str(&format!("  <Domain id=\"{}\">\n", domain_id));
+
+        let mut general = String::new();
+        if !self.interfaces.is_empty() {
+            general.push_str("      <Interfaces>\n");
+            for interface in &self.interfaces {
+                general.push_str(&format!(
+                    "        <NetworkInterface {}=\"{}\"/>\n",
+                    if interface.parse::<std::net::IpAddr>().is_ok() {
+                        "address"
+                    } else {
+                        "name"
+                    },
+                    escape(interface)
+                ));
+            }
+            general.push_str("      </Interfaces>\n");
+        }
+        if let Some(allow) = self.allow_multicast {
+            general.push_str(&format!("      <AllowMulticast>{}</AllowMulticast>\n", allow));
+        }
+        push_section(&mut xml, "General", &general);
+
+        let mut discovery = String::new();
+        if let Some(index) = &self.participant_index {
+            discovery.push_str(&format!(
+                "      <ParticipantIndex>{}</ParticipantIndex>\n",
+                escape(index)
+            ));
+        }
+        if let Some(max) = self.max_auto_participant_index {
+            discovery.push_str(&format!(
+                "      <MaxAutoParticipantIndex>{}</MaxAutoParticipantIndex>\n",
+                max
+            ));
+        }
+        if let Some(interval) = self.spdp_interval {
+            discovery.push_str(&format!(
+                "      <SPDPInterval>{}ms</SPDPInterval>\n",
+                interval.as_millis()
+            ));
+        }
+        if !self.peers.is_empty() {
+            discovery.push_str("      <Peers>\n");
+            for peer in &self.peers {
+                discovery.push_str(&format!("        <Peer address=\"{}\"/>\n", escape(peer)));
+            }
+            discovery.push_str("      </Peers>\n");
+        }
+        push_section(&mut xml, "Discovery", &discovery);
+
+        let mut shm = String::new();
+        if let Some(enable) = self.shared_memory {
+            shm.push_str(&format!("      <Enable>{}</Enable>\n", enable));
+        }
+        if let Some(level) = &self.shared_memory_log_level {
+            shm.push_str(&format!("      <LogLevel>{}</LogLevel>\n", escape(level)));
+        }
+        push_section(&mut xml, "SharedMemory", &shm);
+
+        let mut tracing = String::new();
+        if let Some(verbosity) = self.trace_verbosity {
+            tracing.push_str(&format!(
+                "      <Verbosity>{}</Verbosity>\n",
+                verbosity.as_str()
+            ));
+        }
+        if !self.trace_categories.is_empty() {
+            tracing.push_str(&format!(
+                "      <Category>{}</Category>\n",
+                escape(&self.trace_categories.join(","))
+            ));
+        }
+        if let Some(file) = &self.trace_output_file {
+            tracing.push_str(&format!("      <OutputFile>{}</OutputFile>\n", escape(file)));
+        }
+        push_section(&mut xml, "Tracing", &tracing);
+
+        xml.push_str("  </Domain>\n</CycloneDDS>\n");
+        xml
+    }
+
+    /// Create a domain with this configuration. The domain id must be set.
+    pub fn create_domain(&self) -> Result<DdsDomain, DDSError> {
+        if let Some(domain_id) = self.domain_id {
+            DdsDomain::create(domain_id, Some(&self.to_xml()))
+        } else {
+            Err(DDSError::BadParameter)
+        }
+    }
+
+    /// Create a domain with this configuration and report the configuration
+    /// that cyclone actually applied. Cyclone logs the effective
+    /// configuration in the "config" trace category when the domain is created.
+    /// The trace is captured in `trace_file`, which is removed once read,
+    /// unless an output file is already configured, in which case that file
+    /// is read and kept.
+    pub fn create_domain_with_report<P: AsRef<Path>>(
+        &self,
+        trace_file: P,
+    ) -> Result<(DdsDomain, EffectiveConfig), DDSError> {
+        self.domain_id.ok_or(DDSError::BadParameter)?;
+        let mut config = self.clone();
+        if !config.trace_categories.iter().any(|c| c == "config") {
+            config.trace_categories.push(String::from("config"));
+        }
+        let (trace_file, temporary) = match &config.trace_output_file {
+            Some(file) if file != "stdout" && file != "stderr" => (PathBuf::from(file), false),
+            _ => {
+                let file = trace_file.as_ref().to_path_buf();
+                config.trace_output_file = Some(file.to_string_lossy().into_owned());
+                (file, true)
+            }
+        };
+
+        let domain = config.create_domain();
+        let trace = std::fs::read_to_string(&trace_file).unwrap_or_default();
+        // also if the domain could not be created
+        if temporary {
+            let _ = std::fs::remove_file(&trace_file);
+        }
+        Ok((domain?, EffectiveConfig::parse(&trace)))
+    }
+
+    /// Set CYCLONEDDS_URI so that the configuration is used for domains that are
+    /// implicitly created when the first participant is created.
+    pub fn set_env(&self) {
+        std::env::set_var("CYCLONEDDS_URI", self.to_xml());
+    }
+}
+
+/// The configuration applied by cyclone, by configuration path
+/// (for example "Domain/General/AllowMulticast").
+#[derive(Clone, Debug, Default)]
+pub struct EffectiveConfig {
+    entries: std::collections::BTreeMap<String, String>,
+}
+
+impl EffectiveConfig {
+    /// Parse the "config" lines of a cyclone trace. The lines look like
+    /// `... config: Domain/General/AllowMulticast: default {}`.
+    pub fn parse(trace: &str) -> Self {
+        let mut entries = std::collections::BTreeMap::new();
+        for line in trace.lines() {
+            if let Some(pos) = line.find("config: ") {
+                let entry = &line[pos + "config: ".len()..];
+                // strip the sources of the setting
+                let entry = match entry.rfind(" {") {
+                    Some(end) => &entry[..end],
+                    None => entry,
+                };
+                if let Some(sep) = entry.find(": ") {
+                    entries.insert(
+                        entry[..sep].trim().to_owned(),
+                        entry[sep + 2..].trim().to_owned(),
+                    );
+                }
+            }
+        }
+        EffectiveConfig { entries }
+    }
+
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.entries.get(path).map(|v| v.as_str())
+    }
+
+    /// All settings by path
+    pub fn entries(&self) -> &std::collections::BTreeMap<String, String> {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The network interfaces that were configured
+    pub fn interfaces(&self) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(k, v)| k.contains("NetworkInterface") && !v.is_empty())
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Whether shared memory is enabled, if cyclone was built with it
+    pub fn shared_memory_enabled(&self) -> Option<bool> {
+        self.get("Domain/SharedMemory/Enable").map(|v| v == "true")
+    }
+}
+
+fn push_section(xml: &mut String, name: &str, content: &str) {
+    if !content.is_empty() {
+        xml.push_str(&format!("    <{}>\n{}    </{}>\n", name, content, name));
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_empty_config() {
+        let xml = CycloneConfig::new().to_xml();
+        assert!(xml.contains("<Domain id=\"any\">"));
+        assert!(!xml.contains("<General>"));
+    }
+
+    #[test]
+    fn test_full_config() {
+        let xml = CycloneConfig::new()
+            .with_domain_id(5)
+            .with_interface("eth0")
+            .with_interface("192.168.1.10")
+            .with_multicast(false)
+            .with_participant_index("auto")
+            .with_max_auto_participant_index(50)
+            .with_peer("10.0.0.1")
+            .with_shared_memory(true)
+            .with_shared_memory_log_level("info")
+            .with_trace_verbosity(TraceVerbosity::Config)
+            .with_trace_category("discovery")
+            .with_trace_category("trace")
+            .with_trace_output_file("stdout")
+            .to_xml();
+
+        assert!(xml.contains("<Domain id=\"5\">"));
+        assert!(xml.contains("<NetworkInterface name=\"eth0\"/>"));
+        assert!(xml.contains("<NetworkInterface address=\"192.168.1.10\"/>"));
+        assert!(xml.contains("<AllowMulticast>false</AllowMulticast>"));
+        assert!(xml.contains("<MaxAutoParticipantIndex>50</MaxAutoParticipantIndex>"));
+        assert!(xml.contains("<Peer address=\"10.0.0.1\"/>"));
+        assert!(xml.contains("<SharedMemory>\n      <Enable>true</Enable>"));
+        assert!(xml.contains("<Verbosity>config</Verbosity>"));
+        assert!(xml.contains("<Category>discovery,trace</Category>"));
+    }
+
+    #[test]
+    fn test_escaping() {
+        let xml = CycloneConfig::new().with_peer("a\"<b>").to_xml();
+        assert!(xml.contains("<Peer address=\"a&quot;&lt;b&gt;\"/>"));
+    }
+
+    #[test]
+    fn test_parse_effective_config() {
+        let trace = "1633524224.186355 [0]    main: config: Domain/General/AllowMulticast: false {0}\n\
+                     1633524224.186360 [0]    main: config: Domain/Discovery/Ports/Base: 7400 {}\n\
+                     1633524224.186361 [0]    main: some other line\n";
+        let config = EffectiveConfig::parse(trace);
+        assert_eq!(config.get("Domain/General/AllowMulticast"), Some("false"));
+        assert_eq!(config.get("Domain/Discovery/Ports/Base"), Some("7400"));
+        assert_eq!(config.entries().len(), 2);
+    }
+
+    #[test]
+    fn test_create_domain_with_report() {
+        let trace_file = std::env::temp_dir().join(format!("cyclonedds-config-{}.log", std::process::id()));
+        let (_domain, effective) = CycloneConfig::new()
+            .with_domain_id(14)
+            .with_multicast(false)
+            .create_domain_with_report(&trace_file)
+            .unwrap();
+        assert!(!effective.is_empty());
+        assert!(!trace_file.exists());
+        assert_eq!(effective.get("Domain/General/AllowMulticast"), Some("false"));
+    }
+
+    #[test]
+    fn test_create_domain() {
+        let config = CycloneConfig::new().with_domain_id(11).with_shared_memory(false);
+        assert!(config.create_domain().is_ok());
+        assert_eq!(
+            CycloneConfig::new().create_domain().err(),
+            Some(DDSError::BadParameter)
+        );
+    }
+}
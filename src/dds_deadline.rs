@@ -0,0 +1,349 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Notice instances that stop being updated. A [`DeadlineWatchdog`] sets the
+//! deadline QoS on a reader and turns the requested deadline missed status into
+//! one event per instance, with the last sample of the instance so its key is
+//! known. An update of an overdue instance is reported as well.
+//!
+//! A watchdog can also be attached to a writer created with a deadline QoS, the
+//! offered deadline missed events then carry only the instance handle, see
+//! [`DdsWriter::lookup_instance`].
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! use std::time::Duration;
+//! # async fn example<T: TopicType + Clone + std::fmt::Debug + 'static>(participant: &DdsParticipant, topic: DdsTopic<T>) {
+//! let watchdog = DeadlineWatchdog::create(participant, topic, Duration::from_millis(500), None).unwrap();
+//! loop {
+//!     if let DeadlineEvent::Missed { last: Some(last), .. } = watchdog.next_event().await {
+//!         println!("no update for {:?} in 500ms", last);
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use cyclonedds_sys::*;
+
+use crate::common::DRAIN_BATCH;
+use crate::error::DDSError;
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::{DdsListener, DdsQos, DdsReadable, DdsReader, DdsTopic, DdsWriter, Entity};
+
+/// Events kept for a consumer that does not keep up; older events are dropped
+const MAX_QUEUED_EVENTS: usize = 256;
+
+#[derive(Debug)]
+pub enum DeadlineEvent<T> {
+    /// The instance was not updated within the deadline period. `last` is the
+    /// last sample of the instance, `None` on a writer or if no sample was seen.
+    Missed {
+        instance: dds_instance_handle_t,
+        last: Option<Arc<T>>,
+    },
+    /// An instance that missed its deadline was updated again
+    Resumed {
+        instance: dds_instance_handle_t,
+        sample: Arc<T>,
+    },
+}
+
+struct WatchdogState<T> {
+    latest: HashMap<dds_instance_handle_t, Arc<T>>,
+    updated_at: HashMap<dds_instance_handle_t, Instant>,
+    overdue: HashSet<dds_instance_handle_t>,
+    events: VecDeque<DeadlineEvent<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> WatchdogState<T> {
+    fn new() -> Self {
+        Self {
+            latest: HashMap::new(),
+            updated_at: HashMap::new(),
+            overdue: HashSet::new(),
+            events: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    fn push(&mut self, event: DeadlineEvent<T>) {
+        if self.events.len() == MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn missed(&mut self, instance: dds_instance_handle_t) {
+        self.overdue.insert(instance);
+        let last = self.latest.get(&instance).cloned();
+        self.push(DeadlineEvent::Missed { instance, last });
+    }
+
+    // The status only names the last instance that missed its deadline. The
+    // others counted in `count` are the longest waiting instances that were
+    // not updated within the period.
+    fn missed_reader(&mut self, last: dds_instance_handle_t, count: usize, period: Duration) {
+        let now = Instant::now();
+        let mut stale: Vec<(Instant, dds_instance_handle_t)> = self
+            .updated_at
+            .iter()
+            .filter(|(instance, at)| {
+                **instance != last && !self.overdue.contains(*instance) && now.duration_since(**at) >= period
+            })
+            .map(|(instance, at)| (*at, *instance))
+            .collect();
+        stale.sort_unstable();
+        for (_, instance) in stale.into_iter().take(count.saturating_sub(1)) {
+            self.missed(instance);
+        }
+        self.missed(last);
+    }
+
+    fn updated(&mut self, instance: dds_instance_handle_t, sample: Arc<T>) {
+        self.latest.insert(instance, sample.clone());
+        self.updated_at.insert(instance, Instant::now());
+        if self.overdue.remove(&instance) {
+            self.push(DeadlineEvent::Resumed { instance, sample });
+        }
+    }
+
+    // disposed instances and instances without writers have no deadline
+    fn removed(&mut self, instance: dds_instance_handle_t) {
+        self.latest.remove(&instance);
+        self.updated_at.remove(&instance);
+        self.overdue.remove(&instance);
+    }
+}
+
+/// Per-instance deadline notifications of a reader or writer
+pub struct DeadlineWatchdog<T: TopicType> {
+    // None when attached to a writer
+    reader: Option<DdsReader<T>>,
+    state: Arc<Mutex<WatchdogState<T>>>,
+}
+
+impl<T> DeadlineWatchdog<T>
+where
+    T: TopicType + Clone + Send + Sync + 'static,
+{
+    /// Create a reader that expects every instance to be updated within
+    /// `period`. The deadline replaces the one in `maybe_qos`, writers must offer
+    /// the same or a shorter period.
+    pub fn create(
+        entity: &dyn DdsReadable,
+        topic: DdsTopic<T>,
+        period: Duration,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<Self, DDSError> {
+        let mut qos = match maybe_qos {
+            Some(qos) => qos,
+            None => DdsQos::create()?,
+        };
+        qos.set_deadline(period);
+
+        let state = Arc::new(Mutex::new(WatchdogState::new()));
+        let data_state = state.clone();
+        let missed_state = state.clone();
+        let mut buffer = SampleBuffer::<T>::new(DRAIN_BATCH);
+        let listener = DdsListener::new()
+            .on_data_available(move |reader| take_all(&reader, &mut buffer, &data_state))
+            .on_requested_deadline_missed(move |_reader, status| {
+                if status.total_count_change > 0 {
+                    missed_state.lock().unwrap().missed_reader(
+                        status.last_instance_handle,
+                        status.total_count_change as usize,
+                        period,
+                    );
+                }
+            })
+            .hook();
+
+        let reader = DdsReader::create(entity, topic, Some(qos), Some(listener))?;
+        Ok(Self {
+            reader: Some(reader),
+            state,
+        })
+    }
+
+    /// Watch the offered deadline of a writer created with a deadline QoS. This
+    /// replaces the listener of the writer.
+    pub fn attach_writer(writer: &mut DdsWriter<T>) -> Result<Self, DDSError> {
+        let state = Arc::new(Mutex::new(WatchdogState::new()));
+        let missed_state = state.clone();
+        let listener = DdsListener::new()
+            .on_offered_deadline_missed(move |_writer, status| {
+                if status.total_count_change > 0 {
+                    missed_state.lock().unwrap().missed(status.last_instance_handle);
+                }
+            })
+            .hook();
+        writer.set_listener(listener)?;
+        Ok(Self {
+            reader: None,
+            state,
+        })
+    }
+
+    /// Wait for the next event. Events that were not awaited are queued, the
+    /// oldest are dropped if too many pile up.
+    pub fn next_event(&self) -> NextDeadlineEvent<'_, T> {
+        NextDeadlineEvent { state: &self.state }
+    }
+
+    /// The next queued event, without waiting
+    pub fn try_next_event(&self) -> Option<DeadlineEvent<T>> {
+        self.state.lock().unwrap().events.pop_front()
+    }
+
+    /// The handles of the instances that missed their deadline and were not
+    /// updated since
+    pub fn overdue(&self) -> Vec<dds_instance_handle_t> {
+        self.state.lock().unwrap().overdue.iter().copied().collect()
+    }
+
+    /// The last sample of an instance
+    pub fn latest(&self, instance: dds_instance_handle_t) -> Option<Arc<T>> {
+        self.state.lock().unwrap().latest.get(&instance).cloned()
+    }
+
+    /// The reader of the watchdog, `None` if it watches a writer
+    pub fn reader(&self) -> Option<&DdsReader<T>> {
+        self.reader.as_ref()
+    }
+}
+
+fn take_all<T>(reader: &DdsEntity, buffer: &mut SampleBuffer<T>, state: &Mutex<WatchdogState<T>>)
+where
+    T: TopicType + Clone,
+{
+    let n = match DdsReader::take_all_from_entity(reader, buffer, 0) {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let mut state = state.lock().unwrap();
+    for i in 0..n {
+        let info = &buffer.sample_info[i];
+        if info.valid_data {
+            if let Some(sample) = buffer.get(i).try_deref() {
+                state.updated(info.instance_handle, Arc::new(sample.clone()));
+            }
+        }
+        if info.instance_state != dds_instance_state_DDS_IST_ALIVE {
+            state.removed(info.instance_handle);
+        }
+    }
+}
+
+/// The future returned by [`DeadlineWatchdog::next_event`]
+pub struct NextDeadlineEvent<'a, T> {
+    state: &'a Mutex<WatchdogState<T>>,
+}
+
+impl<'a, T> Future for NextDeadlineEvent<'a, T> {
+    type Output = DeadlineEvent<T>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => {
+                state.waker = Some(ctx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{block_on, LoopbackDomain};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Speed {
+        #[topic_key]
+        wheel: u32,
+        rpm: u32,
+    }
+
+    #[test]
+    fn test_missed_and_resumed() {
+        let domain = LoopbackDomain::create().unwrap();
+        let topic = domain.topic::<Speed>("deadline").unwrap();
+        let mut writer = domain.writer(topic.clone()).unwrap();
+        let watchdog =
+            DeadlineWatchdog::create(domain.participant(), topic, Duration::from_millis(100), None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let first = Speed { wheel: 3, rpm: 100 };
+        writer.write(Arc::new(first.clone())).unwrap();
+        let missed_instance = match block_on(watchdog.next_event()) {
+            DeadlineEvent::Missed { instance, last } => {
+                assert_eq!(last.as_deref(), Some(&first));
+                instance
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(watchdog.overdue(), vec![missed_instance]);
+
+        let second = Speed { wheel: 3, rpm: 200 };
+        writer.write(Arc::new(second.clone())).unwrap();
+        loop {
+            if let DeadlineEvent::Resumed { instance, sample } = block_on(watchdog.next_event()) {
+                assert_eq!(instance, missed_instance);
+                assert_eq!(*sample, second);
+                break;
+            }
+        }
+        assert_eq!(watchdog.latest(missed_instance).as_deref(), Some(&second));
+    }
+
+    #[test]
+    fn test_missed_instances_are_all_reported() {
+        let mut state = WatchdogState::<Speed>::new();
+        state.updated(1, Arc::new(Speed { wheel: 1, rpm: 10 }));
+        state.updated(2, Arc::new(Speed { wheel: 2, rpm: 10 }));
+        state.updated(3, Arc::new(Speed { wheel: 3, rpm: 10 }));
+        std::thread::sleep(Duration::from_millis(20));
+        state.updated(3, Arc::new(Speed { wheel: 3, rpm: 20 }));
+
+        // two instances missed the deadline, the status only names the last
+        state.missed_reader(2, 2, Duration::from_millis(10));
+        let mut missed: Vec<_> = state
+            .events
+            .iter()
+            .map(|event| match event {
+                DeadlineEvent::Missed { instance, .. } => *instance,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        missed.sort_unstable();
+        assert_eq!(missed, vec![1, 2]);
+    }
+}
@@ -15,11 +15,34 @@
 */
 
 
-use cyclonedds_sys::{dds_error::DDSError, DdsDomainId, DdsEntity};
+use cyclonedds_sys::{DdsDomainId, DdsEntity};
+use crate::error::DDSError;
+use std::collections::HashMap;
 use std::convert::From;
 use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-pub struct DdsDomain(DdsEntity);
+use crate::dds_log::{set_trace_categories, TraceCategories};
+use crate::{CycloneConfig, DdsListener, DdsParticipant, DdsQos};
+
+/// The default domain. Participants created with this id join the domain configured
+/// as default in the cyclone configuration.
+pub const DOMAIN_DEFAULT: DdsDomainId = 0xFFFF_FFFF;
+
+struct Inner {
+    entity: DdsEntity,
+    id: DdsDomainId,
+    // set when the domain was deleted explicitly
+    deleted: AtomicBool,
+}
+
+/// An explicitly created domain. Cloning the domain is cheap and all clones refer
+/// to the same domain. Participants created with [`DdsDomain::create_participant`]
+/// hold a clone, so the domain is deleted only after the last participant in it
+/// and the last clone are dropped.
+#[derive(Clone)]
+pub struct DdsDomain(Arc<Inner>);
 
 impl DdsDomain {
     ///Create a domain with a specified domain id
@@ -30,52 +53,254 @@ impl DdsDomain {
                 let d = cyclonedds_sys::dds_create_domain(domain, domain_name.as_ptr());
                 // negative return value signify an error
                 if d > 0 {
-                    Ok(DdsDomain(DdsEntity::new(d)))
+                    Ok(DdsDomain::new(DdsEntity::new(d), domain))
                 } else {
-                    Err(DDSError::from(d))
+                    Err(DDSError::from_retcode("dds_create_domain", d).with_entity_kind("domain"))
                 }
             } else {
                 let d = cyclonedds_sys::dds_create_domain(domain, std::ptr::null());
 
                 if d > 0 {
-                    Ok(DdsDomain(DdsEntity::new(d)))
+                    Ok(DdsDomain::new(DdsEntity::new(d), domain))
                 } else {
-                    Err(DDSError::from(d))
+                    Err(DDSError::from_retcode("dds_create_domain", d).with_entity_kind("domain"))
                 }
             }
         }
     }
+
+    fn new(entity: DdsEntity, id: DdsDomainId) -> Self {
+        DdsDomain(Arc::new(Inner {
+            entity,
+            id,
+            deleted: AtomicBool::new(false),
+        }))
+    }
+
+    /// Delete the domain and every entity in it now, instead of when the last
+    /// clone is dropped. Closing a domain again returns `DDSError::AlreadyDeleted`.
+    pub fn close(&self) -> Result<(), DDSError> {
+        if self.0.deleted.swap(true, Ordering::SeqCst) {
+            return Err(DDSError::AlreadyDeleted);
+        }
+        unsafe {
+            let ret = cyclonedds_sys::dds_delete(self.0.entity.entity());
+            if ret == 0 {
+                Ok(())
+            } else {
+                Err(DDSError::from_retcode("dds_delete", ret).with_entity_kind("domain"))
+            }
+        }
+    }
+
+    /// The domain id of this domain
+    pub fn id(&self) -> DdsDomainId {
+        self.0.id
+    }
+
+    /// Change the trace categories of this domain that are forwarded to `log`
+    /// or `tracing`. See [`dds_log`](crate::dds_log).
+    pub fn set_trace_categories(&self, categories: TraceCategories) {
+        set_trace_categories(self.0.id, categories)
+    }
+
+    /// Create a participant in this domain. The participant keeps the domain alive.
+    pub fn create_participant(
+        &self,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<DdsParticipant, DDSError> {
+        DdsParticipant::create_in_domain(self, maybe_qos, maybe_listener)
+    }
 }
 
 impl PartialEq for DdsDomain {
     fn eq(&self, other: &Self) -> bool {
-        unsafe { self.0.entity() == other.0.entity() }
+        unsafe { self.0.entity.entity() == other.0.entity.entity() }
     }
 }
 
 impl Eq for DdsDomain {}
 
-impl Drop for DdsDomain {
+impl Drop for Inner {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     fn drop(&mut self) {
+        if self.deleted.load(Ordering::SeqCst) {
+            return;
+        }
         unsafe {
-            let ret: DDSError = cyclonedds_sys::dds_delete(self.0.entity()).into();
-            if DDSError::DdsOk != ret {
-                panic!("cannot delete domain: {}", ret);
+            let ret = cyclonedds_sys::dds_delete(self.entity.entity());
+            if ret != 0 {
+                crate::dds_log::drop_failed("domain", &DDSError::from_retcode("dds_delete", ret));
+            }
+        }
+    }
+}
+
+/// Owns the domains of an application that uses more than one domain.
+/// Participants are created in the right domain by id and all domains are
+/// deleted together on `shutdown`.
+/// # Example
+/// ```no_run
+/// use cyclonedds_rs::{CycloneConfig, DomainManager, DOMAIN_DEFAULT};
+/// let mut manager = DomainManager::new();
+/// manager.add_domain(&CycloneConfig::new().with_domain_id(1)).unwrap();
+/// manager.add_domain(&CycloneConfig::new().with_domain_id(2)).unwrap();
+/// manager.set_default(1);
+/// let participant = manager.create_participant(DOMAIN_DEFAULT, None, None).unwrap();
+/// assert_eq!(participant.domain_id().unwrap(), 1);
+/// manager.shutdown().unwrap();
+/// ```
+#[derive(Default)]
+pub struct DomainManager {
+    domains: HashMap<DdsDomainId, DdsDomain>,
+    default: Option<DdsDomainId>,
+}
+
+impl DomainManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a domain from the configuration. The domain id of the
+    /// configuration must be set.
+    pub fn add_domain(&mut self, config: &CycloneConfig) -> Result<DdsDomain, DDSError> {
+        let domain = config.create_domain()?;
+        self.insert(domain.clone())?;
+        Ok(domain)
+    }
+
+    /// Create a domain from XML configuration, or the default configuration if
+    /// `config` is None.
+    pub fn add_domain_xml(
+        &mut self,
+        id: DdsDomainId,
+        config: Option<&str>,
+    ) -> Result<DdsDomain, DDSError> {
+        if self.domains.contains_key(&id) {
+            return Err(DDSError::PreconditionNotMet);
+        }
+        let domain = DdsDomain::create(id, config)?;
+        self.insert(domain.clone())?;
+        Ok(domain)
+    }
+
+    fn insert(&mut self, domain: DdsDomain) -> Result<(), DDSError> {
+        if self.domains.contains_key(&domain.id()) {
+            return Err(DDSError::PreconditionNotMet);
+        }
+        self.domains.insert(domain.id(), domain);
+        Ok(())
+    }
+
+    /// The domain that `DOMAIN_DEFAULT` refers to
+    pub fn set_default(&mut self, id: DdsDomainId) {
+        self.default = Some(id);
+    }
+
+    pub fn domain(&self, id: DdsDomainId) -> Option<&DdsDomain> {
+        self.domains.get(&self.resolve(id))
+    }
+
+    pub fn domain_ids(&self) -> Vec<DdsDomainId> {
+        self.domains.keys().copied().collect()
+    }
+
+    fn resolve(&self, id: DdsDomainId) -> DdsDomainId {
+        if id == DOMAIN_DEFAULT {
+            self.default.unwrap_or(DOMAIN_DEFAULT)
+        } else {
+            id
+        }
+    }
+
+    /// Create a participant in the domain with the given id. Domains that were not
+    /// added to the manager are created implicitly by cyclone using the
+    /// default configuration.
+    pub fn create_participant(
+        &self,
+        id: DdsDomainId,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<DdsParticipant, DDSError> {
+        let id = self.resolve(id);
+        if let Some(domain) = self.domains.get(&id) {
+            domain.create_participant(maybe_qos, maybe_listener)
+        } else {
+            DdsParticipant::create(Some(id), maybe_qos, maybe_listener)
+        }
+    }
+
+    /// Delete all domains and every entity in them. All domains are deleted even if
+    /// some deletions fail; the first failure is returned.
+    pub fn shutdown(&mut self) -> Result<(), DDSError> {
+        let mut result = Ok(());
+        for (_, domain) in self.domains.drain() {
+            let ret = domain.close();
+            if result.is_ok() {
+                result = ret;
             }
         }
+        self.default = None;
+        result
     }
 }
 
 #[cfg(test)]
 mod dds_domain_tests {
-    use cyclonedds_sys::{DDSError};
-    use crate::dds_domain::DdsDomain;
+    use crate::DDSError;
+    use crate::dds_domain::{DdsDomain, DomainManager, DOMAIN_DEFAULT};
+    use crate::CycloneConfig;
 
     #[test]
     fn test_create_domain_with_bad_config() {
         assert!(Err(DDSError::DdsOk) != DdsDomain::create(0, Some("blah")));
     }
-    
-    
-    
+
+    #[test]
+    fn test_participant_keeps_domain_alive() {
+        let domain = DdsDomain::create(21, None).unwrap();
+        let participant = domain.create_participant(None, None).unwrap();
+        assert!(participant.domain() == Some(&domain));
+        drop(domain);
+        // the participant still refers to a live domain
+        assert!(participant.domain().is_some());
+    }
+
+    #[test]
+    fn test_close_domain() {
+        let domain = DdsDomain::create(22, None).unwrap();
+        let participant = domain.create_participant(None, None).unwrap();
+        assert!(domain.close().is_ok());
+        assert_eq!(domain.close(), Err(DDSError::AlreadyDeleted));
+        assert!(participant.domain_id().is_err());
+        // dropping the last clone does not delete the domain again
+        drop(participant);
+        drop(domain);
+    }
+
+    #[test]
+    fn test_domain_manager() {
+        let mut manager = DomainManager::new();
+        manager
+            .add_domain(&CycloneConfig::new().with_domain_id(31))
+            .unwrap();
+        manager.add_domain_xml(32, None).unwrap();
+        assert_eq!(
+            manager.add_domain_xml(32, None).err(),
+            Some(DDSError::PreconditionNotMet)
+        );
+        manager.set_default(32);
+
+        let p31 = manager.create_participant(31, None, None).unwrap();
+        let default = manager.create_participant(DOMAIN_DEFAULT, None, None).unwrap();
+        assert_eq!(p31.domain_id().unwrap(), 31);
+        assert_eq!(default.domain_id().unwrap(), 32);
+        assert!(default.domain() == manager.domain(DOMAIN_DEFAULT));
+
+        assert!(manager.shutdown().is_ok());
+        assert!(manager.domain_ids().is_empty());
+        // the participants were deleted with their domains
+        assert!(p31.domain_id().is_err());
+    }
 }
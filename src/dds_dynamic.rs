@@ -0,0 +1,1613 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Topics whose type is only known at runtime. A [`DynamicType`] is built from a
+//! list of fields or parsed from an IDL struct, and samples are [`DynamicData`]
+//! values. This is meant for generic tools like monitors, recorders and bridges.
+//! The samples are serialized as plain CDR and interoperate with readers and
+//! writers of the same type in Rust (using the `Topic` derive) or C.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! let idl = "module sensors { struct Reading { @key long id; double value; }; };";
+//! let reading = DynamicType::from_idl(idl).unwrap();
+//! let participant = DdsParticipant::create(None, None, None).unwrap();
+//! let topic = DynamicTopic::create(&participant, "readings", reading, None, None).unwrap();
+//! let mut writer = DynamicWriter::create(&participant, &topic, None, None).unwrap();
+//! writer
+//!     .write(&DynamicData::default().with_field("id", 1).with_field("value", 0.5))
+//!     .unwrap();
+//! ```
+
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::sync::Arc;
+
+use cyclonedds_sys::*;
+use thiserror::Error;
+
+use crate::dds_telemetry::{count, Counter};
+use crate::error::{DDSError, SerdesError};
+use crate::dds_topic::topic_name_of;
+use crate::serdes::{SerdesErrors, TopicType};
+use crate::serdes_raw::{create_topic, KeyCodec, RawSerData, RawSerType, CDR_BE_HEADER};
+use crate::serdes_reflect::reflect;
+use crate::{DdsListener, DdsParticipant, DdsQos, DdsReadable, DdsWritable, Entity};
+
+/// A dynamic type or value that does not fit, or IDL that cannot be parsed
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{0}")]
+pub struct DynamicTypeError(String);
+
+impl From<DynamicTypeError> for DDSError {
+    fn from(e: DynamicTypeError) -> Self {
+        DDSError::BadParameter.with_source(e)
+    }
+}
+
+/// The kind of a field of a [`DynamicType`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum DynamicKind {
+    Bool,
+    Char,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    String,
+    Sequence(Box<DynamicKind>),
+    Array(Box<DynamicKind>, usize),
+    Struct(DynamicType),
+}
+
+impl DynamicKind {
+    // size and alignment of primitives
+    fn primitive_size(&self) -> Option<usize> {
+        match self {
+            DynamicKind::Bool | DynamicKind::Char | DynamicKind::Int8 | DynamicKind::UInt8 => Some(1),
+            DynamicKind::Int16 | DynamicKind::UInt16 => Some(2),
+            DynamicKind::Int32 | DynamicKind::UInt32 | DynamicKind::Float32 => Some(4),
+            DynamicKind::Int64 | DynamicKind::UInt64 | DynamicKind::Float64 => Some(8),
+            _ => None,
+        }
+    }
+
+    /// The value of a field that is not set
+    pub fn default_value(&self) -> DynamicData {
+        match self {
+            DynamicKind::Bool => DynamicData::Bool(false),
+            DynamicKind::Char => DynamicData::Char('\0'),
+            DynamicKind::Int8 | DynamicKind::Int16 | DynamicKind::Int32 | DynamicKind::Int64 => {
+                DynamicData::Int(0)
+            }
+            DynamicKind::UInt8 | DynamicKind::UInt16 | DynamicKind::UInt32 | DynamicKind::UInt64 => {
+                DynamicData::UInt(0)
+            }
+            DynamicKind::Float32 | DynamicKind::Float64 => DynamicData::Float(0.0),
+            DynamicKind::String => DynamicData::String(String::new()),
+            DynamicKind::Sequence(_) => DynamicData::Sequence(Vec::new()),
+            DynamicKind::Array(kind, len) => DynamicData::Sequence(vec![kind.default_value(); *len]),
+            DynamicKind::Struct(ty) => ty.default_value(),
+        }
+    }
+
+    // The offset after serializing a value of this kind at `offset`, if the size
+    // does not depend on the value
+    fn fixed_end(&self, offset: usize, key_only: bool) -> Option<usize> {
+        match self {
+            DynamicKind::String | DynamicKind::Sequence(_) => None,
+            DynamicKind::Array(kind, len) => {
+                (0..*len).try_fold(offset, |offset, _| kind.fixed_end(offset, key_only))
+            }
+            DynamicKind::Struct(ty) => ty
+                .selected_fields(key_only, true)
+                .iter()
+                .try_fold(offset, |offset, f| f.kind.fixed_end(offset, key_only)),
+            kind => {
+                let size = kind.primitive_size().unwrap();
+                Some(align(offset, size) + size)
+            }
+        }
+    }
+}
+
+/// A field of a [`DynamicType`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicField {
+    pub name: String,
+    pub kind: DynamicKind,
+    /// Whether the field is part of the key
+    pub key: bool,
+}
+
+/// A struct type described at runtime
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicType {
+    name: String,
+    fields: Vec<DynamicField>,
+}
+
+impl DynamicType {
+    /// A struct without fields. The name is the scoped type name, for example
+    /// `sensors::Reading`, and must match the type name used by other
+    /// applications.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_field(mut self, name: &str, kind: DynamicKind) -> Self {
+        self.fields.push(DynamicField {
+            name: name.to_owned(),
+            kind,
+            key: false,
+        });
+        self
+    }
+
+    pub fn with_key_field(mut self, name: &str, kind: DynamicKind) -> Self {
+        self.fields.push(DynamicField {
+            name: name.to_owned(),
+            kind,
+            key: true,
+        });
+        self
+    }
+
+    /// Parse the types in an IDL snippet. The last struct in the snippet is
+    /// returned, it may use structs and typedefs defined before it. Keys are
+    /// given with `@key` or `#pragma keylist`. Enums, unions, constants and
+    /// inheritance are not supported.
+    pub fn from_idl(idl: &str) -> Result<Self, DynamicTypeError> {
+        IdlParser::parse(idl)
+    }
+
+    /// The structure of a Rust topic type as it is serialized, found from its
+    /// `Deserialize` implementation. The key fields are the ones listed by
+    /// [`TopicType::key_fields`]. Nested structs are named by their Rust name
+    /// without the module. Unit enums are `UInt32` fields, as they are
+    /// serialized as their index. Fails for types that have no CDR form,
+    /// such as `Option` or recursive types.
+    pub fn of<T: TopicType>() -> Result<Self, DynamicTypeError> {
+        let type_name = T::typename();
+        let error = |problem: String| DynamicTypeError(format!("{}: {}", type_name.to_string_lossy(), problem));
+        let mut ty = match reflect::<T>().map_err(|e| error(e.0))? {
+            DynamicKind::Struct(ty) => ty,
+            other => return Err(error(format!("{:?} is not a struct", other))),
+        };
+        ty.name = type_name.to_string_lossy().into_owned();
+        let keys = T::key_fields();
+        if T::has_key() && keys.is_empty() {
+            return Err(error("the key fields are not known".to_owned()));
+        }
+        for key in keys {
+            if !ty.mark_key(&key) {
+                return Err(error(format!("no key field {}", key)));
+            }
+        }
+        Ok(ty)
+    }
+
+    // Mark the field at a path like `inner.id` and the structs on the way as key
+    fn mark_key(&mut self, path: &str) -> bool {
+        let (name, rest) = match path.split_once('.') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (path, None),
+        };
+        let field = match self.fields.iter_mut().find(|f| f.name == name) {
+            Some(field) => field,
+            None => return false,
+        };
+        field.key = true;
+        match (rest, &mut field.kind) {
+            (None, _) => true,
+            (Some(rest), DynamicKind::Struct(ty)) => ty.mark_key(rest),
+            (Some(_), _) => false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn set_name(&mut self, name: &str) {
+        self.name = name.to_owned();
+    }
+
+    pub fn fields(&self) -> &[DynamicField] {
+        &self.fields
+    }
+
+    pub fn field(&self, name: &str) -> Option<&DynamicField> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn has_key(&self) -> bool {
+        self.fields.iter().any(|f| f.key)
+    }
+
+    /// A value with every field set to its default
+    pub fn default_value(&self) -> DynamicData {
+        DynamicData::Struct(
+            self.fields
+                .iter()
+                .map(|f| (f.name.clone(), f.kind.default_value()))
+                .collect(),
+        )
+    }
+
+    // The fields that are serialized. A struct nested in a key contributes all
+    // its fields unless some of them are marked as key.
+    fn selected_fields(&self, key_only: bool, nested: bool) -> Vec<&DynamicField> {
+        if key_only && !(nested && !self.has_key()) {
+            self.fields.iter().filter(|f| f.key).collect()
+        } else {
+            self.fields.iter().collect()
+        }
+    }
+
+    /// Serialize a value as CDR with encapsulation header. Fields missing from
+    /// the value are serialized with their default value.
+    pub fn serialize(&self, value: &DynamicData) -> Result<Vec<u8>, DynamicTypeError> {
+        let mut writer = CdrWriter::new(&CDR_BE_HEADER);
+        self.serialize_fields(&mut writer, value, false, false)?;
+        Ok(writer.buf)
+    }
+
+    /// Deserialize CDR with encapsulation header
+    pub fn deserialize(&self, cdr: &[u8]) -> Result<DynamicData, DynamicTypeError> {
+        let mut reader = CdrReader::with_header(cdr)?;
+        self.deserialize_fields(&mut reader, false, false)
+    }
+
+    /// The key of a value as big endian CDR without encapsulation header, the
+    /// input of the keyhash
+    pub fn key_cdr(&self, value: &DynamicData) -> Result<Vec<u8>, DynamicTypeError> {
+        let mut writer = CdrWriter::new(&[]);
+        self.serialize_fields(&mut writer, value, true, false)?;
+        Ok(writer.buf)
+    }
+
+    // A value with only the key fields set from the CDR of the key
+    fn deserialize_key(&self, key: &[u8]) -> Result<DynamicData, DynamicTypeError> {
+        let mut reader = CdrReader::new(key, false);
+        self.deserialize_fields(&mut reader, true, false)
+    }
+
+    fn serialize_fields(
+        &self,
+        writer: &mut CdrWriter,
+        value: &DynamicData,
+        key_only: bool,
+        nested: bool,
+    ) -> Result<(), DynamicTypeError> {
+        let values = match value {
+            DynamicData::Struct(values) => values,
+            other => return Err(mismatch(other, &self.name)),
+        };
+        if let Some((name, _)) = values.iter().find(|(name, _)| self.field(name).is_none()) {
+            return Err(DynamicTypeError(format!("{} has no field {}", self.name, name)));
+        }
+        for field in self.selected_fields(key_only, nested) {
+            let result = match value.field(&field.name) {
+                Some(value) => serialize_value(writer, &field.kind, value, key_only),
+                None => serialize_value(writer, &field.kind, &field.kind.default_value(), key_only),
+            };
+            result.map_err(|e| DynamicTypeError(format!("{}.{}: {}", self.name, field.name, e.0)))?;
+        }
+        Ok(())
+    }
+
+    fn deserialize_fields(
+        &self,
+        reader: &mut CdrReader,
+        key_only: bool,
+        nested: bool,
+    ) -> Result<DynamicData, DynamicTypeError> {
+        let selected = self.selected_fields(key_only, nested);
+        let mut values = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            let value = if selected.iter().any(|s| std::ptr::eq(*s, field)) {
+                deserialize_value(reader, &field.kind, key_only)
+                    .map_err(|e| DynamicTypeError(format!("{}.{}: {}", self.name, field.name, e.0)))?
+            } else {
+                field.kind.default_value()
+            };
+            values.push((field.name.clone(), value));
+        }
+        Ok(DynamicData::Struct(values))
+    }
+}
+
+impl KeyCodec for DynamicType {
+    fn key_cdr(&self, cdr: &[u8], key_only: bool) -> Result<Vec<u8>, String> {
+        let value = if key_only {
+            let mut reader = CdrReader::with_header(cdr).map_err(|e| e.0)?;
+            self.deserialize_fields(&mut reader, true, false)
+        } else {
+            self.deserialize(cdr)
+        };
+        value
+            .and_then(|value| DynamicType::key_cdr(self, &value))
+            .map_err(|e| e.0)
+    }
+
+    fn force_md5_keyhash(&self) -> bool {
+        // the largest possible key must fit into 16 bytes
+        self.key_size().map_or(true, |size| size > 16)
+    }
+}
+
+impl DynamicType {
+    // The serialized size of the key, None if it depends on the value
+    pub(crate) fn key_size(&self) -> Option<usize> {
+        self.selected_fields(true, false)
+            .iter()
+            .try_fold(0, |offset, f| f.kind.fixed_end(offset, true))
+    }
+}
+
+/// A value of a [`DynamicType`]. Integers and floating point numbers are held
+/// in their widest form and checked against the field when serialized. Arrays
+/// and sequences are both `Sequence`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DynamicData {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Char(char),
+    String(String),
+    Sequence(Vec<DynamicData>),
+    /// The fields of a struct in order
+    Struct(Vec<(String, DynamicData)>),
+}
+
+/// The default is a struct without fields
+impl Default for DynamicData {
+    fn default() -> Self {
+        DynamicData::Struct(Vec::new())
+    }
+}
+
+impl DynamicData {
+    /// A field of a struct
+    pub fn field(&self, name: &str) -> Option<&DynamicData> {
+        match self {
+            DynamicData::Struct(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut DynamicData> {
+        match self {
+            DynamicData::Struct(fields) => fields
+                .iter_mut()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Set a field of a struct. Has no effect on other values.
+    pub fn set_field<V: Into<DynamicData>>(&mut self, name: &str, value: V) {
+        if let Some(field) = self.field_mut(name) {
+            *field = value.into();
+        } else if let DynamicData::Struct(fields) = self {
+            fields.push((name.to_owned(), value.into()));
+        }
+    }
+
+    pub fn with_field<V: Into<DynamicData>>(mut self, name: &str, value: V) -> Self {
+        self.set_field(name, value);
+        self
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            DynamicData::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            DynamicData::Int(v) => Some(*v),
+            DynamicData::UInt(v) => i64::try_from(*v).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            DynamicData::Int(v) => u64::try_from(*v).ok(),
+            DynamicData::UInt(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            DynamicData::Float(v) => Some(*v),
+            DynamicData::Int(v) => Some(*v as f64),
+            DynamicData::UInt(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DynamicData::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_slice(&self) -> Option<&[DynamicData]> {
+        match self {
+            DynamicData::Sequence(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! dynamic_data_from {
+    ($variant:ident, $as:ty, $($t:ty),*) => {
+        $(impl From<$t> for DynamicData {
+            fn from(v: $t) -> Self {
+                DynamicData::$variant(v as $as)
+            }
+        })*
+    };
+}
+
+dynamic_data_from!(Int, i64, i8, i16, i32, i64);
+dynamic_data_from!(UInt, u64, u8, u16, u32, u64);
+dynamic_data_from!(Float, f64, f32, f64);
+
+impl From<bool> for DynamicData {
+    fn from(v: bool) -> Self {
+        DynamicData::Bool(v)
+    }
+}
+
+impl From<char> for DynamicData {
+    fn from(v: char) -> Self {
+        DynamicData::Char(v)
+    }
+}
+
+impl From<&str> for DynamicData {
+    fn from(v: &str) -> Self {
+        DynamicData::String(v.to_owned())
+    }
+}
+
+impl From<String> for DynamicData {
+    fn from(v: String) -> Self {
+        DynamicData::String(v)
+    }
+}
+
+impl<T: Into<DynamicData>> From<Vec<T>> for DynamicData {
+    fn from(v: Vec<T>) -> Self {
+        DynamicData::Sequence(v.into_iter().map(Into::into).collect())
+    }
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+fn mismatch(value: &DynamicData, expected: &str) -> DynamicTypeError {
+    DynamicTypeError(format!("{:?} is not a {}", value, expected))
+}
+
+// Big endian CDR. Alignment is relative to the end of the encapsulation header.
+struct CdrWriter {
+    buf: Vec<u8>,
+    origin: usize,
+}
+
+impl CdrWriter {
+    fn new(header: &[u8]) -> Self {
+        Self {
+            buf: header.to_vec(),
+            origin: header.len(),
+        }
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        let aligned = self.origin + align(self.buf.len() - self.origin, bytes.len());
+        self.buf.resize(aligned, 0);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn put_len(&mut self, len: usize) -> Result<(), DynamicTypeError> {
+        let len = u32::try_from(len).map_err(|_| DynamicTypeError("too long".to_owned()))?;
+        self.put(&len.to_be_bytes());
+        Ok(())
+    }
+}
+
+fn integer<I: TryFrom<i64> + TryFrom<u64>>(value: &DynamicData) -> Result<I, DynamicTypeError> {
+    let converted = match value {
+        DynamicData::Int(v) => <I as TryFrom<i64>>::try_from(*v).ok(),
+        DynamicData::UInt(v) => <I as TryFrom<u64>>::try_from(*v).ok(),
+        _ => None,
+    };
+    converted.ok_or_else(|| DynamicTypeError(format!("{:?} is out of range", value)))
+}
+
+fn serialize_value(
+    writer: &mut CdrWriter,
+    kind: &DynamicKind,
+    value: &DynamicData,
+    key_only: bool,
+) -> Result<(), DynamicTypeError> {
+    match kind {
+        DynamicKind::Bool => writer.put(&[value.as_bool().ok_or_else(|| mismatch(value, "bool"))? as u8]),
+        DynamicKind::Char => match value {
+            DynamicData::Char(c) if c.is_ascii() => writer.put(&[*c as u8]),
+            _ => return Err(mismatch(value, "char")),
+        },
+        DynamicKind::Int8 => writer.put(&integer::<i8>(value)?.to_be_bytes()),
+        DynamicKind::Int16 => writer.put(&integer::<i16>(value)?.to_be_bytes()),
+        DynamicKind::Int32 => writer.put(&integer::<i32>(value)?.to_be_bytes()),
+        DynamicKind::Int64 => writer.put(&integer::<i64>(value)?.to_be_bytes()),
+        DynamicKind::UInt8 => writer.put(&integer::<u8>(value)?.to_be_bytes()),
+        DynamicKind::UInt16 => writer.put(&integer::<u16>(value)?.to_be_bytes()),
+        DynamicKind::UInt32 => writer.put(&integer::<u32>(value)?.to_be_bytes()),
+        DynamicKind::UInt64 => writer.put(&integer::<u64>(value)?.to_be_bytes()),
+        DynamicKind::Float32 => {
+            let v = value.as_f64().ok_or_else(|| mismatch(value, "float"))?;
+            writer.put(&(v as f32).to_be_bytes())
+        }
+        DynamicKind::Float64 => {
+            let v = value.as_f64().ok_or_else(|| mismatch(value, "double"))?;
+            writer.put(&v.to_be_bytes())
+        }
+        DynamicKind::String => {
+            let s = value.as_str().ok_or_else(|| mismatch(value, "string"))?;
+            if s.contains('\0') {
+                return Err(DynamicTypeError("strings cannot contain NUL".to_owned()));
+            }
+            writer.put_len(s.len() + 1)?;
+            writer.buf.extend_from_slice(s.as_bytes());
+            writer.buf.push(0);
+        }
+        DynamicKind::Sequence(element) => {
+            let items = value.as_slice().ok_or_else(|| mismatch(value, "sequence"))?;
+            writer.put_len(items.len())?;
+            for item in items {
+                serialize_value(writer, element, item, key_only)?;
+            }
+        }
+        DynamicKind::Array(element, len) => {
+            let items = value.as_slice().ok_or_else(|| mismatch(value, "array"))?;
+            if items.len() != *len {
+                return Err(DynamicTypeError(format!(
+                    "array needs {} elements, got {}",
+                    len,
+                    items.len()
+                )));
+            }
+            for item in items {
+                serialize_value(writer, element, item, key_only)?;
+            }
+        }
+        DynamicKind::Struct(ty) => ty.serialize_fields(writer, value, key_only, true)?,
+    }
+    Ok(())
+}
+
+struct CdrReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    origin: usize,
+    little_endian: bool,
+}
+
+impl<'a> CdrReader<'a> {
+    fn new(data: &'a [u8], little_endian: bool) -> Self {
+        Self {
+            data,
+            pos: 0,
+            origin: 0,
+            little_endian,
+        }
+    }
+
+    // plain CDR in either byte order
+    fn with_header(data: &'a [u8]) -> Result<Self, DynamicTypeError> {
+        let little_endian = match data {
+            [0, 0, _, _, ..] => false,
+            [0, 1, _, _, ..] => true,
+            [a, b, _, _, ..] => {
+                return Err(DynamicTypeError(format!(
+                    "unsupported encapsulation {:02x}{:02x}",
+                    a, b
+                )))
+            }
+            _ => return Err(DynamicTypeError("missing encapsulation header".to_owned())),
+        };
+        Ok(Self {
+            data,
+            pos: 4,
+            origin: 4,
+            little_endian,
+        })
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], DynamicTypeError> {
+        let start = self.origin + align(self.pos - self.origin, N);
+        let end = start + N;
+        if end > self.data.len() {
+            return Err(DynamicTypeError("unexpected end of data".to_owned()));
+        }
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.data[start..end]);
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+}
+
+macro_rules! read_number {
+    ($reader:expr, $t:ty) => {{
+        let bytes = $reader.bytes()?;
+        if $reader.little_endian {
+            <$t>::from_le_bytes(bytes)
+        } else {
+            <$t>::from_be_bytes(bytes)
+        }
+    }};
+}
+
+fn deserialize_value(
+    reader: &mut CdrReader,
+    kind: &DynamicKind,
+    key_only: bool,
+) -> Result<DynamicData, DynamicTypeError> {
+    Ok(match kind {
+        DynamicKind::Bool => DynamicData::Bool(read_number!(reader, u8) != 0),
+        DynamicKind::Char => DynamicData::Char(read_number!(reader, u8) as char),
+        DynamicKind::Int8 => DynamicData::Int(read_number!(reader, i8) as i64),
+        DynamicKind::Int16 => DynamicData::Int(read_number!(reader, i16) as i64),
+        DynamicKind::Int32 => DynamicData::Int(read_number!(reader, i32) as i64),
+        DynamicKind::Int64 => DynamicData::Int(read_number!(reader, i64)),
+        DynamicKind::UInt8 => DynamicData::UInt(read_number!(reader, u8) as u64),
+        DynamicKind::UInt16 => DynamicData::UInt(read_number!(reader, u16) as u64),
+        DynamicKind::UInt32 => DynamicData::UInt(read_number!(reader, u32) as u64),
+        DynamicKind::UInt64 => DynamicData::UInt(read_number!(reader, u64)),
+        DynamicKind::Float32 => DynamicData::Float(read_number!(reader, f32) as f64),
+        DynamicKind::Float64 => DynamicData::Float(read_number!(reader, f64)),
+        DynamicKind::String => {
+            let len = read_number!(reader, u32) as usize;
+            if len == 0 || len > reader.remaining() {
+                return Err(DynamicTypeError(format!("bad string length {}", len)));
+            }
+            let bytes = &reader.data[reader.pos..reader.pos + len - 1];
+            reader.pos += len;
+            let s = std::str::from_utf8(bytes)
+                .map_err(|e| DynamicTypeError(format!("bad string: {}", e)))?;
+            DynamicData::String(s.to_owned())
+        }
+        DynamicKind::Sequence(element) => {
+            let len = read_number!(reader, u32) as usize;
+            // do not trust the length for the allocation
+            let mut items = Vec::with_capacity(len.min(reader.remaining()));
+            for _ in 0..len {
+                items.push(deserialize_value(reader, element, key_only)?);
+            }
+            DynamicData::Sequence(items)
+        }
+        DynamicKind::Array(element, len) => {
+            let mut items = Vec::with_capacity(*len);
+            for _ in 0..*len {
+                items.push(deserialize_value(reader, element, key_only)?);
+            }
+            DynamicData::Sequence(items)
+        }
+        DynamicKind::Struct(ty) => ty.deserialize_fields(reader, key_only, true)?,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(usize),
+    Literal(String),
+    Scope,
+    Punct(char),
+    // the words of a #pragma keylist
+    Keylist(Vec<String>),
+}
+
+fn tokenize(idl: &str) -> Result<Vec<Token>, DynamicTypeError> {
+    let mut tokens = Vec::new();
+    let mut chars = idl.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '#' => {
+                let line: String = chars.by_ref().take_while(|c| *c != '\n').collect();
+                let words: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+                if words.len() > 2 && words[0] == "pragma" && words[1] == "keylist" {
+                    tokens.push(Token::Keylist(words[2..].to_vec()));
+                }
+            }
+            '"' => tokens.push(Token::Literal(chars.by_ref().take_while(|c| *c != '"').collect())),
+            ':' if chars.peek() == Some(&':') => {
+                chars.next();
+                tokens.push(Token::Scope);
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    number.push(*d);
+                    chars.next();
+                }
+                let number = number
+                    .parse()
+                    .map_err(|_| DynamicTypeError(format!("bad number {}", number)))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(d) = chars.peek().filter(|d| d.is_alphanumeric() || **d == '_') {
+                    ident.push(*d);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            '{' | '}' | ';' | ':' | '<' | '>' | '[' | ']' | ',' | '@' | '(' | ')' | '=' => {
+                tokens.push(Token::Punct(c))
+            }
+            c => return Err(DynamicTypeError(format!("unexpected character '{}'", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+enum Declared {
+    Struct(DynamicType),
+    Typedef(DynamicKind),
+}
+
+// A recursive descent parser for the IDL subset that maps to DynamicType
+struct IdlParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    scope: Vec<String>,
+    declared: Vec<(String, Declared)>,
+    last_struct: Option<String>,
+}
+
+impl IdlParser {
+    fn parse(idl: &str) -> Result<DynamicType, DynamicTypeError> {
+        let mut parser = IdlParser {
+            tokens: tokenize(idl)?,
+            pos: 0,
+            scope: Vec::new(),
+            declared: Vec::new(),
+            last_struct: None,
+        };
+        parser.definitions(false)?;
+        let name = parser
+            .last_struct
+            .clone()
+            .ok_or_else(|| DynamicTypeError("no struct in IDL".to_owned()))?;
+        match parser.lookup(&name) {
+            Some(Declared::Struct(ty)) => Ok(ty.clone()),
+            _ => Err(DynamicTypeError(format!("{} is not declared as a struct", name))),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, DynamicTypeError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| DynamicTypeError("unexpected end of IDL".to_owned()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), DynamicTypeError> {
+        match self.next()? {
+            Token::Punct(p) if p == c => Ok(()),
+            other => Err(DynamicTypeError(format!("expected '{}', found {:?}", c, other))),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, DynamicTypeError> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            other => Err(DynamicTypeError(format!("expected a name, found {:?}", other))),
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, DynamicTypeError> {
+        match self.next()? {
+            Token::Number(n) => Ok(n),
+            other => Err(DynamicTypeError(format!("expected a number, found {:?}", other))),
+        }
+    }
+
+    fn scoped(&self, name: &str) -> String {
+        let mut scoped = self.scope.join("::");
+        if !scoped.is_empty() {
+            scoped.push_str("::");
+        }
+        scoped.push_str(name);
+        scoped
+    }
+
+    fn lookup(&self, scoped_name: &str) -> Option<&Declared> {
+        self.declared
+            .iter()
+            .rev()
+            .find(|(name, _)| name == scoped_name)
+            .map(|(_, declared)| declared)
+    }
+
+    // resolve a name from the innermost scope outwards
+    fn resolve(&self, name: &str, absolute: bool) -> Option<&Declared> {
+        if absolute {
+            return self.lookup(name);
+        }
+        (0..=self.scope.len()).rev().find_map(|depth| {
+            let mut scoped = self.scope[..depth].join("::");
+            if !scoped.is_empty() {
+                scoped.push_str("::");
+            }
+            scoped.push_str(name);
+            self.lookup(&scoped)
+        })
+    }
+
+    fn definitions(&mut self, in_module: bool) -> Result<(), DynamicTypeError> {
+        loop {
+            match self.peek().cloned() {
+                None if in_module => return Err(DynamicTypeError("unexpected end of IDL".to_owned())),
+                None => return Ok(()),
+                Some(Token::Punct('}')) if in_module => return Ok(()),
+                Some(Token::Punct('@')) => {
+                    self.annotation()?;
+                }
+                Some(Token::Keylist(words)) => {
+                    self.pos += 1;
+                    self.keylist(&words)?;
+                }
+                Some(Token::Ident(keyword)) => {
+                    self.pos += 1;
+                    match keyword.as_str() {
+                        "module" => {
+                            let name = self.ident()?;
+                            self.expect('{')?;
+                            self.scope.push(name);
+                            self.definitions(true)?;
+                            self.scope.pop();
+                            self.expect('}')?;
+                            self.expect(';')?;
+                        }
+                        "struct" => self.structure()?,
+                        "typedef" => {
+                            let kind = self.type_spec()?;
+                            let (name, kind) = self.declarator(kind)?;
+                            self.expect(';')?;
+                            let name = self.scoped(&name);
+                            self.declared.push((name, Declared::Typedef(kind)));
+                        }
+                        other => {
+                            return Err(DynamicTypeError(format!("unsupported IDL construct '{}'", other)))
+                        }
+                    }
+                }
+                Some(other) => return Err(DynamicTypeError(format!("unexpected {:?}", other))),
+            }
+        }
+    }
+
+    // returns whether the annotation is @key, or @key(TRUE)
+    fn annotation(&mut self) -> Result<bool, DynamicTypeError> {
+        self.expect('@')?;
+        let name = self.ident()?;
+        let mut value = true;
+        if self.peek() == Some(&Token::Punct('(')) {
+            self.pos += 1;
+            while self.peek() != Some(&Token::Punct(')')) {
+                if let Token::Ident(v) = self.next()? {
+                    value = !v.eq_ignore_ascii_case("false");
+                }
+            }
+            self.pos += 1;
+        }
+        Ok(name == "key" && value)
+    }
+
+    fn structure(&mut self) -> Result<(), DynamicTypeError> {
+        let name = self.ident()?;
+        match self.next()? {
+            // a forward declaration
+            Token::Punct(';') => return Ok(()),
+            Token::Punct('{') => {}
+            Token::Punct(':') => return Err(DynamicTypeError("struct inheritance is not supported".to_owned())),
+            other => return Err(DynamicTypeError(format!("expected '{{', found {:?}", other))),
+        }
+        let mut ty = DynamicType::new(&self.scoped(&name));
+        while self.peek() != Some(&Token::Punct('}')) {
+            let mut key = false;
+            while self.peek() == Some(&Token::Punct('@')) {
+                key |= self.annotation()?;
+            }
+            let kind = self.type_spec()?;
+            loop {
+                let (field, kind) = self.declarator(kind.clone())?;
+                ty.fields.push(DynamicField {
+                    name: field,
+                    kind,
+                    key,
+                });
+                match self.next()? {
+                    Token::Punct(',') => continue,
+                    Token::Punct(';') => break,
+                    other => return Err(DynamicTypeError(format!("expected ';', found {:?}", other))),
+                }
+            }
+        }
+        self.expect('}')?;
+        self.expect(';')?;
+        self.last_struct = Some(ty.name.clone());
+        self.declared.push((ty.name.clone(), Declared::Struct(ty)));
+        Ok(())
+    }
+
+    // a name with optional array dimensions
+    fn declarator(&mut self, kind: DynamicKind) -> Result<(String, DynamicKind), DynamicTypeError> {
+        let name = self.ident()?;
+        let mut dimensions = Vec::new();
+        while self.peek() == Some(&Token::Punct('[')) {
+            self.pos += 1;
+            dimensions.push(self.number()?);
+            self.expect(']')?;
+        }
+        let kind = dimensions
+            .into_iter()
+            .rev()
+            .fold(kind, |kind, len| DynamicKind::Array(Box::new(kind), len));
+        Ok((name, kind))
+    }
+
+    // an optional bound, for example of string<10>
+    fn bound(&mut self) -> Result<(), DynamicTypeError> {
+        if self.peek() == Some(&Token::Punct('<')) {
+            self.pos += 1;
+            self.number()?;
+            self.expect('>')?;
+        }
+        Ok(())
+    }
+
+    fn type_spec(&mut self) -> Result<DynamicKind, DynamicTypeError> {
+        let absolute = self.peek() == Some(&Token::Scope);
+        if absolute {
+            self.pos += 1;
+        }
+        let word = self.ident()?;
+        let kind = match word.as_str() {
+            "boolean" => DynamicKind::Bool,
+            "char" => DynamicKind::Char,
+            "octet" | "uint8" => DynamicKind::UInt8,
+            "int8" => DynamicKind::Int8,
+            "short" | "int16" => DynamicKind::Int16,
+            "int32" => DynamicKind::Int32,
+            "int64" => DynamicKind::Int64,
+            "uint16" => DynamicKind::UInt16,
+            "uint32" => DynamicKind::UInt32,
+            "uint64" => DynamicKind::UInt64,
+            "float" => DynamicKind::Float32,
+            "double" => DynamicKind::Float64,
+            "long" => self.long(DynamicKind::Int32, DynamicKind::Int64)?,
+            "unsigned" => match self.ident()?.as_str() {
+                "short" => DynamicKind::UInt16,
+                "long" => self.long(DynamicKind::UInt32, DynamicKind::UInt64)?,
+                other => return Err(DynamicTypeError(format!("unsupported type unsigned {}", other))),
+            },
+            "string" => {
+                self.bound()?;
+                DynamicKind::String
+            }
+            "sequence" => {
+                self.expect('<')?;
+                let element = self.type_spec()?;
+                if self.peek() == Some(&Token::Punct(',')) {
+                    self.pos += 1;
+                    self.number()?;
+                }
+                self.expect('>')?;
+                DynamicKind::Sequence(Box::new(element))
+            }
+            _ => {
+                let mut name = word;
+                while self.peek() == Some(&Token::Scope) {
+                    self.pos += 1;
+                    name.push_str("::");
+                    name.push_str(&self.ident()?);
+                }
+                match self.resolve(&name, absolute) {
+                    Some(Declared::Struct(ty)) => DynamicKind::Struct(ty.clone()),
+                    Some(Declared::Typedef(kind)) => kind.clone(),
+                    None => return Err(DynamicTypeError(format!("unknown type {}", name))),
+                }
+            }
+        };
+        Ok(kind)
+    }
+
+    // long or long long
+    fn long(&mut self, long: DynamicKind, long_long: DynamicKind) -> Result<DynamicKind, DynamicTypeError> {
+        match self.peek() {
+            Some(Token::Ident(word)) if word == "long" => {
+                self.pos += 1;
+                Ok(long_long)
+            }
+            Some(Token::Ident(word)) if word == "double" => {
+                Err(DynamicTypeError("long double is not supported".to_owned()))
+            }
+            _ => Ok(long),
+        }
+    }
+
+    // #pragma keylist Type field...
+    fn keylist(&mut self, words: &[String]) -> Result<(), DynamicTypeError> {
+        let name = words[0].trim_start_matches("::").to_owned();
+        let scoped = if words[0].starts_with("::") { name.clone() } else { self.scoped(&name) };
+        let declared = self
+            .declared
+            .iter_mut()
+            .rev()
+            .find(|(n, _)| *n == scoped || *n == name);
+        match declared {
+            Some((_, Declared::Struct(ty))) => {
+                for key in &words[1..] {
+                    match ty.fields.iter_mut().find(|f| f.name == *key) {
+                        Some(field) => field.key = true,
+                        None => return Err(DynamicTypeError(format!("{} has no field {}", ty.name, key))),
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(DynamicTypeError(format!("keylist for unknown struct {}", name))),
+        }
+    }
+}
+
+/// A topic of a [`DynamicType`]
+pub struct DynamicTopic {
+    entity: DdsEntity,
+    dynamic_type: Arc<DynamicType>,
+    _listener: Option<DdsListener>,
+}
+
+impl DynamicTopic {
+    /// Create a topic. The type name registered with cyclone is the name of the
+    /// dynamic type.
+    pub fn create(
+        participant: &DdsParticipant,
+        name: &str,
+        dynamic_type: DynamicType,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<Self, DDSError> {
+        let dynamic_type = Arc::new(dynamic_type);
+        let key_codec: Option<Arc<dyn KeyCodec>> = if dynamic_type.has_key() {
+            Some(dynamic_type.clone())
+        } else {
+            None
+        };
+        let entity = create_topic(
+            Entity::entity(participant),
+            name,
+            dynamic_type.name(),
+            key_codec,
+            maybe_qos,
+            maybe_listener.as_ref(),
+        )?;
+        Ok(Self {
+            entity,
+            dynamic_type,
+            _listener: maybe_listener,
+        })
+    }
+
+    pub fn dynamic_type(&self) -> &DynamicType {
+        &self.dynamic_type
+    }
+}
+
+impl Entity for DynamicTopic {
+    fn entity(&self) -> &DdsEntity {
+        &self.entity
+    }
+}
+
+/// A writer of [`DynamicData`] samples
+pub struct DynamicWriter {
+    entity: DdsEntity,
+    dynamic_type: Arc<DynamicType>,
+    _listener: Option<DdsListener>,
+}
+
+impl DynamicWriter {
+    pub fn create(
+        entity: &dyn DdsWritable,
+        topic: &DynamicTopic,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<Self, DDSError> {
+        unsafe {
+            let w = dds_create_writer(
+                entity.entity().entity(),
+                topic.entity.entity(),
+                maybe_qos.map_or(std::ptr::null(), |q| q.into()),
+                maybe_listener
+                    .as_ref()
+                    .map_or(std::ptr::null(), |l| l.into()),
+            );
+            if w >= 0 {
+                Ok(Self {
+                    entity: DdsEntity::new(w),
+                    dynamic_type: topic.dynamic_type.clone(),
+                    _listener: maybe_listener,
+                })
+            } else {
+                Err(DDSError::from_retcode("dds_create_writer", w).with_entity_kind("writer"))
+            }
+        }
+    }
+
+    pub fn write(&mut self, data: &DynamicData) -> Result<(), DDSError> {
+        let cdr = self.dynamic_type.serialize(data)?;
+        self.write_cdr(&cdr)
+    }
+
+    /// Write a sample that is already serialized, including the encapsulation header
+    pub fn write_cdr(&mut self, cdr: &[u8]) -> Result<(), DDSError> {
+        self.with_sample(cdr, "dds_write", |entity, sample| unsafe { dds_write(entity, sample) })
+    }
+
+    /// Dispose the instance of the sample. Only the key fields need to be set.
+    pub fn dispose(&mut self, data: &DynamicData) -> Result<(), DDSError> {
+        let cdr = self.dynamic_type.serialize(data)?;
+        self.with_sample(&cdr, "dds_dispose", |entity, sample| unsafe { dds_dispose(entity, sample) })
+    }
+
+    /// Unregister the instance of the sample. Only the key fields need to be set.
+    pub fn unregister(&mut self, data: &DynamicData) -> Result<(), DDSError> {
+        let cdr = self.dynamic_type.serialize(data)?;
+        self.with_sample(&cdr, "dds_unregister_instance", |entity, sample| unsafe {
+            dds_unregister_instance(entity, sample)
+        })
+    }
+
+    // the sertype takes a Vec<u8> with the CDR as sample
+    fn with_sample<F>(&self, cdr: &[u8], operation: &'static str, f: F) -> Result<(), DDSError>
+    where
+        F: FnOnce(dds_entity_t, *const c_void) -> dds_return_t,
+    {
+        let sample = cdr.to_vec();
+        let ret = f(unsafe { self.entity.entity() }, &sample as *const Vec<u8> as *const c_void);
+        if ret >= 0 {
+            Ok(())
+        } else {
+            Err(DDSError::from_retcode(operation, ret).with_entity_kind("writer"))
+        }
+    }
+}
+
+impl Entity for DynamicWriter {
+    fn entity(&self) -> &DdsEntity {
+        &self.entity
+    }
+}
+
+impl Drop for DynamicWriter {
+    fn drop(&mut self) {
+        unsafe {
+            let ret = dds_delete(self.entity.entity());
+            if ret != 0 && DDSError::from(ret) != DDSError::AlreadyDeleted {
+                crate::dds_log::drop_failed("writer", &DDSError::from_retcode("dds_delete", ret));
+            }
+        }
+    }
+}
+
+/// A sample read by a [`DynamicReader`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicSample {
+    /// Only the key fields are set if `valid_data` is false
+    pub data: DynamicData,
+    /// false if only the key of the data is valid. This is the case when
+    /// the instance was disposed or lost all its writers.
+    pub valid_data: bool,
+    /// false if the instance was disposed or lost all its writers
+    pub alive: bool,
+}
+
+/// A reader of [`DynamicData`] samples
+pub struct DynamicReader {
+    entity: DdsEntity,
+    dynamic_type: Arc<DynamicType>,
+    serdes_errors: SerdesErrors,
+    _listener: Option<DdsListener>,
+}
+
+impl DynamicReader {
+    pub fn create(
+        entity: &dyn DdsReadable,
+        topic: &DynamicTopic,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<Self, DDSError> {
+        unsafe {
+            let r = dds_create_reader(
+                entity.entity().entity(),
+                topic.entity.entity(),
+                maybe_qos.map_or(std::ptr::null(), |q| q.into()),
+                maybe_listener
+                    .as_ref()
+                    .map_or(std::ptr::null(), |l| l.into()),
+            );
+            if r >= 0 {
+                Ok(Self {
+                    entity: DdsEntity::new(r),
+                    dynamic_type: topic.dynamic_type.clone(),
+                    serdes_errors: SerdesErrors::default(),
+                    _listener: maybe_listener,
+                })
+            } else {
+                Err(DDSError::from_retcode("dds_create_reader", r).with_entity_kind("reader"))
+            }
+        }
+    }
+
+    pub fn dynamic_type(&self) -> &DynamicType {
+        &self.dynamic_type
+    }
+
+    /// Take up to `max` samples
+    pub fn take(&self, max: usize) -> Result<Vec<DynamicSample>, DDSError> {
+        self.read_or_take(max, true)
+    }
+
+    /// Read up to `max` samples
+    pub fn read(&self, max: usize) -> Result<Vec<DynamicSample>, DDSError> {
+        self.read_or_take(max, false)
+    }
+
+    /// Samples that could not be decoded since the last call, like
+    /// [`DdsReader::take_serdes_errors`](crate::DdsReader::take_serdes_errors).
+    /// Samples whose key could not be found are dropped by cyclone and are
+    /// collected per topic type, samples that fail to decode in `read` or
+    /// `take` are collected by this reader.
+    pub fn take_serdes_errors(&self) -> Result<Vec<DDSError>, DDSError> {
+        let mut sertype: *const ddsi_sertype = std::ptr::null();
+        let ret = unsafe { dds_get_entity_sertype(self.entity.entity(), &mut sertype) };
+        if ret < 0 {
+            return Err(DDSError::from_retcode("dds_get_entity_sertype", ret).with_entity_kind("reader"));
+        }
+        let topic = unsafe { DdsEntity::new(dds_get_topic(self.entity.entity())) };
+        let topic = topic_name_of(&topic).ok();
+
+        // the sertype of a dynamic topic is always created by serdes_raw
+        let mut errors = unsafe { RawSerType::from_sertype(sertype) }.serdes_errors().take();
+        errors.extend(self.serdes_errors.take());
+        Ok(errors
+            .into_iter()
+            .map(|e| match &topic {
+                Some(topic) => e.with_topic(topic.clone()).into(),
+                None => e.into(),
+            })
+            .collect())
+    }
+
+    fn read_or_take(&self, max: usize, take: bool) -> Result<Vec<DynamicSample>, DDSError> {
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+        let mut serdata: Vec<*mut ddsi_serdata> = vec![std::ptr::null_mut(); max];
+        let mut infos = vec![dds_sample_info_t::default(); max];
+
+        unsafe {
+            let n = if take {
+                dds_takecdr(self.entity.entity(), serdata.as_mut_ptr(), max as u32, infos.as_mut_ptr(), 0)
+            } else {
+                dds_readcdr(self.entity.entity(), serdata.as_mut_ptr(), max as u32, infos.as_mut_ptr(), 0)
+            };
+            if n < 0 {
+                return Err(DDSError::from_retcode(if take { "dds_takecdr" } else { "dds_readcdr" }, n)
+                    .with_entity_kind("reader"));
+            }
+
+            let mut samples = Vec::with_capacity(n as usize);
+            for (d, info) in serdata[..n as usize].iter().zip(infos.iter()) {
+                let raw = RawSerData::from_serdata(*d);
+                let decoded = if info.valid_data {
+                    self.dynamic_type.deserialize(raw.cdr())
+                } else {
+                    self.dynamic_type.deserialize_key(raw.key())
+                };
+                match decoded {
+                    Ok(data) => samples.push(DynamicSample {
+                        data,
+                        valid_data: info.valid_data,
+                        alive: info.instance_state == dds_instance_state_DDS_IST_ALIVE,
+                    }),
+                    Err(e) => self.deserialize_failed(raw.cdr().len(), e),
+                }
+                ddsi_serdata_removeref(*d);
+            }
+            Ok(samples)
+        }
+    }
+
+    // The key of the sample was found when it was received, so the sample is
+    // well formed up to the key.
+    fn deserialize_failed(&self, size: usize, e: DynamicTypeError) {
+        count(Counter::DeserializeFailures);
+        let error = SerdesError::new(self.dynamic_type.name().to_owned(), size, e.0);
+        crate::dds_log::warn_failed(format_args!("deserialization failed"), &error);
+        self.serdes_errors.push(error);
+    }
+}
+
+impl Entity for DynamicReader {
+    fn entity(&self) -> &DdsEntity {
+        &self.entity
+    }
+}
+
+impl Drop for DynamicReader {
+    fn drop(&mut self) {
+        unsafe {
+            let ret = dds_delete(self.entity.entity());
+            if ret != 0 && DDSError::from(ret) != DDSError::AlreadyDeleted {
+                crate::dds_log::drop_failed("reader", &DDSError::from_retcode("dds_delete", ret));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsReader, DdsTopic, DdsWriter, SampleBuffer, TopicType};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Reading {
+        #[topic_key]
+        sensor: u32,
+        flag: bool,
+        value: f64,
+        label: String,
+        samples: Vec<i16>,
+        position: [f32; 3],
+    }
+
+    fn reading_type() -> DynamicType {
+        DynamicType::new(Reading::typename().to_str().unwrap())
+            .with_key_field("sensor", DynamicKind::UInt32)
+            .with_field("flag", DynamicKind::Bool)
+            .with_field("value", DynamicKind::Float64)
+            .with_field("label", DynamicKind::String)
+            .with_field("samples", DynamicKind::Sequence(Box::new(DynamicKind::Int16)))
+            .with_field("position", DynamicKind::Array(Box::new(DynamicKind::Float32), 3))
+    }
+
+    fn reading() -> (Reading, DynamicData) {
+        let typed = Reading {
+            sensor: 7,
+            flag: true,
+            value: 2.5,
+            label: "kitchen".to_owned(),
+            samples: vec![-1, 2, 3],
+            position: [1.0, 2.0, 3.0],
+        };
+        let dynamic = DynamicData::default()
+            .with_field("sensor", 7u32)
+            .with_field("flag", true)
+            .with_field("value", 2.5)
+            .with_field("label", "kitchen")
+            .with_field("samples", vec![-1i16, 2, 3])
+            .with_field("position", vec![1.0f32, 2.0, 3.0]);
+        (typed, dynamic)
+    }
+
+    #[test]
+    fn test_type_of() {
+        assert_eq!(DynamicType::of::<Reading>().unwrap(), reading_type());
+
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Station {
+            #[topic_key]
+            site: String,
+            #[topic_key]
+            reading: Reading,
+            note: String,
+        }
+        let station = DynamicType::of::<Station>().unwrap();
+        assert!(station.field("site").unwrap().key);
+        assert!(!station.field("note").unwrap().key);
+        let reading = station.field("reading").unwrap();
+        assert!(reading.key);
+        match &reading.kind {
+            DynamicKind::Struct(ty) => {
+                assert_eq!(ty.name(), "Reading");
+                assert!(ty.field("sensor").unwrap().key);
+                assert!(!ty.field("label").unwrap().key);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_idl() {
+        let idl = r#"
+            // a comment
+            module outer {
+                typedef double Vector[3];
+                struct Header { string<32> frame; unsigned long long stamp; };
+                module inner {
+                    /* another comment */
+                    @topic
+                    struct Pose {
+                        @key long id;
+                        @key(FALSE) Header header;
+                        Vector position;
+                        sequence<sequence<octet>, 4> blobs;
+                        short a, b[2];
+                    };
+                };
+            };
+        "#;
+        let pose = DynamicType::from_idl(idl).unwrap();
+        assert_eq!(pose.name(), "outer::inner::Pose");
+        assert!(pose.field("id").unwrap().key);
+        assert!(!pose.field("header").unwrap().key);
+        match &pose.field("header").unwrap().kind {
+            DynamicKind::Struct(header) => {
+                assert_eq!(header.name(), "outer::Header");
+                assert_eq!(header.field("stamp").unwrap().kind, DynamicKind::UInt64);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            pose.field("position").unwrap().kind,
+            DynamicKind::Array(Box::new(DynamicKind::Float64), 3)
+        );
+        assert_eq!(
+            pose.field("blobs").unwrap().kind,
+            DynamicKind::Sequence(Box::new(DynamicKind::Sequence(Box::new(DynamicKind::UInt8))))
+        );
+        assert_eq!(
+            pose.field("b").unwrap().kind,
+            DynamicKind::Array(Box::new(DynamicKind::Int16), 2)
+        );
+
+        let keylist = DynamicType::from_idl("struct S { long a; long b; };\n#pragma keylist S b\n").unwrap();
+        assert!(!keylist.field("a").unwrap().key);
+        assert!(keylist.field("b").unwrap().key);
+
+        assert!(DynamicType::from_idl("enum E { A };").is_err());
+        assert!(DynamicType::from_idl("struct S { Unknown u; };").is_err());
+    }
+
+    #[test]
+    fn test_serialize_like_serde() {
+        let (typed, dynamic) = reading();
+        let ty = reading_type();
+        let cdr = ty.serialize(&dynamic).unwrap();
+        assert_eq!(cdr, cdr::serialize::<_, _, cdr::CdrBe>(&typed, cdr::Infinite).unwrap());
+        assert_eq!(ty.key_cdr(&dynamic).unwrap(), typed.key_cdr()[4..].to_vec());
+        assert!(!KeyCodec::force_md5_keyhash(&ty));
+
+        // both byte orders can be read
+        assert_eq!(ty.deserialize(&cdr).unwrap(), dynamic);
+        let le = cdr::serialize::<_, _, cdr::CdrLe>(&typed, cdr::Infinite).unwrap();
+        assert_eq!(ty.deserialize(&le).unwrap(), dynamic);
+
+        assert!(ty.deserialize(&cdr[..cdr.len() - 2]).is_err());
+        assert!(ty.serialize(&dynamic.clone().with_field("sensor", -1)).is_err());
+        assert!(ty.serialize(&dynamic.clone().with_field("nonsense", 1)).is_err());
+        assert!(ty.serialize(&dynamic.with_field("position", vec![1.0])).is_err());
+    }
+
+    #[test]
+    fn test_dynamic_topic() {
+        let typed_participant = DdsParticipant::create(None, None, None).unwrap();
+        let dynamic_participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = DdsTopic::<Reading>::create(&typed_participant, "dynamic_reading", None, None).unwrap();
+        let dynamic_topic =
+            DynamicTopic::create(&dynamic_participant, "dynamic_reading", reading_type(), None, None).unwrap();
+
+        let mut writer = DdsWriter::create(&typed_participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&typed_participant, topic, None, None).unwrap();
+        let mut dynamic_writer = DynamicWriter::create(&dynamic_participant, &dynamic_topic, None, None).unwrap();
+        let dynamic_reader = DynamicReader::create(&dynamic_participant, &dynamic_topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let (typed, dynamic) = reading();
+        writer.write(Arc::new(typed.clone())).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let samples = dynamic_reader.take(4).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert!(samples[0].valid_data);
+        assert_eq!(samples[0].data, dynamic);
+
+        // the typed reader also received its own sample
+        let mut buf = SampleBuffer::<Reading>::new(4);
+        reader.take_now(&mut buf).unwrap();
+
+        let dynamic = dynamic.with_field("sensor", 8u32);
+        dynamic_writer.write(&dynamic).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let n = reader.take_now(&mut buf).unwrap();
+        let received: Vec<Reading> = (0..n)
+            .filter_map(|i| buf.get(i).try_deref().cloned())
+            .collect();
+        assert_eq!(received, vec![Reading { sensor: 8, ..typed }]);
+        dynamic_reader.take(4).unwrap();
+
+        dynamic_writer.dispose(&DynamicData::default().with_field("sensor", 8u32)).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let samples = dynamic_reader.take(4).unwrap();
+        let disposed = samples.iter().find(|s| !s.valid_data).expect("no dispose");
+        assert!(!disposed.alive);
+        assert_eq!(disposed.data.field("sensor"), Some(&DynamicData::UInt(8)));
+    }
+}
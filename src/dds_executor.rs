@@ -0,0 +1,175 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! A single threaded executor built on a waitset. This is an alternative to
+//! listeners: the executor owns the readers and all callbacks run on the
+//! thread calling [`WaitsetExecutor::spin_once`], never on a cyclonedds thread.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example<T: TopicType + 'static>(participant: &DdsParticipant, reader: DdsReader<T>) {
+//! let mut executor = WaitsetExecutor::create(participant).unwrap();
+//! executor.add_reader(reader, 10, |_sample: &T| println!("Got a sample")).unwrap();
+//! loop {
+//!     executor.spin_once(std::time::Duration::from_secs(1)).unwrap();
+//! }
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::dds_api::DdsStatus;
+use crate::dds_statuscondition::DdsStatusCondition;
+use crate::dds_waitset::{DdsWaitset, WaitsetDispatcher, WaitsetToken};
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::{DdsParticipant, DdsReader, Entity};
+pub use cyclonedds_sys::DdsEntity;
+pub use crate::error::DDSError;
+
+pub struct WaitsetExecutor {
+    dispatcher: WaitsetDispatcher,
+}
+
+impl WaitsetExecutor {
+    pub fn create(participant: &DdsParticipant) -> Result<Self, DDSError> {
+        Ok(Self {
+            dispatcher: WaitsetDispatcher::new(DdsWaitset::create(participant)?),
+        })
+    }
+
+    /// Hand over a reader to the executor. Whenever data is available, all samples
+    /// are taken and `on_sample` is called for each valid sample. The buffer of
+    /// the reader starts with room for `batch_size` samples and grows as needed.
+    pub fn add_reader<T, F>(
+        &mut self,
+        reader: DdsReader<T>,
+        batch_size: usize,
+        mut on_sample: F,
+    ) -> Result<WaitsetToken, DDSError>
+    where
+        T: TopicType + 'static,
+        F: FnMut(&T) + 'static,
+    {
+        reader.set_status_mask(DdsStatus::DATA_AVAILABLE)?;
+        let mut buffer = SampleBuffer::<T>::new(std::cmp::max(batch_size, 1));
+        let token = self.dispatcher.attach(&reader, move |_entity| {
+            // drain the reader so the data available status is reset
+            let n = reader.take_all_into(&mut buffer).unwrap_or(0);
+            for i in 0..n {
+                if buffer.is_valid_sample(i) {
+                    if let Some(sample) = buffer.get(i).try_deref() {
+                        on_sample(sample);
+                    }
+                }
+            }
+        })?;
+        Ok(token)
+    }
+
+    /// Call `on_status` with the changed statuses whenever any of the statuses
+    /// in `mask` change on the entity. The statuses are reset before the call.
+    pub fn add_status<F>(
+        &mut self,
+        entity: &dyn Entity,
+        mask: DdsStatus,
+        mut on_status: F,
+    ) -> Result<WaitsetToken, DDSError>
+    where
+        F: FnMut(DdsStatus) + 'static,
+    {
+        let condition = DdsStatusCondition::create(entity, mask)?;
+        let token = self.dispatcher.attach(&condition, move |_entity| {
+            if let Ok(status) = condition.take_status() {
+                on_status(status);
+            }
+        })?;
+        Ok(token)
+    }
+
+    /// Remove a reader or status callback. This drops the reader if it
+    /// was handed over with [`WaitsetExecutor::add_reader`].
+    pub fn remove(&mut self, token: WaitsetToken) -> Result<(), DDSError> {
+        self.dispatcher.detach(token)
+    }
+
+    /// Wait for at most `timeout` and run the callbacks of the triggered
+    /// readers and conditions. Returns the number of callbacks that were run.
+    pub fn spin_once(&mut self, timeout: Duration) -> Result<usize, DDSError> {
+        self.dispatcher.dispatch(timeout)
+    }
+
+    /// Keep running callbacks until the deadline is reached.
+    pub fn spin_until(&mut self, deadline: Instant) -> Result<(), DDSError> {
+        while Instant::now() < deadline {
+            self.dispatcher.dispatch_until(deadline)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsPublisher, DdsSubscriber, DdsWriter};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize, Topic, Debug, PartialEq, Default)]
+    struct ExecutorTopic {
+        #[topic_key]
+        id: u32,
+        value: String,
+    }
+
+    #[test]
+    fn test_executor_dispatches_samples() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = ExecutorTopic::create_topic(&participant, Some("executor"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+
+        let mut executor = WaitsetExecutor::create(&participant).unwrap();
+        executor
+            .add_reader(reader, 4, move |sample: &ExecutorTopic| {
+                sink.borrow_mut().push(sample.id)
+            })
+            .unwrap();
+
+        for id in 0..3 {
+            writer
+                .write(Arc::new(ExecutorTopic {
+                    id,
+                    value: "hello".to_owned(),
+                }))
+                .unwrap();
+        }
+
+        executor
+            .spin_until(Instant::now() + Duration::from_millis(200))
+            .unwrap();
+        let mut received = received.borrow().clone();
+        received.sort_unstable();
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,266 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! The domains, participants and bridges of a gateway process in one place. A
+//! [`Gateway`] creates a domain and one participant per domain, hands out a
+//! [`DomainFactory`] per domain to create topics, readers and writers, and owns
+//! the [`DomainBridge`]s between the domains.
+//!
+//! Topics are created once per domain and name, so every reader and writer of a
+//! type in a domain uses the same sertype. Cyclone registers a sertype with a
+//! single domain, samples that cross domains through a bridge are moved to the
+//! sertype of the destination.
+//!
+//! Shutting down, or dropping the gateway, deletes the bridges first, then the
+//! topics and participants and finally the domains. Readers and writers handed
+//! out by the factories are deleted with their participant.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example<T: TopicType + 'static>() {
+//! let mut gateway = Gateway::new();
+//! gateway.add_domain(&CycloneConfig::new().with_domain_id(0), None).unwrap();
+//! gateway.add_domain(&CycloneConfig::new().with_domain_id(1), None).unwrap();
+//! gateway
+//!     .bridge(0, 1, DomainBridgeBuilder::new().with_topic(BridgedTopic::new("status", "robot::Status")))
+//!     .unwrap();
+//! let writer = gateway.factory(1).unwrap().writer::<T>("events", None).unwrap();
+//! // ...
+//! gateway.shutdown().unwrap();
+//! # }
+//! ```
+
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use cyclonedds_sys::DdsDomainId;
+
+use crate::error::DDSError;
+use crate::serdes::TopicType;
+use crate::{
+    CycloneConfig, DdsParticipant, DdsQos, DdsReader, DdsTopic, DdsWriter, DomainBridge,
+    DomainBridgeBuilder, DomainManager,
+};
+
+type TopicCache = HashMap<(DdsDomainId, String), Box<dyn Any>>;
+
+/// Participants in several domains and the bridges between them
+#[derive(Default)]
+pub struct Gateway {
+    bridges: Vec<DomainBridge>,
+    topics: Mutex<TopicCache>,
+    participants: BTreeMap<DdsDomainId, DdsParticipant>,
+    domains: DomainManager,
+}
+
+impl Gateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the domain of the configuration and a participant in it. The
+    /// domain id of the configuration must be set.
+    pub fn add_domain(
+        &mut self,
+        config: &CycloneConfig,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<DomainFactory<'_>, DDSError> {
+        let domain = self.domains.add_domain(config)?;
+        let participant = domain.create_participant(maybe_qos, None)?;
+        let id = domain.id();
+        self.participants.insert(id, participant);
+        Ok(self.factory(id).expect("participant was just added"))
+    }
+
+    /// The factory of a domain added to the gateway
+    pub fn factory(&self, id: DdsDomainId) -> Option<DomainFactory<'_>> {
+        self.participants.get(&id).map(|participant| DomainFactory {
+            gateway: self,
+            id,
+            participant,
+        })
+    }
+
+    pub fn participant(&self, id: DdsDomainId) -> Option<&DdsParticipant> {
+        self.participants.get(&id)
+    }
+
+    /// The ids of the domains, in ascending order
+    pub fn domain_ids(&self) -> Vec<DdsDomainId> {
+        self.participants.keys().copied().collect()
+    }
+
+    /// Forward the topics of `builder` from one domain of the gateway to another
+    pub fn bridge(
+        &mut self,
+        from: DdsDomainId,
+        to: DdsDomainId,
+        builder: DomainBridgeBuilder,
+    ) -> Result<&DomainBridge, DDSError> {
+        let from = self.participants.get(&from).ok_or(DDSError::BadParameter)?;
+        let to = self.participants.get(&to).ok_or(DDSError::BadParameter)?;
+        let bridge = builder.create(from, to)?;
+        self.bridges.push(bridge);
+        Ok(self.bridges.last().expect("bridge was just added"))
+    }
+
+    pub fn bridges(&self) -> &[DomainBridge] {
+        &self.bridges
+    }
+
+    /// Delete the bridges, the participants and the domains, in that order.
+    /// Everything is deleted even if some deletions fail; the first failure is
+    /// returned.
+    pub fn shutdown(&mut self) -> Result<(), DDSError> {
+        // routes hold readers and writers of the participants
+        self.bridges.clear();
+        self.topics.lock().unwrap().clear();
+        let mut result = Ok(());
+        for (_, mut participant) in std::mem::take(&mut self.participants) {
+            let ret = participant.close();
+            if result.is_ok() && ret.is_err() {
+                result = ret;
+            }
+        }
+        let ret = self.domains.shutdown();
+        if result.is_ok() {
+            result = ret;
+        }
+        result
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        if let Err(err) = self.shutdown() {
+            crate::dds_log::drop_failed("gateway", &err);
+        }
+    }
+}
+
+/// Creates topics, readers and writers in one domain of a [`Gateway`]
+#[derive(Clone, Copy)]
+pub struct DomainFactory<'g> {
+    gateway: &'g Gateway,
+    id: DdsDomainId,
+    participant: &'g DdsParticipant,
+}
+
+impl<'g> DomainFactory<'g> {
+    pub fn domain_id(&self) -> DdsDomainId {
+        self.id
+    }
+
+    pub fn participant(&self) -> &'g DdsParticipant {
+        self.participant
+    }
+
+    /// The topic with the given name in this domain, created on first use.
+    /// Returns `DDSError::BadParameter` if the topic was created with another type.
+    pub fn topic<T: TopicType + 'static>(&self, name: &str) -> Result<DdsTopic<T>, DDSError> {
+        let mut topics = self.gateway.topics.lock().unwrap();
+        let key = (self.id, name.to_owned());
+        if let Some(topic) = topics.get(&key) {
+            return topic
+                .downcast_ref::<DdsTopic<T>>()
+                .cloned()
+                .ok_or(DDSError::BadParameter);
+        }
+        let topic = DdsTopic::<T>::create(self.participant, name, None, None)?;
+        topics.insert(key, Box::new(topic.clone()));
+        Ok(topic)
+    }
+
+    pub fn reader<T: TopicType + 'static>(
+        &self,
+        topic_name: &str,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<DdsReader<T>, DDSError> {
+        DdsReader::create(self.participant, self.topic(topic_name)?, maybe_qos, None)
+    }
+
+    pub fn writer<T: TopicType + 'static>(
+        &self,
+        topic_name: &str,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<DdsWriter<T>, DDSError> {
+        DdsWriter::create(self.participant, self.topic(topic_name)?, maybe_qos, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{wait_for_sample, LoopbackDomain};
+    use crate::{BridgedTopic, Entity};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Status {
+        level: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Other {
+        value: u32,
+    }
+
+    fn writer_topic(writer: &DdsWriter<Status>) -> cyclonedds_sys::dds_entity_t {
+        unsafe { cyclonedds_sys::dds_get_topic(writer.entity().entity()) }
+    }
+
+    #[test]
+    fn test_gateway() {
+        let a = LoopbackDomain::create().unwrap();
+        let b = LoopbackDomain::create().unwrap();
+        let (a_id, b_id) = (a.domain_id(), b.domain_id());
+        // the gateway creates its own domains with the same configuration
+        drop((a, b));
+
+        let mut gateway = Gateway::new();
+        gateway.add_domain(&LoopbackDomain::config(a_id), None).unwrap();
+        gateway.add_domain(&LoopbackDomain::config(b_id), None).unwrap();
+        assert_eq!(gateway.domain_ids().len(), 2);
+
+        let type_name = Status::typename().to_str().unwrap().to_owned();
+        gateway
+            .bridge(a_id, b_id, DomainBridgeBuilder::new().with_topic(BridgedTopic::new("status", &type_name)))
+            .unwrap();
+
+        let from = gateway.factory(a_id).unwrap();
+        let to = gateway.factory(b_id).unwrap();
+        let mut writer = from.writer::<Status>("status", None).unwrap();
+        let reader = to.reader::<Status>("status", None).unwrap();
+        // one topic per domain and name
+        let topic = from.topic::<Status>("status").unwrap();
+        assert!(unsafe { topic.entity().entity() == writer_topic(&writer) });
+        assert_eq!(from.topic::<Other>("status").err(), Some(DDSError::BadParameter));
+        std::thread::sleep(Duration::from_millis(200));
+
+        writer.write(Arc::new(Status { level: 3 })).unwrap();
+        assert_eq!(
+            wait_for_sample(&reader, Duration::from_secs(2), |_| true),
+            Some(Status { level: 3 })
+        );
+
+        assert!(gateway.shutdown().is_ok());
+        assert!(gateway.domain_ids().is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! A model of everything discovered in a domain, in the spirit of `ddsls`. The
+//! [`DomainGraph`] follows the builtin topics and can be queried at any time
+//! for participants, topics, readers and writers and which of them match.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! let participant = DdsParticipant::create(None, None, None).unwrap();
+//! let graph = DomainGraph::create(&participant).unwrap();
+//! std::thread::sleep(std::time::Duration::from_secs(1));
+//! for topic in graph.topics() {
+//!     println!("{}: {} writers, {} readers", topic.name, topic.writers.len(), topic.readers.len());
+//! }
+//! for endpoint in graph.unmatched() {
+//!     println!("{:?} {} on {} has no match", endpoint.kind, endpoint.key, endpoint.topic_name);
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::dds_builtin::{
+    DiscoveryCallbacks, DiscoveryReaders, EndpointBuiltinTopicData, EndpointKind,
+    ParticipantBuiltinTopicData,
+};
+use crate::dds_qos::{incompatible_qos_policies, QosPolicies};
+use crate::error::DDSError;
+use crate::{DdsParticipant, Entity, Guid};
+
+/// A discovered participant
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticipantInfo {
+    pub key: Guid,
+    pub qos: Option<QosPolicies>,
+}
+
+/// A discovered reader or writer
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointInfo {
+    pub key: Guid,
+    pub kind: EndpointKind,
+    pub participant_key: Guid,
+    pub topic_name: String,
+    pub type_name: String,
+    pub qos: Option<QosPolicies>,
+}
+
+impl EndpointInfo {
+    // the default partition is the empty string
+    fn partitions(&self) -> Vec<String> {
+        match self.qos.as_ref().and_then(|q| q.partitions.clone()) {
+            Some(partitions) if !partitions.is_empty() => partitions,
+            _ => vec![String::new()],
+        }
+    }
+
+    /// Whether a reader and a writer would communicate. Compares the topic,
+    /// the type and the partitions (wildcards are not expanded), and checks
+    /// that the QoS the writer offers satisfy the QoS the reader requests:
+    /// reliability, durability, ownership and the other requested/offered
+    /// policies, see [`incompatible_qos_policies`].
+    pub fn matches(&self, other: &EndpointInfo) -> bool {
+        if self.kind == other.kind
+            || self.topic_name != other.topic_name
+            || self.type_name != other.type_name
+        {
+            return false;
+        }
+        let theirs = other.partitions();
+        if !self.partitions().iter().any(|p| theirs.contains(p)) {
+            return false;
+        }
+        let (writer, reader) = match self.kind {
+            EndpointKind::Writer => (self, other),
+            EndpointKind::Reader => (other, self),
+        };
+        let policies = |e: &EndpointInfo| e.qos.clone().unwrap_or_default();
+        incompatible_qos_policies(&policies(writer), &policies(reader)).is_empty()
+    }
+}
+
+/// A topic as seen from the discovered readers and writers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TopicInfo {
+    pub name: String,
+    /// More than one type name means the topic is used inconsistently
+    pub type_names: Vec<String>,
+    pub writers: Vec<Guid>,
+    pub readers: Vec<Guid>,
+}
+
+#[derive(Default)]
+struct GraphState {
+    participants: BTreeMap<Guid, ParticipantInfo>,
+    endpoints: BTreeMap<Guid, EndpointInfo>,
+}
+
+/// Continuously updated model of the domain of a participant
+pub struct DomainGraph {
+    state: Arc<Mutex<GraphState>>,
+    _readers: DiscoveryReaders,
+}
+
+impl DomainGraph {
+    pub fn create(participant: &DdsParticipant) -> Result<Self, DDSError> {
+        let state = Arc::new(Mutex::new(GraphState::default()));
+
+        let discovered_participant = state.clone();
+        let lost_participant = state.clone();
+        let discovered_endpoint = state.clone();
+        let lost_endpoint = state.clone();
+        let callbacks = DiscoveryCallbacks {
+            on_participant_discovered: Some(Box::new(move |data: &ParticipantBuiltinTopicData| {
+                let info = ParticipantInfo {
+                    key: data.key,
+                    qos: data.qos.as_ref().map(|q| q.policies()),
+                };
+                discovered_participant
+                    .lock()
+                    .unwrap()
+                    .participants
+                    .insert(data.key, info);
+            })),
+            on_participant_lost: Some(Box::new(move |key: &Guid| {
+                let mut state = lost_participant.lock().unwrap();
+                state.participants.remove(key);
+                // the endpoints of the participant are gone with it
+                state.endpoints.retain(|_, e| e.participant_key != *key);
+            })),
+            on_endpoint_discovered: Some(Box::new(move |kind: EndpointKind, data: &EndpointBuiltinTopicData| {
+                let info = EndpointInfo {
+                    key: data.key,
+                    kind,
+                    participant_key: data.participant_key,
+                    topic_name: data.topic_name.clone(),
+                    type_name: data.type_name.clone(),
+                    qos: data.policies(),
+                };
+                discovered_endpoint
+                    .lock()
+                    .unwrap()
+                    .endpoints
+                    .insert(data.key, info);
+            })),
+            on_endpoint_lost: Some(Box::new(move |_kind: EndpointKind, key: &Guid| {
+                lost_endpoint.lock().unwrap().endpoints.remove(key);
+            })),
+        };
+
+        let readers = DiscoveryReaders::create(Entity::entity(participant), callbacks)?;
+        Ok(Self {
+            state,
+            _readers: readers,
+        })
+    }
+
+    pub fn participants(&self) -> Vec<ParticipantInfo> {
+        self.state.lock().unwrap().participants.values().cloned().collect()
+    }
+
+    pub fn participant(&self, key: &Guid) -> Option<ParticipantInfo> {
+        self.state.lock().unwrap().participants.get(key).cloned()
+    }
+
+    /// All readers and writers
+    pub fn endpoints(&self) -> Vec<EndpointInfo> {
+        self.state.lock().unwrap().endpoints.values().cloned().collect()
+    }
+
+    pub fn endpoint(&self, key: &Guid) -> Option<EndpointInfo> {
+        self.state.lock().unwrap().endpoints.get(key).cloned()
+    }
+
+    pub fn writers(&self) -> Vec<EndpointInfo> {
+        self.endpoints_where(|e| e.kind == EndpointKind::Writer)
+    }
+
+    pub fn readers(&self) -> Vec<EndpointInfo> {
+        self.endpoints_where(|e| e.kind == EndpointKind::Reader)
+    }
+
+    /// The readers and writers of a participant
+    pub fn endpoints_of(&self, participant: &Guid) -> Vec<EndpointInfo> {
+        self.endpoints_where(|e| e.participant_key == *participant)
+    }
+
+    fn endpoints_where<F: Fn(&EndpointInfo) -> bool>(&self, f: F) -> Vec<EndpointInfo> {
+        self.state
+            .lock()
+            .unwrap()
+            .endpoints
+            .values()
+            .filter(|e| f(e))
+            .cloned()
+            .collect()
+    }
+
+    /// The topics in use, sorted by name
+    pub fn topics(&self) -> Vec<TopicInfo> {
+        let state = self.state.lock().unwrap();
+        let mut topics: BTreeMap<&str, TopicInfo> = BTreeMap::new();
+        for endpoint in state.endpoints.values() {
+            let topic = topics
+                .entry(&endpoint.topic_name)
+                .or_insert_with(|| TopicInfo {
+                    name: endpoint.topic_name.clone(),
+                    ..Default::default()
+                });
+            if !topic.type_names.contains(&endpoint.type_name) {
+                topic.type_names.push(endpoint.type_name.clone());
+            }
+            match endpoint.kind {
+                EndpointKind::Writer => topic.writers.push(endpoint.key),
+                EndpointKind::Reader => topic.readers.push(endpoint.key),
+            }
+        }
+        topics.into_iter().map(|(_, t)| t).collect()
+    }
+
+    /// The endpoints that match the endpoint with the given key. See
+    /// [`EndpointInfo::matches`].
+    pub fn matches(&self, key: &Guid) -> Vec<Guid> {
+        let state = self.state.lock().unwrap();
+        match state.endpoints.get(key) {
+            Some(endpoint) => state
+                .endpoints
+                .values()
+                .filter(|other| endpoint.matches(other))
+                .map(|other| other.key)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Readers without any matching writer and writers without any matching reader
+    pub fn unmatched(&self) -> Vec<EndpointInfo> {
+        let state = self.state.lock().unwrap();
+        state
+            .endpoints
+            .values()
+            .filter(|e| !state.endpoints.values().any(|other| e.matches(other)))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsReader, DdsWriter, TopicType};
+    use cyclonedds_sys::{dds_ownership_kind, dds_reliability_kind};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Topic, Debug, PartialEq, Default)]
+    struct GraphTopic {
+        #[topic_key]
+        id: u32,
+    }
+
+    fn endpoint(kind: EndpointKind, topic: &str, partitions: Option<Vec<&str>>) -> EndpointInfo {
+        EndpointInfo {
+            key: Guid::default(),
+            kind,
+            participant_key: Guid::default(),
+            topic_name: topic.to_owned(),
+            type_name: "T".to_owned(),
+            qos: partitions.map(|p| QosPolicies {
+                partitions: Some(p.iter().map(|s| s.to_string()).collect()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_endpoint_matches() {
+        let writer = endpoint(EndpointKind::Writer, "a", None);
+        assert!(writer.matches(&endpoint(EndpointKind::Reader, "a", None)));
+        assert!(writer.matches(&endpoint(EndpointKind::Reader, "a", Some(vec![""]))));
+        assert!(!writer.matches(&endpoint(EndpointKind::Reader, "a", Some(vec!["p"]))));
+        assert!(!writer.matches(&endpoint(EndpointKind::Reader, "b", None)));
+        assert!(!writer.matches(&endpoint(EndpointKind::Writer, "a", None)));
+
+        // a reliable reader does not match a best effort writer
+        let mut best_effort = endpoint(EndpointKind::Writer, "a", None);
+        best_effort.qos = Some(QosPolicies {
+            reliability: Some((dds_reliability_kind::DDS_RELIABILITY_BEST_EFFORT, 0)),
+            ..Default::default()
+        });
+        let mut reliable = endpoint(EndpointKind::Reader, "a", None);
+        reliable.qos = Some(QosPolicies {
+            reliability: Some((dds_reliability_kind::DDS_RELIABILITY_RELIABLE, 0)),
+            ..Default::default()
+        });
+        assert!(!best_effort.matches(&reliable));
+        assert!(!reliable.matches(&best_effort));
+        assert!(writer.matches(&reliable));
+
+        let mut exclusive = endpoint(EndpointKind::Reader, "a", None);
+        exclusive.qos = Some(QosPolicies {
+            ownership: Some(dds_ownership_kind::DDS_OWNERSHIP_EXCLUSIVE),
+            ..Default::default()
+        });
+        assert!(!writer.matches(&exclusive));
+    }
+
+    #[test]
+    fn test_graph() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let graph = DomainGraph::create(&participant).unwrap();
+
+        let topic = GraphTopic::create_topic(&participant, Some("graph"), None, None).unwrap();
+        let writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        let me = participant.guid().unwrap();
+        assert!(graph.participant(&me).is_some());
+
+        let name = GraphTopic::topic_name(Some("graph"));
+        let topic = graph
+            .topics()
+            .into_iter()
+            .find(|t| t.name == name)
+            .expect("topic not found");
+        let writer_guid = writer.guid().unwrap();
+        let reader_guid = reader.guid().unwrap();
+        assert_eq!(topic.writers, vec![writer_guid]);
+        assert_eq!(topic.readers, vec![reader_guid]);
+        assert_eq!(graph.matches(&writer_guid), vec![reader_guid]);
+        assert!(graph.endpoints_of(&me).len() >= 2);
+    }
+}
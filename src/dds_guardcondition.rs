@@ -0,0 +1,96 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+use crate::common::{EntityWrapper, Uses};
+use crate::{DdsParticipant, Entity};
+pub use cyclonedds_sys::DdsEntity;
+pub use crate::error::DDSError;
+
+/// A guard condition is a condition whose trigger value is set by the application.
+/// Attach it to a [`DdsWaitset`](crate::DdsWaitset) to wake up a thread blocked in
+/// `wait()` from any other thread.
+pub struct DdsGuardCondition(EntityWrapper);
+
+impl DdsGuardCondition {
+    pub fn create(participant: &DdsParticipant) -> Result<Self, DDSError> {
+        Entity::check_valid(participant)?;
+        unsafe {
+            let p = cyclonedds_sys::dds_create_guardcondition(participant.entity().entity());
+            if p > 0 {
+                Ok(DdsGuardCondition(EntityWrapper::new(
+                    "guard condition",
+                    DdsEntity::new(p),
+                    Some(participant.keep_alive()),
+                    None,
+                    Uses::Nothing,
+                )))
+            } else {
+                Err(DDSError::from_retcode("dds_create_guardcondition", p).with_entity_kind("guard condition"))
+            }
+        }
+    }
+
+    /// Set the trigger value. Setting it to true wakes up waitsets this condition is attached to.
+    pub fn set(&self, triggered: bool) -> Result<(), DDSError> {
+        self.0.check_valid()?;
+        unsafe {
+            let p = cyclonedds_sys::dds_set_guardcondition(self.0.entity().entity(), triggered);
+            if p == 0 {
+                Ok(())
+            } else {
+                Err(DDSError::from_retcode("dds_set_guardcondition", p).with_entity_kind("guard condition"))
+            }
+        }
+    }
+
+    /// Read the trigger value
+    pub fn read(&self) -> Result<bool, DDSError> {
+        self.0.check_valid()?;
+        let mut triggered = false;
+        unsafe {
+            let p = cyclonedds_sys::dds_read_guardcondition(self.0.entity().entity(), &mut triggered);
+            if p == 0 {
+                Ok(triggered)
+            } else {
+                Err(DDSError::from_retcode("dds_read_guardcondition", p).with_entity_kind("guard condition"))
+            }
+        }
+    }
+
+    /// Read the trigger value and reset it to false
+    pub fn take(&self) -> Result<bool, DDSError> {
+        self.0.check_valid()?;
+        let mut triggered = false;
+        unsafe {
+            let p = cyclonedds_sys::dds_take_guardcondition(self.0.entity().entity(), &mut triggered);
+            if p == 0 {
+                Ok(triggered)
+            } else {
+                Err(DDSError::from_retcode("dds_take_guardcondition", p).with_entity_kind("guard condition"))
+            }
+        }
+    }
+}
+
+impl Entity for DdsGuardCondition {
+    fn entity(&self) -> &DdsEntity {
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
+    }
+}
@@ -0,0 +1,1293 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Topics of types generated by the C idlc compiler. An [`Idlc<T>`] holds a
+//! sample of the C struct `T` and serializes it as the topic descriptor that
+//! idlc generated with it describes, so it can be used with [`DdsTopic`],
+//! [`DdsReader`](crate::DdsReader), [`DdsWriter`](crate::DdsWriter) and their
+//! builders like a serde type. The samples interoperate with C applications
+//! built from the same IDL.
+//!
+//! Final structs of primitives, enums, strings, bounded strings, sequences,
+//! arrays and nested structs are supported. Unions, optional members and
+//! appendable or mutable types are not, [`Idlc::create_topic`] fails for them.
+//! # Example
+//! ```ignore
+//! // bindgen output of the C code that idlc generated for HelloWorldData.idl
+//! use helloworld_data::{HelloWorldData_Msg, HelloWorldData_Msg_desc};
+//! cyclonedds_rs::idlc_type!(HelloWorldData_Msg, HelloWorldData_Msg_desc);
+//!
+//! let topic = Idlc::<HelloWorldData_Msg>::create_topic(&participant, "HelloWorldData_Msg", None, None)?;
+//! let mut writer = DdsWriter::create(&participant, topic, None, None)?;
+//! let mut msg = Idlc::<HelloWorldData_Msg>::zeroed();
+//! msg.set(|m| &m.userID, 1)?;
+//! msg.set_string(|m| &m.message, "Hello World")?;
+//! writer.write(Arc::new(msg))?;
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::ops::Deref;
+use std::os::raw::{c_char, c_void};
+use std::sync::Mutex;
+
+use cyclonedds_sys::{dds_alloc, dds_free};
+use serde::de::{self, DeserializeSeed, SeqAccess, Visitor};
+use serde::ser::{self, SerializeSeq, SerializeTuple};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+pub use cyclonedds_sys::dds_topic_descriptor_t;
+
+use crate::error::DDSError;
+use crate::serdes::TopicType;
+use crate::{DdsListener, DdsParticipant, DdsQos, DdsTopic};
+
+/// A C struct generated by idlc together with its topic descriptor. Use
+/// [`idlc_type!`](crate::idlc_type) to implement it for the bindgen output.
+///
+/// # Safety
+/// The descriptor must describe the layout of `Self`, and a `Self` with all
+/// bytes zero must be a valid sample.
+pub unsafe trait IdlcType: Sized + 'static {
+    fn descriptor() -> &'static dds_topic_descriptor_t;
+}
+
+/// Implement [`IdlcType`] for a C struct and the static descriptor that idlc
+/// generated for it
+#[macro_export]
+macro_rules! idlc_type {
+    ($ty:ty, $descriptor:path) => {
+        unsafe impl $crate::dds_idlc::IdlcType for $ty {
+            fn descriptor() -> &'static $crate::dds_idlc::dds_topic_descriptor_t {
+                unsafe { &$descriptor }
+            }
+        }
+    };
+}
+
+/// A descriptor with instructions that are not supported, or that are malformed
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("idlc type {type_name}: {problem}")]
+pub struct IdlcTypeError {
+    type_name: String,
+    problem: String,
+}
+
+impl IdlcTypeError {
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+}
+
+impl From<IdlcTypeError> for DDSError {
+    fn from(e: IdlcTypeError) -> Self {
+        DDSError::Unsupported.with_source(e)
+    }
+}
+
+// The serializer instructions of cyclone 0.10, see dds_opcodes.h
+const OP_RTS: u32 = 0x00;
+const OP_ADR: u32 = 0x01;
+const OP_KOF: u32 = 0x07;
+
+const VAL_1BY: u32 = 0x01;
+const VAL_2BY: u32 = 0x02;
+const VAL_4BY: u32 = 0x03;
+const VAL_8BY: u32 = 0x04;
+const VAL_STR: u32 = 0x05;
+const VAL_BST: u32 = 0x06;
+const VAL_SEQ: u32 = 0x07;
+const VAL_ARR: u32 = 0x08;
+const VAL_UNI: u32 = 0x09;
+const VAL_STU: u32 = 0x0a;
+const VAL_BSQ: u32 = 0x0b;
+const VAL_ENU: u32 = 0x0c;
+const VAL_EXT: u32 = 0x0d;
+const VAL_BLN: u32 = 0x0e;
+
+const FLAG_KEY: u32 = 0x01;
+const FLAG_FP: u32 = 0x02;
+const FLAG_SGN: u32 = 0x04;
+const FLAG_OPT: u32 = 0x20;
+const FLAG_EXT: u32 = 0x40;
+
+// nested types deeper than this are taken as a malformed descriptor
+const MAX_DEPTH: usize = 32;
+
+// The layout of the sequences generated by idlc
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawSequence {
+    maximum: u32,
+    length: u32,
+    buffer: *mut u8,
+    release: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum IdlcKind {
+    Primitive { size: usize, signed: bool, float: bool },
+    Bool,
+    Enum(u32),
+    String,
+    // the size of the char array, including the terminating NUL
+    BoundedString(usize),
+    Sequence { elem: Box<IdlcKind>, elem_size: usize, bound: Option<usize> },
+    Array { elem: Box<IdlcKind>, elem_size: usize, len: usize },
+    Struct(Vec<IdlcMember>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct IdlcMember {
+    offset: usize,
+    kind: IdlcKind,
+    // the index of the ADR instruction, keys refer to members by it
+    op_index: usize,
+}
+
+struct Layout {
+    members: Vec<IdlcMember>,
+    // members of the key, in the order of the keylist
+    keys: Vec<usize>,
+    force_md5: bool,
+}
+
+struct IdlcInfo {
+    typename: CString,
+    topic_name: String,
+    layout: Result<Layout, IdlcTypeError>,
+}
+
+// The instructions of a descriptor, parsed once per descriptor
+static INFOS: Mutex<Option<HashMap<usize, &'static IdlcInfo>>> = Mutex::new(None);
+
+fn info_of<T: IdlcType>() -> &'static IdlcInfo {
+    let descriptor = T::descriptor();
+    let mut infos = INFOS.lock().unwrap();
+    infos
+        .get_or_insert_with(HashMap::new)
+        .entry(descriptor as *const dds_topic_descriptor_t as usize)
+        .or_insert_with(|| Box::leak(Box::new(unsafe { IdlcInfo::new(descriptor) })))
+}
+
+fn layout_of<T: IdlcType>() -> Result<&'static Layout, &'static IdlcTypeError> {
+    info_of::<T>().layout.as_ref()
+}
+
+impl IdlcInfo {
+    unsafe fn new(descriptor: &dds_topic_descriptor_t) -> Self {
+        let typename = if descriptor.m_typename.is_null() {
+            CString::default()
+        } else {
+            CStr::from_ptr(descriptor.m_typename).to_owned()
+        };
+        // "module::Type" is found as "/module/Type", like the Rust types
+        let topic_name = typename
+            .to_string_lossy()
+            .split("::")
+            .fold(String::new(), |mut name, part| {
+                name.push('/');
+                name.push_str(part);
+                name
+            });
+        let layout = Parser {
+            ops: descriptor.m_ops,
+            type_name: &typename.to_string_lossy(),
+        }
+        .layout(descriptor);
+        Self {
+            typename,
+            topic_name,
+            layout,
+        }
+    }
+}
+
+// Reads the instructions of a descriptor. The instructions have no length,
+// each type ends with an RTS, so the descriptor must come from idlc.
+struct Parser<'a> {
+    ops: *const u32,
+    type_name: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, problem: String) -> IdlcTypeError {
+        IdlcTypeError {
+            type_name: self.type_name.to_owned(),
+            problem,
+        }
+    }
+
+    fn op(&self, index: usize) -> u32 {
+        unsafe { *self.ops.add(index) }
+    }
+
+    // the target of a jump stored in the low 16 bits of the op at `index`,
+    // relative to the instruction at `from`
+    fn jump(&self, from: usize, index: usize) -> Result<usize, IdlcTypeError> {
+        let offset = self.op(index) as u16 as i16 as isize;
+        let target = from as isize + offset;
+        if offset == 0 || target < 0 {
+            return Err(self.error(format!("bad jump at instruction {}", index)));
+        }
+        Ok(target as usize)
+    }
+
+    unsafe fn layout(&self, descriptor: &dds_topic_descriptor_t) -> Result<Layout, IdlcTypeError> {
+        if self.ops.is_null() {
+            return Err(self.error("the descriptor has no instructions".to_owned()));
+        }
+        let members = self.members(0, 0)?;
+        let mut keys = Vec::new();
+        for i in 0..descriptor.m_nkeys as usize {
+            let key = &*descriptor.m_keys.add(i);
+            keys.push(self.key(&members, key.m_offset as usize)?);
+        }
+        let force_md5 = key_max_size(keys.iter().map(|k| &members[*k].kind)).map_or(true, |size| size > 16);
+        Ok(Layout {
+            members,
+            keys,
+            force_md5,
+        })
+    }
+
+    // The member of the ADR that the KOF instruction at `index` refers to
+    fn key(&self, members: &[IdlcMember], index: usize) -> Result<usize, IdlcTypeError> {
+        let kof = self.op(index);
+        if kof >> 24 != OP_KOF {
+            return Err(self.error(format!("no key offset at instruction {}", index)));
+        }
+        if kof & 0xffff != 1 {
+            return Err(self.error("keys in nested structs are not supported".to_owned()));
+        }
+        let adr = self.op(index + 1) as usize;
+        members
+            .iter()
+            .position(|m| m.op_index == adr)
+            .ok_or_else(|| self.error(format!("key refers to instruction {}", adr)))
+    }
+
+    fn members(&self, mut pc: usize, depth: usize) -> Result<Vec<IdlcMember>, IdlcTypeError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("types are nested too deep".to_owned()));
+        }
+        let mut members = Vec::new();
+        loop {
+            let op = self.op(pc);
+            match op >> 24 {
+                OP_RTS => return Ok(members),
+                OP_ADR => {
+                    let (kind, len) = self.adr(pc, depth)?;
+                    members.push(IdlcMember {
+                        offset: self.op(pc + 1) as usize,
+                        kind,
+                        op_index: pc,
+                    });
+                    pc += len;
+                }
+                _ => {
+                    return Err(self.error(format!(
+                        "instruction {:#x} is not supported, only final structs are",
+                        op
+                    )))
+                }
+            }
+        }
+    }
+
+    // The kind of the ADR at `pc` and the number of words of the instruction
+    fn adr(&self, pc: usize, depth: usize) -> Result<(IdlcKind, usize), IdlcTypeError> {
+        let op = self.op(pc);
+        if op & FLAG_OPT != 0 {
+            return Err(self.error("optional members are not supported".to_owned()));
+        }
+        let subtype = (op >> 8) & 0xff;
+        match (op >> 16) & 0xff {
+            VAL_1BY | VAL_2BY | VAL_4BY | VAL_8BY | VAL_BLN => Ok((primitive(op >> 16, op), 2)),
+            VAL_ENU => Ok((IdlcKind::Enum(self.op(pc + 2)), 3)),
+            VAL_STR => Ok((IdlcKind::String, 2)),
+            VAL_BST => Ok((IdlcKind::BoundedString(self.op(pc + 2) as usize), 3)),
+            ty @ (VAL_SEQ | VAL_BSQ) => {
+                let (bound, at) = if ty == VAL_BSQ {
+                    (Some(self.op(pc + 2) as usize), pc + 3)
+                } else {
+                    (None, pc + 2)
+                };
+                let (elem, elem_size, words) = match subtype {
+                    VAL_ENU => (IdlcKind::Enum(self.op(at)), 4, 1),
+                    VAL_BST => {
+                        let bound = self.op(at) as usize;
+                        (IdlcKind::BoundedString(bound), bound, 1)
+                    }
+                    VAL_SEQ | VAL_BSQ | VAL_ARR | VAL_STU => {
+                        let elem = self.element(pc, subtype, at + 1, depth)?;
+                        (elem, self.op(at) as usize, 2)
+                    }
+                    _ => {
+                        let elem = self.simple_element(subtype, op)?;
+                        let size = elem.size();
+                        (elem, size, 0)
+                    }
+                };
+                let elem = Box::new(elem);
+                Ok((IdlcKind::Sequence { elem, elem_size, bound }, at - pc + words))
+            }
+            VAL_ARR => {
+                let len = self.op(pc + 2) as usize;
+                let at = pc + 3;
+                let (elem, elem_size, words) = match subtype {
+                    VAL_ENU => (IdlcKind::Enum(self.op(at)), 4, 1),
+                    VAL_BST => {
+                        let bound = self.op(at + 1) as usize;
+                        (IdlcKind::BoundedString(bound), bound, 2)
+                    }
+                    VAL_SEQ | VAL_BSQ | VAL_ARR | VAL_STU => {
+                        let elem = self.element(pc, subtype, at, depth)?;
+                        (elem, self.op(at + 1) as usize, 2)
+                    }
+                    _ => {
+                        let elem = self.simple_element(subtype, op)?;
+                        let size = elem.size();
+                        (elem, size, 0)
+                    }
+                };
+                let elem = Box::new(elem);
+                Ok((IdlcKind::Array { elem, elem_size, len }, at - pc + words))
+            }
+            VAL_EXT => {
+                if op & FLAG_EXT != 0 {
+                    return Err(self.error("external members are not supported".to_owned()));
+                }
+                let target = self.jump(pc, pc + 2)?;
+                Ok((IdlcKind::Struct(self.members(target, depth + 1)?), 3))
+            }
+            VAL_UNI => Err(self.error("unions are not supported".to_owned())),
+            ty => Err(self.error(format!("member type {:#x} is not supported", ty))),
+        }
+    }
+
+    // The element of a sequence or array of structs, sequences or arrays. The
+    // jump at `index` leads to the instructions of the element.
+    fn element(&self, pc: usize, subtype: u32, index: usize, depth: usize) -> Result<IdlcKind, IdlcTypeError> {
+        let target = self.jump(pc, index)?;
+        if subtype == VAL_STU {
+            Ok(IdlcKind::Struct(self.members(target, depth + 1)?))
+        } else if self.op(target) >> 24 == OP_ADR {
+            Ok(self.adr(target, depth + 1)?.0)
+        } else {
+            Err(self.error(format!("no element type at instruction {}", target)))
+        }
+    }
+
+    fn simple_element(&self, subtype: u32, op: u32) -> Result<IdlcKind, IdlcTypeError> {
+        match subtype {
+            VAL_1BY | VAL_2BY | VAL_4BY | VAL_8BY | VAL_BLN => Ok(primitive(subtype, op)),
+            VAL_STR => Ok(IdlcKind::String),
+            VAL_UNI => Err(self.error("unions are not supported".to_owned())),
+            ty => Err(self.error(format!("element type {:#x} is not supported", ty))),
+        }
+    }
+}
+
+fn primitive(ty: u32, op: u32) -> IdlcKind {
+    let size = match ty & 0xff {
+        VAL_1BY => 1,
+        VAL_2BY => 2,
+        VAL_4BY => 4,
+        VAL_8BY => 8,
+        _ => return IdlcKind::Bool,
+    };
+    IdlcKind::Primitive {
+        size,
+        signed: op & FLAG_SGN != 0,
+        float: op & FLAG_FP != 0,
+    }
+}
+
+fn member_at(members: &[IdlcMember], offset: usize) -> Option<&IdlcKind> {
+    members.iter().find_map(|m| {
+        let inner = offset.checked_sub(m.offset)?;
+        if inner < m.kind.size() {
+            m.kind.at(inner)
+        } else {
+            None
+        }
+    })
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// The primitive members of an idlc sample that [`Idlc::set`] can write.
+/// Enums are written as their `u32` or `i32` value.
+pub trait IdlcValue: Copy + private::Sealed {
+    #[doc(hidden)]
+    const FLOAT: bool;
+    #[doc(hidden)]
+    const BOOL: bool = false;
+}
+
+macro_rules! idlc_value {
+    ($float:expr, $($ty:ty),*) => {
+        $(
+            impl private::Sealed for $ty {}
+            impl IdlcValue for $ty {
+                const FLOAT: bool = $float;
+            }
+        )*
+    };
+}
+
+idlc_value!(false, i8, u8, i16, u16, i32, u32, i64, u64);
+idlc_value!(true, f32, f64);
+
+impl private::Sealed for bool {}
+impl IdlcValue for bool {
+    const FLOAT: bool = false;
+    const BOOL: bool = true;
+}
+
+// Whether a V can be written to a member of this kind
+fn fits<V: IdlcValue>(kind: &IdlcKind) -> bool {
+    match kind {
+        _ if V::BOOL => *kind == IdlcKind::Bool,
+        IdlcKind::Primitive { size, float, .. } => *size == std::mem::size_of::<V>() && *float == V::FLOAT,
+        IdlcKind::Enum(_) => !V::FLOAT && std::mem::size_of::<V>() == 4,
+        _ => false,
+    }
+}
+
+// The largest serialized size of the key, None if it is unbounded
+fn key_max_size<'a>(mut kinds: impl Iterator<Item = &'a IdlcKind>) -> Option<usize> {
+    kinds.try_fold(0, |offset, kind| kind.max_end(offset))
+}
+
+impl IdlcKind {
+    // the size in the C struct
+    fn size(&self) -> usize {
+        match self {
+            IdlcKind::Primitive { size, .. } => *size,
+            IdlcKind::Bool => 1,
+            IdlcKind::Enum(_) => 4,
+            IdlcKind::String => std::mem::size_of::<*mut c_char>(),
+            IdlcKind::BoundedString(bound) => *bound,
+            IdlcKind::Sequence { .. } => std::mem::size_of::<RawSequence>(),
+            IdlcKind::Array { elem_size, len, .. } => elem_size * len,
+            IdlcKind::Struct(members) => members.iter().map(|m| m.offset + m.kind.size()).max().unwrap_or(0),
+        }
+    }
+
+    // The primitive, enum or string at `offset` in a value of this kind
+    fn at(&self, offset: usize) -> Option<&IdlcKind> {
+        match self {
+            IdlcKind::Struct(members) => member_at(members, offset),
+            IdlcKind::Array { elem, elem_size, .. } if *elem_size > 0 => elem.at(offset % elem_size),
+            IdlcKind::Array { .. } | IdlcKind::Sequence { .. } | IdlcKind::BoundedString(_) => None,
+            _ if offset == 0 => Some(self),
+            _ => None,
+        }
+    }
+
+    // The end of the largest value of this kind serialized at `offset`
+    fn max_end(&self, offset: usize) -> Option<usize> {
+        let align = |offset: usize, size: usize| (offset + size - 1) / size * size + size;
+        match self {
+            IdlcKind::Primitive { size, .. } => Some(align(offset, *size)),
+            IdlcKind::Bool => Some(offset + 1),
+            IdlcKind::Enum(_) => Some(align(offset, 4)),
+            IdlcKind::BoundedString(bound) => Some(align(offset, 4) + bound),
+            IdlcKind::String | IdlcKind::Sequence { .. } => None,
+            IdlcKind::Array { elem, len, .. } => (0..*len).try_fold(offset, |offset, _| elem.max_end(offset)),
+            IdlcKind::Struct(members) => members.iter().try_fold(offset, |offset, m| m.kind.max_end(offset)),
+        }
+    }
+}
+
+/// A sample of the idlc generated type `T`. The strings and sequences of the
+/// sample are allocated by cyclone and released with the sample.
+pub struct Idlc<T: IdlcType>(T);
+
+// the sample owns everything it points to
+unsafe impl<T: IdlcType> Send for Idlc<T> {}
+unsafe impl<T: IdlcType> Sync for Idlc<T> {}
+
+impl<T: IdlcType> Idlc<T> {
+    /// A sample with all members zero, strings and sequences are empty
+    pub fn zeroed() -> Self {
+        Self(unsafe { std::mem::zeroed() })
+    }
+
+    /// Take ownership of a C sample.
+    ///
+    /// # Safety
+    /// The strings and sequence buffers of `sample` must be allocated with
+    /// `dds_alloc` and not be owned by anything else.
+    pub unsafe fn from_raw(sample: T) -> Self {
+        Self(sample)
+    }
+
+    /// Check that the descriptor of `T` can be used, the samples of a type
+    /// that is not supported cannot be written or read
+    pub fn check() -> Result<(), DDSError> {
+        layout_of::<T>().map(|_| ()).map_err(|e| e.clone().into())
+    }
+
+    /// Create a topic of `T` after checking its descriptor
+    pub fn create_topic(
+        participant: &DdsParticipant,
+        name: &str,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<DdsTopic<Self>, DDSError> {
+        Self::check()?;
+        DdsTopic::create(participant, name, maybe_qos, maybe_listener)
+    }
+
+    /// The C struct, for members that have no setter.
+    ///
+    /// # Safety
+    /// Strings and sequence buffers written to the sample must be allocated
+    /// with `dds_alloc` and not be owned by anything else, they are released
+    /// with the sample. Sequence lengths must match their buffers.
+    pub unsafe fn as_mut_raw(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    /// Write `value` to the primitive or enum member selected by `field`.
+    /// Members of nested structs and elements of arrays can be selected as
+    /// well. `DDSError::BadParameter` if the selected member is not a
+    /// member of this type of the type of `value`.
+    pub fn set<V, F>(&mut self, field: F, value: V) -> Result<(), DDSError>
+    where
+        V: IdlcValue,
+        F: FnOnce(&T) -> &V,
+    {
+        let offset = self.member_offset(field, fits::<V>)?;
+        unsafe { (&mut self.0 as *mut T as *mut u8).add(offset).cast::<V>().write_unaligned(value) };
+        Ok(())
+    }
+
+    /// Replace the string member selected by `field` with a copy of `value`
+    pub fn set_string<F>(&mut self, field: F, value: &str) -> Result<(), DDSError>
+    where
+        F: FnOnce(&T) -> &*mut c_char,
+    {
+        let offset = self.member_offset(field, |kind| *kind == IdlcKind::String)?;
+        let copy = alloc_string(value).map_err(|_| DDSError::BadParameter)?;
+        unsafe {
+            let member = (&mut self.0 as *mut T as *mut u8).add(offset) as *mut *mut c_char;
+            dds_free(member.read_unaligned() as *mut c_void);
+            member.write_unaligned(copy);
+        }
+        Ok(())
+    }
+
+    // The offset of the member that `field` selects, if it is inside the
+    // sample and the descriptor has a member of a kind that `fits` there
+    fn member_offset<V, F>(&self, field: F, fits: impl Fn(&IdlcKind) -> bool) -> Result<usize, DDSError>
+    where
+        F: FnOnce(&T) -> &V,
+    {
+        let layout = layout_of::<T>().map_err(|e| DDSError::from(e.clone()))?;
+        let base = &self.0 as *const T as usize;
+        let offset = (field(&self.0) as *const V as usize)
+            .checked_sub(base)
+            .filter(|offset| offset + std::mem::size_of::<V>() <= std::mem::size_of::<T>())
+            .ok_or(DDSError::BadParameter)?;
+        match member_at(&layout.members, offset) {
+            Some(kind) if fits(kind) => Ok(offset),
+            _ => Err(DDSError::BadParameter),
+        }
+    }
+
+    /// The string member selected by `field`, None if it is not UTF-8
+    pub fn string<'a, F>(&'a self, field: F) -> Option<&'a str>
+    where
+        F: FnOnce(&'a T) -> &'a *mut c_char,
+    {
+        let member = *field(&self.0);
+        if member.is_null() {
+            Some("")
+        } else {
+            unsafe { CStr::from_ptr(member) }.to_str().ok()
+        }
+    }
+}
+
+impl<T: IdlcType> Deref for Idlc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: IdlcType> Drop for Idlc<T> {
+    fn drop(&mut self) {
+        if let Ok(layout) = layout_of::<T>() {
+            unsafe { free_members(&layout.members, &mut self.0 as *mut T as *mut u8) };
+        }
+    }
+}
+
+impl<T: IdlcType> fmt::Debug for Idlc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Idlc").field(&info_of::<T>().typename).finish()
+    }
+}
+
+impl<T: IdlcType> TopicType for Idlc<T> {
+    fn typename() -> CString {
+        info_of::<T>().typename.clone()
+    }
+
+    fn default_topic_name() -> &'static str {
+        &info_of::<T>().topic_name
+    }
+
+    fn has_key() -> bool {
+        T::descriptor().m_nkeys > 0
+    }
+
+    fn key_cdr(&self) -> Vec<u8> {
+        match layout_of::<T>() {
+            Ok(layout) => {
+                let key = KeyRef {
+                    layout,
+                    ptr: &self.0 as *const T as *const u8,
+                };
+                cdr::serialize::<_, _, cdr::CdrBe>(&key, cdr::Infinite).expect("Unable to serialize key")
+            }
+            // nothing is written or read with this type
+            Err(_) => vec![0, 0, 0, 0],
+        }
+    }
+
+    fn force_md5_keyhash() -> bool {
+        layout_of::<T>().map_or(true, |layout| layout.force_md5)
+    }
+}
+
+impl<T: IdlcType> Serialize for Idlc<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let layout = layout_of::<T>().map_err(<S::Error as ser::Error>::custom)?;
+        let members = MembersRef {
+            members: &layout.members,
+            ptr: &self.0 as *const T as *const u8,
+        };
+        members.serialize(serializer)
+    }
+}
+
+impl<'de, T: IdlcType> Deserialize<'de> for Idlc<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let layout = layout_of::<T>().map_err(<D::Error as de::Error>::custom)?;
+        // released by drop if deserialization fails half way
+        let mut sample = Self::zeroed();
+        let seed = MembersSeed {
+            members: &layout.members,
+            ptr: &mut sample.0 as *mut T as *mut u8,
+        };
+        seed.deserialize(deserializer)?;
+        Ok(sample)
+    }
+}
+
+// A NUL terminated copy of `value` that C can release
+fn alloc_string(value: &str) -> Result<*mut c_char, &'static str> {
+    if value.as_bytes().contains(&0) {
+        return Err("string with a NUL byte");
+    }
+    unsafe {
+        let p = dds_alloc(value.len() + 1) as *mut u8;
+        if p.is_null() {
+            return Err("out of memory");
+        }
+        std::ptr::copy_nonoverlapping(value.as_ptr(), p, value.len());
+        *p.add(value.len()) = 0;
+        Ok(p as *mut c_char)
+    }
+}
+
+unsafe fn free_members(members: &[IdlcMember], ptr: *mut u8) {
+    for member in members {
+        free_value(&member.kind, ptr.add(member.offset));
+    }
+}
+
+// Release what the value at `ptr` points to, the value is left zeroed
+unsafe fn free_value(kind: &IdlcKind, ptr: *mut u8) {
+    match kind {
+        IdlcKind::String => {
+            let p = (ptr as *mut *mut c_char).read_unaligned();
+            dds_free(p as *mut c_void);
+            (ptr as *mut *mut c_char).write_unaligned(std::ptr::null_mut());
+        }
+        IdlcKind::Sequence { elem, elem_size, .. } => {
+            let seq = (ptr as *mut RawSequence).read_unaligned();
+            if !seq.buffer.is_null() {
+                for i in 0..seq.length as usize {
+                    free_value(elem, seq.buffer.add(i * elem_size));
+                }
+                if seq.release {
+                    dds_free(seq.buffer as *mut c_void);
+                }
+            }
+            std::ptr::write_bytes(ptr, 0, std::mem::size_of::<RawSequence>());
+        }
+        IdlcKind::Array { elem, elem_size, len } => {
+            for i in 0..*len {
+                free_value(elem, ptr.add(i * elem_size));
+            }
+        }
+        IdlcKind::Struct(members) => free_members(members, ptr),
+        _ => {}
+    }
+}
+
+// The members of a struct at `ptr`, serialized like a serde struct
+struct MembersRef<'a> {
+    members: &'a [IdlcMember],
+    ptr: *const u8,
+}
+
+impl Serialize for MembersRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(self.members.len())?;
+        for member in self.members {
+            tuple.serialize_element(&ValueRef {
+                kind: &member.kind,
+                ptr: unsafe { self.ptr.add(member.offset) },
+            })?;
+        }
+        tuple.end()
+    }
+}
+
+// The key members of a sample, serialized like the key holder of a derived type
+struct KeyRef<'a> {
+    layout: &'a Layout,
+    ptr: *const u8,
+}
+
+impl Serialize for KeyRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(self.layout.keys.len())?;
+        for key in &self.layout.keys {
+            let member = &self.layout.members[*key];
+            tuple.serialize_element(&ValueRef {
+                kind: &member.kind,
+                ptr: unsafe { self.ptr.add(member.offset) },
+            })?;
+        }
+        tuple.end()
+    }
+}
+
+struct ValueRef<'a> {
+    kind: &'a IdlcKind,
+    ptr: *const u8,
+}
+
+impl Serialize for ValueRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        unsafe {
+            let ptr = self.ptr;
+            match self.kind {
+                IdlcKind::Primitive { size, signed, float } => match (size, signed, float) {
+                    (1, true, _) => serializer.serialize_i8((ptr as *const i8).read_unaligned()),
+                    (1, false, _) => serializer.serialize_u8(ptr.read_unaligned()),
+                    (2, true, _) => serializer.serialize_i16((ptr as *const i16).read_unaligned()),
+                    (2, false, _) => serializer.serialize_u16((ptr as *const u16).read_unaligned()),
+                    (4, _, true) => serializer.serialize_f32((ptr as *const f32).read_unaligned()),
+                    (4, true, _) => serializer.serialize_i32((ptr as *const i32).read_unaligned()),
+                    (4, false, _) => serializer.serialize_u32((ptr as *const u32).read_unaligned()),
+                    (8, _, true) => serializer.serialize_f64((ptr as *const f64).read_unaligned()),
+                    (8, true, _) => serializer.serialize_i64((ptr as *const i64).read_unaligned()),
+                    _ => serializer.serialize_u64((ptr as *const u64).read_unaligned()),
+                },
+                IdlcKind::Bool => serializer.serialize_bool(ptr.read_unaligned() != 0),
+                IdlcKind::Enum(_) => serializer.serialize_u32((ptr as *const u32).read_unaligned()),
+                IdlcKind::String => {
+                    let p = (ptr as *const *const c_char).read_unaligned();
+                    if p.is_null() {
+                        serializer.serialize_str("")
+                    } else {
+                        let s = CStr::from_ptr(p).to_str().map_err(<S::Error as ser::Error>::custom)?;
+                        serializer.serialize_str(s)
+                    }
+                }
+                IdlcKind::BoundedString(bound) => {
+                    let bytes = std::slice::from_raw_parts(ptr, *bound);
+                    let len = bytes.iter().position(|b| *b == 0).unwrap_or(*bound);
+                    let s = std::str::from_utf8(&bytes[..len]).map_err(<S::Error as ser::Error>::custom)?;
+                    serializer.serialize_str(s)
+                }
+                IdlcKind::Sequence { elem, elem_size, bound } => {
+                    let seq = (ptr as *const RawSequence).read_unaligned();
+                    let len = if seq.buffer.is_null() { 0 } else { seq.length as usize };
+                    if bound.map_or(false, |bound| len > bound) {
+                        return Err(ser::Error::custom("sequence longer than its bound"));
+                    }
+                    let mut s = serializer.serialize_seq(Some(len))?;
+                    for i in 0..len {
+                        s.serialize_element(&ValueRef {
+                            kind: elem,
+                            ptr: seq.buffer.add(i * elem_size),
+                        })?;
+                    }
+                    s.end()
+                }
+                IdlcKind::Array { elem, elem_size, len } => {
+                    let mut tuple = serializer.serialize_tuple(*len)?;
+                    for i in 0..*len {
+                        tuple.serialize_element(&ValueRef {
+                            kind: elem,
+                            ptr: ptr.add(i * elem_size),
+                        })?;
+                    }
+                    tuple.end()
+                }
+                IdlcKind::Struct(members) => MembersRef { members, ptr }.serialize(serializer),
+            }
+        }
+    }
+}
+
+// Deserializes the members of a struct into the zeroed memory at `ptr`
+struct MembersSeed<'a> {
+    members: &'a [IdlcMember],
+    ptr: *mut u8,
+}
+
+impl<'de> DeserializeSeed<'de> for MembersSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_tuple(self.members.len(), self)
+    }
+}
+
+impl<'de> Visitor<'de> for MembersSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a struct of {} members", self.members.len())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        for (i, member) in self.members.iter().enumerate() {
+            let seed = ValueSeed {
+                kind: &member.kind,
+                ptr: unsafe { self.ptr.add(member.offset) },
+            };
+            seq.next_element_seed(seed)?
+                .ok_or_else(|| <A::Error as de::Error>::invalid_length(i, &self))?;
+        }
+        Ok(())
+    }
+}
+
+// Deserializes a value into the zeroed memory at `ptr`
+struct ValueSeed<'a> {
+    kind: &'a IdlcKind,
+    ptr: *mut u8,
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        unsafe {
+            let ptr = self.ptr;
+            match self.kind {
+                IdlcKind::Primitive { size, signed, float } => match (size, signed, float) {
+                    (1, true, _) => (ptr as *mut i8).write_unaligned(i8::deserialize(deserializer)?),
+                    (1, false, _) => ptr.write_unaligned(u8::deserialize(deserializer)?),
+                    (2, true, _) => (ptr as *mut i16).write_unaligned(i16::deserialize(deserializer)?),
+                    (2, false, _) => (ptr as *mut u16).write_unaligned(u16::deserialize(deserializer)?),
+                    (4, _, true) => (ptr as *mut f32).write_unaligned(f32::deserialize(deserializer)?),
+                    (4, true, _) => (ptr as *mut i32).write_unaligned(i32::deserialize(deserializer)?),
+                    (4, false, _) => (ptr as *mut u32).write_unaligned(u32::deserialize(deserializer)?),
+                    (8, _, true) => (ptr as *mut f64).write_unaligned(f64::deserialize(deserializer)?),
+                    (8, true, _) => (ptr as *mut i64).write_unaligned(i64::deserialize(deserializer)?),
+                    _ => (ptr as *mut u64).write_unaligned(u64::deserialize(deserializer)?),
+                },
+                IdlcKind::Bool => ptr.write_unaligned(bool::deserialize(deserializer)? as u8),
+                IdlcKind::Enum(max) => {
+                    let value = u32::deserialize(deserializer)?;
+                    if value > *max {
+                        return Err(de::Error::custom(format!("enum value {} is larger than {}", value, max)));
+                    }
+                    (ptr as *mut u32).write_unaligned(value);
+                }
+                IdlcKind::String => {
+                    let s = String::deserialize(deserializer)?;
+                    let p = alloc_string(&s).map_err(<D::Error as de::Error>::custom)?;
+                    (ptr as *mut *mut c_char).write_unaligned(p);
+                }
+                IdlcKind::BoundedString(bound) => {
+                    let s = String::deserialize(deserializer)?;
+                    if s.len() >= *bound || s.as_bytes().contains(&0) {
+                        return Err(de::Error::custom(format!("string does not fit {} bytes", bound)));
+                    }
+                    std::ptr::copy_nonoverlapping(s.as_ptr(), ptr, s.len());
+                    ptr.add(s.len()).write(0);
+                }
+                IdlcKind::Sequence { .. } => deserializer.deserialize_seq(self)?,
+                IdlcKind::Array { len, .. } => deserializer.deserialize_tuple(*len, self)?,
+                IdlcKind::Struct(members) => MembersSeed { members, ptr }.deserialize(deserializer)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a sequence or an array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        match self.kind {
+            IdlcKind::Array { elem, elem_size, len } => {
+                for i in 0..*len {
+                    let seed = ValueSeed {
+                        kind: elem,
+                        ptr: unsafe { self.ptr.add(i * elem_size) },
+                    };
+                    seq.next_element_seed(seed)?
+                        .ok_or_else(|| <A::Error as de::Error>::invalid_length(i, &self))?;
+                }
+                Ok(())
+            }
+            IdlcKind::Sequence { elem, elem_size, bound } => {
+                let len = seq.size_hint().unwrap_or(0);
+                if bound.map_or(false, |bound| len > bound) {
+                    return Err(de::Error::custom("sequence longer than its bound"));
+                }
+                // the elements are collected before the length is trusted with
+                // an allocation
+                let mut buffer: Vec<u8> = Vec::new();
+                for i in 0..len {
+                    buffer.resize((i + 1) * elem_size, 0);
+                    let seed = ValueSeed {
+                        kind: elem,
+                        ptr: unsafe { buffer.as_mut_ptr().add(i * elem_size) },
+                    };
+                    let result = seq
+                        .next_element_seed(seed)
+                        .and_then(|e| e.ok_or_else(|| de::Error::invalid_length(i, &self)));
+                    if let Err(e) = result {
+                        for j in 0..=i {
+                            unsafe { free_value(elem, buffer.as_mut_ptr().add(j * elem_size)) };
+                        }
+                        return Err(e);
+                    }
+                }
+                let mut raw = RawSequence {
+                    maximum: len as u32,
+                    length: len as u32,
+                    buffer: std::ptr::null_mut(),
+                    release: true,
+                };
+                if len > 0 {
+                    unsafe {
+                        raw.buffer = dds_alloc(buffer.len()) as *mut u8;
+                        if raw.buffer.is_null() {
+                            for j in 0..len {
+                                free_value(elem, buffer.as_mut_ptr().add(j * elem_size));
+                            }
+                            return Err(de::Error::custom("out of memory"));
+                        }
+                        std::ptr::copy_nonoverlapping(buffer.as_ptr(), raw.buffer, buffer.len());
+                    }
+                }
+                unsafe { (self.ptr as *mut RawSequence).write_unaligned(raw) };
+                Ok(())
+            }
+            _ => Err(de::Error::invalid_type(de::Unexpected::Seq, &self)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsReader, DdsWriter, SampleBuffer};
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::{Arc, OnceLock};
+    use std::time::Duration;
+
+    const ADR: u32 = OP_ADR << 24;
+    const KOF: u32 = OP_KOF << 24;
+    const fn ty(val: u32) -> u32 {
+        val << 16
+    }
+    const fn sub(val: u32) -> u32 {
+        val << 8
+    }
+
+    // What idlc generates for
+    //   struct Point { double x; double y; };
+    //   enum Color { RED, GREEN, BLUE };
+    //   struct Track { @key long id; string name; char label[8]; Point at;
+    //     sequence<Point> path; sequence<string> tags; unsigned short hist[3];
+    //     boolean live; Color color; };
+    #[repr(C)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    #[repr(C)]
+    struct Track {
+        id: i32,
+        name: *mut c_char,
+        label: [c_char; 8],
+        at: Point,
+        path: RawSequence,
+        tags: RawSequence,
+        hist: [u16; 3],
+        live: bool,
+        color: u32,
+    }
+
+    fn descriptor(typename: &'static CStr, size: usize, ops: Vec<u32>, keys: &[(&'static CStr, u32)]) -> usize {
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|(name, offset)| {
+                let mut key: cyclonedds_sys::dds_key_descriptor_t = unsafe { std::mem::zeroed() };
+                key.m_name = name.as_ptr();
+                key.m_offset = *offset;
+                key
+            })
+            .collect();
+        let mut d: dds_topic_descriptor_t = unsafe { std::mem::zeroed() };
+        d.m_size = size as u32;
+        d.m_align = 8;
+        d.m_nkeys = keys.len() as u32;
+        d.m_typename = typename.as_ptr();
+        d.m_keys = Box::leak(keys.into_boxed_slice()).as_ptr();
+        d.m_nops = ops.iter().filter(|op| *op >> 24 == OP_ADR).count() as u32 + 1;
+        d.m_ops = Box::leak(ops.into_boxed_slice()).as_ptr();
+        Box::leak(Box::new(d)) as *const dds_topic_descriptor_t as usize
+    }
+
+    macro_rules! offset {
+        ($ty:ty, $field:ident) => {{
+            let base = std::mem::MaybeUninit::<$ty>::uninit();
+            let p = base.as_ptr();
+            (unsafe { std::ptr::addr_of!((*p).$field) } as usize - p as usize) as u32
+        }};
+    }
+
+    unsafe impl IdlcType for Track {
+        fn descriptor() -> &'static dds_topic_descriptor_t {
+            static DESCRIPTOR: OnceLock<usize> = OnceLock::new();
+            let d = *DESCRIPTOR.get_or_init(|| {
+                // Point is at 27
+                let ops = vec![
+                    ADR | ty(VAL_4BY) | FLAG_SGN | FLAG_KEY, offset!(Track, id),
+                    ADR | ty(VAL_STR), offset!(Track, name),
+                    ADR | ty(VAL_BST), offset!(Track, label), 8,
+                    ADR | ty(VAL_EXT), offset!(Track, at), (3 << 16) + (27 - 7),
+                    ADR | ty(VAL_SEQ) | sub(VAL_STU), offset!(Track, path), 16, (4 << 16) + (27 - 10),
+                    ADR | ty(VAL_SEQ) | sub(VAL_STR), offset!(Track, tags),
+                    ADR | ty(VAL_ARR) | sub(VAL_2BY), offset!(Track, hist), 3,
+                    ADR | ty(VAL_BLN), offset!(Track, live),
+                    ADR | ty(VAL_ENU), offset!(Track, color), 2,
+                    OP_RTS,
+                    // key: id
+                    KOF | 1, 0,
+                    // Point
+                    ADR | ty(VAL_8BY) | FLAG_FP, offset!(Point, x),
+                    ADR | ty(VAL_8BY) | FLAG_FP, offset!(Point, y),
+                    OP_RTS,
+                ];
+                descriptor(
+                    CStr::from_bytes_with_nul(b"tracking::Track\0").unwrap(),
+                    std::mem::size_of::<Track>(),
+                    ops,
+                    &[(CStr::from_bytes_with_nul(b"id\0").unwrap(), 25)],
+                )
+            });
+            unsafe { &*(d as *const dds_topic_descriptor_t) }
+        }
+    }
+
+    // The same type as serde sees it
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct RustPoint {
+        x: f64,
+        y: f64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct RustTrack {
+        id: i32,
+        name: String,
+        label: String,
+        at: RustPoint,
+        path: Vec<RustPoint>,
+        tags: Vec<String>,
+        hist: [u16; 3],
+        live: bool,
+        color: u32,
+    }
+
+    fn rust_track() -> RustTrack {
+        RustTrack {
+            id: -7,
+            name: "north".to_owned(),
+            label: "N1".to_owned(),
+            at: RustPoint { x: 1.5, y: -2.0 },
+            path: vec![RustPoint { x: 0.0, y: 0.0 }, RustPoint { x: 1.0, y: 0.5 }],
+            tags: vec!["a".to_owned(), String::new(), "ccc".to_owned()],
+            hist: [1, 2, 3],
+            live: true,
+            color: 2,
+        }
+    }
+
+    fn track() -> Idlc<Track> {
+        let rust = rust_track();
+        let cdr = cdr::serialize::<_, _, cdr::CdrLe>(&rust, cdr::Infinite).unwrap();
+        cdr::deserialize::<Idlc<Track>>(&cdr).unwrap()
+    }
+
+    #[test]
+    fn test_idlc_layout() {
+        let layout = layout_of::<Track>().unwrap();
+        assert_eq!(layout.members.len(), 9);
+        assert_eq!(layout.keys, vec![0]);
+        assert!(!layout.force_md5);
+        assert_eq!(layout.members[3].kind.size(), 16);
+        assert_eq!(Idlc::<Track>::typename().to_str().unwrap(), "tracking::Track");
+        assert_eq!(Idlc::<Track>::default_topic_name(), "/tracking/Track");
+        assert!(Idlc::<Track>::has_key());
+    }
+
+    #[test]
+    fn test_idlc_cdr_matches_serde() {
+        let sample = track();
+        assert_eq!(sample.id, -7);
+        assert_eq!(sample.string(|t| &t.name), Some("north"));
+        assert_eq!(sample.path.length, 2);
+        assert_eq!(sample.hist, [1, 2, 3]);
+        assert!(sample.live);
+
+        let rust = rust_track();
+        for (idlc, serde) in [
+            (
+                cdr::serialize::<_, _, cdr::CdrLe>(&sample, cdr::Infinite).unwrap(),
+                cdr::serialize::<_, _, cdr::CdrLe>(&rust, cdr::Infinite).unwrap(),
+            ),
+            (
+                cdr::serialize::<_, _, cdr::CdrBe>(&sample, cdr::Infinite).unwrap(),
+                cdr::serialize::<_, _, cdr::CdrBe>(&rust, cdr::Infinite).unwrap(),
+            ),
+        ] {
+            assert_eq!(idlc, serde);
+            assert_eq!(cdr::deserialize::<RustTrack>(&idlc).unwrap(), rust);
+        }
+        assert_eq!(sample.key_cdr(), cdr::serialize::<_, _, cdr::CdrBe>(&(-7i32,), cdr::Infinite).unwrap());
+    }
+
+    #[test]
+    fn test_idlc_rejects_bad_samples() {
+        let mut rust = rust_track();
+        rust.label = "too long for 8".to_owned();
+        let cdr = cdr::serialize::<_, _, cdr::CdrLe>(&rust, cdr::Infinite).unwrap();
+        assert!(cdr::deserialize::<Idlc<Track>>(&cdr).is_err());
+
+        let mut rust = rust_track();
+        rust.color = 3;
+        let cdr = cdr::serialize::<_, _, cdr::CdrLe>(&rust, cdr::Infinite).unwrap();
+        assert!(cdr::deserialize::<Idlc<Track>>(&cdr).is_err());
+
+        // truncated, the members read so far are released
+        let cdr = cdr::serialize::<_, _, cdr::CdrLe>(&rust_track(), cdr::Infinite).unwrap();
+        assert!(cdr::deserialize::<Idlc<Track>>(&cdr[..cdr.len() - 16]).is_err());
+
+        let mut sample = Idlc::<Track>::zeroed();
+        assert!(sample.set_string(|t| &t.name, "a\0b").is_err());
+        sample.set_string(|t| &t.name, "first").unwrap();
+        sample.set_string(|t| &t.name, "second").unwrap();
+        assert_eq!(sample.string(|t| &t.name), Some("second"));
+    }
+
+    #[test]
+    fn test_idlc_setters() {
+        static OUTSIDE: i32 = 0;
+        let mut sample = Idlc::<Track>::zeroed();
+        sample.set(|t| &t.id, 7).unwrap();
+        sample.set(|t| &t.at.y, 2.5).unwrap();
+        sample.set(|t| &t.hist[2], 9).unwrap();
+        sample.set(|t| &t.live, true).unwrap();
+        sample.set(|t| &t.color, 1).unwrap();
+        assert_eq!((sample.id, sample.at.y, sample.hist, sample.live, sample.color), (7, 2.5, [0, 0, 9], true, 1));
+
+        // not a member, or a member of another type
+        assert_eq!(sample.set(|_| &OUTSIDE, 1), Err(DDSError::BadParameter));
+        assert_eq!(sample.set(|t| &t.path.length, 3), Err(DDSError::BadParameter));
+        assert_eq!(sample.set(|t| &t.label[0], 65), Err(DDSError::BadParameter));
+        assert_eq!(sample.path.length, 0);
+    }
+
+    #[test]
+    fn test_idlc_unsupported() {
+        struct Union;
+        unsafe impl IdlcType for Union {
+            fn descriptor() -> &'static dds_topic_descriptor_t {
+                static DESCRIPTOR: OnceLock<usize> = OnceLock::new();
+                let d = *DESCRIPTOR.get_or_init(|| {
+                    let ops = vec![ADR | ty(VAL_UNI) | sub(VAL_4BY), 0, 2, (4 << 16) + 5, OP_RTS];
+                    descriptor(CStr::from_bytes_with_nul(b"U\0").unwrap(), 16, ops, &[])
+                });
+                unsafe { &*(d as *const dds_topic_descriptor_t) }
+            }
+        }
+        let err = Idlc::<Union>::check().unwrap_err();
+        assert_eq!(err, DDSError::Unsupported);
+        assert!(err.to_string().contains("unions are not supported"));
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        assert!(Idlc::<Union>::create_topic(&participant, "idlc_union", None, None).is_err());
+    }
+
+    #[test]
+    fn test_idlc_reader_writer() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = Idlc::<Track>::create_topic(&participant, "idlc_tracks", None, None).unwrap();
+        assert_eq!(topic.type_name().unwrap(), "tracking::Track");
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        writer.write(Arc::new(track())).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let mut samples = SampleBuffer::new(1);
+        assert_eq!(reader.take_now(&mut samples).unwrap(), 1);
+        let received = samples.get(0).try_deref().unwrap();
+        let cdr = cdr::serialize::<_, _, cdr::CdrLe>(received, cdr::Infinite).unwrap();
+        assert_eq!(cdr::deserialize::<RustTrack>(&cdr).unwrap(), rust_track());
+    }
+}
@@ -0,0 +1,146 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! JSON of samples, for logging and debugging without code for each type.
+//! Needs the `serde_json` feature.
+//!
+//! Typed samples are converted through their `Serialize` implementation, so
+//! the JSON is the same as `serde_json` makes of the type. Samples of a
+//! [`DynamicType`] are objects of their fields, with chars as strings of one
+//! character and arrays and sequences as arrays.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example<T: TopicType>(reader: &DdsReader<T>) {
+//! let mut buf = reader.sample_buffer(16);
+//! let taken = reader.take_now(&mut buf).unwrap();
+//! for i in 0..taken {
+//!     if let Some(sample) = buf.get(i).get_sample() {
+//!         println!("{}", dds_json::sample_to_json(&sample).unwrap());
+//!     }
+//! }
+//! # }
+//! ```
+
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+use crate::dds_dynamic::{DynamicData, DynamicType, DynamicTypeError};
+use crate::error::{DDSError, SerdesError};
+use crate::serdes::{SampleStorage, TopicType};
+
+/// The JSON of a received sample
+pub fn sample_to_json<T: Serialize>(sample: &SampleStorage<T>) -> Result<Value, serde_json::Error> {
+    serde_json::to_value(&**sample)
+}
+
+/// The JSON of a sample of T serialized as CDR with encapsulation header
+pub fn cdr_to_json<T: TopicType>(cdr: &[u8]) -> Result<Value, DDSError> {
+    let failed = |cause: String| {
+        DDSError::from(SerdesError::new(
+            T::typename().to_string_lossy().into_owned(),
+            cdr.len(),
+            cause,
+        ))
+    };
+    let sample = cdr::deserialize::<T>(cdr).map_err(|e| failed(e.to_string()))?;
+    serde_json::to_value(&sample).map_err(|e| failed(e.to_string()))
+}
+
+/// The JSON of a sample of a dynamic type serialized as CDR with
+/// encapsulation header
+pub fn dynamic_to_json(ty: &DynamicType, cdr: &[u8]) -> Result<Value, DynamicTypeError> {
+    Ok(data_to_json(&ty.deserialize(cdr)?))
+}
+
+/// The JSON of a dynamic value. Floating point numbers that JSON cannot
+/// represent, infinities and NaN, are null.
+pub fn data_to_json(data: &DynamicData) -> Value {
+    match data {
+        DynamicData::Bool(v) => Value::Bool(*v),
+        DynamicData::Int(v) => Value::from(*v),
+        DynamicData::UInt(v) => Value::from(*v),
+        DynamicData::Float(v) => Number::from_f64(*v).map_or(Value::Null, Value::Number),
+        DynamicData::Char(v) => Value::String(v.to_string()),
+        DynamicData::String(v) => Value::String(v.clone()),
+        DynamicData::Sequence(items) => Value::Array(items.iter().map(data_to_json).collect()),
+        DynamicData::Struct(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), data_to_json(value)))
+                .collect::<Map<String, Value>>(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dds_dynamic::DynamicKind;
+    use cdds_derive::Topic;
+    use cdr::{CdrBe, Infinite};
+    use serde_derive::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize, Topic, Default)]
+    struct Position {
+        #[topic_key]
+        id: u32,
+        name: String,
+        at: [f64; 2],
+        tags: Vec<char>,
+    }
+
+    fn position() -> Position {
+        Position {
+            id: 7,
+            name: "buoy".to_owned(),
+            at: [1.5, -2.0],
+            tags: vec!['a', 'b'],
+        }
+    }
+
+    fn expected() -> Value {
+        json!({"id": 7, "name": "buoy", "at": [1.5, -2.0], "tags": ["a", "b"]})
+    }
+
+    #[test]
+    fn test_sample_to_json() {
+        let sample = SampleStorage::Owned(Arc::new(position()));
+        assert_eq!(sample_to_json(&sample).unwrap(), expected());
+
+        let cdr = cdr::serialize::<_, _, CdrBe>(&position(), Infinite).unwrap();
+        assert_eq!(cdr_to_json::<Position>(&cdr).unwrap(), expected());
+        assert!(cdr_to_json::<Position>(&cdr[..cdr.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_dynamic_to_json() {
+        let ty = DynamicType::new("Position")
+            .with_key_field("id", DynamicKind::UInt32)
+            .with_field("name", DynamicKind::String)
+            .with_field("at", DynamicKind::Array(Box::new(DynamicKind::Float64), 2))
+            .with_field("tags", DynamicKind::Sequence(Box::new(DynamicKind::Char)));
+        // the CDR of the typed sample reads the same as a dynamic sample
+        let cdr = cdr::serialize::<_, _, CdrBe>(&position(), Infinite).unwrap();
+        assert_eq!(dynamic_to_json(&ty, &cdr).unwrap(), expected());
+        assert!(dynamic_to_json(&ty, &cdr[..6]).is_err());
+
+        let data = DynamicData::default().with_field("x", f64::NAN).with_field("n", -3i32);
+        assert_eq!(data_to_json(&data), json!({"x": null, "n": -3}));
+    }
+}
@@ -28,128 +28,222 @@
 //! }).
 //! hook(); // The hook call will finalize the listener. No more callbacks can be attached after this.
 //! ```
+//! Only a hooked [`DdsListener`] can be attached to an entity, a listener that
+//! is still being built is a `DdsListener<Unhooked>`.
+//!
+//! Each callback is called by one thread at a time. A callback that writes or
+//! reads may make cyclone call the listeners of other local entities from
+//! within the callback. If one of them has the same listener, that nested call
+//! of the callback is skipped and counted in
+//! [`Stats::reentrant_listener_calls`](crate::dds_telemetry::Stats::reentrant_listener_calls),
+//! it would wait for the callback that is running on the same thread.
 
 use cyclonedds_sys::dds_listener_t;
 use cyclonedds_sys::*;
 use std::convert::From;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use crate::dds_telemetry::{count, Counter};
+
+// Run a callback from cyclone. A panic must not unwind into C, it is counted
+// and the callback returns normally.
+fn guarded<F: FnOnce()>(f: F) {
+    if std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).is_err() {
+        count(Counter::ListenerPanics);
+    }
+}
 
 /*
  Each listener has its own set of callbacks.
 */
 
+// Cyclone calls the listener of an entity from the threads of the entity, a
+// listener shared by entities may be called concurrently. A callback is only
+// called by one thread at a time.
+type Callback<F> = Option<CallbackCell<F>>;
+
+// A callback and the thread that runs it. A callback that writes a sample or
+// changes a status makes cyclone call the listeners of the affected local
+// entities on the same thread. When one of them shares the listener, the
+// callback would wait for its own lock: the nested call is skipped and
+// counted instead.
+struct CallbackCell<F: ?Sized> {
+    // the thread_token of the thread running the callback, 0 if none is
+    running_on: AtomicUsize,
+    callback: Mutex<Box<F>>,
+}
+
+impl<F: ?Sized> CallbackCell<F> {
+    fn new(callback: Box<F>) -> Self {
+        Self {
+            running_on: AtomicUsize::new(0),
+            callback: Mutex::new(callback),
+        }
+    }
+
+    fn call(&self, call: impl FnOnce(&mut F)) {
+        let thread = thread_token();
+        // only this thread stores its own token, and clears it before returning
+        if self.running_on.load(Ordering::Relaxed) == thread {
+            count(Counter::ReentrantListenerCalls);
+            return;
+        }
+        // a callback that panicked poisons its lock and is still called afterwards
+        let mut callback = self.callback.lock().unwrap_or_else(PoisonError::into_inner);
+        self.running_on.store(thread, Ordering::Relaxed);
+        guarded(|| call(&mut callback));
+        self.running_on.store(0, Ordering::Relaxed);
+    }
+}
+
+// A non zero id of the current thread, unique among the running threads
+fn thread_token() -> usize {
+    thread_local!(static TOKEN: u8 = 0);
+    TOKEN.with(|token| token as *const u8 as usize)
+}
+
 /// The callbacks are in a different structure that is always
 /// heap allocated.
 #[derive(Default)]
 struct Callbacks {
     // Callbacks for readers
-    on_sample_lost: Option<Box<dyn FnMut(DdsEntity, dds_sample_lost_status_t) + 'static>>,
-    on_data_available: Option<Box<dyn FnMut(DdsEntity) + 'static>>,
-    on_sample_rejected: Option<Box<dyn FnMut(DdsEntity, dds_sample_rejected_status_t) + 'static>>,
+    on_sample_lost: Callback<dyn FnMut(DdsEntity, dds_sample_lost_status_t) + Send + 'static>,
+    on_data_available: Callback<dyn FnMut(DdsEntity) + Send + 'static>,
+    on_sample_rejected: Callback<dyn FnMut(DdsEntity, dds_sample_rejected_status_t) + Send + 'static>,
     on_liveliness_changed:
-        Option<Box<dyn FnMut(DdsEntity, dds_liveliness_changed_status_t) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_liveliness_changed_status_t) + Send + 'static>,
     on_requested_deadline_missed:
-        Option<Box<dyn FnMut(DdsEntity, dds_requested_deadline_missed_status_t) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_requested_deadline_missed_status_t) + Send + 'static>,
     on_requested_incompatible_qos:
-        Option<Box<dyn FnMut(DdsEntity, dds_requested_incompatible_qos_status_t) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_requested_incompatible_qos_status_t) + Send + 'static>,
     on_subscription_matched:
-        Option<Box<dyn FnMut(DdsEntity, dds_subscription_matched_status_t) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_subscription_matched_status_t) + Send + 'static>,
 
     //callbacks for writers
-    on_liveliness_lost: Option<Box<dyn FnMut(DdsEntity, dds_liveliness_lost_status_t) + 'static>>,
+    on_liveliness_lost: Callback<dyn FnMut(DdsEntity, dds_liveliness_lost_status_t) + Send + 'static>,
     on_offered_deadline_missed:
-        Option<Box<dyn FnMut(DdsEntity, dds_offered_deadline_missed_status_t) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_offered_deadline_missed_status_t) + Send + 'static>,
     on_offered_incompatible_qos:
-        Option<Box<dyn FnMut(DdsEntity, dds_offered_incompatible_qos_status_t) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_offered_incompatible_qos_status_t) + Send + 'static>,
     on_publication_matched:
-        Option<Box<dyn FnMut(DdsEntity, dds_publication_matched_status_t) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_publication_matched_status_t) + Send + 'static>,
 
     on_inconsistent_topic:
-        Option<Box<dyn FnMut(DdsEntity, dds_inconsistent_topic_status_t) + 'static>>,
-    on_data_on_readers: Option<Box<dyn FnMut(DdsEntity) + 'static>>,
+        Callback<dyn FnMut(DdsEntity, dds_inconsistent_topic_status_t) + Send + 'static>,
+    on_data_on_readers: Callback<dyn FnMut(DdsEntity) + Send + 'static>,
+}
+
+/// The state of a listener whose callbacks are still being set. It cannot be
+/// attached to an entity or cloned, [`DdsListener::hook`] turns it into a
+/// [`DdsListener`] that can.
+///
+/// ```compile_fail
+/// use cyclonedds_rs::{DdsListener, DdsParticipant};
+/// let unhooked = DdsListener::new().on_data_available(|_| {});
+/// let participant = DdsParticipant::create(None, None, Some(unhooked)).unwrap();
+/// ```
+///
+/// ```compile_fail
+/// use cyclonedds_rs::DdsListener;
+/// let unhooked = DdsListener::new();
+/// let other = unhooked.clone();
+/// ```
+///
+/// The callbacks are called from the threads of cyclone and must be `Send`.
+///
+/// ```compile_fail
+/// use cyclonedds_rs::DdsListenerBuilder;
+/// let count = std::rc::Rc::new(std::cell::Cell::new(0));
+/// let listener = DdsListenerBuilder::new()
+///     .on_data_available(move |_| count.set(count.get() + 1))
+///     .build();
+/// ```
+pub struct Unhooked {
+    callbacks: Box<Callbacks>,
 }
 
-unsafe impl Send for Inner {}
+/// The state of a listener that is ready to be attached to entities. The
+/// callbacks are fixed, a hooked listener cannot be hooked again.
+///
+/// ```compile_fail
+/// use cyclonedds_rs::DdsListener;
+/// let listener = DdsListener::new().hook();
+/// let again = listener.hook();
+/// ```
+#[derive(Clone)]
+pub struct Hooked {
+    inner: std::sync::Arc<Inner>,
+}
+
+// The callbacks do not change once the listener is hooked, the trampolines
+// reach them through the callback argument of cyclone. They are called from
+// the threads of cyclone, whichever thread built the listener, which is why
+// every callback has to be Send.
 struct Inner {
-    listener: Option<*mut dds_listener_t>,
-    callbacks: Option<Box<Callbacks>>,
-    raw_ptr: Option<*mut Callbacks>,
+    listener: ListenerPtr,
+    callbacks: Box<Callbacks>,
 }
 
+// A cyclone listener is a plain table of function pointers and arguments, it
+// can be handed to entities and deleted from any thread
+struct ListenerPtr(*mut dds_listener_t);
+
+unsafe impl Send for ListenerPtr {}
+unsafe impl Sync for ListenerPtr {}
+
+/// A listener, hooked unless it is a `DdsListener<Unhooked>` that is being
+/// built. Clones of a hooked listener share the callbacks.
 #[derive(Clone)]
-pub struct DdsListener {
-    inner: std::sync::Arc<std::sync::Mutex<Inner>>,
+pub struct DdsListener<State = Hooked> {
+    state: State,
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     pub fn new() -> Self {
         Self {
-            inner: std::sync::Arc::new(std::sync::Mutex::new(Inner {
-                listener: None,
-                callbacks: Some(Box::default()),
-                raw_ptr: None,
-            })),
+            state: Unhooked {
+                callbacks: Box::default(),
+            },
         }
     }
 }
 
-impl<'a> Default for DdsListener {
+impl Default for DdsListener<Unhooked> {
     fn default() -> Self {
         DdsListener::new()
     }
 }
 
-impl<'a> From<&DdsListener> for *const dds_listener_t {
+impl From<&DdsListener> for *const dds_listener_t {
     fn from(listener: &DdsListener) -> Self {
-        if let Some(listener) = listener.inner.lock().unwrap().listener {
-            listener
-        } else {
-            panic!("Attempt to convert from unitialized &listener");
-        }
+        listener.state.inner.listener.0
     }
 }
 
-impl<'a> DdsListener {
-    // take ownership as we're going to do some bad stuff here.
-    pub fn hook(self) -> Self {
-        // we're going to grab the Boxed callbacks and keep them separately as
-        // we will send a pointer to the callback array into C. We convert the
-        // pointer back to a box in the Drop function.
-
-        // free the previous pointer if present
-        if let Some(raw) = self.inner.lock().unwrap().raw_ptr.take() {
-            unsafe {
-                // take ownership and free when out of scope
-                Box::from_raw(raw);
+impl DdsListener<Unhooked> {
+    /// Hand the callbacks to cyclone. No more callbacks can be set after this.
+    pub fn hook(self) -> DdsListener {
+        // we keep the boxed callbacks separately as we send a pointer to them
+        // into C. Moving the box does not move the callbacks, the box is
+        // dropped after the listener is deleted.
+        let callbacks = self.state.callbacks;
+        unsafe {
+            let l = dds_create_listener(&*callbacks as *const Callbacks as *mut std::ffi::c_void);
+            if l.is_null() {
+                panic!("Error creating listener");
             }
-        }
-
-        let inner = &self.inner;
-
-        {
-            let mut inner = inner.lock().unwrap();
-            if let Some(b) = inner.callbacks.take() {
-                let raw = Box::into_raw(b);
-                unsafe {
-                    let l = dds_create_listener(raw as *mut std::ffi::c_void);
-                    if !l.is_null() {
-                        let callbacks_ptr = raw as *mut Callbacks;
-                        let callbacks = &*callbacks_ptr;
-                        self.register_callbacks(l, callbacks);
-                        inner.raw_ptr = Some(raw);
-                        inner.listener = Some(l);
-                    } else {
-                        panic!("Error creating listener");
-                    }
-                }
-            } else {
-                println!("No callbacks to take");
+            Self::register_callbacks(l, &callbacks);
+            DdsListener {
+                state: Hooked {
+                    inner: std::sync::Arc::new(Inner { listener: ListenerPtr(l), callbacks }),
+                },
             }
         }
-        self
     }
 
     /// register the callbacks for the closures that have been set.DdsListener
-    unsafe fn register_callbacks(&self, listener: *mut dds_listener_t, callbacks: &Callbacks) {
+    unsafe fn register_callbacks(listener: *mut dds_listener_t, callbacks: &Callbacks) {
         if callbacks.on_data_available.is_some() {
             //println!("Listener hooked for data available");
             dds_lset_data_available(listener, Some(Self::call_data_available_closure));
@@ -211,15 +305,13 @@ impl<'a> DdsListener {
 }
 
 //////
-impl DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_data_available<F>(self, callback: F) -> Self
+    pub fn on_data_available<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity) + 'static,
+        F: FnMut(DdsEntity) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_data_available = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_data_available = Some(CallbackCell::new(Box::new(callback)));
 
         self
     }
@@ -228,25 +320,22 @@ impl DdsListener {
         reader: dds_entity_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //        println!("C Callback!");
-        if let Some(avail) = &mut callbacks.on_data_available {
-            avail(DdsEntity::new(reader));
+        if let Some(avail) = &callbacks.on_data_available {
+            avail.call(|callback| callback(DdsEntity::new(reader)));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     /////
     #[deprecated]
-    pub fn on_sample_lost<F>(self, callback: F) -> Self
+    pub fn on_sample_lost<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_sample_lost_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_sample_lost_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_sample_lost = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_sample_lost = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -255,25 +344,22 @@ impl<'a> DdsListener {
         status: dds_sample_lost_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - sample lost");
-        if let Some(lost) = &mut callbacks.on_sample_lost {
-            lost(DdsEntity::new(reader), status);
+        if let Some(lost) = &callbacks.on_sample_lost {
+            lost.call(|callback| callback(DdsEntity::new(reader), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     //////
     #[deprecated]
-    pub fn on_sample_rejected<F>(self, callback: F) -> Self
+    pub fn on_sample_rejected<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_sample_rejected_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_sample_rejected_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_sample_rejected = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_sample_rejected = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -282,25 +368,22 @@ impl<'a> DdsListener {
         status: dds_sample_rejected_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - sample rejected");
-        if let Some(rejected) = &mut callbacks.on_sample_rejected {
-            rejected(DdsEntity::new(reader), status);
+        if let Some(rejected) = &callbacks.on_sample_rejected {
+            rejected.call(|callback| callback(DdsEntity::new(reader), status));
         }
     }
 }
 
 // Liveliness changed
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_liveliness_changed<F>(self, callback: F) -> Self
+    pub fn on_liveliness_changed<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_liveliness_changed_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_liveliness_changed_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_liveliness_changed = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_liveliness_changed = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -309,24 +392,21 @@ impl<'a> DdsListener {
         status: dds_liveliness_changed_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - Liveliness changed");
-        if let Some(changed) = &mut callbacks.on_liveliness_changed {
-            changed(DdsEntity::new(entity), status);
+        if let Some(changed) = &callbacks.on_liveliness_changed {
+            changed.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_requested_deadline_missed<F>(self, callback: F) -> Self
+    pub fn on_requested_deadline_missed<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_requested_deadline_missed_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_requested_deadline_missed_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_requested_deadline_missed = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_requested_deadline_missed = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -335,24 +415,21 @@ impl<'a> DdsListener {
         status: dds_requested_deadline_missed_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - requested deadline missed");
-        if let Some(missed) = &mut callbacks.on_requested_deadline_missed {
-            missed(DdsEntity::new(entity), status);
+        if let Some(missed) = &callbacks.on_requested_deadline_missed {
+            missed.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_requested_incompatible_qos<F>(self, callback: F) -> Self
+    pub fn on_requested_incompatible_qos<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_requested_incompatible_qos_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_requested_incompatible_qos_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_requested_incompatible_qos = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_requested_incompatible_qos = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -361,24 +438,21 @@ impl<'a> DdsListener {
         status: dds_requested_incompatible_qos_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - requested incompatible QOS");
-        if let Some(incompatible_qos) = &mut callbacks.on_requested_incompatible_qos {
-            incompatible_qos(DdsEntity::new(entity), status);
+        if let Some(incompatible_qos) = &callbacks.on_requested_incompatible_qos {
+            incompatible_qos.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_subscription_matched<F>(self, callback: F) -> Self
+    pub fn on_subscription_matched<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_subscription_matched_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_subscription_matched_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_subscription_matched = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_subscription_matched = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -387,24 +461,21 @@ impl<'a> DdsListener {
         status: dds_subscription_matched_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - subscription matched");
-        if let Some(matched) = &mut callbacks.on_subscription_matched {
-            matched(DdsEntity::new(entity), status);
+        if let Some(matched) = &callbacks.on_subscription_matched {
+            matched.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_liveliness_lost<F>(self, callback: F) -> Self
+    pub fn on_liveliness_lost<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_liveliness_lost_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_liveliness_lost_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_liveliness_lost = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_liveliness_lost = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -413,24 +484,21 @@ impl<'a> DdsListener {
         status: dds_liveliness_lost_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - liveliness lost");
-        if let Some(lost) = &mut callbacks.on_liveliness_lost {
-            lost(DdsEntity::new(entity), status);
+        if let Some(lost) = &callbacks.on_liveliness_lost {
+            lost.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_offered_deadline_missed<F>(self, callback: F) -> Self
+    pub fn on_offered_deadline_missed<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_offered_deadline_missed_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_offered_deadline_missed_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_offered_deadline_missed = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_offered_deadline_missed = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -439,24 +507,21 @@ impl<'a> DdsListener {
         status: dds_offered_deadline_missed_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - offered deadline missed");
-        if let Some(missed) = &mut callbacks.on_offered_deadline_missed {
-            missed(DdsEntity::new(entity), status);
+        if let Some(missed) = &callbacks.on_offered_deadline_missed {
+            missed.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_offered_incompatible_qos<F>(self, callback: F) -> Self
+    pub fn on_offered_incompatible_qos<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_offered_incompatible_qos_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_offered_incompatible_qos_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_offered_incompatible_qos = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_offered_incompatible_qos = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -465,24 +530,21 @@ impl<'a> DdsListener {
         status: dds_offered_incompatible_qos_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - offered incompatible QOS");
-        if let Some(incompatible) = &mut callbacks.on_offered_incompatible_qos {
-            incompatible(DdsEntity::new(entity), status);
+        if let Some(incompatible) = &callbacks.on_offered_incompatible_qos {
+            incompatible.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_publication_matched<F>(self, callback: F) -> Self
+    pub fn on_publication_matched<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_publication_matched_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_publication_matched_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_publication_matched = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_publication_matched = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -491,24 +553,21 @@ impl<'a> DdsListener {
         status: dds_publication_matched_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - publication matched");
-        if let Some(matched) = &mut callbacks.on_publication_matched {
-            matched(DdsEntity::new(entity), status);
+        if let Some(matched) = &callbacks.on_publication_matched {
+            matched.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_inconsistent_topic<F>(self, callback: F) -> Self
+    pub fn on_inconsistent_topic<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity, dds_inconsistent_topic_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_inconsistent_topic_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_inconsistent_topic = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_inconsistent_topic = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -517,24 +576,21 @@ impl<'a> DdsListener {
         status: dds_inconsistent_topic_status_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - inconsistent topic");
-        if let Some(inconsistant) = &mut callbacks.on_inconsistent_topic {
-            inconsistant(DdsEntity::new(entity), status);
+        if let Some(inconsistant) = &callbacks.on_inconsistent_topic {
+            inconsistant.call(|callback| callback(DdsEntity::new(entity), status));
         }
     }
 }
 
-impl<'a> DdsListener {
+impl DdsListener<Unhooked> {
     #[deprecated]
-    pub fn on_data_on_readers<F>(self, callback: F) -> Self
+    pub fn on_data_on_readers<F>(mut self, callback: F) -> Self
     where
-        F: FnMut(DdsEntity) + 'static,
+        F: FnMut(DdsEntity) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.inner.lock().unwrap().callbacks {
-            callbacks.on_data_on_readers = Some(Box::new(callback));
-        }
+        self.state.callbacks.on_data_on_readers = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
@@ -542,38 +598,31 @@ impl<'a> DdsListener {
         entity: dds_entity_t,
         data: *mut std::ffi::c_void,
     ) {
-        let callbacks_ptr = data as *mut Callbacks;
-        let callbacks = &mut *callbacks_ptr;
+        let callbacks = &*(data as *const Callbacks);
         //println!("C Callback - data on readers");
-        if let Some(data) = &mut callbacks.on_data_on_readers {
-            data(DdsEntity::new(entity));
+        if let Some(data) = &callbacks.on_data_on_readers {
+            data.call(|callback| callback(DdsEntity::new(entity)));
         }
     }
 }
 
-impl<'a> Drop for DdsListener {
+// Entities copy the listener when it is set, the callbacks must live as long
+// as any handle of the listener, which the entities keep.
+impl Drop for Inner {
     fn drop(&mut self) {
-        // delete the listener so we are sure of not
-        // getting any callbacks
-        if let Some(listener) = &self.inner.lock().unwrap().listener {
-            unsafe {
-                dds_reset_listener(*listener);
-                dds_delete_listener(*listener);
-            }
-        }
-        // gain back control of the Callback structure
-        if let Some(raw) = self.inner.lock().unwrap().raw_ptr.take() {
-            unsafe {
-                // take ownership and free when out of scope
-                let _ = Box::from_raw(raw);
-            }
+        unsafe {
+            // delete the listener so we are sure of not
+            // getting any callbacks
+            dds_reset_listener(self.listener.0);
+            dds_delete_listener(self.listener.0);
+            // the callbacks are dropped with the Inner
         }
     }
 }
 
 #[derive(Default)]
 pub struct DdsListenerBuilder {
-    listener: Option<DdsListener>,
+    listener: Option<DdsListener<Unhooked>>,
 }
 
 impl DdsListenerBuilder {
@@ -589,11 +638,9 @@ impl DdsListenerBuilder {
 
     pub fn on_data_available<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity) + 'static,
+        F: FnMut(DdsEntity) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_data_available = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_data_available = Some(CallbackCell::new(Box::new(callback)));
 
         self
     }
@@ -601,123 +648,271 @@ impl DdsListenerBuilder {
     /////
     pub fn on_sample_lost<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_sample_lost_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_sample_lost_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_sample_lost = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_sample_lost = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     //////
     pub fn on_sample_rejected<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_sample_rejected_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_sample_rejected_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_sample_rejected = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_sample_rejected = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     // Liveliness changed
     pub fn on_liveliness_changed<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_liveliness_changed_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_liveliness_changed_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_liveliness_changed = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_liveliness_changed = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_requested_deadline_missed<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_requested_deadline_missed_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_requested_deadline_missed_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_requested_deadline_missed = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_requested_deadline_missed = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_requested_incompatible_qos<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_requested_incompatible_qos_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_requested_incompatible_qos_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_requested_incompatible_qos = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_requested_incompatible_qos = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_subscription_matched<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_subscription_matched_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_subscription_matched_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_subscription_matched = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_subscription_matched = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_liveliness_lost<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_liveliness_lost_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_liveliness_lost_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_liveliness_lost = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_liveliness_lost = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_offered_deadline_missed<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_offered_deadline_missed_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_offered_deadline_missed_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_offered_deadline_missed = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_offered_deadline_missed = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_offered_incompatible_qos<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_offered_incompatible_qos_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_offered_incompatible_qos_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_offered_incompatible_qos = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_offered_incompatible_qos = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_publication_matched<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_publication_matched_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_publication_matched_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_publication_matched = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_publication_matched = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_inconsistent_topic<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity, dds_inconsistent_topic_status_t) + 'static,
+        F: FnMut(DdsEntity, dds_inconsistent_topic_status_t) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_inconsistent_topic = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_inconsistent_topic = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 
     pub fn on_data_on_readers<F>(&mut self, callback: F) -> &mut Self
     where
-        F: FnMut(DdsEntity) + 'static,
+        F: FnMut(DdsEntity) + Send + 'static,
     {
-        if let Some(callbacks) = &mut self.listener.as_ref().unwrap().inner.lock().unwrap().callbacks {
-            callbacks.on_data_on_readers = Some(Box::new(callback));
-        }
+        self.listener.as_mut().unwrap().state.callbacks.on_data_on_readers = Some(CallbackCell::new(Box::new(callback)));
         self
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsParticipant, DdsReader, DdsTopic, DdsWriter};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Default)]
+    struct Ping {
+        #[topic_key]
+        id: u32,
+    }
+
+    #[test]
+    fn test_dropped_clone_keeps_callbacks() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let listener = DdsListenerBuilder::new()
+            .on_data_available(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .build();
+        drop(listener.clone());
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = DdsTopic::<Ping>::create(&participant, "listener_ping", None, None).unwrap();
+        let _reader = DdsReader::create(&participant, topic.clone(), None, Some(listener)).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(Ping { id: 1 })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_callback_called_after_panic() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let listener = DdsListenerBuilder::new()
+            .on_data_available(move |_| {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("first sample");
+                }
+            })
+            .build();
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = DdsTopic::<Ping>::create(&participant, "listener_panic", None, None).unwrap();
+        let _reader = DdsReader::create(&participant, topic.clone(), None, Some(listener)).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(Ping { id: 1 })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(Ping { id: 2 })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_nested_call_is_skipped() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let first = DdsTopic::<Ping>::create(&participant, "listener_nested_first", None, None).unwrap();
+        let second = DdsTopic::<Ping>::create(&participant, "listener_nested_second", None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, first.clone(), None, None).unwrap();
+        let mut forward = DdsWriter::create(&participant, second.clone(), None, None).unwrap();
+
+        // the callback writes to the reader of the second topic, which has the
+        // same listener
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let listener = DdsListenerBuilder::new()
+            .on_data_available(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                forward.write(Arc::new(Ping { id: 2 })).unwrap();
+            })
+            .build();
+        let _first = DdsReader::create(&participant, first, None, Some(listener.clone())).unwrap();
+        let _second = DdsReader::create(&participant, second, None, Some(listener)).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let before = crate::dds_telemetry::stats().reentrant_listener_calls;
+        writer.write(Arc::new(Ping { id: 1 })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(crate::dds_telemetry::stats().reentrant_listener_calls > before);
+    }
+
+    #[test]
+    fn test_listeners_are_send() {
+        fn send_sync<T: Send + Sync>() {}
+        fn send<T: Send>() {}
+        send_sync::<DdsListener>();
+        send::<DdsListener<Unhooked>>();
+    }
+
+    #[test]
+    fn test_callbacks_released() {
+        let state = Arc::new(AtomicUsize::new(0));
+        let held = state.clone();
+        #[allow(deprecated)]
+        let unhooked = DdsListener::new().on_data_available(move |_| {
+            held.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(Arc::strong_count(&state), 2);
+        drop(unhooked);
+        assert_eq!(Arc::strong_count(&state), 1);
+
+        let held = state.clone();
+        let listener = DdsListenerBuilder::new()
+            .on_data_available(move |_| {
+                held.fetch_add(1, Ordering::SeqCst);
+            })
+            .build();
+        let clone = listener.clone();
+        drop(listener);
+        assert_eq!(Arc::strong_count(&state), 2);
+        drop(clone);
+        assert_eq!(Arc::strong_count(&state), 1);
+    }
+
+    #[test]
+    fn test_listener_shared_by_readers() {
+        const READERS: usize = 4;
+        const WRITERS: usize = 4;
+        const SAMPLES: u32 = 50;
+
+        // the callback state is not synchronized, cyclone calls the same
+        // callback from the delivery threads of all writers
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut seen = 0;
+        let listener = DdsListenerBuilder::new()
+            .on_data_available(move |_| {
+                seen += 1;
+                counter.store(seen, Ordering::SeqCst);
+            })
+            .build();
+
+        let domain = crate::test_support::LoopbackDomain::create().unwrap();
+        let topic = domain.topic::<Ping>("listener_shared").unwrap();
+        let _readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                DdsReader::create(domain.participant(), topic.clone(), None, Some(listener.clone()))
+                    .unwrap()
+            })
+            .collect();
+        let writers: Vec<_> = (0..WRITERS).map(|_| domain.writer(topic.clone()).unwrap()).collect();
+        std::thread::sleep(Duration::from_millis(100));
+
+        std::thread::scope(|scope| {
+            for (w, mut writer) in writers.into_iter().enumerate() {
+                scope.spawn(move || {
+                    for i in 0..SAMPLES {
+                        writer.write(Arc::new(Ping { id: w as u32 * SAMPLES + i })).unwrap();
+                    }
+                });
+            }
+        });
+
+        let expected = READERS * WRITERS * SAMPLES as usize;
+        for _ in 0..100 {
+            if calls.load(Ordering::SeqCst) == expected {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), expected);
+    }
+}
@@ -0,0 +1,367 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Watch the liveliness of the writers of a topic. A [`LivelinessMonitor`]
+//! has a reader of its own that follows the liveliness changed status and the
+//! matched publications, it reports writers that appear, lose their liveliness,
+//! recover and disappear. The events can be awaited in any async runtime.
+//!
+//! The monitor only observes liveliness, the samples received by its reader are
+//! discarded without being deserialized.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # async fn example<T: TopicType>(participant: &DdsParticipant, topic: DdsTopic<T>) {
+//! let monitor = LivelinessMonitor::create(participant, topic, None).unwrap();
+//! loop {
+//!     match monitor.next_event().await {
+//!         LivelinessEvent::Lost(writer) => println!("writer {:?} stopped", writer.guid),
+//!         LivelinessEvent::Recovered(writer) => println!("writer {:?} is back", writer.guid),
+//!         _ => {}
+//!     }
+//!     println!("{} writers alive", monitor.alive_count());
+//! }
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::os::raw::c_void;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use cyclonedds_sys::*;
+
+use crate::common::{drain, max_samples, sample_count, DRAIN_BATCH};
+use crate::dds_builtin::EndpointBuiltinTopicData;
+use crate::error::DDSError;
+use crate::serdes::TopicType;
+use crate::{DdsListener, DdsQos, DdsReadable, DdsReader, DdsTopic, Entity, Guid};
+
+/// Events kept for a consumer that does not keep up; older events are dropped
+const MAX_QUEUED_EVENTS: usize = 256;
+
+/// A matched writer as seen by the monitor
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriterLiveliness {
+    /// The instance handle of the publication on the reader of the monitor
+    pub handle: dds_instance_handle_t,
+    /// `None` if the writer was gone before its GUID could be looked up
+    pub guid: Option<Guid>,
+    pub alive: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LivelinessEvent {
+    /// A writer was matched
+    Alive(WriterLiveliness),
+    /// A writer did not assert its liveliness within its lease duration
+    Lost(WriterLiveliness),
+    /// A writer that had lost its liveliness is alive again
+    Recovered(WriterLiveliness),
+    /// A writer is no longer matched, it was deleted or its participant is gone
+    Gone(WriterLiveliness),
+}
+
+#[derive(Default)]
+struct MonitorState {
+    writers: HashMap<dds_instance_handle_t, WriterLiveliness>,
+    alive_count: u32,
+    not_alive_count: u32,
+    events: VecDeque<LivelinessEvent>,
+    waker: Option<Waker>,
+}
+
+impl MonitorState {
+    fn push(&mut self, event: LivelinessEvent) {
+        if self.events.len() == MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    // The status only names the writer that changed last, several changes may
+    // be reported at once. The matched publications fill in the rest.
+    fn update(&mut self, reader: &DdsEntity, status: &dds_liveliness_changed_status_t) {
+        let matched = matched_publications(reader);
+
+        let gone: Vec<dds_instance_handle_t> = self
+            .writers
+            .keys()
+            .filter(|h| !matched.contains(h))
+            .copied()
+            .collect();
+        for handle in gone {
+            if let Some(writer) = self.writers.remove(&handle) {
+                self.push(LivelinessEvent::Gone(writer));
+            }
+        }
+
+        let handle = status.last_publication_handle;
+        let now_alive = if status.alive_count_change > 0 {
+            Some(true)
+        } else if status.not_alive_count_change > 0 {
+            Some(false)
+        } else {
+            None
+        };
+        if let (Some(alive), Some(writer)) = (now_alive, self.writers.get_mut(&handle)) {
+            if writer.alive != alive {
+                writer.alive = alive;
+                let writer = writer.clone();
+                self.push(if alive {
+                    LivelinessEvent::Recovered(writer)
+                } else {
+                    LivelinessEvent::Lost(writer)
+                });
+            }
+        } else if let Some(alive) = now_alive.filter(|_| matched.contains(&handle)) {
+            self.insert(reader, handle, alive);
+        }
+
+        for handle in matched {
+            if !self.writers.contains_key(&handle) {
+                self.insert(reader, handle, true);
+            }
+        }
+
+        self.alive_count = status.alive_count;
+        self.not_alive_count = status.not_alive_count;
+    }
+
+    fn insert(&mut self, reader: &DdsEntity, handle: dds_instance_handle_t, alive: bool) {
+        let writer = WriterLiveliness {
+            handle,
+            guid: publication_guid(reader, handle),
+            alive,
+        };
+        self.writers.insert(handle, writer.clone());
+        self.push(if alive {
+            LivelinessEvent::Alive(writer)
+        } else {
+            LivelinessEvent::Lost(writer)
+        });
+    }
+}
+
+fn matched_publications(reader: &DdsEntity) -> Vec<dds_instance_handle_t> {
+    let mut handles: Vec<dds_instance_handle_t> = vec![0; 8];
+    loop {
+        let n = unsafe {
+            dds_get_matched_publications(reader.entity(), handles.as_mut_ptr(), handles.len() as size_t)
+        };
+        if n < 0 {
+            return Vec::new();
+        }
+        // more writers than fit in the buffer
+        if n as usize > handles.len() {
+            handles.resize(n as usize, 0);
+            continue;
+        }
+        handles.truncate(n as usize);
+        return handles;
+    }
+}
+
+fn publication_guid(reader: &DdsEntity, handle: dds_instance_handle_t) -> Option<Guid> {
+    unsafe {
+        let data = dds_get_matched_publication_data(reader.entity(), handle);
+        if data.is_null() {
+            return None;
+        }
+        let guid = EndpointBuiltinTopicData::from_loaned(data as *const c_void).key;
+        dds_builtintopic_free_endpoint(data);
+        Some(guid)
+    }
+}
+
+fn discard_all(reader: &DdsEntity) {
+    let _ = drain(|| {
+        let mut serdata: [*mut ddsi_serdata; DRAIN_BATCH] = [std::ptr::null_mut(); DRAIN_BATCH];
+        let mut infos = [dds_sample_info_t::default(); DRAIN_BATCH];
+        let n = unsafe {
+            dds_takecdr(reader.entity(), serdata.as_mut_ptr(), max_samples(DRAIN_BATCH), infos.as_mut_ptr(), 0)
+        };
+        if n < 0 {
+            return Err(DDSError::from_retcode("dds_takecdr", n).with_entity_kind("reader"));
+        }
+        for d in &serdata[..sample_count(n)] {
+            unsafe { ddsi_serdata_removeref(*d) };
+        }
+        Ok(sample_count(n))
+    });
+}
+
+/// Tracks the liveliness of the writers matched with a reader of a topic
+pub struct LivelinessMonitor<T: TopicType> {
+    reader: DdsReader<T>,
+    state: Arc<Mutex<MonitorState>>,
+}
+
+impl<T: TopicType> LivelinessMonitor<T> {
+    /// Create the reader of the monitor. The liveliness QoS of the reader must
+    /// be compatible with the writers to be watched.
+    pub fn create(
+        entity: &dyn DdsReadable,
+        topic: DdsTopic<T>,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<Self, DDSError> {
+        let state = Arc::new(Mutex::new(MonitorState::default()));
+
+        let listener_state = state.clone();
+        let listener = DdsListener::new()
+            .on_liveliness_changed(move |reader, status| {
+                listener_state.lock().unwrap().update(&reader, &status);
+            })
+            .on_data_available(|reader| discard_all(&reader))
+            .hook();
+
+        let reader = DdsReader::create(entity, topic, maybe_qos, Some(listener))?;
+        Ok(Self { reader, state })
+    }
+
+    /// Wait for the next event. Events that were not awaited are queued, the
+    /// oldest are dropped if too many pile up.
+    pub fn next_event(&self) -> NextLivelinessEvent<'_> {
+        NextLivelinessEvent { state: &self.state }
+    }
+
+    /// The next queued event, without waiting
+    pub fn try_next_event(&self) -> Option<LivelinessEvent> {
+        self.state.lock().unwrap().events.pop_front()
+    }
+
+    /// The number of matched writers that are alive
+    pub fn alive_count(&self) -> u32 {
+        self.state.lock().unwrap().alive_count
+    }
+
+    /// The number of matched writers that lost their liveliness
+    pub fn not_alive_count(&self) -> u32 {
+        self.state.lock().unwrap().not_alive_count
+    }
+
+    /// Every matched writer, in no particular order
+    pub fn writers(&self) -> Vec<WriterLiveliness> {
+        self.state.lock().unwrap().writers.values().cloned().collect()
+    }
+
+    /// The writer with the given GUID, if it is matched
+    pub fn writer(&self, guid: &Guid) -> Option<WriterLiveliness> {
+        let state = self.state.lock().unwrap();
+        state.writers.values().find(|w| w.guid.as_ref() == Some(guid)).cloned()
+    }
+
+    pub fn reader(&self) -> &DdsReader<T> {
+        &self.reader
+    }
+}
+
+impl<T: TopicType> Entity for LivelinessMonitor<T> {
+    fn entity(&self) -> &DdsEntity {
+        self.reader.entity()
+    }
+}
+
+/// The future returned by [`LivelinessMonitor::next_event`]
+pub struct NextLivelinessEvent<'a> {
+    state: &'a Mutex<MonitorState>,
+}
+
+impl<'a> Future for NextLivelinessEvent<'a> {
+    type Output = LivelinessEvent;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => {
+                state.waker = Some(ctx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsParticipant, DdsWriter};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Default)]
+    struct Heartbeat {
+        #[topic_key]
+        id: u32,
+    }
+
+    fn next(monitor: &LivelinessMonitor<Heartbeat>) -> LivelinessEvent {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            tokio::time::timeout(Duration::from_secs(2), monitor.next_event())
+                .await
+                .expect("no liveliness event")
+        })
+    }
+
+    #[test]
+    fn test_writer_lost_and_recovered() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = Heartbeat::create_topic(&participant, Some("liveliness"), None, None).unwrap();
+        let monitor = LivelinessMonitor::create(&participant, topic.clone(), None).unwrap();
+
+        // the writer has to assert its liveliness every 100ms
+        let mut qos = DdsQos::create().unwrap();
+        qos.set_liveliness(
+            dds_liveliness_kind::DDS_LIVELINESS_MANUAL_BY_TOPIC,
+            Duration::from_millis(100).as_nanos() as dds_duration_t,
+        );
+        let mut writer = DdsWriter::create(&participant, topic, Some(qos), None).unwrap();
+        writer.write(Arc::new(Heartbeat { id: 1 })).unwrap();
+
+        let guid = writer.guid().unwrap();
+        match next(&monitor) {
+            LivelinessEvent::Alive(w) => assert_eq!(w.guid, Some(guid)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(monitor.alive_count(), 1);
+
+        // no writes, no liveliness
+        assert!(matches!(next(&monitor), LivelinessEvent::Lost(_)));
+        assert_eq!(monitor.alive_count(), 0);
+        assert_eq!(monitor.not_alive_count(), 1);
+        assert_eq!(monitor.writer(&guid).map(|w| w.alive), Some(false));
+
+        writer.write(Arc::new(Heartbeat { id: 1 })).unwrap();
+        assert!(matches!(next(&monitor), LivelinessEvent::Recovered(_)));
+
+        drop(writer);
+        loop {
+            if let LivelinessEvent::Gone(w) = next(&monitor) {
+                assert_eq!(w.guid, Some(guid));
+                break;
+            }
+        }
+        assert!(monitor.writers().is_empty());
+    }
+}
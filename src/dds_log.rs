@@ -0,0 +1,344 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Route the log and trace output of cyclone into the Rust `log` crate (feature `log`)
+//! or `tracing` (feature `tracing`). Messages are emitted with the target
+//! "cyclonedds". Cyclone writes its log to stderr and its trace to the file
+//! configured in Tracing/OutputFile unless they are routed.
+//!
+//! The categories of a domain that are forwarded by the routed sinks can be
+//! changed at runtime with [`set_trace_categories`]. Cyclone only produces the
+//! categories enabled in the configuration of the domain, so enable the
+//! categories that may be needed (or "trace" for most of them) when the domain is
+//! created and narrow them down at runtime.
+//! # Example
+//! ```ignore
+//! cyclonedds_rs::dds_log::route_to_log();
+//! ```
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+use std::os::raw::c_void;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use cyclonedds_sys::DdsDomainId;
+
+use crate::common::bit_flags;
+use crate::error::DDSError;
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+use cyclonedds_sys::dds_log_data_t;
+
+// log categories of cyclone
+const DDS_LC_FATAL: u32 = 1;
+const DDS_LC_ERROR: u32 = 2;
+const DDS_LC_WARNING: u32 = 4;
+const DDS_LC_INFO: u32 = 8;
+const DDS_LC_CONFIG: u32 = 16;
+
+bit_flags! {
+    /// A set of cyclone log and trace categories. The names are the ones used in
+    /// the Tracing/Category element of the configuration.
+    pub struct TraceCategories(u32) {
+        FATAL = DDS_LC_FATAL, "fatal";
+        ERROR = DDS_LC_ERROR, "error";
+        WARNING = DDS_LC_WARNING, "warning";
+        INFO = DDS_LC_INFO, "info";
+        CONFIG = DDS_LC_CONFIG, "config";
+        DISCOVERY = 32, "discovery";
+        DATA = 64, "data";
+        TRACE = 128, "trace";
+        RADMIN = 256, "radmin";
+        TIMING = 512, "timing";
+        TRAFFIC = 1024, "traffic";
+        TOPIC = 2048, "topic";
+        TCP = 4096, "tcp";
+        PLIST = 8192, "plist";
+        WHC = 16384, "whc";
+        THROTTLE = 32768, "throttle";
+        RHC = 65536, "rhc";
+        CONTENT = 131072, "content";
+        SHM = 262144, "shm";
+    }
+    // "trace" in the configuration selects most categories
+    aliases {
+        "trace" => TraceCategories::trace();
+    }
+}
+
+impl TraceCategories {
+    /// The categories enabled by "trace" in the configuration
+    pub const fn trace() -> Self {
+        Self(
+            Self::FATAL.0
+                | Self::ERROR.0
+                | Self::WARNING.0
+                | Self::INFO.0
+                | Self::CONFIG.0
+                | Self::DISCOVERY.0
+                | Self::DATA.0
+                | Self::TRACE.0
+                | Self::TIMING.0
+                | Self::TRAFFIC.0
+                | Self::TCP.0
+                | Self::THROTTLE.0
+                | Self::CONTENT.0,
+        )
+    }
+}
+
+// Categories forwarded by the routed sinks, per domain. Domains without an entry
+// forward everything cyclone produces.
+static TRACE_CATEGORIES: Mutex<Option<HashMap<DdsDomainId, TraceCategories>>> = Mutex::new(None);
+
+// The same for the domain ids up to 232, read by the sinks for every message
+// without locking: the categories, with LIMITED set if there is an entry.
+const LIMITED: u64 = 1 << 32;
+const FAST_DOMAINS: usize = 233;
+#[allow(clippy::declare_interior_mutable_const)]
+const UNLIMITED: AtomicU64 = AtomicU64::new(0);
+static FAST_CATEGORIES: [AtomicU64; FAST_DOMAINS] = [UNLIMITED; FAST_DOMAINS];
+
+/// Forward only these categories of the domain to `log` or `tracing`. Takes effect
+/// immediately in a running process. Categories that are not enabled in the
+/// configuration of the domain are not produced by cyclone and cannot be enabled here.
+pub fn set_trace_categories(domain: DdsDomainId, categories: TraceCategories) {
+    if let Ok(mut map) = TRACE_CATEGORIES.lock() {
+        map.get_or_insert_with(HashMap::new).insert(domain, categories);
+        if let Some(slot) = FAST_CATEGORIES.get(domain as usize) {
+            slot.store(LIMITED | u64::from(categories.0), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Forward all categories cyclone produces for the domain again
+pub fn clear_trace_categories(domain: DdsDomainId) {
+    if let Ok(mut map) = TRACE_CATEGORIES.lock() {
+        if let Some(map) = map.as_mut() {
+            map.remove(&domain);
+        }
+        if let Some(slot) = FAST_CATEGORIES.get(domain as usize) {
+            slot.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The categories forwarded for the domain, None if not limited
+pub fn trace_categories(domain: DdsDomainId) -> Option<TraceCategories> {
+    TRACE_CATEGORIES
+        .lock()
+        .ok()
+        .and_then(|map| map.as_ref().and_then(|m| m.get(&domain).copied()))
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+fn forwarded(domain: DdsDomainId, priority: u32) -> bool {
+    match FAST_CATEGORIES.get(domain as usize) {
+        Some(slot) => {
+            let categories = slot.load(Ordering::Relaxed);
+            categories & LIMITED == 0 || categories as u32 & priority != 0
+        }
+        None => trace_categories(domain).map_or(true, |c| c.0 & priority != 0),
+    }
+}
+
+/// Level of a cyclone message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// The level for a cyclone log category (priority)
+    pub fn from_priority(priority: u32) -> Self {
+        if priority & (DDS_LC_FATAL | DDS_LC_ERROR) != 0 {
+            LogLevel::Error
+        } else if priority & DDS_LC_WARNING != 0 {
+            LogLevel::Warn
+        } else if priority & DDS_LC_INFO != 0 {
+            LogLevel::Info
+        } else if priority & DDS_LC_CONFIG != 0 {
+            LogLevel::Debug
+        } else {
+            LogLevel::Trace
+        }
+    }
+}
+
+/// The message of a log record without the header cyclone prepends
+/// (timestamp and thread) and without the trailing newline.
+#[cfg(any(feature = "log", feature = "tracing"))]
+unsafe fn message(data: &dds_log_data_t) -> String {
+    if data.message.is_null() {
+        return String::new();
+    }
+    let bytes = std::slice::from_raw_parts(data.message as *const u8, data.size as usize);
+    let hdrsize = std::cmp::min(data.hdrsize as usize, bytes.len());
+    strip_message(&bytes[hdrsize..])
+}
+
+fn strip_message(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(|c| c == '\n' || c == '\r')
+        .to_owned()
+}
+
+#[cfg(feature = "log")]
+unsafe extern "C" fn log_sink(_ptr: *mut c_void, data: *const dds_log_data_t) {
+    if let Some(data) = data.as_ref() {
+        let level = match LogLevel::from_priority(data.priority) {
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Trace => log::Level::Trace,
+        };
+        if log::log_enabled!(target: "cyclonedds", level) && forwarded(data.domid, data.priority) {
+            log::log!(target: "cyclonedds", level, "[domain {}] {}", data.domid, message(data));
+        }
+    }
+}
+
+/// Route cyclone log and trace output to the `log` crate
+#[cfg(feature = "log")]
+pub fn route_to_log() {
+    unsafe {
+        cyclonedds_sys::dds_set_log_sink(Some(log_sink), std::ptr::null_mut());
+        cyclonedds_sys::dds_set_trace_sink(Some(log_sink), std::ptr::null_mut());
+    }
+}
+
+#[cfg(feature = "tracing")]
+unsafe extern "C" fn tracing_sink(_ptr: *mut c_void, data: *const dds_log_data_t) {
+    if let Some(data) = data.as_ref() {
+        let domain = data.domid;
+        if !forwarded(domain, data.priority) {
+            return;
+        }
+        match LogLevel::from_priority(data.priority) {
+            LogLevel::Error => tracing::error!(target: "cyclonedds", domain, "{}", message(data)),
+            LogLevel::Warn => tracing::warn!(target: "cyclonedds", domain, "{}", message(data)),
+            LogLevel::Info => tracing::info!(target: "cyclonedds", domain, "{}", message(data)),
+            LogLevel::Debug => tracing::debug!(target: "cyclonedds", domain, "{}", message(data)),
+            LogLevel::Trace => tracing::trace!(target: "cyclonedds", domain, "{}", message(data)),
+        }
+    }
+}
+
+/// Route cyclone log and trace output to `tracing`
+#[cfg(feature = "tracing")]
+pub fn route_to_tracing() {
+    unsafe {
+        cyclonedds_sys::dds_set_log_sink(Some(tracing_sink), std::ptr::null_mut());
+        cyclonedds_sys::dds_set_trace_sink(Some(tracing_sink), std::ptr::null_mut());
+    }
+}
+
+/// Report a failure that cannot be returned to the caller, in Drop or in a
+/// callback from cyclone. Goes to `tracing` or `log` with the target
+/// "cyclonedds_rs", and nowhere if neither feature is enabled.
+#[cfg_attr(not(any(feature = "log", feature = "tracing")), allow(unused_variables))]
+pub(crate) fn warn_failed(what: std::fmt::Arguments<'_>, err: &dyn std::fmt::Display) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(target: "cyclonedds_rs", error = %err, "{}", what);
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::warn!(target: "cyclonedds_rs", "{}: {}", what, err);
+}
+
+/// Report an entity that could not be deleted when its wrapper was dropped.
+/// Drop cannot return the error and must not panic, as a panic during unwinding aborts.
+pub(crate) fn drop_failed(entity_kind: &'static str, err: &DDSError) {
+    crate::dds_telemetry::count(crate::dds_telemetry::Counter::DropFailures);
+    warn_failed(format_args!("cannot delete {} on drop", entity_kind), err);
+}
+
+/// Restore the default destinations of the cyclone log and trace
+pub fn reset_sinks() {
+    unsafe {
+        cyclonedds_sys::dds_set_log_sink(None, std::ptr::null_mut());
+        cyclonedds_sys::dds_set_trace_sink(None, std::ptr::null_mut());
+    }
+}
+
+/// Set the categories that are written to the log, for example
+/// `DDS_LC_ERROR | DDS_LC_WARNING`.
+pub fn set_log_mask(categories: u32) {
+    unsafe {
+        cyclonedds_sys::dds_set_log_mask(categories);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(LogLevel::from_priority(DDS_LC_FATAL), LogLevel::Error);
+        assert_eq!(LogLevel::from_priority(DDS_LC_ERROR), LogLevel::Error);
+        assert_eq!(LogLevel::from_priority(DDS_LC_WARNING), LogLevel::Warn);
+        assert_eq!(LogLevel::from_priority(DDS_LC_INFO), LogLevel::Info);
+        assert_eq!(LogLevel::from_priority(DDS_LC_CONFIG), LogLevel::Debug);
+        // discovery
+        assert_eq!(LogLevel::from_priority(32), LogLevel::Trace);
+    }
+
+    #[test]
+    fn test_trace_categories() {
+        let c = TraceCategories::parse("discovery, Data").unwrap();
+        assert_eq!(c, TraceCategories::DISCOVERY | TraceCategories::DATA);
+        assert_eq!(c.to_string(), "discovery,data");
+        assert_eq!(TraceCategories::parse(&c.to_string()), Ok(c));
+        assert_eq!(TraceCategories::parse("trace"), Ok(TraceCategories::trace()));
+        assert!(TraceCategories::trace().contains(TraceCategories::TRAFFIC));
+        assert!(!TraceCategories::trace().contains(TraceCategories::RHC));
+        assert!(TraceCategories::all().contains(TraceCategories::RHC));
+        assert_eq!(format!("{:?}", c), "TraceCategories(DISCOVERY | DATA)");
+        assert_eq!(TraceCategories::parse("nonsense"), Err(DDSError::BadParameter));
+
+        assert_eq!(trace_categories(77), None);
+        set_trace_categories(77, TraceCategories::DISCOVERY);
+        assert_eq!(trace_categories(77), Some(TraceCategories::DISCOVERY));
+        clear_trace_categories(77);
+        assert_eq!(trace_categories(77), None);
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    #[test]
+    fn test_forwarded() {
+        set_trace_categories(78, TraceCategories::DISCOVERY);
+        set_trace_categories(1000, TraceCategories::DISCOVERY);
+        for domain in [78, 1000] {
+            assert!(forwarded(domain, TraceCategories::DISCOVERY.bits()));
+            assert!(!forwarded(domain, TraceCategories::DATA.bits()));
+            clear_trace_categories(domain);
+            assert!(forwarded(domain, TraceCategories::DATA.bits()));
+        }
+        assert!(forwarded(79, TraceCategories::DATA.bits()));
+    }
+
+    #[test]
+    fn test_strip_message() {
+        assert_eq!(strip_message(b"hello\n"), "hello");
+        assert_eq!(strip_message(b"hello"), "hello");
+    }
+}
@@ -0,0 +1,326 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Middleware health as metrics: the cyclone statistics and the number of
+//! matched endpoints of selected readers and writers, and the crate counters of
+//! [`dds_telemetry`](crate::dds_telemetry). The metrics can be rendered in the
+//! Prometheus text format, or, with the `metrics` feature, recorded through the
+//! `metrics` facade.
+//!
+//! Endpoint metrics are labelled with the topic name and the GUID of the endpoint.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example(writer: &dyn Entity, reader: &dyn Entity) {
+//! let mut metrics = MetricsCollector::new();
+//! metrics.add_writer(writer).unwrap();
+//! metrics.add_reader(reader).unwrap();
+//! // serve this on /metrics
+//! let text = metrics.render_prometheus();
+//! # }
+//! ```
+
+use std::fmt::Write;
+
+use cyclonedds_sys::*;
+
+use crate::dds_statistics::DdsStatistics;
+use crate::dds_telemetry::stats;
+use crate::dds_topic::topic_name_of;
+use crate::error::DDSError;
+use crate::{Entity, Guid};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Only ever increases
+    Counter,
+    /// Can go up and down
+    Gauge,
+}
+
+/// One value of a metric
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub kind: MetricKind,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EndpointKind {
+    Reader,
+    Writer,
+}
+
+impl EndpointKind {
+    fn name(&self) -> &'static str {
+        match self {
+            EndpointKind::Reader => "reader",
+            EndpointKind::Writer => "writer",
+        }
+    }
+}
+
+struct Endpoint {
+    kind: EndpointKind,
+    entity: DdsEntity,
+    guid: Guid,
+    topic: String,
+    statistics: DdsStatistics,
+}
+
+impl Endpoint {
+    fn matched(&self) -> Result<u64, DDSError> {
+        // with no buffer, only the number of matched endpoints is returned
+        let ret = unsafe {
+            match self.kind {
+                EndpointKind::Reader => {
+                    dds_get_matched_publications(self.entity.entity(), std::ptr::null_mut(), 0)
+                }
+                EndpointKind::Writer => {
+                    dds_get_matched_subscriptions(self.entity.entity(), std::ptr::null_mut(), 0)
+                }
+            }
+        };
+        if ret >= 0 {
+            Ok(ret as u64)
+        } else {
+            Err(DDSError::from_retcode("dds_get_matched", ret).with_entity_kind(self.kind.name()))
+        }
+    }
+}
+
+/// Collects the metrics of the readers and writers added to it and of the crate
+#[derive(Default)]
+pub struct MetricsCollector {
+    endpoints: Vec<Endpoint>,
+}
+
+impl MetricsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_reader(&mut self, reader: &dyn Entity) -> Result<(), DDSError> {
+        self.add(reader, EndpointKind::Reader)
+    }
+
+    pub fn add_writer(&mut self, writer: &dyn Entity) -> Result<(), DDSError> {
+        self.add(writer, EndpointKind::Writer)
+    }
+
+    fn add(&mut self, entity: &dyn Entity, kind: EndpointKind) -> Result<(), DDSError> {
+        let topic = unsafe { DdsEntity::new(dds_get_topic(entity.entity().entity())) };
+        self.endpoints.push(Endpoint {
+            kind,
+            entity: entity.entity().clone(),
+            guid: entity.guid()?,
+            topic: topic_name_of(&topic)?,
+            statistics: DdsStatistics::create(entity)?,
+        });
+        Ok(())
+    }
+
+    /// Stop collecting the metrics of a reader or writer. Endpoints that were
+    /// deleted are dropped by [`MetricsCollector::collect`].
+    pub fn remove(&mut self, guid: &Guid) {
+        self.endpoints.retain(|e| e.guid != *guid);
+    }
+
+    /// The current value of every metric
+    pub fn collect(&mut self) -> Vec<Metric> {
+        let mut metrics = crate_metrics();
+
+        // the statistics of a deleted entity can no longer be refreshed
+        self.endpoints.retain_mut(|e| e.statistics.refresh().is_ok());
+        for endpoint in &self.endpoints {
+            let labels = vec![
+                ("topic", endpoint.topic.clone()),
+                ("guid", endpoint.guid.to_string()),
+            ];
+            for (name, value) in endpoint.statistics.values() {
+                metrics.push(Metric {
+                    name: format!("cyclonedds_{}_{}_total", endpoint.kind.name(), name),
+                    kind: MetricKind::Counter,
+                    labels: labels.clone(),
+                    value: value.as_u64(),
+                });
+            }
+            if let Ok(matched) = endpoint.matched() {
+                metrics.push(Metric {
+                    name: format!("cyclonedds_{}_matched", endpoint.kind.name()),
+                    kind: MetricKind::Gauge,
+                    labels,
+                    value: matched,
+                });
+            }
+        }
+        metrics
+    }
+
+    /// The current metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&mut self) -> String {
+        render_prometheus(&self.collect())
+    }
+
+    /// Record the current metrics through the `metrics` facade
+    #[cfg(feature = "metrics")]
+    pub fn export(&mut self) {
+        for metric in self.collect() {
+            let labels: Vec<metrics::Label> = metric
+                .labels
+                .iter()
+                .map(|(k, v)| metrics::Label::new(*k, v.clone()))
+                .collect();
+            match metric.kind {
+                MetricKind::Counter => metrics::absolute_counter!(metric.name, metric.value, labels),
+                MetricKind::Gauge => metrics::gauge!(metric.name, metric.value as f64, labels),
+            }
+        }
+    }
+}
+
+/// The counters of [`dds_telemetry`](crate::dds_telemetry) as metrics
+pub fn crate_metrics() -> Vec<Metric> {
+    let stats = stats();
+    [
+        ("deserialize_failures", stats.deserialize_failures),
+        ("serdes_errors_discarded", stats.serdes_errors_discarded),
+        ("rejected_loans", stats.rejected_loans),
+        ("listener_panics", stats.listener_panics),
+        ("full_buffer_reads", stats.full_buffer_reads),
+        ("drop_failures", stats.drop_failures),
+        ("reentrant_listener_calls", stats.reentrant_listener_calls),
+    ]
+    .iter()
+    .map(|(name, value)| Metric {
+        name: format!("cyclonedds_rs_{}_total", name),
+        kind: MetricKind::Counter,
+        labels: Vec::new(),
+        value: *value,
+    })
+    .collect()
+}
+
+/// Render metrics in the Prometheus text exposition format
+pub fn render_prometheus(metrics: &[Metric]) -> String {
+    // all values of a metric go under one TYPE line
+    let mut sorted: Vec<&Metric> = metrics.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out = String::new();
+    let mut previous: Option<&str> = None;
+    for metric in sorted {
+        if previous != Some(metric.name.as_str()) {
+            let kind = match metric.kind {
+                MetricKind::Counter => "counter",
+                MetricKind::Gauge => "gauge",
+            };
+            let _ = writeln!(out, "# TYPE {} {}", metric.name, kind);
+            previous = Some(&metric.name);
+        }
+        out.push_str(&metric.name);
+        if !metric.labels.is_empty() {
+            let labels: Vec<String> = metric
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+                .collect();
+            let _ = write!(out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(out, " {}", metric.value);
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsParticipant, DdsReader, DdsWriter, TopicType};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize, Topic, Default)]
+    struct MetricsTopic {
+        #[topic_key]
+        id: u32,
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = vec![
+            Metric {
+                name: "a_total".to_owned(),
+                kind: MetricKind::Counter,
+                labels: vec![("topic", "x\"y".to_owned())],
+                value: 1,
+            },
+            Metric {
+                name: "a_total".to_owned(),
+                kind: MetricKind::Counter,
+                labels: vec![("topic", "z".to_owned())],
+                value: 2,
+            },
+            Metric {
+                name: "b".to_owned(),
+                kind: MetricKind::Gauge,
+                labels: Vec::new(),
+                value: 3,
+            },
+        ];
+        assert_eq!(
+            render_prometheus(&metrics),
+            "# TYPE a_total counter\na_total{topic=\"x\\\"y\"} 1\na_total{topic=\"z\"} 2\n# TYPE b gauge\nb 3\n"
+        );
+    }
+
+    #[test]
+    fn test_collect() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = MetricsTopic::create_topic(&participant, Some("metrics"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        writer.write(Arc::new(MetricsTopic { id: 1 })).unwrap();
+
+        let mut collector = MetricsCollector::new();
+        collector.add_writer(&writer).unwrap();
+        collector.add_reader(&reader).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let text = collector.render_prometheus();
+        let labels = format!(
+            "{{topic=\"{}\",guid=\"{}\"}}",
+            MetricsTopic::topic_name(Some("metrics")),
+            writer.guid().unwrap()
+        );
+        assert!(text.contains("# TYPE cyclonedds_rs_listener_panics_total counter"));
+        assert!(text.contains(&format!("cyclonedds_writer_rexmit_bytes_total{} ", labels)));
+        assert!(text.contains(&format!("cyclonedds_writer_matched{} 1", labels)));
+        assert!(text.contains("# TYPE cyclonedds_reader_matched gauge"));
+
+        drop(writer);
+        assert!(!collector.render_prometheus().contains("cyclonedds_writer_matched"));
+    }
+}
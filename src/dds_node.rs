@@ -0,0 +1,265 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! A node bundles what a typical application needs: a participant, a publisher
+//! and a subscriber with default QoS for the writers and readers created through
+//! it, and a [`WaitsetExecutor`] running the subscription callbacks.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example<T: TopicType + 'static>(sample: T) {
+//! let mut node = NodeBuilder::new().with_domain(0).create().unwrap();
+//! let mut writer = node.publish::<T>("chatter").unwrap();
+//! node.subscribe("chatter", |_sample: &T| println!("Got a sample")).unwrap();
+//! writer.write(std::sync::Arc::new(sample)).unwrap();
+//! loop {
+//!     node.spin_once(std::time::Duration::from_secs(1)).unwrap();
+//! }
+//! # }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use cyclonedds_sys::DdsDomainId;
+
+use crate::common::DRAIN_BATCH;
+use crate::dds_waitset::WaitsetToken;
+use crate::error::DDSError;
+use crate::serdes::TopicType;
+use crate::{
+    DdsParticipant, DdsPublisher, DdsQos, DdsReader, DdsSubscriber, DdsTopic, DdsWriter,
+    ParticipantBuilder, WaitsetExecutor,
+};
+
+pub struct NodeBuilder {
+    participant: ParticipantBuilder,
+    publisher_qos: Option<DdsQos>,
+    subscriber_qos: Option<DdsQos>,
+    reader_qos: Option<DdsQos>,
+    writer_qos: Option<DdsQos>,
+}
+
+impl NodeBuilder {
+    pub fn new() -> Self {
+        Self {
+            participant: ParticipantBuilder::new(),
+            publisher_qos: None,
+            subscriber_qos: None,
+            reader_qos: None,
+            writer_qos: None,
+        }
+    }
+
+    pub fn with_domain(mut self, domain: DdsDomainId) -> Self {
+        self.participant = self.participant.with_domain(domain);
+        self
+    }
+
+    /// Create the participant with this builder, for example to set the
+    /// participant QoS or discovery callbacks. This replaces `with_domain`.
+    pub fn with_participant(mut self, participant: ParticipantBuilder) -> Self {
+        self.participant = participant;
+        self
+    }
+
+    pub fn with_publisher_qos(mut self, qos: DdsQos) -> Self {
+        self.publisher_qos = Some(qos);
+        self
+    }
+
+    pub fn with_subscriber_qos(mut self, qos: DdsQos) -> Self {
+        self.subscriber_qos = Some(qos);
+        self
+    }
+
+    /// The QoS of the readers created by the node
+    pub fn with_reader_qos(mut self, qos: DdsQos) -> Self {
+        self.reader_qos = Some(qos);
+        self
+    }
+
+    /// The QoS of the writers created by the node
+    pub fn with_writer_qos(mut self, qos: DdsQos) -> Self {
+        self.writer_qos = Some(qos);
+        self
+    }
+
+    pub fn create(self) -> Result<Node, DDSError> {
+        let participant = self.participant.create()?;
+        let publisher = DdsPublisher::create(&participant, self.publisher_qos, None)?;
+        let subscriber = DdsSubscriber::create(&participant, self.subscriber_qos, None)?;
+        let executor = WaitsetExecutor::create(&participant)?;
+        Ok(Node {
+            executor,
+            publisher,
+            subscriber,
+            participant,
+            reader_qos: self.reader_qos,
+            writer_qos: self.writer_qos,
+        })
+    }
+}
+
+impl Default for NodeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A participant with a default publisher and subscriber. The callbacks of
+/// subscriptions run on the thread calling [`Node::spin_once`] or [`Node::spin_until`].
+pub struct Node {
+    // the readers owned by the executor go before the subscriber and participant
+    executor: WaitsetExecutor,
+    publisher: DdsPublisher,
+    subscriber: DdsSubscriber,
+    participant: DdsParticipant,
+    reader_qos: Option<DdsQos>,
+    writer_qos: Option<DdsQos>,
+}
+
+impl Node {
+    /// A node in the given domain, or the default domain, with default QoS
+    pub fn create(maybe_domain: Option<DdsDomainId>) -> Result<Self, DDSError> {
+        match maybe_domain {
+            Some(domain) => NodeBuilder::new().with_domain(domain).create(),
+            None => NodeBuilder::new().create(),
+        }
+    }
+
+    /// Create a topic of type T in the participant of the node
+    pub fn topic<T: TopicType>(&self, name: &str) -> Result<DdsTopic<T>, DDSError> {
+        DdsTopic::create(&self.participant, name, None, None)
+    }
+
+    /// Create a writer on the default publisher
+    pub fn publish<T: TopicType>(&self, topic_name: &str) -> Result<DdsWriter<T>, DDSError> {
+        self.publish_with_qos(topic_name, self.writer_qos.clone())
+    }
+
+    /// Create a writer on the default publisher with the given QoS instead of
+    /// the default writer QoS of the node
+    pub fn publish_with_qos<T: TopicType>(
+        &self,
+        topic_name: &str,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<DdsWriter<T>, DDSError> {
+        DdsWriter::create(&self.publisher, self.topic(topic_name)?, maybe_qos, None)
+    }
+
+    /// Create a reader on the default subscriber. Use this to read samples
+    /// yourself, [`Node::subscribe`] hands the reader to the node.
+    pub fn reader<T: TopicType>(&self, topic_name: &str) -> Result<DdsReader<T>, DDSError> {
+        self.reader_with_qos(topic_name, self.reader_qos.clone())
+    }
+
+    /// Create a reader on the default subscriber with the given QoS instead of
+    /// the default reader QoS of the node
+    pub fn reader_with_qos<T: TopicType>(
+        &self,
+        topic_name: &str,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<DdsReader<T>, DDSError> {
+        DdsReader::create(&self.subscriber, self.topic(topic_name)?, maybe_qos, None)
+    }
+
+    /// Call `on_sample` for every valid sample received on the topic. Returns a
+    /// token to cancel the subscription with [`Node::unsubscribe`].
+    pub fn subscribe<T, F>(&mut self, topic_name: &str, on_sample: F) -> Result<WaitsetToken, DDSError>
+    where
+        T: TopicType + 'static,
+        F: FnMut(&T) + 'static,
+    {
+        let reader = self.reader(topic_name)?;
+        self.executor.add_reader(reader, DRAIN_BATCH, on_sample)
+    }
+
+    /// Cancel a subscription, this deletes its reader
+    pub fn unsubscribe(&mut self, token: WaitsetToken) -> Result<(), DDSError> {
+        self.executor.remove(token)
+    }
+
+    /// Wait for at most `timeout` and run the callbacks of the subscriptions that
+    /// received data. Returns the number of callbacks that were run.
+    pub fn spin_once(&mut self, timeout: Duration) -> Result<usize, DDSError> {
+        self.executor.spin_once(timeout)
+    }
+
+    /// Keep running callbacks until the deadline is reached
+    pub fn spin_until(&mut self, deadline: Instant) -> Result<(), DDSError> {
+        self.executor.spin_until(deadline)
+    }
+
+    pub fn participant(&self) -> &DdsParticipant {
+        &self.participant
+    }
+
+    pub fn publisher(&self) -> &DdsPublisher {
+        &self.publisher
+    }
+
+    pub fn subscriber(&self) -> &DdsSubscriber {
+        &self.subscriber
+    }
+
+    /// The executor running the subscriptions, to add status callbacks
+    pub fn executor(&mut self) -> &mut WaitsetExecutor {
+        &mut self.executor
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Entity;
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize, Topic, Debug, PartialEq, Default)]
+    struct NodeTopic {
+        #[topic_key]
+        id: u32,
+    }
+
+    #[test]
+    fn test_node() {
+        let mut node = Node::create(None).unwrap();
+        assert_eq!(
+            node.subscriber().parent().unwrap().entity(),
+            Entity::entity(node.participant()).entity()
+        );
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let token = node
+            .subscribe("node", move |sample: &NodeTopic| sink.borrow_mut().push(sample.id))
+            .unwrap();
+        let mut writer = node.publish::<NodeTopic>("node").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        writer.write(Arc::new(NodeTopic { id: 1 })).unwrap();
+        node.spin_until(Instant::now() + Duration::from_millis(200)).unwrap();
+        assert_eq!(*received.borrow(), vec![1]);
+
+        node.unsubscribe(token).unwrap();
+        writer.write(Arc::new(NodeTopic { id: 2 })).unwrap();
+        node.spin_until(Instant::now() + Duration::from_millis(100)).unwrap();
+        assert_eq!(*received.borrow(), vec![1]);
+    }
+}
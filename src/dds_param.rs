@@ -0,0 +1,248 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Named parameters shared through a topic. The topic is TRANSIENT_LOCAL with a
+//! history of one sample per parameter, so a store created later receives the
+//! current value of every parameter from the stores that set them, as long as
+//! those are still running.
+//!
+//! Values can be of any serde type, they are stored as CDR.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! let participant = DdsParticipant::create(None, None, None).unwrap();
+//! let mut params = ParamStore::create(&participant, "robot_config").unwrap();
+//! params.set("max_speed", &1.5f64).unwrap();
+//! params.watch("max_speed", |speed: f64| println!("max speed is now {}", speed));
+//! let speed: Option<f64> = params.get("max_speed").unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use cdr::{CdrBe, Infinite};
+use cyclonedds_sys::{dds_durability_kind, dds_history_kind, dds_reliability_kind};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::DRAIN_BATCH;
+use crate::dds_telemetry::{count, Counter};
+use crate::error::{DDSError, SerdesError};
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::{DdsListener, DdsParticipant, DdsQos, DdsReader, DdsTopic, DdsWriter};
+
+/// A parameter as sent on the topic, the value is the CDR of the parameter value
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+impl TopicType for Parameter {
+    fn typename() -> CString {
+        CString::new("dds_param::Parameter").expect("Unable to create CString for type name")
+    }
+
+    fn has_key() -> bool {
+        true
+    }
+
+    fn key_cdr(&self) -> Vec<u8> {
+        cdr::serialize::<_, _, CdrBe>(&self.name, Infinite).expect("Unable to serialize key")
+    }
+
+    // the name is unbounded
+    fn force_md5_keyhash() -> bool {
+        true
+    }
+
+    fn key_fields() -> Vec<String> {
+        vec!["name".to_owned()]
+    }
+}
+
+// Late joiners get the last value of every parameter
+fn param_qos() -> Result<DdsQos, DDSError> {
+    let mut qos = DdsQos::create()?;
+    qos.set_durability(dds_durability_kind::DDS_DURABILITY_TRANSIENT_LOCAL)
+        .set_reliability(
+            dds_reliability_kind::DDS_RELIABILITY_RELIABLE,
+            Duration::from_millis(100),
+        )
+        .set_history(dds_history_kind::DDS_HISTORY_KEEP_LAST, 1);
+    Ok(qos)
+}
+
+type Watcher = Box<dyn FnMut(&[u8]) + Send + 'static>;
+
+#[derive(Default)]
+struct Params {
+    values: Mutex<HashMap<String, Vec<u8>>>,
+    watchers: Mutex<HashMap<String, Vec<Watcher>>>,
+}
+
+impl Params {
+    fn received(&self, param: &Parameter) {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(param.name.clone(), param.value.clone());
+        if let Some(watchers) = self.watchers.lock().unwrap().get_mut(&param.name) {
+            for watcher in watchers.iter_mut() {
+                watcher(&param.value);
+            }
+        }
+    }
+}
+
+/// A set of named parameters shared with every store using the same topic
+pub struct ParamStore {
+    writer: DdsWriter<Parameter>,
+    _reader: DdsReader<Parameter>,
+    params: Arc<Params>,
+}
+
+impl ParamStore {
+    /// Create a store on the topic `topic_name`
+    pub fn create(participant: &DdsParticipant, topic_name: &str) -> Result<Self, DDSError> {
+        let qos = param_qos()?;
+        let topic = DdsTopic::<Parameter>::create(participant, topic_name, Some(qos.clone()), None)?;
+        let params = Arc::new(Params::default());
+
+        let listener_params = params.clone();
+        let mut buffer = SampleBuffer::<Parameter>::new(DRAIN_BATCH);
+        let listener = DdsListener::new()
+            .on_data_available(move |entity| {
+                let n = DdsReader::take_all_from_entity(&entity, &mut buffer, 0).unwrap_or(0);
+                for i in 0..n {
+                    if let Some(param) = buffer.get(i).try_deref() {
+                        listener_params.received(param);
+                    }
+                }
+            })
+            .hook();
+
+        let writer = DdsWriter::create(participant, topic.clone(), Some(qos.clone()), None)?;
+        let reader = DdsReader::create(participant, topic, Some(qos), Some(listener))?;
+        Ok(Self {
+            writer,
+            _reader: reader,
+            params,
+        })
+    }
+
+    /// Set a parameter in every store on the topic
+    pub fn set<V: Serialize>(&mut self, name: &str, value: &V) -> Result<(), DDSError> {
+        let value = cdr::serialize::<_, _, CdrBe>(value, Infinite).map_err(|e| {
+            DDSError::from(SerdesError::new(
+                std::any::type_name::<V>().to_owned(),
+                0,
+                e.to_string(),
+            ))
+        })?;
+        self.params
+            .values
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), value.clone());
+        self.writer.write(Arc::new(Parameter {
+            name: name.to_owned(),
+            value,
+        }))
+    }
+
+    /// The current value of a parameter, `None` if the parameter was never set.
+    /// Fails if the value cannot be deserialized as `V`.
+    pub fn get<V: DeserializeOwned>(&self, name: &str) -> Result<Option<V>, DDSError> {
+        match self.params.values.lock().unwrap().get(name) {
+            Some(value) => decode(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The names of the parameters that were set
+    pub fn names(&self) -> Vec<String> {
+        self.params.values.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Call `on_change` with every new value of the parameter, starting with the
+    /// current value if the parameter is set. The callback runs on a cyclonedds
+    /// thread. Values that cannot be deserialized as `V` are skipped.
+    pub fn watch<V, F>(&self, name: &str, mut on_change: F)
+    where
+        V: DeserializeOwned,
+        F: FnMut(V) + Send + 'static,
+    {
+        // hold the watchers so no new value is missed while calling with the current one
+        let mut watchers = self.params.watchers.lock().unwrap();
+        if let Some(value) = self.params.values.lock().unwrap().get(name) {
+            if let Ok(value) = decode(value) {
+                on_change(value);
+            }
+        }
+        watchers
+            .entry(name.to_owned())
+            .or_default()
+            .push(Box::new(move |value| match decode(value) {
+                Ok(value) => on_change(value),
+                Err(e) => {
+                    count(Counter::DeserializeFailures);
+                    crate::dds_log::warn_failed(format_args!("cannot deserialize parameter"), &e);
+                }
+            }));
+    }
+}
+
+fn decode<V: DeserializeOwned>(value: &[u8]) -> Result<V, DDSError> {
+    cdr::deserialize::<V>(value).map_err(|e| {
+        DDSError::from(SerdesError::new(
+            std::any::type_name::<V>().to_owned(),
+            value.len(),
+            e.to_string(),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_late_joiner() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let mut first = ParamStore::create(&participant, "params").unwrap();
+        first.set("speed", &1.5f64).unwrap();
+        first.set("name", &"robot".to_owned()).unwrap();
+        assert_eq!(first.get::<f64>("speed").unwrap(), Some(1.5));
+        assert_eq!(first.get::<f64>("missing").unwrap(), None);
+
+        let other = DdsParticipant::create(None, None, None).unwrap();
+        let second = ParamStore::create(&other, "params").unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(second.get::<f64>("speed").unwrap(), Some(1.5));
+        assert_eq!(second.get::<String>("name").unwrap(), Some("robot".to_owned()));
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        second.watch("speed", move |speed: f64| sink.lock().unwrap().push(speed));
+        first.set("speed", &2.5f64).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(*seen.lock().unwrap(), vec![1.5, 2.5]);
+    }
+}
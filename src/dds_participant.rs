@@ -14,9 +14,15 @@
     limitations under the License.
 */
 
+use std::collections::HashMap;
 use std::convert::From;
-pub use cyclonedds_sys::{DDSError, DdsDomainId, DdsEntity};
-use crate::{DdsReadable, DdsWritable, Entity, dds_listener::DdsListener, dds_qos::DdsQos};
+use std::sync::{Arc, Mutex, Weak};
+pub use cyclonedds_sys::{DdsDomainId, DdsEntity};
+pub use crate::error::DDSError;
+use crate::{DdsReadable, DdsWritable, Entity, Guid, dds_domain::{DdsDomain, DOMAIN_DEFAULT}, dds_listener::DdsListener, dds_qos::DdsQos};
+use crate::common::{EntityWrapper, Uses};
+use crate::dds_security::{SecurityConfig, SecurityConfigError};
+use crate::dds_builtin::{DiscoveryCallbacks, DiscoveryReaders, EndpointBuiltinTopicData, EndpointKind, ParticipantBuiltinTopicData};
 
 /// Builder struct for a Participant. 
 /// #Example
@@ -38,8 +44,11 @@ use crate::{DdsReadable, DdsWritable, Entity, dds_listener::DdsListener, dds_qos
 ///
 pub struct ParticipantBuilder {
     maybe_domain : Option<DdsDomainId>,
+    maybe_dds_domain : Option<DdsDomain>,
     maybe_qos : Option<DdsQos>,
     maybe_listener : Option<DdsListener>,
+    maybe_security : Option<SecurityConfig>,
+    discovery : DiscoveryCallbacks,
 }
 
 impl ParticipantBuilder {
@@ -47,8 +56,11 @@ impl ParticipantBuilder {
     pub fn new() -> Self {
         ParticipantBuilder {
             maybe_domain: None,
+            maybe_dds_domain: None,
             maybe_qos: None,
             maybe_listener: None,
+            maybe_security: None,
+            discovery: DiscoveryCallbacks::default(),
         }
     }
 
@@ -57,6 +69,13 @@ impl ParticipantBuilder {
         self
     }
 
+    /// Create the participant in an explicitly created domain. The
+    /// participant keeps the domain alive. This overrides `with_domain`.
+    pub fn with_dds_domain(mut self, domain: &DdsDomain) -> Self {
+        self.maybe_dds_domain = Some(domain.clone());
+        self
+    }
+
     pub fn with_qos(mut self, qos : DdsQos) -> Self {
         self.maybe_qos = Some(qos);
         self
@@ -67,66 +86,368 @@ impl ParticipantBuilder {
         self
     }
 
+    /// Enable DDS Security. The security properties are added to the QoS
+    /// given with `with_qos`.
+    pub fn with_security(mut self, security: SecurityConfig) -> Self {
+        self.maybe_security = Some(security);
+        self
+    }
+
+    /// Called for every participant that is discovered, including this one.
+    pub fn on_participant_discovered<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&ParticipantBuiltinTopicData) + Send + 'static,
+    {
+        self.discovery.on_participant_discovered = Some(Box::new(callback));
+        self
+    }
+
+    /// Called with the GUID of a participant that was deleted or lost
+    pub fn on_participant_lost<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&Guid) + Send + 'static,
+    {
+        self.discovery.on_participant_lost = Some(Box::new(callback));
+        self
+    }
+
+    /// Called for every reader and writer that is discovered, including
+    /// the local ones.
+    pub fn on_endpoint_discovered<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(EndpointKind, &EndpointBuiltinTopicData) + Send + 'static,
+    {
+        self.discovery.on_endpoint_discovered = Some(Box::new(callback));
+        self
+    }
+
+    /// Called with the GUID of a reader or writer that was deleted or lost
+    pub fn on_endpoint_lost<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(EndpointKind, &Guid) + Send + 'static,
+    {
+        self.discovery.on_endpoint_lost = Some(Box::new(callback));
+        self
+    }
+
     pub fn create(self) -> Result<DdsParticipant, DDSError> {
-        DdsParticipant::create(self.maybe_domain, self.maybe_qos, self.maybe_listener)
+        let maybe_qos = match &self.maybe_security {
+            Some(security) => {
+                let mut qos = match self.maybe_qos {
+                    Some(qos) => qos,
+                    None => DdsQos::create()?,
+                };
+                security.apply(&mut qos)?;
+                Some(qos)
+            }
+            None => self.maybe_qos,
+        };
+
+        let created = if let Some(domain) = &self.maybe_dds_domain {
+            DdsParticipant::create_in_domain(domain, maybe_qos, self.maybe_listener)
+        } else {
+            DdsParticipant::create(self.maybe_domain, maybe_qos, self.maybe_listener)
+        };
+        let mut participant = match created {
+            Err(e) if self.maybe_security.is_some() && rejected_by_security(&e) => {
+                return Err(e.with_source(SecurityConfigError::Rejected))
+            }
+            created => created?,
+        };
+
+        if !self.discovery.is_empty() {
+            match DiscoveryReaders::create(participant.inner.entity(), self.discovery) {
+                Ok(readers) => participant.discovery = Some(readers),
+                Err(e) => {
+                    let _ = participant.close();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(participant)
     }
 }
 
+// Cyclone fails dds_create_participant with a generic error when the
+// security plugins reject the configuration.
+fn rejected_by_security(e: &DDSError) -> bool {
+    let in_create = match e {
+        DDSError::Context(context) => context.operation() == Some("dds_create_participant"),
+        _ => false,
+    };
+    in_create && matches!(e.kind(), DDSError::Error | DDSError::NotAllowedBySecurity)
+}
 
-pub struct DdsParticipant(DdsEntity, Option<DdsListener>);
+/// A participant. The participant and everything in it is deleted once the
+/// participant and the publishers, subscribers and topics created from it are
+/// dropped, or when it is closed with [`DdsParticipant::close`].
+pub struct DdsParticipant {
+    // dropped first, the builtin readers for discovery callbacks are
+    // children of the participant
+    discovery: Option<DiscoveryReaders>,
+    inner: EntityWrapper,
+}
 
 impl DdsParticipant {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err))]
     pub fn create(
         maybe_domain: Option<DdsDomainId>,
         maybe_qos: Option<DdsQos>,
         maybe_listener: Option<DdsListener>,
     ) -> Result<Self, DDSError> {
+        Self::create_with_domain(maybe_domain, None, maybe_qos, maybe_listener)
+    }
+
+    fn create_with_domain(
+        maybe_domain: Option<DdsDomainId>,
+        dds_domain: Option<DdsDomain>,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<Self, DDSError> {
 
         unsafe {
             let p = cyclonedds_sys::dds_create_participant(
-                maybe_domain.unwrap_or(0xFFFF_FFFF),
+                maybe_domain.unwrap_or(DOMAIN_DEFAULT),
                 maybe_qos.map_or(std::ptr::null(), |d| d.into()),
                 maybe_listener.as_ref().map_or(std::ptr::null(), |l| l.into()),
             );
             if p > 0 {
-                Ok(DdsParticipant(DdsEntity::new(p), maybe_listener))
+                Ok(Self::from_inner(EntityWrapper::new(
+                    "participant",
+                    DdsEntity::new(p),
+                    None,
+                    maybe_listener,
+                    dds_domain.map_or(Uses::Nothing, Uses::Domain),
+                )))
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_create_participant", p).with_entity_kind("participant"))
             }
         }
     }
-}
 
-/* 
-impl Drop for DdsParticipant {
-    fn drop(&mut self) {
+    /// Create a participant in an explicitly created domain. The participant
+    /// keeps the domain alive.
+    pub fn create_in_domain(
+        domain: &DdsDomain,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<Self, DDSError> {
+        Self::create_with_domain(Some(domain.id()), Some(domain.clone()), maybe_qos, maybe_listener)
+    }
+
+    fn from_inner(inner: EntityWrapper) -> Self {
+        DdsParticipant {
+            discovery: None,
+            inner,
+        }
+    }
+
+    /// Held by the entities created from the participant, so the participant
+    /// is not deleted under them
+    pub(crate) fn keep_alive(&self) -> EntityWrapper {
+        self.inner.clone()
+    }
+
+    /// The explicitly created domain this participant belongs to, if any
+    pub fn domain(&self) -> Option<&DdsDomain> {
+        self.inner.domain()
+    }
+
+    /// The id of the domain this participant belongs to
+    pub fn domain_id(&self) -> Result<DdsDomainId, DDSError> {
+        self.inner.check_valid()?;
+        let mut id: DdsDomainId = 0;
         unsafe {
-            let ret: DDSError = cyclonedds_sys::dds_delete(self.0.entity()).into();
-            if DDSError::DdsOk != ret {
-                panic!("cannot delete participant: {}", ret);
+            let ret = cyclonedds_sys::dds_get_domainid(self.inner.entity().entity(), &mut id);
+            if ret == 0 {
+                Ok(id)
             } else {
-                //println!("Participant dropped");
+                Err(DDSError::from_retcode("dds_get_domainid", ret).with_entity_kind("participant"))
+            }
+        }
+    }
+
+    /// Assert the liveliness of the participant. This is needed for writers with
+    /// MANUAL_BY_PARTICIPANT liveliness that do not write often enough.
+    pub fn assert_liveliness(&self) -> Result<(), DDSError> {
+        self.inner.check_valid()?;
+        unsafe {
+            let ret = cyclonedds_sys::dds_assert_liveliness(self.inner.entity().entity());
+            if ret == 0 {
+                Ok(())
+            } else {
+                Err(DDSError::from_retcode("dds_assert_liveliness", ret).with_entity_kind("participant"))
+            }
+        }
+    }
+
+    /// The handles of all participants in this process that are on the given domain.
+    pub fn enumerate(domain_id: DdsDomainId) -> Result<Vec<DdsEntity>, DDSError> {
+        crate::common::entity_list("dds_lookup_participant", |buf, size| unsafe {
+            cyclonedds_sys::dds_lookup_participant(domain_id, buf, size)
+        })
+    }
+
+    /// Delete the participant and everything created under it. Readers and
+    /// writers are deleted first, then publishers, subscribers and topics and
+    /// finally the participant itself, so the outcome does not depend on the order in
+    /// which the wrappers are dropped. All entities are deleted even if some
+    /// deletions fail; the first failure is returned. Closing a participant
+    /// again returns `DDSError::UseAfterClose`, as do the functions of the
+    /// participant and its clones afterwards.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err))]
+    pub fn close(&mut self) -> Result<(), DDSError> {
+        self.inner.invalidate()?;
+
+        let mut result = Ok(());
+        let mut delete = |entity: &DdsEntity| {
+            let ret: DDSError = unsafe { cyclonedds_sys::dds_delete(entity.entity()).into() };
+            // children deleted by an earlier step are already gone
+            if ret != DDSError::DdsOk && ret != DDSError::AlreadyDeleted && result.is_ok() {
+                result = Err(ret);
+            }
+        };
+
+        let children = Entity::children(self).unwrap_or_default();
+        for child in &children {
+            for grandchild in child.children().unwrap_or_default() {
+                delete(&grandchild);
             }
         }
+        for child in &children {
+            delete(child);
+        }
+        delete(self.inner.entity());
+        result
+    }
+
+    /// true if the participant was closed with `close`
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Find a participant that already exists in this process on the given domain
+    /// (or the default domain), so a library can reuse the participant of
+    /// the application instead of creating its own. The returned participant
+    /// refers to the existing entity and does not own it.
+    pub fn lookup(maybe_domain: Option<DdsDomainId>) -> Result<Option<Self>, DDSError> {
+        let handles = Self::enumerate(maybe_domain.unwrap_or(DOMAIN_DEFAULT))?;
+        Ok(handles
+            .into_iter()
+            .next()
+            .map(|entity| Self::from_inner(EntityWrapper::unowned("participant", entity))))
+    }
+}
+
+/// Registry of shared participants, one per domain id.
+static SHARED_PARTICIPANTS: Mutex<Option<HashMap<DdsDomainId, Weak<SharedParticipant>>>> =
+    Mutex::new(None);
+
+/// A participant that is shared by independent components of an application.
+/// Get one with `SharedParticipant::get`. The participant is deleted like any
+/// other once the last reference is dropped.
+pub struct SharedParticipant(DdsParticipant);
+
+impl SharedParticipant {
+    /// Return the shared participant for the domain (or the default domain),
+    /// creating it if it does not exist yet.
+    pub fn get(maybe_domain: Option<DdsDomainId>) -> Result<Arc<SharedParticipant>, DDSError> {
+        let domain_id = maybe_domain.unwrap_or(DOMAIN_DEFAULT);
+        let mut registry = SHARED_PARTICIPANTS.lock().unwrap();
+        let registry = registry.get_or_insert_with(HashMap::new);
+
+        if let Some(participant) = registry.get(&domain_id).and_then(|p| p.upgrade()) {
+            return Ok(participant);
+        }
+
+        let participant = Arc::new(SharedParticipant(DdsParticipant::create(
+            maybe_domain,
+            None,
+            None,
+        )?));
+        registry.insert(domain_id, Arc::downgrade(&participant));
+        // forget participants that were dropped
+        registry.retain(|_, p| p.strong_count() > 0);
+        Ok(participant)
+    }
+}
+
+impl std::ops::Deref for SharedParticipant {
+    type Target = DdsParticipant;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DdsWritable for SharedParticipant {
+    fn entity(&self) -> &DdsEntity {
+        self.0.inner.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.inner.check_valid()
+    }
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        Some(self.0.keep_alive())
+    }
+}
+
+impl DdsReadable for SharedParticipant {
+    fn entity(&self) -> &DdsEntity {
+        self.0.inner.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.inner.check_valid()
+    }
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        Some(self.0.keep_alive())
+    }
+}
+
+impl Entity for SharedParticipant {
+    fn entity(&self) -> &DdsEntity {
+        self.0.inner.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.inner.check_valid()
     }
 }
-*/
 
 impl DdsWritable for DdsParticipant {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.inner.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.inner.check_valid()
+    }
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        Some(self.keep_alive())
     }
 }
 
 impl DdsReadable for DdsParticipant {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.inner.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.inner.check_valid()
+    }
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        Some(self.keep_alive())
     }
 }
 
 impl Entity for DdsParticipant {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.inner.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.inner.check_valid()
     }
 }
 
@@ -140,4 +461,240 @@ mod dds_participant_tests {
         qos.set_lifespan(std::time::Duration::from_nanos(1000));
         let _par = DdsParticipant::create(None, Some(qos), None);
     }
+
+    #[test]
+    fn test_domain_id_and_enumerate() {
+        let participant = DdsParticipant::create(Some(7), None, None).unwrap();
+        assert_eq!(participant.domain_id().unwrap(), 7);
+
+        let handles = DdsParticipant::enumerate(7).unwrap();
+        assert!(handles
+            .iter()
+            .any(|h| unsafe { h.entity() == participant.inner.entity().entity() }));
+    }
+
+    #[test]
+    fn test_assert_liveliness() {
+        let mut participant = DdsParticipant::create(None, None, None).unwrap();
+        assert!(participant.assert_liveliness().is_ok());
+        participant.close().unwrap();
+        assert_eq!(participant.assert_liveliness(), Err(DDSError::UseAfterClose));
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert!(DdsParticipant::lookup(Some(9)).unwrap().is_none());
+
+        let participant = DdsParticipant::create(Some(9), None, None).unwrap();
+        let found = DdsParticipant::lookup(Some(9)).unwrap().expect("participant not found");
+        unsafe {
+            assert_eq!(found.inner.entity().entity(), participant.inner.entity().entity());
+        }
+        // the found participant can be used to create entities
+        assert!(crate::DdsSubscriber::create(&found, None, None).is_ok());
+    }
+
+    #[test]
+    fn test_rejected_by_security() {
+        assert!(rejected_by_security(&DDSError::from_retcode("dds_create_participant", -1)));
+        assert!(rejected_by_security(&DDSError::from_retcode("dds_create_participant", -13)));
+        // a bad domain id is not a security problem
+        assert!(!rejected_by_security(&DDSError::from_retcode("dds_create_participant", -3)));
+        assert!(!rejected_by_security(&DDSError::from_retcode("dds_create_domain", -1)));
+    }
+
+    #[test]
+    fn test_close() {
+        use crate::{DdsPublisher, DdsSubscriber, DdsWriter, DdsReader, SampleBuffer};
+        use cdds_derive::Topic;
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct CloseTopic {
+            #[topic_key]
+            id: u32,
+        }
+
+        let mut participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = CloseTopic::create_topic(&participant, None, None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+
+        assert!(participant.close().is_ok());
+        assert!(participant.is_closed());
+        assert_eq!(participant.close(), Err(DDSError::UseAfterClose));
+        assert_eq!(participant.domain_id(), Err(DDSError::UseAfterClose));
+        // the id is not handed to cyclone any more, it may belong to a new entity
+        assert_eq!(participant.guid(), Err(DDSError::UseAfterClose));
+        assert!(matches!(
+            DdsPublisher::create(&participant, None, None),
+            Err(DDSError::UseAfterClose)
+        ));
+        // so are the children deleted with it
+        assert_eq!(publisher.guid(), Err(DDSError::UseAfterClose));
+        assert_eq!(subscriber.close(), Err(DDSError::UseAfterClose));
+        assert_eq!(
+            writer.write(std::sync::Arc::new(CloseTopic { id: 1 })),
+            Err(DDSError::UseAfterClose)
+        );
+        let mut buffer = SampleBuffer::new(1);
+        assert_eq!(reader.take_now(&mut buffer), Err(DDSError::UseAfterClose));
+        assert!(matches!(reader.take_loan(1), Err(DDSError::UseAfterClose)));
+    }
+
+    #[test]
+    fn test_children_keep_participant_alive() {
+        use crate::{DdsPublisher, DdsSubscriber};
+
+        let alive = |entity: cyclonedds_sys::dds_entity_t| unsafe { cyclonedds_sys::dds_get_parent(entity) } > 0;
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let handle = unsafe { participant.inner.entity().entity() };
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let subscriber_handle = unsafe { Entity::entity(&subscriber).entity() };
+
+        drop(participant);
+        assert!(alive(handle));
+
+        // clones share the subscriber
+        let clone = subscriber.clone();
+        drop(subscriber);
+        assert!(alive(subscriber_handle));
+        drop(clone);
+        assert!(!alive(subscriber_handle));
+
+        assert!(alive(handle));
+        drop(publisher);
+        assert!(!alive(handle));
+    }
+
+    #[test]
+    fn test_readers_keep_subscriber_alive() {
+        use crate::{DdsReader, DdsSubscriber, DdsWriter};
+        use cdds_derive::Topic;
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct KeptTopic {
+            #[topic_key]
+            id: u32,
+        }
+
+        let alive = |entity: cyclonedds_sys::dds_entity_t| unsafe { cyclonedds_sys::dds_get_parent(entity) } > 0;
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = KeptTopic::create_topic(&participant, None, None, None).unwrap();
+        // the subscriber and publisher handles are dropped right away
+        let reader = DdsReader::create(&DdsSubscriber::create(&participant, None, None).unwrap(), topic.clone(), None, None).unwrap();
+        let writer = DdsWriter::create(&crate::DdsPublisher::create(&participant, None, None).unwrap(), topic, None, None).unwrap();
+        let reader_handle = unsafe { reader.entity().entity() };
+        let writer_handle = unsafe { writer.entity().entity() };
+        assert!(alive(reader_handle));
+        assert!(alive(writer_handle));
+
+        let subscriber_handle = unsafe { cyclonedds_sys::dds_get_parent(reader_handle) };
+        drop(reader);
+        assert!(!alive(subscriber_handle));
+        drop(writer);
+        assert!(!alive(writer_handle));
+    }
+
+    #[test]
+    fn test_shared_participant() {
+        let a = SharedParticipant::get(Some(12)).unwrap();
+        let b = SharedParticipant::get(Some(12)).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+
+        let other = SharedParticipant::get(Some(13)).unwrap();
+        assert!(!Arc::ptr_eq(&a, &other));
+        assert_eq!(other.domain_id().unwrap(), 13);
+
+        // usable wherever a participant is expected
+        assert!(crate::DdsPublisher::create(&a, None, None).is_ok());
+
+        let handle = Entity::instance_handle(&*a).unwrap();
+        drop(a);
+        drop(b);
+        let c = SharedParticipant::get(Some(12)).unwrap();
+        assert_ne!(Entity::instance_handle(&*c).unwrap(), handle);
+    }
+
+    // Topics, readers and writers of several types created and dropped on one
+    // participant from many threads. The threads share the topic names, so
+    // cyclone registers and looks up the same sertypes concurrently.
+    #[test]
+    fn stress_entities_on_shared_participant() {
+        use crate::test_support::LoopbackDomain;
+        use crate::TopicType;
+        use cdds_derive::Topic;
+        use serde_derive::{Deserialize, Serialize};
+        use std::fmt::Debug;
+        use std::time::Duration;
+
+        #[derive(Serialize, Deserialize, Topic, Default, Clone, PartialEq, Debug)]
+        struct Counter {
+            #[topic_key]
+            id: u32,
+        }
+
+        #[derive(Serialize, Deserialize, Topic, Default, Clone, PartialEq, Debug)]
+        struct Reading {
+            #[topic_key]
+            name: String,
+            value: f64,
+        }
+
+        #[derive(Serialize, Deserialize, Topic, Default, Clone, PartialEq, Debug)]
+        struct Blob {
+            data: Vec<u8>,
+        }
+
+        #[derive(Serialize, Deserialize, Topic, Default, Clone, PartialEq, Debug)]
+        struct Pair {
+            #[topic_key]
+            a: u8,
+            #[topic_key]
+            b: u64,
+        }
+
+        fn round_trip<T: TopicType + Default + Clone + PartialEq + Debug>(domain: &LoopbackDomain, name: &str) {
+            let topic = domain.topic::<T>(name).unwrap();
+            let reader = domain.reader(topic.clone()).unwrap();
+            let mut writer = domain.writer(topic).unwrap();
+            domain.assert_received(&mut writer, &reader, T::default(), Duration::from_secs(5));
+        }
+
+        let domain = LoopbackDomain::create().unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let domain = &domain;
+                scope.spawn(move || {
+                    for i in 0..40 {
+                        let n = i % 3;
+                        match (thread + i) % 4 {
+                            0 => round_trip::<Counter>(domain, &format!("stress_counter_{}", n)),
+                            1 => round_trip::<Reading>(domain, &format!("stress_reading_{}", n)),
+                            2 => round_trip::<Blob>(domain, &format!("stress_blob_{}", n)),
+                            _ => round_trip::<Pair>(domain, &format!("stress_pair_{}", n)),
+                        }
+                    }
+                });
+            }
+        });
+
+        // the topics went with their last reader and writer
+        for name in ["stress_counter_0", "stress_reading_1", "stress_blob_2", "stress_pair_0"] {
+            let found = crate::DdsFoundTopic::find(
+                domain.participant(),
+                name,
+                crate::FindScope::Participant,
+                Duration::from_millis(0),
+            )
+            .unwrap();
+            assert!(found.is_none(), "{} was not deleted", name);
+        }
+    }
 }
@@ -14,10 +14,14 @@
     limitations under the License.
 */
 
+use crate::dds_coherent::CoherentSet;
 use crate::{DdsListener, DdsParticipant, DdsQos, DdsWritable};
-pub use cyclonedds_sys::{DDSError, DdsDomainId, DdsEntity};
+pub use cyclonedds_sys::{DdsDomainId, DdsEntity};
+pub use crate::error::DDSError;
 use std::convert::From;
 
+use crate::common::{EntityWrapper, Uses};
+
 pub struct PublisherBuilder {
     maybe_qos: Option<DdsQos>,
     maybe_listener: Option<DdsListener>,
@@ -47,15 +51,19 @@ impl PublisherBuilder {
 }
 
 
+/// A publisher. Clones share the publisher, which is deleted once the last clone
+/// and the last of its writers are dropped. The publisher keeps its participant alive.
 #[derive(Clone)]
-pub struct DdsPublisher(DdsEntity, Option<DdsListener>);
+pub struct DdsPublisher(EntityWrapper);
 
 impl<'a> DdsPublisher {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err))]
     pub fn create(
         participant: &DdsParticipant,
         maybe_qos: Option<DdsQos>,
         maybe_listener: Option<DdsListener>,
     ) -> Result<Self, DDSError> {
+        crate::Entity::check_valid(participant)?;
         unsafe {
             let p = cyclonedds_sys::dds_create_publisher(
                 participant.entity().entity(),
@@ -63,17 +71,52 @@ impl<'a> DdsPublisher {
                 maybe_listener.as_ref().map_or(std::ptr::null(), |l| l.into()),
             );
             if p > 0 {
-                Ok(DdsPublisher(DdsEntity::new(p), maybe_listener))
+                Ok(DdsPublisher(EntityWrapper::new(
+                    "publisher",
+                    DdsEntity::new(p),
+                    Some(participant.keep_alive()),
+                    maybe_listener,
+                    Uses::Nothing,
+                )))
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_create_publisher", p).with_entity_kind("publisher"))
             }
         }
     }
+
+    /// Delete the publisher and its writers now, instead of when the last clone
+    /// is dropped. Closing it again returns `DDSError::UseAfterClose`, as
+    /// do the functions of the publisher and its clones afterwards.
+    pub fn close(&self) -> Result<(), DDSError> {
+        self.0.close()
+    }
+
+    /// Start a coherent set over the writers of the publisher
+    pub fn begin_coherent(&self) -> Result<CoherentSet, DDSError> {
+        CoherentSet::begin(self, "publisher")
+    }
 }
 
 impl<'a> DdsWritable for DdsPublisher {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
+    }
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        Some(self.0.clone())
+    }
+}
+
+impl crate::Entity for DdsPublisher {
+    fn entity(&self) -> &DdsEntity {
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
     }
 }
 
@@ -15,6 +15,8 @@
 */
 
 use cyclonedds_sys::{dds_qos_t, *};
+use crate::dds_time::duration_to_dds;
+use crate::error::DDSError;
 use std::clone::Clone;
 use std::convert::From;
 
@@ -44,6 +46,36 @@ impl DdsQos {
         }
     }
 
+    /// Create a copy of a Qos owned by cyclone. Returns None if the pointer is null.
+    pub(crate) fn from_raw_copy(qos: *const dds_qos_t) -> Option<Self> {
+        if qos.is_null() {
+            return None;
+        }
+        unsafe {
+            let q = dds_create_qos();
+            let err: DDSError = dds_copy_qos(q, qos).into();
+            if let DDSError::DdsOk = err {
+                Some(DdsQos(q))
+            } else {
+                dds_delete_qos(q);
+                None
+            }
+        }
+    }
+
+    /// Get the current Qos of an entity
+    pub(crate) fn of_entity(entity: &DdsEntity) -> Result<Self, DDSError> {
+        let qos = Self::create()?;
+        unsafe {
+            let ret = dds_get_qos(entity.entity(), qos.0);
+            if ret == 0 {
+                Ok(qos)
+            } else {
+                Err(DDSError::from_retcode("dds_get_qos", ret))
+            }
+        }
+    }
+
     pub fn merge(&mut self, src: &Self) {
         unsafe {
             dds_merge_qos(self.0, src.0);
@@ -90,14 +122,14 @@ impl DdsQos {
 
     pub fn set_lifespan(&mut self, lifespan: std::time::Duration) -> &mut Self {
         unsafe {
-            dds_qset_lifespan(self.0, lifespan.as_nanos() as i64);
+            dds_qset_lifespan(self.0, duration_to_dds(lifespan));
         }
         self
     }
 
     pub fn set_deadline(&mut self, deadline: std::time::Duration) -> &mut Self {
         unsafe {
-            dds_qset_deadline(self.0, deadline.as_nanos() as i64);
+            dds_qset_deadline(self.0, duration_to_dds(deadline));
         }
         self
     }
@@ -143,7 +175,7 @@ impl DdsQos {
         max_blocking_time: std::time::Duration,
     ) -> &mut Self {
         unsafe {
-            dds_qset_reliability(self.0, kind, max_blocking_time.as_nanos() as i64);
+            dds_qset_reliability(self.0, kind, duration_to_dds(max_blocking_time));
         }
         self
     }
@@ -214,11 +246,240 @@ impl DdsQos {
         self
     }
 
-    pub fn set_partition( &mut self, name: &std::ffi::CStr) -> &mut Self {
+    /// Control whether the children of the entity are enabled when created. If
+    /// false, children must be enabled with `Entity::enable` after they are configured.
+    pub fn set_entity_factory(&mut self, autoenable_created_entities: bool) -> &mut Self {
+        unsafe {
+            dds_qset_entity_factory(self.0, autoenable_created_entities);
+        }
+        self
+    }
+
+    /// Set a single partition. The name may be empty for the default partition
+    /// and may hold the wildcards `*` and `?`. Names that are not printable ASCII
+    /// or are too long are rejected with `DDSError::BadParameter`.
+    pub fn set_partition(&mut self, name: &str) -> Result<&mut Self, DDSError> {
+        let name = crate::common::partition_name(name)?;
         unsafe { dds_qset_partition1(self.0, name.as_ptr()) }
+        Ok(self)
+    }
+
+    /// Set a property, replacing the value if the property is already set
+    pub fn set_property(&mut self, name: &std::ffi::CStr, value: &std::ffi::CStr) -> &mut Self {
+        unsafe { dds_qset_prop(self.0, name.as_ptr(), value.as_ptr()) }
         self
     }
-    //TODO:  Not implementing any getters for now
+
+    /// The value of a property
+    pub fn property(&self, name: &std::ffi::CStr) -> Option<String> {
+        unsafe {
+            let mut value: *mut std::os::raw::c_char = std::ptr::null_mut();
+            if dds_qget_prop(self.0, name.as_ptr(), &mut value) && !value.is_null() {
+                let property = std::ffi::CStr::from_ptr(value).to_string_lossy().into_owned();
+                dds_free(value as *mut std::ffi::c_void);
+                Some(property)
+            } else {
+                None
+            }
+        }
+    }
+    /// The policies that are set in this Qos
+    pub fn policies(&self) -> QosPolicies {
+        let mut policies = QosPolicies::default();
+        unsafe {
+            let mut durability = dds_durability_kind::DDS_DURABILITY_VOLATILE;
+            if dds_qget_durability(self.0, &mut durability) {
+                policies.durability = Some(durability);
+            }
+
+            let mut history = dds_history_kind::DDS_HISTORY_KEEP_LAST;
+            let mut depth = 0;
+            if dds_qget_history(self.0, &mut history, &mut depth) {
+                policies.history = Some((history, depth));
+            }
+
+            let mut reliability = dds_reliability_kind::DDS_RELIABILITY_BEST_EFFORT;
+            let mut max_blocking_time = 0;
+            if dds_qget_reliability(self.0, &mut reliability, &mut max_blocking_time) {
+                policies.reliability = Some((reliability, max_blocking_time));
+            }
+
+            let mut duration = 0;
+            if dds_qget_deadline(self.0, &mut duration) {
+                policies.deadline = Some(duration);
+            }
+            if dds_qget_latency_budget(self.0, &mut duration) {
+                policies.latency_budget = Some(duration);
+            }
+            if dds_qget_lifespan(self.0, &mut duration) {
+                policies.lifespan = Some(duration);
+            }
+
+            let mut ownership = dds_ownership_kind::DDS_OWNERSHIP_SHARED;
+            if dds_qget_ownership(self.0, &mut ownership) {
+                policies.ownership = Some(ownership);
+            }
+            let mut strength = 0;
+            if dds_qget_ownership_strength(self.0, &mut strength) {
+                policies.ownership_strength = Some(strength);
+            }
+
+            let mut liveliness = dds_liveliness_kind::DDS_LIVELINESS_AUTOMATIC;
+            let mut lease_duration = 0;
+            if dds_qget_liveliness(self.0, &mut liveliness, &mut lease_duration) {
+                policies.liveliness = Some((liveliness, lease_duration));
+            }
+
+            let mut order = dds_destination_order_kind::DDS_DESTINATIONORDER_BY_RECEPTION_TIMESTAMP;
+            if dds_qget_destination_order(self.0, &mut order) {
+                policies.destination_order = Some(order);
+            }
+
+            let mut scope = dds_presentation_access_scope_kind::DDS_PRESENTATION_INSTANCE;
+            let mut coherent = false;
+            let mut ordered = false;
+            if dds_qget_presentation(self.0, &mut scope, &mut coherent, &mut ordered) {
+                policies.presentation = Some((scope, coherent, ordered));
+            }
+
+            let mut n: u32 = 0;
+            let mut names: *mut *mut std::os::raw::c_char = std::ptr::null_mut();
+            if dds_qget_partition(self.0, &mut n, &mut names) {
+                let mut partitions = Vec::new();
+                for i in 0..n as usize {
+                    let name = *names.add(i);
+                    partitions.push(std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned());
+                    dds_free(name as *mut std::ffi::c_void);
+                }
+                if !names.is_null() {
+                    dds_free(names as *mut std::ffi::c_void);
+                }
+                policies.partitions = Some(partitions);
+            }
+        }
+        policies
+    }
+}
+
+/// The policies of a Qos as plain data. Policies that are not set in the
+/// Qos are None. Durations are in nanoseconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QosPolicies {
+    pub durability: Option<dds_durability_kind>,
+    /// kind and depth
+    pub history: Option<(dds_history_kind, i32)>,
+    /// kind and max blocking time
+    pub reliability: Option<(dds_reliability_kind, dds_duration_t)>,
+    pub deadline: Option<dds_duration_t>,
+    pub latency_budget: Option<dds_duration_t>,
+    pub lifespan: Option<dds_duration_t>,
+    pub ownership: Option<dds_ownership_kind>,
+    pub ownership_strength: Option<i32>,
+    /// kind and lease duration
+    pub liveliness: Option<(dds_liveliness_kind, dds_duration_t)>,
+    pub destination_order: Option<dds_destination_order_kind>,
+    /// access scope, coherent access and ordered access
+    pub presentation: Option<(dds_presentation_access_scope_kind, bool, bool)>,
+    pub partitions: Option<Vec<String>>,
+}
+
+/// A requested/offered policy, one that must be compatible between a writer
+/// and a reader for them to match
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RxoPolicy {
+    Durability,
+    Reliability,
+    Deadline,
+    LatencyBudget,
+    Liveliness,
+    Ownership,
+    DestinationOrder,
+    Presentation,
+}
+
+/// The policies for which the `offered` Qos of a writer does not satisfy the
+/// `requested` Qos of a reader, empty if the two would match. Policies that are
+/// not set take the defaults of cyclone, writers default to reliable and readers
+/// to best effort. Partitions are not checked, they limit what a reader sees but
+/// are not a Qos conflict.
+pub fn incompatible_policies(offered: &DdsQos, requested: &DdsQos) -> Vec<RxoPolicy> {
+    incompatible_qos_policies(&offered.policies(), &requested.policies())
+}
+
+/// [`incompatible_policies`] of the policies of a writer and a reader, such as
+/// those of discovered endpoints
+pub fn incompatible_qos_policies(offered: &QosPolicies, requested: &QosPolicies) -> Vec<RxoPolicy> {
+    let mut incompatible = Vec::new();
+
+    let durability = |p: &QosPolicies| {
+        p.durability.unwrap_or(dds_durability_kind::DDS_DURABILITY_VOLATILE) as i32
+    };
+    if durability(offered) < durability(requested) {
+        incompatible.push(RxoPolicy::Durability);
+    }
+
+    let offered_reliability = offered
+        .reliability
+        .map_or(dds_reliability_kind::DDS_RELIABILITY_RELIABLE, |r| r.0);
+    let requested_reliability = requested
+        .reliability
+        .map_or(dds_reliability_kind::DDS_RELIABILITY_BEST_EFFORT, |r| r.0);
+    if (offered_reliability as i32) < (requested_reliability as i32) {
+        incompatible.push(RxoPolicy::Reliability);
+    }
+
+    // an offered period must not be longer than the requested one
+    let deadline = |p: &QosPolicies| p.deadline.unwrap_or(dds_duration_t::MAX);
+    if deadline(offered) > deadline(requested) {
+        incompatible.push(RxoPolicy::Deadline);
+    }
+    let latency_budget = |p: &QosPolicies| p.latency_budget.unwrap_or(0);
+    if latency_budget(offered) > latency_budget(requested) {
+        incompatible.push(RxoPolicy::LatencyBudget);
+    }
+
+    let liveliness = |p: &QosPolicies| {
+        p.liveliness
+            .map(|(kind, lease)| (kind as i32, lease))
+            .unwrap_or((dds_liveliness_kind::DDS_LIVELINESS_AUTOMATIC as i32, dds_duration_t::MAX))
+    };
+    let (offered_kind, offered_lease) = liveliness(offered);
+    let (requested_kind, requested_lease) = liveliness(requested);
+    if offered_kind < requested_kind || offered_lease > requested_lease {
+        incompatible.push(RxoPolicy::Liveliness);
+    }
+
+    let ownership = |p: &QosPolicies| {
+        p.ownership.unwrap_or(dds_ownership_kind::DDS_OWNERSHIP_SHARED) as i32
+    };
+    if ownership(offered) != ownership(requested) {
+        incompatible.push(RxoPolicy::Ownership);
+    }
+
+    let destination_order = |p: &QosPolicies| {
+        p.destination_order
+            .unwrap_or(dds_destination_order_kind::DDS_DESTINATIONORDER_BY_RECEPTION_TIMESTAMP)
+            as i32
+    };
+    if destination_order(offered) < destination_order(requested) {
+        incompatible.push(RxoPolicy::DestinationOrder);
+    }
+
+    let presentation = |p: &QosPolicies| {
+        p.presentation
+            .map(|(scope, coherent, ordered)| (scope as i32, coherent, ordered))
+            .unwrap_or((dds_presentation_access_scope_kind::DDS_PRESENTATION_INSTANCE as i32, false, false))
+    };
+    let (offered_scope, offered_coherent, offered_ordered) = presentation(offered);
+    let (requested_scope, requested_coherent, requested_ordered) = presentation(requested);
+    if offered_scope < requested_scope
+        || (requested_coherent && !offered_coherent)
+        || (requested_ordered && !offered_ordered)
+    {
+        incompatible.push(RxoPolicy::Presentation);
+    }
+
+    incompatible
 }
 
 impl Default for DdsQos {
@@ -337,9 +598,57 @@ mod dds_qos_tests {
             .set_writer_data_lifecycle(true)
             .set_reader_data_lifecycle(100, 100)
             .set_durability_service(0, dds_history_kind::DDS_HISTORY_KEEP_LAST, 3, 3, 3, 3)
-            .set_partition(&std::ffi::CString::new("partition1").unwrap());
+            .set_entity_factory(false)
+            .set_partition("partition1")
+            .unwrap();
         } else {
             assert!(false);
         }
     }
+
+    #[test]
+    fn test_policies() {
+        let mut qos = DdsQos::create().unwrap();
+        assert_eq!(qos.policies(), QosPolicies::default());
+
+        qos.set_history(dds_history_kind::DDS_HISTORY_KEEP_LAST, 5)
+            .set_reliability(dds_reliability_kind::DDS_RELIABILITY_RELIABLE, std::time::Duration::from_nanos(100))
+            .set_partition("p1")
+            .unwrap();
+        let policies = qos.policies();
+        assert_eq!(policies.history, Some((dds_history_kind::DDS_HISTORY_KEEP_LAST, 5)));
+        assert_eq!(
+            policies.reliability,
+            Some((dds_reliability_kind::DDS_RELIABILITY_RELIABLE, 100))
+        );
+        assert_eq!(policies.partitions, Some(vec![String::from("p1")]));
+        assert_eq!(policies.durability, None);
+    }
+
+    #[test]
+    fn test_incompatible_policies() {
+        let writer = DdsQos::create().unwrap();
+        let mut reader = DdsQos::create().unwrap();
+        assert!(incompatible_policies(&writer, &reader).is_empty());
+
+        reader
+            .set_reliability(dds_reliability_kind::DDS_RELIABILITY_RELIABLE, std::time::Duration::from_millis(100))
+            .set_durability(dds_durability_kind::DDS_DURABILITY_TRANSIENT_LOCAL)
+            .set_deadline(std::time::Duration::from_millis(100));
+        assert_eq!(
+            incompatible_policies(&writer, &reader),
+            vec![RxoPolicy::Durability, RxoPolicy::Deadline]
+        );
+
+        let mut writer = DdsQos::create().unwrap();
+        writer
+            .set_reliability(dds_reliability_kind::DDS_RELIABILITY_BEST_EFFORT, std::time::Duration::from_millis(0))
+            .set_durability(dds_durability_kind::DDS_DURABILITY_TRANSIENT_LOCAL)
+            .set_deadline(std::time::Duration::from_millis(50))
+            .set_ownership(dds_ownership_kind::DDS_OWNERSHIP_EXCLUSIVE);
+        assert_eq!(
+            incompatible_policies(&writer, &reader),
+            vec![RxoPolicy::Reliability, RxoPolicy::Ownership]
+        );
+    }
 }
@@ -15,23 +15,65 @@
 */
 
 use cyclonedds_sys::*;
+use crate::error::DDSError;
 use std::convert::From;
 use std::future::Future;
 use std::os::raw::c_void;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll, Waker};
-//use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use std::convert::TryInto;
 
 pub use cyclonedds_sys::{DdsDomainId, DdsEntity};
 
 use std::marker::PhantomData;
 
 
+use crate::atomic_waker::AtomicWaker;
+use crate::common::{c_len, max_samples, sample_count, EntityWrapper, Uses};
 use crate::dds_listener::DdsListenerBuilder;
-use crate::error::ReaderError;
 use crate::{dds_listener::DdsListener, dds_qos::DdsQos, dds_topic::DdsTopic, DdsReadable, Entity};
-use crate::serdes::{TopicType, SampleBuffer};
+use crate::dds_telemetry::{count, Counter};
+use crate::dds_topic::topic_name_of;
+use crate::serdes::{Sample, SamplePool, SerType, TopicType, SampleBuffer};
+
+/// How [`DdsReader::poll_take`] waits for samples: a number of attempts
+/// spinning on the CPU, then an optional short sleep before the next round.
+/// Without a sleep the polling thread keeps a core busy until samples arrive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusyPoll {
+    spins: u32,
+    sleep: Option<Duration>,
+}
+
+impl Default for BusyPoll {
+    fn default() -> Self {
+        Self {
+            spins: 1000,
+            sleep: None,
+        }
+    }
+}
+
+impl BusyPoll {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attempts to take samples between sleeps
+    pub fn with_spins(mut self, spins: u32) -> Self {
+        self.spins = spins.max(1);
+        self
+    }
+
+    /// Sleep after every round of attempts
+    pub fn with_sleep(mut self, sleep: Duration) -> Self {
+        self.sleep = Some(sleep);
+        self
+    }
+}
 
 /// Builder structure for reader
 pub struct ReaderBuilder<T: TopicType> {
@@ -87,15 +129,24 @@ impl <T>ReaderBuilder<T> where T: TopicType {
 
 
 enum ReaderType {
-    Async(Arc<Mutex<(Option<Waker>,Result<(),crate::error::ReaderError>)>>),
+    Async(Arc<ReaderWaker>),
     Sync,
 }
 
+// Shared by the listener of an async reader and its futures
+#[derive(Default)]
+struct ReaderWaker {
+    waker: AtomicWaker,
+    // a requested deadline was missed, the futures fail from then on
+    deadline_missed: AtomicBool,
+}
+
 
  struct Inner<T: Sized + TopicType> {
-    entity: DdsEntity,
-    _listener: Option<DdsListener>,
+    // the reader with its listener and topic
+    entity: EntityWrapper,
     reader_type : ReaderType,
+    pool: Arc<SamplePool<T>>,
     _phantom: PhantomData<T>,
     // The callback closures that can be attached to a reader
 }
@@ -118,6 +169,7 @@ where
         Self::create_sync_or_async(entity, topic, maybe_qos, maybe_listener, ReaderType::Sync)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err))]
     fn create_sync_or_async(
         entity: &dyn DdsReadable,
         topic: DdsTopic<T>,
@@ -125,6 +177,8 @@ where
         maybe_listener: Option<DdsListener>,
         reader_type : ReaderType,
     ) -> Result<Self, DDSError> {
+        entity.check_valid()?;
+        Entity::check_valid(&topic)?;
         unsafe {
             let w = dds_create_reader(
                 entity.entity().entity(),
@@ -137,67 +191,204 @@ where
 
             if w >= 0 {
                 Ok(DdsReader {
-                    inner : Arc::new(Inner {entity: DdsEntity::new(w),
-                        _listener: maybe_listener,
+                    inner : Arc::new(Inner {
+                        entity: EntityWrapper::new(
+                            "reader",
+                            DdsEntity::new(w),
+                            entity.keep_alive(),
+                            maybe_listener,
+                            Uses::Topic(topic.keep_alive()),
+                        ),
                         reader_type,
+                        pool: Arc::new(SamplePool::new()),
                         _phantom: PhantomData,})
                 })
             } else {
-                Err(DDSError::from(w))
+                // cyclone keeps no reference to a listener of a failed reader
+                drop(maybe_listener);
+                Err(DDSError::from_retcode("dds_create_reader", w).with_entity_kind("reader"))
             }
         }
     }
 
+    // A reader found under a subscriber, which is not deleted when dropped,
+    // with the pool of the buffers it is taken into
+    pub(crate) fn found(entity: DdsEntity, pool: Arc<SamplePool<T>>) -> Self {
+        DdsReader {
+            inner: Arc::new(Inner {
+                entity: EntityWrapper::unowned("reader", entity),
+                reader_type: ReaderType::Sync,
+                pool,
+                _phantom: PhantomData,
+            }),
+        }
+    }
+
     /// Create an async reader. This constructor must be used if using any of the async functions.
+    /// The futures do not depend on an async runtime, they are woken from the cyclone
+    /// thread that calls the listener. One task at a time may wait on the reader.
     pub fn create_async(
         entity: &dyn DdsReadable,
         topic: DdsTopic<T>,
         maybe_qos: Option<DdsQos>,
     ) -> Result<Self, DDSError> {
+        // nothing to roll back if the reader cannot be created anyway
+        entity.check_valid()?;
+        Entity::check_valid(&topic)?;
+        Self::create_with_waker(entity, topic, maybe_qos, Arc::new(ReaderWaker::default()))
+    }
 
-        let waker = Arc::new(<Mutex<(Option<Waker>,Result<(),crate::error::ReaderError>)>>::new((None,Ok(()))));
+    fn create_with_waker(
+        entity: &dyn DdsReadable,
+        topic: DdsTopic<T>,
+        maybe_qos: Option<DdsQos>,
+        waker: Arc<ReaderWaker>,
+    ) -> Result<Self, DDSError> {
         let waker_cb = waker.clone();
         let requested_deadline_waker = waker.clone();
-        
+
         let listener = DdsListenerBuilder::new()
             .on_data_available(move|_entity| {
                 //println!("Data available ");
-                let mut maybe_waker = waker_cb.lock().unwrap();
-                if let Some(waker) = maybe_waker.0.take() {
-                    waker.wake();
-                }
+                waker_cb.waker.wake();
             })
             .on_requested_deadline_missed(move |entity, status| {
                 println!("Deadline missed: Entity:{:?} Status:{:?}", unsafe {entity.entity()}, status);
-                let mut maybe_waker = requested_deadline_waker.lock().unwrap();
-                maybe_waker.1 = Err(ReaderError::RequestedDeadLineMissed);
-                if let Some(waker) = maybe_waker.0.take() {
-                    waker.wake();
-                }
+                requested_deadline_waker.deadline_missed.store(true, Ordering::Release);
+                requested_deadline_waker.waker.wake();
             })
             .build();
 
-        match Self::create_sync_or_async(entity, topic, maybe_qos, Some(listener),ReaderType::Async(waker) ) {
-            Ok(reader) => {
-                Ok(reader)
-            },
-            Err(e) => Err(e),
-        }
-        
+        // On failure the listener is the only handle left, dropping it deletes
+        // the cyclone listener and the callbacks with their waker clones.
+        Self::create_sync_or_async(entity, topic, maybe_qos, Some(listener), ReaderType::Async(waker))
+    }
+
+    /// A buffer for `len` samples from the pool of the reader. Buffers that are
+    /// created and dropped at a high rate reuse the sample allocations.
+    pub fn sample_buffer(&self, len: usize) -> SampleBuffer<T> {
+        SampleBuffer::from_pool(len, &self.inner.pool)
+    }
+
+    pub fn sample_pool(&self) -> &Arc<SamplePool<T>> {
+        &self.inner.pool
     }
 
     /// read synchronously
     pub fn read_now(&self,buf: &mut SampleBuffer<T>) -> Result<usize,DDSError> {
+        self.inner.entity.check_valid()?;
         Self::readn_from_entity_now(self.entity(),buf,false)
     }
 
     /// take synchronously
     pub fn take_now(&self,buf: &mut SampleBuffer<T>) -> Result<usize,DDSError> {
+        self.inner.entity.check_valid()?;
         Self::readn_from_entity_now(self.entity(),buf,true)
     }
 
+    /// Take samples without a listener or waitset: spin on `dds_take` as set by
+    /// `poll` until samples arrive, for consumers where the latency of a
+    /// listener dispatch matters. Returns `DDSError::Timeout` if nothing
+    /// arrived within `timeout`.
+    pub fn poll_take(&self, buf: &mut SampleBuffer<T>, poll: BusyPoll, timeout: Duration) -> Result<usize, DDSError> {
+        self.inner.entity.check_valid()?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            for _ in 0..poll.spins {
+                let ret = unsafe {
+                    let (samples, infos) = buf.as_mut_ptr();
+                    dds_take(
+                        self.entity().entity(),
+                        samples as *mut *mut c_void,
+                        infos as *mut _,
+                        c_len(buf.len()),
+                        max_samples(buf.len()),
+                    )
+                };
+                match ret {
+                    0 => std::hint::spin_loop(),
+                    n if n > 0 => return Ok(sample_count(n)),
+                    err => return Err(DDSError::from_retcode("dds_take", err).with_entity_kind("reader")),
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(DDSError::Timeout);
+            }
+            if let Some(sleep) = poll.sleep {
+                std::thread::sleep(sleep.min(deadline - now));
+            }
+        }
+    }
+
+    /// Take every available sample into `buf` and return the number taken, 0
+    /// if there was none. The buffer grows if the samples do not fit, a buffer
+    /// that is kept for the next call stops growing once it holds the history
+    /// of the reader.
+    pub fn take_all_into(&self, buf: &mut SampleBuffer<T>) -> Result<usize, DDSError> {
+        self.take_all_at(buf, 0)
+    }
+
+    // take_all_into, filling `buf` from index `start` on
+    pub(crate) fn take_all_at(&self, buf: &mut SampleBuffer<T>, start: usize) -> Result<usize, DDSError> {
+        self.inner.entity.check_valid()?;
+        Self::take_all_from_entity(self.entity(), buf, start)
+    }
+
+    // Take every available sample of the reader `entity` into `buf` from
+    // index `start` on, growing the buffer as needed
+    pub(crate) fn take_all_from_entity(
+        entity: &DdsEntity,
+        buf: &mut SampleBuffer<T>,
+        start: usize,
+    ) -> Result<usize, DDSError> {
+        if buf.len() <= start {
+            buf.grow(start + 1 - buf.len());
+        }
+        let mut total = start;
+        loop {
+            let remaining = buf.len() - total;
+            let ret = unsafe {
+                let (samples, infos) = buf.as_mut_ptr();
+                dds_take(
+                    entity.entity(),
+                    samples.add(total) as *mut *mut c_void,
+                    infos.add(total) as *mut _,
+                    c_len(remaining),
+                    max_samples(remaining),
+                )
+            };
+            if ret < 0 {
+                return Err(DDSError::from_retcode("dds_take", ret).with_entity_kind("reader"));
+            }
+            total += sample_count(ret);
+            if total < buf.len() {
+                return Ok(total - start);
+            }
+            count(Counter::FullBufferReads);
+            buf.grow(buf.len());
+        }
+    }
+
+    /// Read up to `max` samples into buffers loaned from cyclone instead of a
+    /// [`SampleBuffer`]. The reader keeps its loan between reads, so bursts of
+    /// reads do not allocate samples. The loan is returned when the
+    /// [`LoanedSamples`] are dropped.
+    pub fn read_loan(&self, max: usize) -> Result<LoanedSamples<T>, DDSError> {
+        self.inner.entity.check_valid()?;
+        LoanedSamples::create(&self.inner.entity, max, false)
+    }
+
+    /// Take up to `max` samples into buffers loaned from cyclone, see
+    /// [`DdsReader::read_loan`]
+    pub fn take_loan(&self, max: usize) -> Result<LoanedSamples<T>, DDSError> {
+        self.inner.entity.check_valid()?;
+        LoanedSamples::create(&self.inner.entity, max, true)
+    }
+
     /// Read multiple samples from the reader synchronously. The buffer for the sampes must be passed in.
     /// On success, returns the number of samples read.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(entity, buf), fields(len = buf.len())))]
     pub fn readn_from_entity_now(entity: &DdsEntity, buf: &mut SampleBuffer<T>, take: bool) -> Result<usize,DDSError> {
 
         let (voidp, info_ptr) = unsafe {buf.as_mut_ptr()};
@@ -206,15 +397,18 @@ where
 
         let ret = unsafe {
             if take {
-                dds_take(entity.entity(), voidpp ,  info_ptr as *mut _, buf.len() as size_t, buf.len() as u32)
+                dds_take(entity.entity(), voidpp ,  info_ptr as *mut _, c_len(buf.len()), max_samples(buf.len()))
             } else {
-                dds_read(entity.entity(), voidpp ,  info_ptr as *mut _, buf.len() as size_t, buf.len() as u32)
+                dds_read(entity.entity(), voidpp ,  info_ptr as *mut _, c_len(buf.len()), max_samples(buf.len()))
             }
         };
         if ret > 0 {
+            if sample_count(ret) == buf.len() {
+                count(Counter::FullBufferReads);
+            }
             // If first sample is value we assume all are
             if buf.is_valid_sample(0) {
-                   Ok(ret as usize) 
+                   Ok(sample_count(ret))
             } else {
                     Err(DDSError::NoData)
             }
@@ -224,29 +418,59 @@ where
     }
   
     /// Read samples asynchronously. The number of samples actually read is returned.
-    pub async fn read(&self, samples : &mut SampleBuffer<T>) -> Result<usize,ReaderError> {
+    pub async fn read(&self, samples : &mut SampleBuffer<T>) -> Result<usize,DDSError> {
+        self.inner.entity.check_valid()?;
         if let ReaderType::Async(waker) = &self.inner.reader_type {
-               let future_sample = SampleArrayFuture::new(self.inner.entity.clone(), waker.clone(),samples ,FutureType::Read);
+               let future_sample = SampleArrayFuture::new(self.inner.entity.entity().clone(), waker.clone(),samples ,FutureType::Read);
                 future_sample.await
            } else {
-            Err(ReaderError::ReaderNotAsync)
+            Err(DDSError::ReaderNotAsync)
         }
     }
 
     /// Get samples asynchronously. The number of samples actually read is returned.
-    pub async fn take(&self, samples : &mut SampleBuffer<T>) -> Result<usize,ReaderError> {
+    pub async fn take(&self, samples : &mut SampleBuffer<T>) -> Result<usize,DDSError> {
+        self.inner.entity.check_valid()?;
         if let ReaderType::Async(waker) = &self.inner.reader_type {
-            let future_sample = SampleArrayFuture::new(self.inner.entity.clone(), waker.clone(),samples ,FutureType::Take);
+            let future_sample = SampleArrayFuture::new(self.inner.entity.entity().clone(), waker.clone(),samples ,FutureType::Take);
              future_sample.await
         } else {
-            Err(ReaderError::ReaderNotAsync)
+            Err(DDSError::ReaderNotAsync)
      }
     }
 
+    /// Samples that were received for this reader but could not be deserialized,
+    /// since the last call. Cyclone drops these samples, so they never show up in
+    /// `read` or `take`. Each error is a `DDSError::Serdes` with the topic, the size
+    /// of the sample and the cause. Failures are collected per type, so they are
+    /// returned by the first reader of that type that asks.
+    pub fn take_serdes_errors(&self) -> Result<Vec<DDSError>, DDSError> {
+        self.inner.entity.check_valid()?;
+        let mut sertype: *const ddsi_sertype = std::ptr::null();
+        let ret = unsafe { dds_get_entity_sertype(self.inner.entity.entity().entity(), &mut sertype) };
+        if ret < 0 {
+            return Err(DDSError::from_retcode("dds_get_entity_sertype", ret).with_entity_kind("reader"));
+        }
+        let topic = unsafe { DdsEntity::new(dds_get_topic(self.inner.entity.entity().entity())) };
+        let topic = topic_name_of(&topic).ok();
+
+        let errors = unsafe { SerType::<T>::ref_from_sertype(sertype) }
+            .map(|s| s.take_serdes_errors())
+            .unwrap_or_default();
+        Ok(errors
+            .into_iter()
+            .map(|e| match &topic {
+                Some(topic) => e.with_topic(topic.clone()).into(),
+                None => e.into(),
+            })
+            .collect())
+    }
+
     pub fn create_readcondition(
         &'a mut self,
         mask: StateMask,
     ) -> Result<DdsReadCondition<T>, DDSError> {
+        self.inner.entity.check_valid()?;
         DdsReadCondition::create(self, mask)
     }
 }
@@ -256,29 +480,116 @@ where
     T: std::marker::Sized + TopicType,
 {
     fn entity(&self) -> &DdsEntity {
-        &self.inner.entity
+        self.inner.entity.entity().entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.inner.entity.check_valid()
     }
 }
 
-impl<'a, T> Drop for DdsReader<T>
-where
-    T: Sized + TopicType,
-{
-    fn drop(&mut self) {
-        unsafe {
-            //println!("Drop reader:{:?}", self.entity().entity());
-            let ret: DDSError = cyclonedds_sys::dds_delete(self.inner.entity.entity()).into();
-            if DDSError::DdsOk != ret {
-                //panic!("cannot delete Reader: {}", ret);
-                println!("Ignoring dds_delete failure for DdsReader");
+ 
+/// Samples read into buffers loaned from cyclone, see [`DdsReader::read_loan`].
+/// The samples keep the reader alive, its loan is freed when the reader is
+/// deleted. They must not be used once the participant or subscriber of the
+/// reader is closed, which deletes the reader: the accessors panic then.
+pub struct LoanedSamples<T: TopicType> {
+    // the reader that owns the loan
+    reader: EntityWrapper,
+    // the sample pointers, null pointers make cyclone loan the samples
+    samples: Vec<*mut Sample<T>>,
+    sample_info: Vec<dds_sample_info>,
+    len: usize,
+}
+
+impl<T: TopicType> LoanedSamples<T> {
+    fn create(reader: &EntityWrapper, max: usize, take: bool) -> Result<Self, DDSError> {
+        let entity = reader.entity();
+        let mut loaned = Self {
+            reader: reader.clone(),
+            samples: vec![std::ptr::null_mut(); max],
+            sample_info: vec![dds_sample_info::default(); max],
+            len: 0,
+        };
+        if max == 0 {
+            return Ok(loaned);
+        }
+        let samples = loaned.samples.as_mut_ptr() as *mut *mut c_void;
+        let infos = loaned.sample_info.as_mut_ptr();
+        let ret = unsafe {
+            if take {
+                dds_take(entity.entity(), samples, infos, c_len(max), max_samples(max))
             } else {
-                //println!("Reader dropped");
+                dds_read(entity.entity(), samples, infos, c_len(max), max_samples(max))
             }
+        };
+        if ret < 0 {
+            let operation = if take { "dds_take" } else { "dds_read" };
+            return Err(DDSError::from_retcode(operation, ret).with_entity_kind("reader"));
         }
+        loaned.len = sample_count(ret);
+        Ok(loaned)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get a sample. Will panic if out of bounds.
+    pub fn get(&self, index: usize) -> &Sample<T> {
+        assert!(index < self.len, "sample index out of bounds");
+        self.assert_loaned();
+        unsafe { &*self.samples[index] }
+    }
+
+    /// Check if sample is valid. Will panic if out of bounds.
+    pub fn is_valid_sample(&self, index: usize) -> bool {
+        assert!(index < self.len, "sample index out of bounds");
+        self.sample_info[index].valid_data
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.assert_loaned();
+        self.samples[..self.len]
+            .iter()
+            .filter_map(|p| unsafe { &**p }.try_deref())
+    }
+
+    // the loan is freed with the reader
+    fn assert_loaned(&self) {
+        assert!(self.reader.check_valid().is_ok(), "the reader of the loaned samples was closed");
     }
 }
- 
-pub struct DdsReadCondition<'a, T: Sized + TopicType>(DdsEntity, &'a DdsReader<T>);
+
+impl<T: TopicType> Drop for LoanedSamples<T> {
+    fn drop(&mut self) {
+        // cyclone keeps nothing loaned when there was no data, and a deleted
+        // reader took its loan with it
+        if self.len == 0 || self.reader.check_valid().is_err() {
+            return;
+        }
+        // cyclone frees a loan that is not the loan of the reader with the
+        // given size, return it with the size it was loaned with
+        let ret = unsafe {
+            dds_return_loan(
+                self.reader.entity().entity(),
+                self.samples.as_mut_ptr() as *mut *mut c_void,
+                self.samples.len().try_into().unwrap_or(i32::MAX),
+            )
+        };
+        if ret != 0 && DDSError::from(ret) != DDSError::AlreadyDeleted {
+            crate::dds_log::drop_failed("loaned samples", &DDSError::from_retcode("dds_return_loan", ret));
+        }
+    }
+}
+
+/// A read condition of a reader, deleted when dropped. Cyclone detaches a
+/// deleted condition from the waitsets it is attached to.
+pub struct DdsReadCondition<'a, T: Sized + TopicType>(EntityWrapper, PhantomData<&'a DdsReader<T>>);
 
 impl<'a, T> DdsReadCondition<'a, T>
 where
@@ -289,9 +600,18 @@ where
             let mask: u32 = *mask;
             let p = cyclonedds_sys::dds_create_readcondition(reader.entity().entity(), mask);
             if p > 0 {
-                Ok(DdsReadCondition(DdsEntity::new(p), reader))
+                Ok(DdsReadCondition(
+                    EntityWrapper::new(
+                        "readcondition",
+                        DdsEntity::new(p),
+                        Some(reader.inner.entity.clone()),
+                        None,
+                        Uses::Nothing,
+                    ),
+                    PhantomData,
+                ))
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_create_readcondition", p).with_entity_kind("reader"))
             }
         }
     }
@@ -302,7 +622,11 @@ where
     T: std::marker::Sized + TopicType,
 {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
     }
 }
 
@@ -323,14 +647,14 @@ impl FutureType {
 
 struct SampleArrayFuture<'a,T> {
     entity : DdsEntity,
-    waker : Arc<Mutex<(Option<Waker>,Result<(),crate::error::ReaderError>)>>,
+    waker : Arc<ReaderWaker>,
     take_or_read : FutureType,
     buffer : &'a mut SampleBuffer<T>,
 }
 
 
 impl <'a,T>SampleArrayFuture<'a,T> {
-    fn new(entity: DdsEntity, waker : Arc<Mutex<(Option<Waker>,Result<(),crate::error::ReaderError>)>>, buffer: &'a mut SampleBuffer<T>, ty : FutureType) -> Self {
+    fn new(entity: DdsEntity, waker : Arc<ReaderWaker>, buffer: &'a mut SampleBuffer<T>, ty : FutureType) -> Self {
         Self {
             entity,
             waker,
@@ -341,33 +665,28 @@ impl <'a,T>SampleArrayFuture<'a,T> {
 }
 
 impl <'a,T>Future for SampleArrayFuture<'a,T> where T: TopicType {
-    type Output = Result<usize,ReaderError>;
+    type Output = Result<usize,DDSError>;
 
     fn poll(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
 
-        // Lock the waker first in case a callback for read complete happens and we miss it
-        // clone to avoid the lifetime problem with self
-        let waker = self.waker.clone();
-        let mut waker = waker.lock().unwrap();
+        // Register the waker first in case a callback for read complete happens and we miss it
+        self.waker.waker.register(ctx.waker());
         let is_take = self.take_or_read.is_take();
         let entity = self.entity.clone();
         
         // check if we have an error from any of the callbacks
-        if let Err(e) = &waker.1 {
-                return Poll::Ready(Err(e.clone()))    
+        if self.waker.deadline_missed.load(Ordering::Acquire) {
+                return Poll::Ready(Err(DDSError::RequestedDeadlineMissed))
         }
         
 
         match DdsReader::<T>::readn_from_entity_now(&entity, self.buffer, is_take) {
             Ok(len) =>  Poll::Ready(Ok(len)),
-            Err(DDSError::NoData) | Err(DDSError::OutOfResources) => {
-                let _ = waker.0.replace(ctx.waker().clone()); 
-                Poll::Pending
-            },
+            Err(DDSError::NoData) | Err(DDSError::OutOfResources) => Poll::Pending,
             Err(e) => {    
                 //println!("Error:{}",e);
                 // Some other error happened
-                Poll::Ready(Err(ReaderError::DdsError(e)))
+                Poll::Ready(Err(e))
             }
         }
     }
@@ -508,7 +827,243 @@ mod test {
 
         });
     }
-/* 
+
+    #[test]
+    fn test_pooled_sample_buffer() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("pooled"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        for round in 0..3 {
+            writer.write(Arc::new(TestTopic { e: round, ..Default::default() })).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            let mut samples = reader.sample_buffer(4);
+            assert_eq!(reader.sample_pool().idle(), 0);
+            assert_eq!(reader.take_now(&mut samples).unwrap(), 1);
+            assert_eq!(samples.get(0).try_deref().map(|s| s.e), Some(round));
+            drop(samples);
+            // the samples are back in the pool for the next round
+            assert_eq!(reader.sample_pool().idle(), 4);
+        }
+
+        reader.sample_pool().preallocate(8);
+        assert_eq!(reader.sample_pool().idle(), 8);
+        let samples = reader.sample_buffer(6);
+        assert_eq!(reader.sample_pool().idle(), 2);
+        drop(samples);
+        assert_eq!(reader.sample_pool().idle(), 8);
+    }
+
+    #[test]
+    fn test_poll_take() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("poll_take"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        let mut samples = reader.sample_buffer(4);
+        let poll = BusyPoll::new().with_spins(100).with_sleep(Duration::from_micros(50));
+
+        assert_eq!(
+            reader.poll_take(&mut samples, poll, Duration::from_millis(20)),
+            Err(DDSError::Timeout)
+        );
+
+        let writer_thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            writer.write(Arc::new(TestTopic { e: 5, ..Default::default() })).unwrap();
+            writer
+        });
+        assert_eq!(reader.poll_take(&mut samples, poll, Duration::from_secs(2)), Ok(1));
+        assert_eq!(samples.get(0).try_deref().map(|s| s.e), Some(5));
+        writer_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_readcondition_drop() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("readcondition_drop"), None, None).unwrap();
+        let mut reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        let waitset = crate::DdsWaitset::create(&participant).unwrap();
+
+        for _ in 0..1000 {
+            let condition = reader.create_readcondition(StateMask::from(DDS_ANY_STATE)).unwrap();
+            waitset.attach(&condition).unwrap();
+            assert_eq!(waitset.attached_count(), Ok(1));
+            drop(condition);
+            assert_eq!(waitset.attached_count(), Ok(0));
+        }
+
+        let children = unsafe {
+            dds_get_children(reader.entity().entity(), std::ptr::null_mut(), 0)
+        };
+        assert_eq!(children, 0);
+    }
+
+    #[test]
+    fn test_take_all_into() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("take_all"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut samples = reader.sample_buffer(2);
+        assert_eq!(reader.take_all_into(&mut samples).unwrap(), 0);
+        for e in 0..10 {
+            writer.write(Arc::new(TestTopic { e, ..Default::default() })).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(reader.take_all_into(&mut samples).unwrap(), 10);
+        assert!(samples.len() >= 10);
+        let mut keys: Vec<u32> = (0..10).filter_map(|i| samples.get(i).try_deref().map(|s| s.e)).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..10).collect::<Vec<_>>());
+        assert_eq!(samples.infos().len(), samples.len());
+        assert!(samples.infos()[..10].iter().all(|info| info.valid_data() && info.is_alive()));
+        assert_eq!(reader.take_all_into(&mut samples).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_lazy_sample_buffer() {
+        let mut samples = SampleBuffer::<TestTopic>::new(4096);
+        assert_eq!(samples.initialized(), 0);
+        assert!(samples.get(4095).try_deref().is_none());
+        assert_eq!(samples.iter().count(), 0);
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("lazy_buffer"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(TestTopic { e: 7, ..Default::default() })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(reader.take_now(&mut samples).unwrap(), 1);
+        assert_eq!(samples.initialized(), 4096);
+        samples.grow(4);
+        assert_eq!(samples.initialized(), 4096);
+        assert_eq!(samples.get(0).try_deref().map(|s| s.e), Some(7));
+    }
+
+    #[test]
+    fn test_loaned_reads() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("loaned_reads"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert!(reader.take_loan(4).unwrap().is_empty());
+        for round in 0..3 {
+            for e in 0..3 {
+                writer.write(Arc::new(TestTopic { e, a: round, ..Default::default() })).unwrap();
+            }
+            std::thread::sleep(Duration::from_millis(100));
+
+            let read = reader.read_loan(8).unwrap();
+            assert_eq!(read.len(), 3);
+            drop(read);
+            let taken = reader.take_loan(8).unwrap();
+            assert_eq!(taken.len(), 3);
+            assert!((0..taken.len()).all(|i| taken.is_valid_sample(i)));
+            let mut keys: Vec<u32> = taken.iter().map(|s| s.e).collect();
+            keys.sort_unstable();
+            assert_eq!(keys, vec![0, 1, 2]);
+            assert!(taken.iter().all(|s| s.a == round));
+        }
+    }
+
+    #[test]
+    fn test_loan_outlives_the_reader() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("loan_outlives_reader"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(TestTopic { e: 5, ..Default::default() })).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let taken = reader.take_loan(4).unwrap();
+        drop(reader);
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken.iter().map(|s| s.e).collect::<Vec<_>>(), vec![5]);
+        assert_eq!(taken.get(0).try_deref().map(|s| s.e), Some(5));
+    }
+
+    #[test]
+    fn test_reader_async_without_runtime() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("no_runtime"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create_async(&participant, topic, None).unwrap();
+
+        let writer_thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            writer.write(Arc::new(TestTopic::default())).unwrap();
+            writer
+        });
+
+        let mut samples = SampleBuffer::new(1);
+        let n = crate::test_support::block_on(reader.take(&mut samples)).unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(samples.get(0).try_deref(), Some(&TestTopic::default()));
+        drop(writer_thread.join());
+    }
+
+    #[test]
+    fn test_async_reader_rejected_qos() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("rejected_qos"), None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+
+        // a history deeper than the samples an instance may hold is inconsistent
+        let inconsistent = || {
+            let mut qos = DdsQos::create().unwrap();
+            qos.set_history(dds_history_kind::DDS_HISTORY_KEEP_LAST, 10)
+                .set_resource_limits(-1, -1, 5);
+            Some(qos)
+        };
+
+        for _ in 0..3 {
+            let waker = Arc::new(ReaderWaker::default());
+            let err = DdsReader::create_with_waker(&subscriber, topic.clone(), inconsistent(), waker.clone())
+                .err()
+                .unwrap();
+            assert_eq!(err, DDSError::InconsistentPolicy);
+            // the callbacks of the listener are gone with their clones
+            assert_eq!(Arc::strong_count(&waker), 1);
+        }
+        assert_eq!(
+            DdsReader::create_async(&participant, topic.clone(), inconsistent()).err().unwrap(),
+            DDSError::InconsistentPolicy
+        );
+        assert!(subscriber.children().unwrap().is_empty());
+
+        // the subscriber and the topic are still usable
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create_async(&subscriber, topic, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        writer.write(Arc::new(TestTopic::default())).unwrap();
+        let mut samples = SampleBuffer::new(1);
+        let n = crate::test_support::block_on(reader.take(&mut samples)).unwrap();
+        assert_eq!(n, 1);
+    }
+
+    #[test]
+    fn test_async_reader_on_closed_subscriber() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = TestTopic::create_topic(&participant, Some("closed_subscriber"), None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        subscriber.close().unwrap();
+        assert_eq!(
+            DdsReader::create_async(&subscriber, topic, None).err().unwrap(),
+            DDSError::UseAfterClose
+        );
+    }
+/*
     #[test]
     fn test_requested_deadline_miss() {
         let participant = DdsParticipant::create(None, None, None).unwrap();
@@ -0,0 +1,567 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Request and reply on top of readers and writers, in the style of DDS-RPC.
+//! A service uses two topics, `<service>_Request` and `<service>_Reply`.
+//! Every request carries the identity of the requester (the GUID of its writer
+//! and a sequence number) and the replier copies it into the reply, so each
+//! [`Requester`] only sees the replies to its own requests.
+//!
+//! The futures do not depend on a particular async runtime.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::{*, dds_rpc::{Replier, Requester}};
+//! # use serde_derive::{Deserialize, Serialize};
+//! # use cdds_derive::Topic;
+//! #[derive(Serialize, Deserialize, Topic, Clone, Default)]
+//! struct Add { a: u32, b: u32 }
+//! #[derive(Serialize, Deserialize, Topic, Clone, Default)]
+//! struct Sum { value: u32 }
+//!
+//! async fn example(participant: &DdsParticipant) -> Result<(), DDSError> {
+//!     let mut replier = Replier::<Add, Sum>::create(participant, "adder")?;
+//!     let requester = Requester::<Add, Sum>::create(participant, "adder")?;
+//!     let mut add = |req: Add| async move { Sum { value: req.a + req.b } };
+//!     let (sum, _) = tokio::join!(
+//!         requester.call(Add { a: 1, b: 2 }, std::time::Duration::from_secs(1)),
+//!         replier.dispatch(&mut add)
+//!     );
+//!     assert_eq!(sum?.value, 3);
+//!     Ok(())
+//! }
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::ffi::CString;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use cdr::{CdrBe, Infinite};
+use cyclonedds_sys::{dds_history_kind, dds_reliability_kind};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::DRAIN_BATCH;
+use crate::error::DDSError;
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::{DdsListenerBuilder, DdsParticipant, DdsQos, DdsReader, DdsTopic, DdsWriter, Entity};
+
+/// Identifies a request. The replier copies it into the reply.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SampleIdentity {
+    /// GUID of the writer of the requester
+    pub writer_guid: [u8; 16],
+    pub sequence_number: u64,
+}
+
+/// A request as sent on the request topic
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Request<T> {
+    pub id: SampleIdentity,
+    pub data: T,
+}
+
+/// A reply as sent on the reply topic
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Reply<T> {
+    pub related_id: SampleIdentity,
+    pub data: T,
+}
+
+// Request and reply samples have no key, every request is a new sample.
+macro_rules! keyless_envelope {
+    ($envelope:ident) => {
+        impl<T: TopicType> TopicType for $envelope<T> {
+            fn typename() -> CString {
+                let name = format!(
+                    "dds_rpc::{}<{}>",
+                    stringify!($envelope),
+                    T::typename().to_string_lossy()
+                );
+                CString::new(name).expect("Unable to create CString for type name")
+            }
+
+            fn has_key() -> bool {
+                false
+            }
+
+            fn key_cdr(&self) -> Vec<u8> {
+                cdr::serialize::<_, _, CdrBe>(&(), Infinite).expect("Unable to serialize key")
+            }
+
+            fn force_md5_keyhash() -> bool {
+                false
+            }
+        }
+    };
+}
+
+keyless_envelope!(Request);
+keyless_envelope!(Reply);
+
+fn request_topic_name(service: &str) -> String {
+    format!("{}_Request", service)
+}
+
+fn reply_topic_name(service: &str) -> String {
+    format!("{}_Reply", service)
+}
+
+// Requests and replies must not be lost, and no reply may be replaced
+// by a newer one before it is taken.
+fn rpc_qos() -> Result<DdsQos, DDSError> {
+    let mut qos = DdsQos::create()?;
+    qos.set_reliability(
+        dds_reliability_kind::DDS_RELIABILITY_RELIABLE,
+        Duration::from_millis(100),
+    )
+    .set_history(dds_history_kind::DDS_HISTORY_KEEP_ALL, 0);
+    Ok(qos)
+}
+
+struct Slot<Rep> {
+    result: Option<Result<Rep, DDSError>>,
+    waker: Option<Waker>,
+}
+
+struct Deadlines {
+    queue: BinaryHeap<Reverse<(Instant, u64)>>,
+    stop: bool,
+}
+
+// Deadlines of finished calls that may stay queued besides twice the waiting
+// calls before the queue is compacted
+const STALE_DEADLINES: usize = 64;
+
+// Calls waiting for a reply, shared by the requester, the listener of the reply
+// reader and the timer thread
+struct Pending<Rep> {
+    slots: Mutex<HashMap<u64, Slot<Rep>>>,
+    deadlines: Mutex<Deadlines>,
+    deadline_changed: Condvar,
+}
+
+impl<Rep> Pending<Rep> {
+    fn new() -> Self {
+        Self {
+            slots: Mutex::new(HashMap::new()),
+            deadlines: Mutex::new(Deadlines {
+                queue: BinaryHeap::new(),
+                stop: false,
+            }),
+            deadline_changed: Condvar::new(),
+        }
+    }
+
+    fn insert(&self, sequence: u64, deadline: Instant) {
+        self.slots.lock().unwrap().insert(
+            sequence,
+            Slot {
+                result: None,
+                waker: None,
+            },
+        );
+        self.deadlines
+            .lock()
+            .unwrap()
+            .queue
+            .push(Reverse((deadline, sequence)));
+        self.deadline_changed.notify_one();
+    }
+
+    // The first result wins, a reply arriving after the timeout is ignored
+    fn complete(&self, sequence: u64, result: Result<Rep, DDSError>) {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(slot) = slots.get_mut(&sequence) {
+            if slot.result.is_none() {
+                slot.result = Some(result);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    // A reply arrived
+    fn reply(&self, sequence: u64, result: Result<Rep, DDSError>) {
+        self.complete(sequence, result);
+        self.forget_finished();
+    }
+
+    // Drop the deadlines of calls that are no longer waiting: those at the head
+    // of the queue, so the timer does not wake for them, and all of them once
+    // they outnumber the waiting calls
+    fn forget_finished(&self) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let slots = self.slots.lock().unwrap();
+        let waiting = |sequence: &u64| slots.get(sequence).map_or(false, |slot| slot.result.is_none());
+        let head = deadlines.queue.peek().copied();
+        if deadlines.queue.len() > 2 * slots.len() + STALE_DEADLINES {
+            deadlines.queue.retain(|Reverse((_, sequence))| waiting(sequence));
+        } else {
+            while matches!(deadlines.queue.peek(), Some(Reverse((_, sequence))) if !waiting(sequence)) {
+                deadlines.queue.pop();
+            }
+        }
+        drop(slots);
+        if deadlines.queue.peek().copied() != head {
+            // sleep until the new head instead
+            self.deadline_changed.notify_one();
+        }
+    }
+
+    fn run_timers(&self) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        loop {
+            if deadlines.stop {
+                return;
+            }
+            let now = Instant::now();
+            match deadlines.queue.peek().copied() {
+                Some(Reverse((deadline, sequence))) if deadline <= now => {
+                    deadlines.queue.pop();
+                    self.complete(sequence, Err(DDSError::Timeout));
+                }
+                Some(Reverse((deadline, _))) => {
+                    deadlines = self
+                        .deadline_changed
+                        .wait_timeout(deadlines, deadline - now)
+                        .unwrap()
+                        .0;
+                }
+                None => {
+                    deadlines = self.deadline_changed.wait(deadlines).unwrap();
+                }
+            }
+        }
+    }
+
+    fn stop(&self) {
+        self.deadlines.lock().unwrap().stop = true;
+        self.deadline_changed.notify_one();
+    }
+}
+
+/// The future returned by [`Requester::call`]. Dropping it cancels the call;
+/// a reply that arrives later is discarded.
+pub struct ReplyFuture<Rep> {
+    pending: Arc<Pending<Rep>>,
+    sequence: u64,
+}
+
+impl<Rep> Future for ReplyFuture<Rep> {
+    type Output = Result<Rep, DDSError>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        let mut slots = self.pending.slots.lock().unwrap();
+        match slots.get_mut(&self.sequence) {
+            Some(slot) => {
+                if let Some(result) = slot.result.take() {
+                    slots.remove(&self.sequence);
+                    Poll::Ready(result)
+                } else {
+                    slot.waker = Some(ctx.waker().clone());
+                    Poll::Pending
+                }
+            }
+            // already returned the result
+            None => Poll::Ready(Err(DDSError::PreconditionNotMet)),
+        }
+    }
+}
+
+impl<Rep> Drop for ReplyFuture<Rep> {
+    fn drop(&mut self) {
+        let cancelled = match self.pending.slots.lock() {
+            Ok(mut slots) => slots.remove(&self.sequence).is_some(),
+            Err(_) => false,
+        };
+        // a cancelled call does not wait for its deadline
+        if cancelled {
+            self.pending.forget_finished();
+        }
+    }
+}
+
+/// The client side of a service. Calls may be made concurrently from several tasks.
+pub struct Requester<Req, Rep>
+where
+    Req: TopicType,
+    Rep: TopicType,
+{
+    writer: Mutex<DdsWriter<Request<Req>>>,
+    _reader: DdsReader<Reply<Rep>>,
+    identity: [u8; 16],
+    sequence: AtomicU64,
+    pending: Arc<Pending<Rep>>,
+    timers: Option<JoinHandle<()>>,
+}
+
+impl<Req, Rep> Requester<Req, Rep>
+where
+    Req: TopicType,
+    Rep: TopicType + Clone + Send + Sync + 'static,
+{
+    pub fn create(participant: &DdsParticipant, service_name: &str) -> Result<Self, DDSError> {
+        let request_topic = DdsTopic::<Request<Req>>::create(
+            participant,
+            &request_topic_name(service_name),
+            None,
+            None,
+        )?;
+        let reply_topic =
+            DdsTopic::<Reply<Rep>>::create(participant, &reply_topic_name(service_name), None, None)?;
+
+        let writer = DdsWriter::create(participant, request_topic, Some(rpc_qos()?), None)?;
+        let identity = writer.guid()?.0;
+
+        let pending = Arc::new(Pending::<Rep>::new());
+        let on_reply = pending.clone();
+        let mut buffer = SampleBuffer::<Reply<Rep>>::new(DRAIN_BATCH);
+        let listener = DdsListenerBuilder::new()
+            .on_data_available(move |entity| {
+                let n = DdsReader::take_all_from_entity(&entity, &mut buffer, 0).unwrap_or(0);
+                for i in 0..n {
+                    if let Some(reply) = buffer.get(i).try_deref() {
+                        // replies to other requesters are on the same topic
+                        if reply.related_id.writer_guid == identity {
+                            on_reply.reply(reply.related_id.sequence_number, Ok(reply.data.clone()));
+                        }
+                    }
+                }
+            })
+            .build();
+        let reader = DdsReader::create(participant, reply_topic, Some(rpc_qos()?), Some(listener))?;
+
+        let timer_pending = pending.clone();
+        let timers = std::thread::Builder::new()
+            .name(format!("{}-timeouts", service_name))
+            .spawn(move || timer_pending.run_timers())
+            .map_err(|e| DDSError::OutOfResources.with_source(e))?;
+
+        Ok(Self {
+            writer: Mutex::new(writer),
+            _reader: reader,
+            identity,
+            sequence: AtomicU64::new(0),
+            pending,
+            timers: Some(timers),
+        })
+    }
+
+    /// Send a request and wait for the reply. `DDSError::Timeout` is returned if no
+    /// reply arrives within `timeout`.
+    pub async fn call(&self, request: Req, timeout: Duration) -> Result<Rep, DDSError> {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        // register before writing, the reply may arrive before write returns
+        self.pending.insert(sequence, Instant::now() + timeout);
+        let reply = ReplyFuture {
+            pending: self.pending.clone(),
+            sequence,
+        };
+
+        let request = Request {
+            id: SampleIdentity {
+                writer_guid: self.identity,
+                sequence_number: sequence,
+            },
+            data: request,
+        };
+        self.writer.lock().unwrap().write(Arc::new(request))?;
+
+        reply.await
+    }
+}
+
+impl<Req, Rep> Drop for Requester<Req, Rep>
+where
+    Req: TopicType,
+    Rep: TopicType,
+{
+    fn drop(&mut self) {
+        self.pending.stop();
+        if let Some(timers) = self.timers.take() {
+            let _ = timers.join();
+        }
+    }
+}
+
+/// The server side of a service. Requests are taken from the request topic and
+/// passed to a handler; the value returned by the handler is sent as the reply.
+pub struct Replier<Req, Rep>
+where
+    Req: TopicType,
+    Rep: TopicType,
+{
+    reader: DdsReader<Request<Req>>,
+    writer: DdsWriter<Reply<Rep>>,
+    buffer: SampleBuffer<Request<Req>>,
+}
+
+impl<Req, Rep> Replier<Req, Rep>
+where
+    Req: TopicType + Clone,
+    Rep: TopicType,
+{
+    pub fn create(participant: &DdsParticipant, service_name: &str) -> Result<Self, DDSError> {
+        let request_topic = DdsTopic::<Request<Req>>::create(
+            participant,
+            &request_topic_name(service_name),
+            None,
+            None,
+        )?;
+        let reply_topic =
+            DdsTopic::<Reply<Rep>>::create(participant, &reply_topic_name(service_name), None, None)?;
+
+        let reader = DdsReader::create_async(participant, request_topic, Some(rpc_qos()?))?;
+        let writer = DdsWriter::create(participant, reply_topic, Some(rpc_qos()?), None)?;
+
+        Ok(Self {
+            reader,
+            writer,
+            buffer: SampleBuffer::new(DRAIN_BATCH),
+        })
+    }
+
+    /// Wait for requests and answer them. The handler is awaited for each request in
+    /// turn. Returns the number of requests answered.
+    pub async fn dispatch<F, Fut>(&mut self, handler: &mut F) -> Result<usize, DDSError>
+    where
+        F: FnMut(Req) -> Fut,
+        Fut: Future<Output = Rep>,
+    {
+        let n = self.reader.take(&mut self.buffer).await?;
+        let requests: Vec<Request<Req>> = (0..n)
+            .filter_map(|i| self.buffer.get(i).try_deref().cloned())
+            .collect();
+
+        let count = requests.len();
+        for request in requests {
+            let data = handler(request.data).await;
+            self.writer.write(Arc::new(Reply {
+                related_id: request.id,
+                data,
+            }))?;
+        }
+        Ok(count)
+    }
+
+    /// Answer requests until an error occurs
+    pub async fn run<F, Fut>(&mut self, mut handler: F) -> Result<(), DDSError>
+    where
+        F: FnMut(Req) -> Fut,
+        Fut: Future<Output = Rep>,
+    {
+        loop {
+            self.dispatch(&mut handler).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cdds_derive::Topic;
+    use tokio::runtime::Runtime;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, Default, PartialEq)]
+    struct AddRequest {
+        a: u32,
+        b: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, Default, PartialEq)]
+    struct AddReply {
+        sum: u32,
+    }
+
+    #[test]
+    fn test_typename() {
+        assert_eq!(
+            Request::<AddRequest>::typename().to_str().unwrap(),
+            "dds_rpc::Request<dds_rpc::test::AddRequest>"
+        );
+        assert!(!Reply::<AddReply>::has_key());
+    }
+
+    #[test]
+    fn test_call() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let mut replier = Replier::<AddRequest, AddReply>::create(&participant, "adder").unwrap();
+        let requester = Requester::<AddRequest, AddReply>::create(&participant, "adder").unwrap();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut add = |req: AddRequest| async move { AddReply { sum: req.a + req.b } };
+            let (reply, handled) = tokio::join!(
+                requester.call(AddRequest { a: 1, b: 2 }, Duration::from_secs(5)),
+                replier.dispatch(&mut add)
+            );
+            assert_eq!(reply.unwrap(), AddReply { sum: 3 });
+            assert_eq!(handled.unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn test_finished_calls_leave_the_deadlines() {
+        let pending = Arc::new(Pending::<u32>::new());
+        let queued = || pending.deadlines.lock().unwrap().queue.len();
+        let far = Instant::now() + Duration::from_secs(3600);
+
+        // a call without a reply keeps its deadline at the head
+        pending.insert(0, far);
+        for sequence in 1..1000 {
+            pending.insert(sequence, far + Duration::from_secs(sequence));
+            pending.reply(sequence, Ok(1));
+            // as polling the reply does
+            pending.slots.lock().unwrap().remove(&sequence);
+        }
+        assert!(queued() <= 2 + STALE_DEADLINES + 1);
+
+        // cancelled calls
+        for sequence in 1000..2000 {
+            pending.insert(sequence, far + Duration::from_secs(sequence));
+            drop(ReplyFuture {
+                pending: pending.clone(),
+                sequence,
+            });
+        }
+        assert!(queued() <= 2 + STALE_DEADLINES + 1);
+
+        // the head is dropped as soon as it finishes
+        pending.reply(0, Ok(1));
+        assert_eq!(queued(), 0);
+    }
+
+    #[test]
+    fn test_call_timeout() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let requester = Requester::<AddRequest, AddReply>::create(&participant, "nobody").unwrap();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let reply = requester
+                .call(AddRequest { a: 1, b: 2 }, Duration::from_millis(100))
+                .await;
+            assert_eq!(reply, Err(DDSError::Timeout));
+        });
+    }
+}
@@ -0,0 +1,206 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Isolate groups of readers and writers, for example per vehicle or per
+//! tenant, in one domain. A [`Scope`] has a publisher and a subscriber in a
+//! partition, so every reader and writer created through the scope only
+//! communicates within that partition. The topic names can also be given a
+//! prefix.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example<T: TopicType>(participant: &DdsParticipant) {
+//! let vehicle = ScopeBuilder::new("vehicle42")
+//!     .with_topic_prefix("fleet/")
+//!     .create(participant)
+//!     .unwrap();
+//! // the topic is "fleet/odometry" and only readers in partition "vehicle42" get the samples
+//! let writer = vehicle.writer::<T>("odometry", None).unwrap();
+//! # }
+//! ```
+
+
+use crate::error::DDSError;
+use crate::serdes::TopicType;
+use crate::{DdsParticipant, DdsPublisher, DdsQos, DdsReader, DdsSubscriber, DdsTopic, DdsWriter};
+
+pub struct ScopeBuilder {
+    partition: String,
+    topic_prefix: String,
+    publisher_qos: Option<DdsQos>,
+    subscriber_qos: Option<DdsQos>,
+}
+
+impl ScopeBuilder {
+    /// A scope in the given partition
+    pub fn new(partition: &str) -> Self {
+        Self {
+            partition: partition.to_owned(),
+            topic_prefix: String::new(),
+            publisher_qos: None,
+            subscriber_qos: None,
+        }
+    }
+
+    /// Put the prefix in front of the name of every topic of the scope
+    pub fn with_topic_prefix(mut self, prefix: &str) -> Self {
+        self.topic_prefix = prefix.to_owned();
+        self
+    }
+
+    /// The partition of the scope replaces any partition in the QoS
+    pub fn with_publisher_qos(mut self, qos: DdsQos) -> Self {
+        self.publisher_qos = Some(qos);
+        self
+    }
+
+    /// The partition of the scope replaces any partition in the QoS
+    pub fn with_subscriber_qos(mut self, qos: DdsQos) -> Self {
+        self.subscriber_qos = Some(qos);
+        self
+    }
+
+    pub fn create(self, participant: &DdsParticipant) -> Result<Scope<'_>, DDSError> {
+        let with_partition = |maybe_qos: Option<DdsQos>| -> Result<DdsQos, DDSError> {
+            let mut qos = match maybe_qos {
+                Some(qos) => qos,
+                None => DdsQos::create()?,
+            };
+            qos.set_partition(&self.partition)?;
+            Ok(qos)
+        };
+
+        let publisher_qos = with_partition(self.publisher_qos)?;
+        let subscriber_qos = with_partition(self.subscriber_qos)?;
+        let publisher = DdsPublisher::create(participant, Some(publisher_qos), None)?;
+        let subscriber = DdsSubscriber::create(participant, Some(subscriber_qos), None)?;
+        Ok(Scope {
+            participant,
+            partition: self.partition,
+            topic_prefix: self.topic_prefix,
+            publisher,
+            subscriber,
+        })
+    }
+}
+
+/// Readers and writers in one partition of a participant
+pub struct Scope<'p> {
+    participant: &'p DdsParticipant,
+    partition: String,
+    topic_prefix: String,
+    publisher: DdsPublisher,
+    subscriber: DdsSubscriber,
+}
+
+impl<'p> Scope<'p> {
+    /// A scope in the given partition, without topic prefix
+    pub fn create(participant: &'p DdsParticipant, partition: &str) -> Result<Self, DDSError> {
+        ScopeBuilder::new(partition).create(participant)
+    }
+
+    pub fn partition(&self) -> &str {
+        &self.partition
+    }
+
+    pub fn topic_prefix(&self) -> &str {
+        &self.topic_prefix
+    }
+
+    /// The name of a topic of the scope
+    pub fn topic_name(&self, name: &str) -> String {
+        format!("{}{}", self.topic_prefix, name)
+    }
+
+    /// Create a topic, the name gets the prefix of the scope
+    pub fn topic<T: TopicType>(&self, name: &str) -> Result<DdsTopic<T>, DDSError> {
+        DdsTopic::create(self.participant, &self.topic_name(name), None, None)
+    }
+
+    /// Create a writer in the partition of the scope
+    pub fn writer<T: TopicType>(
+        &self,
+        topic_name: &str,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<DdsWriter<T>, DDSError> {
+        DdsWriter::create(&self.publisher, self.topic(topic_name)?, maybe_qos, None)
+    }
+
+    /// Create a reader in the partition of the scope
+    pub fn reader<T: TopicType>(
+        &self,
+        topic_name: &str,
+        maybe_qos: Option<DdsQos>,
+    ) -> Result<DdsReader<T>, DDSError> {
+        DdsReader::create(&self.subscriber, self.topic(topic_name)?, maybe_qos, None)
+    }
+
+    /// The publisher in the partition, to create writers with a listener
+    pub fn publisher(&self) -> &DdsPublisher {
+        &self.publisher
+    }
+
+    /// The subscriber in the partition, to create readers with a listener
+    pub fn subscriber(&self) -> &DdsSubscriber {
+        &self.subscriber
+    }
+
+    pub fn participant(&self) -> &'p DdsParticipant {
+        self.participant
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_support::{assert_nothing_received, wait_for_sample};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, PartialEq, Default)]
+    struct Odometry {
+        #[topic_key]
+        id: u32,
+        distance: f64,
+    }
+
+    #[test]
+    fn test_scopes_are_isolated() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let a = ScopeBuilder::new("vehicle_a")
+            .with_topic_prefix("fleet/")
+            .create(&participant)
+            .unwrap();
+        let b = Scope::create(&participant, "vehicle_b").unwrap();
+        let other_a = ScopeBuilder::new("vehicle_a")
+            .with_topic_prefix("fleet/")
+            .create(&participant)
+            .unwrap();
+        assert_eq!(a.topic_name("odometry"), "fleet/odometry");
+
+        let mut writer = a.writer::<Odometry>("odometry", None).unwrap();
+        let same = other_a.reader::<Odometry>("odometry", None).unwrap();
+        let other_partition = b.reader::<Odometry>("fleet/odometry", None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let sample = Odometry { id: 1, distance: 2.0 };
+        writer.write(Arc::new(sample.clone())).unwrap();
+        assert_eq!(wait_for_sample(&same, Duration::from_secs(1), |_| true), Some(sample));
+        assert_nothing_received(&other_partition, Duration::from_millis(200));
+    }
+}
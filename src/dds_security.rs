@@ -0,0 +1,323 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! DDS Security configuration. The documents and certificates are passed to
+//! cyclone as properties of the participant QoS, using the property names of
+//! the DDS Security specification. Values are URIs: `file:` followed by a path,
+//! `data:,` followed by the PEM or XML document itself, or `pkcs11:`.
+//!
+//! Cyclone must be built with security support (`ENABLE_SECURITY`) and the
+//! builtin security plugins must be on the library path.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! use cyclonedds_rs::dds_security::file_uri;
+//! let security = SecurityConfig::new()
+//!     .with_identity_ca(&file_uri("/etc/dds/identity_ca.pem"))
+//!     .with_identity_certificate(&file_uri("/etc/dds/robot1_cert.pem"))
+//!     .with_private_key(&file_uri("/etc/dds/robot1_key.pem"))
+//!     .with_permissions_ca(&file_uri("/etc/dds/permissions_ca.pem"))
+//!     .with_governance(&file_uri("/etc/dds/governance.p7s"))
+//!     .with_permissions(&file_uri("/etc/dds/permissions.p7s"));
+//! let participant = ParticipantBuilder::new().with_security(security).create().unwrap();
+//! ```
+
+use std::ffi::CString;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::error::DDSError;
+use crate::DdsQos;
+
+pub const AUTH_LIBRARY_PATH: &str = "dds.sec.auth.library.path";
+pub const AUTH_LIBRARY_INIT: &str = "dds.sec.auth.library.init";
+pub const AUTH_LIBRARY_FINALIZE: &str = "dds.sec.auth.library.finalize";
+pub const AUTH_IDENTITY_CA: &str = "dds.sec.auth.identity_ca";
+pub const AUTH_IDENTITY_CERTIFICATE: &str = "dds.sec.auth.identity_certificate";
+pub const AUTH_PRIVATE_KEY: &str = "dds.sec.auth.private_key";
+pub const AUTH_PASSWORD: &str = "dds.sec.auth.password";
+pub const AUTH_TRUSTED_CA_DIR: &str = "dds.sec.auth.trusted_ca_dir";
+pub const ACCESS_LIBRARY_PATH: &str = "dds.sec.access.library.path";
+pub const ACCESS_LIBRARY_INIT: &str = "dds.sec.access.library.init";
+pub const ACCESS_LIBRARY_FINALIZE: &str = "dds.sec.access.library.finalize";
+pub const ACCESS_PERMISSIONS_CA: &str = "dds.sec.access.permissions_ca";
+pub const ACCESS_GOVERNANCE: &str = "dds.sec.access.governance";
+pub const ACCESS_PERMISSIONS: &str = "dds.sec.access.permissions";
+pub const CRYPTO_LIBRARY_PATH: &str = "dds.sec.crypto.library.path";
+pub const CRYPTO_LIBRARY_INIT: &str = "dds.sec.crypto.library.init";
+pub const CRYPTO_LIBRARY_FINALIZE: &str = "dds.sec.crypto.library.finalize";
+
+// the builtin plugins of cyclone
+const BUILTIN_PLUGINS: [(&str, &str); 9] = [
+    (AUTH_LIBRARY_PATH, "dds_security_auth"),
+    (AUTH_LIBRARY_INIT, "init_authentication"),
+    (AUTH_LIBRARY_FINALIZE, "finalize_authentication"),
+    (ACCESS_LIBRARY_PATH, "dds_security_ac"),
+    (ACCESS_LIBRARY_INIT, "init_access_control"),
+    (ACCESS_LIBRARY_FINALIZE, "finalize_access_control"),
+    (CRYPTO_LIBRARY_PATH, "dds_security_crypto"),
+    (CRYPTO_LIBRARY_INIT, "init_crypto"),
+    (CRYPTO_LIBRARY_FINALIZE, "finalize_crypto"),
+];
+
+/// A security configuration that cannot be used
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SecurityConfigError {
+    #[error("{0} is not set")]
+    Missing(&'static str),
+    #[error("{property}: \"{uri}\" is not a file:, data: or pkcs11: URI")]
+    InvalidUri { property: String, uri: String },
+    #[error("{property}: {path} does not exist")]
+    NotFound { property: String, path: String },
+    #[error("{property} contains a NUL character")]
+    Nul { property: String },
+    /// Cyclone rejected the participant. It does this when the certificates or
+    /// documents are not valid, and when it is built without security support.
+    #[error("the participant was rejected, check that the certificates and documents are valid and that cyclonedds is built with security support (ENABLE_SECURITY)")]
+    Rejected,
+}
+
+impl From<SecurityConfigError> for DDSError {
+    fn from(e: SecurityConfigError) -> Self {
+        DDSError::BadParameter.with_source(e)
+    }
+}
+
+/// A `file:` URI for a path
+pub fn file_uri<P: AsRef<Path>>(path: P) -> String {
+    format!("file:{}", path.as_ref().display())
+}
+
+/// A `data:` URI with the document itself
+pub fn data_uri(document: &str) -> String {
+    format!("data:,{}", document)
+}
+
+/// Authentication, access control and cryptography with the builtin plugins
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecurityConfig {
+    identity_ca: Option<String>,
+    identity_certificate: Option<String>,
+    private_key: Option<String>,
+    password: Option<String>,
+    trusted_ca_dir: Option<String>,
+    permissions_ca: Option<String>,
+    governance: Option<String>,
+    permissions: Option<String>,
+    // set after the standard properties, so these can replace them
+    extra: Vec<(String, String)>,
+}
+
+impl SecurityConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The CA that signed the identity certificates
+    pub fn with_identity_ca(mut self, uri: &str) -> Self {
+        self.identity_ca = Some(uri.to_owned());
+        self
+    }
+
+    /// The identity certificate of the participant
+    pub fn with_identity_certificate(mut self, uri: &str) -> Self {
+        self.identity_certificate = Some(uri.to_owned());
+        self
+    }
+
+    /// The private key of the identity certificate
+    pub fn with_private_key(mut self, uri: &str) -> Self {
+        self.private_key = Some(uri.to_owned());
+        self
+    }
+
+    /// The password of an encrypted private key
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_owned());
+        self
+    }
+
+    /// A directory with CAs trusted in addition to the identity CA
+    pub fn with_trusted_ca_dir(mut self, dir: &str) -> Self {
+        self.trusted_ca_dir = Some(dir.to_owned());
+        self
+    }
+
+    /// The CA that signed the governance and permissions documents
+    pub fn with_permissions_ca(mut self, uri: &str) -> Self {
+        self.permissions_ca = Some(uri.to_owned());
+        self
+    }
+
+    /// The signed governance document
+    pub fn with_governance(mut self, uri: &str) -> Self {
+        self.governance = Some(uri.to_owned());
+        self
+    }
+
+    /// The signed permissions document of the participant
+    pub fn with_permissions(mut self, uri: &str) -> Self {
+        self.permissions = Some(uri.to_owned());
+        self
+    }
+
+    /// Set any other property, for example to load a plugin from another library
+    /// with [`AUTH_LIBRARY_PATH`]
+    pub fn with_property(mut self, name: &str, value: &str) -> Self {
+        self.extra.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    // the required documents
+    fn documents(&self) -> [(&'static str, Option<&String>); 6] {
+        [
+            (AUTH_IDENTITY_CA, self.identity_ca.as_ref()),
+            (AUTH_IDENTITY_CERTIFICATE, self.identity_certificate.as_ref()),
+            (AUTH_PRIVATE_KEY, self.private_key.as_ref()),
+            (ACCESS_PERMISSIONS_CA, self.permissions_ca.as_ref()),
+            (ACCESS_GOVERNANCE, self.governance.as_ref()),
+            (ACCESS_PERMISSIONS, self.permissions.as_ref()),
+        ]
+    }
+
+    /// Check that all documents are given and that the files they refer to exist
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        for (property, uri) in self.documents().iter() {
+            match uri {
+                Some(uri) => check_uri(property, uri)?,
+                None => return Err(SecurityConfigError::Missing(*property)),
+            }
+        }
+        if let Some(dir) = &self.trusted_ca_dir {
+            if !Path::new(dir).is_dir() {
+                return Err(SecurityConfigError::NotFound {
+                    property: AUTH_TRUSTED_CA_DIR.to_owned(),
+                    path: dir.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// All properties in the order they are set
+    pub fn properties(&self) -> Vec<(String, String)> {
+        let mut properties: Vec<(String, String)> = BUILTIN_PLUGINS
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let optional = [
+            (AUTH_PASSWORD, self.password.as_ref()),
+            (AUTH_TRUSTED_CA_DIR, self.trusted_ca_dir.as_ref()),
+        ];
+        for (name, value) in self.documents().iter().chain(optional.iter()) {
+            if let Some(value) = value {
+                properties.push((name.to_string(), value.to_string()));
+            }
+        }
+        properties.extend(self.extra.iter().cloned());
+        properties
+    }
+
+    /// Validate the configuration and set the properties in `qos`
+    pub fn apply(&self, qos: &mut DdsQos) -> Result<(), DDSError> {
+        self.validate()?;
+        for (name, value) in self.properties() {
+            let nul = || SecurityConfigError::Nul {
+                property: name.clone(),
+            };
+            let c_name = CString::new(name.as_str()).map_err(|_| nul())?;
+            let c_value = CString::new(value).map_err(|_| nul())?;
+            qos.set_property(&c_name, &c_value);
+        }
+        Ok(())
+    }
+}
+
+fn check_uri(property: &str, uri: &str) -> Result<(), SecurityConfigError> {
+    if let Some(path) = uri.strip_prefix("file:") {
+        // both file:/path and file:///path are used
+        let path = path.strip_prefix("//").unwrap_or(path);
+        if Path::new(path).is_file() {
+            Ok(())
+        } else {
+            Err(SecurityConfigError::NotFound {
+                property: property.to_owned(),
+                path: path.to_owned(),
+            })
+        }
+    } else if uri.starts_with("data:,") || uri.starts_with("pkcs11:") {
+        Ok(())
+    } else {
+        Err(SecurityConfigError::InvalidUri {
+            property: property.to_owned(),
+            uri: uri.to_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> SecurityConfig {
+        let pem = data_uri("-----BEGIN CERTIFICATE-----");
+        SecurityConfig::new()
+            .with_identity_ca(&pem)
+            .with_identity_certificate(&pem)
+            .with_private_key(&pem)
+            .with_permissions_ca(&pem)
+            .with_governance(&data_uri("<dds/>"))
+            .with_permissions(&data_uri("<dds/>"))
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(config().validate(), Ok(()));
+        assert_eq!(
+            SecurityConfig::new().validate(),
+            Err(SecurityConfigError::Missing(AUTH_IDENTITY_CA))
+        );
+        assert!(matches!(
+            config().with_governance("/etc/governance.p7s").validate(),
+            Err(SecurityConfigError::InvalidUri { .. })
+        ));
+        assert!(matches!(
+            config().with_governance(&file_uri("/nonexistent/governance.p7s")).validate(),
+            Err(SecurityConfigError::NotFound { .. })
+        ));
+        let manifest = file_uri(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"));
+        assert_eq!(config().with_governance(&manifest).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut qos = DdsQos::create().unwrap();
+        config()
+            .with_property(AUTH_LIBRARY_PATH, "my_auth")
+            .apply(&mut qos)
+            .unwrap();
+        let get = |name: &str| qos.property(&CString::new(name).unwrap());
+        assert_eq!(get(ACCESS_GOVERNANCE), Some("data:,<dds/>".to_owned()));
+        assert_eq!(get(CRYPTO_LIBRARY_PATH), Some("dds_security_crypto".to_owned()));
+        assert_eq!(get(AUTH_LIBRARY_PATH), Some("my_auth".to_owned()));
+        assert_eq!(get(AUTH_PASSWORD), None);
+
+        let mut qos = DdsQos::create().unwrap();
+        assert!(SecurityConfig::new().apply(&mut qos).is_err());
+        assert_eq!(qos.property(&CString::new(AUTH_IDENTITY_CA).unwrap()), None);
+    }
+}
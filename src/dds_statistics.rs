@@ -0,0 +1,205 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Statistics maintained by cyclone for readers and writers.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::{DdsStatistics, StatisticKey, Entity};
+//! # fn example(writer: &dyn Entity) {
+//! let mut stats = DdsStatistics::create(writer).unwrap();
+//! stats.refresh().unwrap();
+//! println!("retransmitted {:?} bytes", stats.get(StatisticKey::RexmitBytes));
+//! # }
+//! ```
+
+use std::ffi::{CStr, CString};
+
+use cyclonedds_sys::*;
+use crate::error::DDSError;
+
+use crate::Entity;
+
+/// Known statistics. Writers have `RexmitBytes`, `ThrottleCount`,
+/// `TimeThrottle` and `TimeRexmit`, readers have `DiscardedBytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatisticKey {
+    /// Bytes retransmitted by the writer
+    RexmitBytes,
+    /// Number of times the writer was throttled
+    ThrottleCount,
+    /// Time (ns) the writer spent throttled
+    TimeThrottle,
+    /// Time (ns) the writer spent retransmitting
+    TimeRexmit,
+    /// Bytes discarded by the reader
+    DiscardedBytes,
+}
+
+impl StatisticKey {
+    /// The name cyclone uses for this statistic
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatisticKey::RexmitBytes => "rexmit_bytes",
+            StatisticKey::ThrottleCount => "throttle_count",
+            StatisticKey::TimeThrottle => "time_throttle",
+            StatisticKey::TimeRexmit => "time_rexmit",
+            StatisticKey::DiscardedBytes => "discarded_bytes",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatisticValue {
+    U32(u32),
+    U64(u64),
+    /// Length of a queue multiplied by the time it had that length
+    LengthTime(u64),
+}
+
+impl StatisticValue {
+    pub fn as_u64(&self) -> u64 {
+        match self {
+            StatisticValue::U32(v) => *v as u64,
+            StatisticValue::U64(v) => *v,
+            StatisticValue::LengthTime(v) => *v,
+        }
+    }
+
+    unsafe fn from_keyvalue(kv: &dds_stat_keyvalue) -> Self {
+        match kv.kind {
+            dds_stat_kind::DDS_STAT_KIND_UINT32 => StatisticValue::U32(kv.u.u32),
+            dds_stat_kind::DDS_STAT_KIND_UINT64 => StatisticValue::U64(kv.u.u64),
+            dds_stat_kind::DDS_STAT_KIND_LENGTHTIME => StatisticValue::LengthTime(kv.u.lengthtime),
+        }
+    }
+}
+
+/// A snapshot of the statistics of an entity. Call `refresh` to update it.
+pub struct DdsStatistics(*mut dds_statistics);
+
+/// Safety: the statistics object is owned by this struct and only
+/// accessed through it.
+unsafe impl Send for DdsStatistics {}
+
+impl DdsStatistics {
+    pub fn create(entity: &dyn Entity) -> Result<Self, DDSError> {
+        unsafe {
+            let stat = dds_create_statistics(entity.entity().entity());
+            if !stat.is_null() {
+                Ok(DdsStatistics(stat))
+            } else {
+                // no statistics for this kind of entity
+                Err(DDSError::BadParameter)
+            }
+        }
+    }
+
+    /// Update the values to the current values of the entity
+    pub fn refresh(&mut self) -> Result<(), DDSError> {
+        unsafe {
+            let ret = dds_refresh_statistics(self.0);
+            if ret == 0 {
+                Ok(())
+            } else {
+                Err(DDSError::from_retcode("dds_refresh_statistics", ret).with_entity_kind("statistics"))
+            }
+        }
+    }
+
+    /// The time of the last refresh
+    pub fn time(&self) -> dds_time_t {
+        unsafe { (*self.0).time }
+    }
+
+    pub fn get(&self, key: StatisticKey) -> Option<StatisticValue> {
+        self.lookup(key.name())
+    }
+
+    /// Look up a statistic by the name cyclone uses for it
+    pub fn lookup(&self, name: &str) -> Option<StatisticValue> {
+        let name = CString::new(name).ok()?;
+        unsafe {
+            let kv = dds_lookup_statistic(self.0, name.as_ptr());
+            if kv.is_null() {
+                None
+            } else {
+                Some(StatisticValue::from_keyvalue(&*kv))
+            }
+        }
+    }
+
+    /// All statistics of the entity by name
+    pub fn values(&self) -> Vec<(String, StatisticValue)> {
+        unsafe {
+            let stat = &*self.0;
+            stat.kv
+                .as_slice(stat.count as usize)
+                .iter()
+                .map(|kv| {
+                    (
+                        CStr::from_ptr(kv.name).to_string_lossy().into_owned(),
+                        StatisticValue::from_keyvalue(kv),
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+impl Drop for DdsStatistics {
+    fn drop(&mut self) {
+        unsafe { dds_delete_statistics(self.0) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsParticipant, DdsPublisher, DdsWriter};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize, Topic, Default)]
+    struct StatsTopic {
+        #[topic_key]
+        id: u32,
+    }
+
+    #[test]
+    fn test_writer_statistics() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = StatsTopic::create_topic(&participant, None, None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic, None, None).unwrap();
+        writer.write(Arc::new(StatsTopic { id: 1 })).unwrap();
+
+        let mut stats = DdsStatistics::create(&writer).unwrap();
+        stats.refresh().unwrap();
+        assert!(stats.get(StatisticKey::RexmitBytes).is_some());
+        assert!(stats.get(StatisticKey::DiscardedBytes).is_none());
+        assert!(stats
+            .values()
+            .iter()
+            .any(|(name, _)| name == StatisticKey::ThrottleCount.name()));
+    }
+
+    #[test]
+    fn test_participant_has_no_statistics() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        assert!(DdsStatistics::create(&participant).is_err());
+    }
+}
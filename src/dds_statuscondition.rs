@@ -0,0 +1,140 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! The status condition of an entity. In cyclonedds, every entity doubles as its
+//! own status condition: the enabled statuses are selected with the status mask
+//! and the entity is attached to a waitset directly.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! # fn example(participant: &DdsParticipant, writer: &dyn Entity) {
+//! let condition = DdsStatusCondition::create(writer, DdsStatus::PUBLICATION_MATCHED).unwrap();
+//! let waitset = DdsWaitset::create(participant).unwrap();
+//! waitset.attach(&condition).unwrap();
+//! let _triggered = waitset.wait(std::time::Duration::from_secs(1)).unwrap();
+//! let status = condition.take_status().unwrap();
+//! assert!(status.contains(DdsStatus::PUBLICATION_MATCHED));
+//! # }
+//! ```
+
+use crate::dds_api::DdsStatus;
+use crate::Entity;
+pub use cyclonedds_sys::DdsEntity;
+pub use crate::error::DDSError;
+
+pub struct DdsStatusCondition {
+    entity: DdsEntity,
+    mask: DdsStatus,
+}
+
+impl DdsStatusCondition {
+    /// Create a status condition for the entity, enabling the statuses in `mask`.
+    pub fn create(entity: &dyn Entity, mask: DdsStatus) -> Result<Self, DDSError> {
+        entity.set_status_mask(mask)?;
+        Ok(Self {
+            entity: entity.entity().clone(),
+            mask,
+        })
+    }
+
+    /// Change the statuses the condition triggers on
+    pub fn set_mask(&mut self, mask: DdsStatus) -> Result<(), DDSError> {
+        self.entity.set_status_mask(mask)?;
+        self.mask = mask;
+        Ok(())
+    }
+
+    pub fn mask(&self) -> DdsStatus {
+        self.mask
+    }
+
+    /// Get the statuses that changed, without resetting them
+    pub fn status_changes(&self) -> Result<DdsStatus, DDSError> {
+        self.entity.get_status_changes()
+    }
+
+    /// Get and reset the statuses in the mask that changed. The condition
+    /// stops triggering once the statuses are taken.
+    pub fn take_status(&self) -> Result<DdsStatus, DDSError> {
+        self.entity.take_status(self.mask)
+    }
+}
+
+impl Entity for DdsStatusCondition {
+    fn entity(&self) -> &DdsEntity {
+        &self.entity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsParticipant, DdsPublisher, DdsReader, DdsSubscriber};
+    use crate::{DdsWaitset, DdsWriter};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Topic, Debug, PartialEq, Default)]
+    struct StatusTopic {
+        #[topic_key]
+        id: u32,
+    }
+
+    #[test]
+    fn test_publication_matched_condition() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = StatusTopic::create_topic(&participant, Some("status"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+
+        let condition = DdsStatusCondition::create(&writer, DdsStatus::PUBLICATION_MATCHED).unwrap();
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let token = waitset.attach(&condition).unwrap();
+
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let _reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+
+        assert_eq!(
+            waitset.wait(std::time::Duration::from_secs(1)).unwrap(),
+            vec![token]
+        );
+        assert!(condition
+            .take_status()
+            .unwrap()
+            .contains(DdsStatus::PUBLICATION_MATCHED));
+    }
+
+    #[test]
+    fn test_entity_status() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = StatusTopic::create_topic(&participant, Some("entity_status"), None, None).unwrap();
+        let writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let matched = DdsStatus::PUBLICATION_MATCHED;
+        writer.set_status_mask(matched).unwrap();
+        assert_eq!(writer.get_status_mask().unwrap(), matched);
+        assert!(!writer.triggered().unwrap());
+
+        let _reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        waitset.attach(&writer).unwrap();
+        waitset.wait(std::time::Duration::from_secs(1)).unwrap();
+        assert!(writer.triggered().unwrap());
+        assert!(writer.read_status(matched).unwrap().contains(DdsStatus::PUBLICATION_MATCHED));
+        assert!(writer.get_status_changes().unwrap().contains(DdsStatus::PUBLICATION_MATCHED));
+        assert!(writer.take_status(matched).unwrap().contains(DdsStatus::PUBLICATION_MATCHED));
+        assert!(!writer.triggered().unwrap());
+    }
+}
@@ -14,9 +14,16 @@
     limitations under the License.
 */
 
+use crate::dds_coherent::CoherentSet;
 use crate::{DdsListener, DdsParticipant, DdsQos, DdsReadable};
-pub use cyclonedds_sys::{DDSError, DdsDomainId, DdsEntity};
-use std::{convert::From};
+pub use cyclonedds_sys::{DdsDomainId, DdsEntity};
+pub use crate::error::DDSError;
+use std::convert::From;
+
+use crate::common::{entity_list, EntityWrapper, Uses};
+use crate::serdes::{is_sertype_of, SampleBuffer, TopicType};
+use crate::DdsReader;
+use cyclonedds_sys::ddsi_sertype;
 
 pub struct SubscriberBuilder {
     maybe_qos: Option<DdsQos>,
@@ -47,15 +54,19 @@ impl SubscriberBuilder {
 }
 
 
+/// A subscriber. Clones share the subscriber, which is deleted once the last clone
+/// and the last of its readers are dropped. The subscriber keeps its participant alive.
 #[derive(Clone)]
-pub struct DdsSubscriber(DdsEntity, Option<DdsListener>);
+pub struct DdsSubscriber(EntityWrapper);
 
 impl<'a> DdsSubscriber {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err))]
     pub fn create(
         participant: &DdsParticipant,
         maybe_qos: Option<DdsQos>,
         maybe_listener: Option<DdsListener>,
     ) -> Result<Self, DDSError> {
+        crate::Entity::check_valid(participant)?;
         unsafe {
             let p = cyclonedds_sys::dds_create_subscriber(
                 participant.entity().entity(),
@@ -63,17 +74,158 @@ impl<'a> DdsSubscriber {
                 maybe_listener.as_ref().map_or(std::ptr::null(), |l| l.into()),
             );
             if p > 0 {
-                Ok(DdsSubscriber(DdsEntity::new(p),maybe_listener))
+                Ok(DdsSubscriber(EntityWrapper::new(
+                    "subscriber",
+                    DdsEntity::new(p),
+                    Some(participant.keep_alive()),
+                    maybe_listener,
+                    Uses::Nothing,
+                )))
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_create_subscriber", p).with_entity_kind("subscriber"))
+            }
+        }
+    }
+
+    /// Delete the subscriber and its readers now, instead of when the last clone
+    /// is dropped. Closing it again returns `DDSError::UseAfterClose`, as
+    /// do the functions of the subscriber and its clones afterwards.
+    pub fn close(&self) -> Result<(), DDSError> {
+        self.0.close()
+    }
+
+    /// Start a coherent access, reads from the readers of the subscriber see
+    /// complete coherent sets until the access is ended
+    pub fn begin_access(&self) -> Result<CoherentSet, DDSError> {
+        CoherentSet::begin(self, "subscriber")
+    }
+
+    /// The readers created on the subscriber
+    pub fn readers(&self) -> Result<Vec<DdsEntity>, DDSError> {
+        crate::Entity::children(self)
+    }
+
+    /// Take every available sample of the readers of T on the subscriber into
+    /// `buf` and return the number taken, readers of other types are left
+    /// alone. The buffer grows if the samples do not fit.
+    pub fn take_from_all<T: TopicType>(&self, buf: &mut SampleBuffer<T>) -> Result<usize, DDSError> {
+        crate::Entity::check_valid(self)?;
+        Self::take_from_all_of(self.0.entity(), buf)
+    }
+
+    /// [`DdsSubscriber::take_from_all`] for the subscriber entity passed to
+    /// the data on readers callback. Cyclone calls that callback instead of
+    /// the data available callbacks of the readers, so that the samples of all
+    /// readers can be processed as a group. A reader deleted meanwhile is
+    /// skipped.
+    pub fn take_from_all_of<T: TopicType>(
+        subscriber: &DdsEntity,
+        buf: &mut SampleBuffer<T>,
+    ) -> Result<usize, DDSError> {
+        let readers = entity_list("dds_get_children", |children, size| unsafe {
+            cyclonedds_sys::dds_get_children(subscriber.entity(), children, size)
+        })?;
+        let mut total = 0;
+        for reader in readers {
+            let mut sertype: *const ddsi_sertype = std::ptr::null();
+            let ret = unsafe { cyclonedds_sys::dds_get_entity_sertype(reader.entity(), &mut sertype) };
+            // the reader may be deleted meanwhile
+            if ret < 0 || !is_sertype_of::<T>(sertype) {
+                continue;
+            }
+            // taken the way the reader takes itself, into samples of the pool
+            // of `buf`
+            let pool = buf.pool().cloned().unwrap_or_default();
+            match DdsReader::<T>::found(reader, pool).take_all_at(buf, total) {
+                Ok(taken) => total += taken,
+                Err(e) if *e.kind() == DDSError::AlreadyDeleted => {}
+                Err(e) => return Err(e),
             }
         }
+        Ok(total)
     }
 }
 
 
 impl<'a> DdsReadable for DdsSubscriber {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
+    }
+    fn keep_alive(&self) -> Option<EntityWrapper> {
+        Some(self.0.clone())
+    }
+}
+
+impl crate::Entity for DdsSubscriber {
+    fn entity(&self) -> &DdsEntity {
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DdsTopic, DdsWriter};
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Default, Deserialize, Serialize, Topic)]
+    struct Left {
+        #[topic_key]
+        id: u32,
+    }
+
+    #[derive(Default, Deserialize, Serialize, Topic)]
+    struct Right {
+        #[topic_key]
+        id: u32,
+        name: String,
+    }
+
+    #[test]
+    fn test_take_from_all() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let a = DdsTopic::<Left>::create(&participant, "take_from_all_a", None, None).unwrap();
+        let b = DdsTopic::<Left>::create(&participant, "take_from_all_b", None, None).unwrap();
+        let c = DdsTopic::<Right>::create(&participant, "take_from_all_c", None, None).unwrap();
+        let _readers = (
+            DdsReader::create(&subscriber, a.clone(), None, None).unwrap(),
+            DdsReader::create(&subscriber, b.clone(), None, None).unwrap(),
+            DdsReader::create(&subscriber, c.clone(), None, None).unwrap(),
+        );
+        assert_eq!(subscriber.readers().unwrap().len(), 3);
+
+        let mut writer_a = DdsWriter::create(&participant, a, None, None).unwrap();
+        let mut writer_b = DdsWriter::create(&participant, b, None, None).unwrap();
+        let mut writer_c = DdsWriter::create(&participant, c, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        for id in 0..3 {
+            writer_a.write(Arc::new(Left { id })).unwrap();
+            writer_b.write(Arc::new(Left { id: id + 10 })).unwrap();
+        }
+        writer_c.write(Arc::new(Right::default())).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        // the Right sample stays in its reader
+        let mut samples = SampleBuffer::<Left>::new(2);
+        assert_eq!(subscriber.take_from_all(&mut samples).unwrap(), 6);
+        let mut ids: Vec<u32> = (0..6).filter_map(|i| samples.get(i).try_deref().map(|s| s.id)).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![0, 1, 2, 10, 11, 12]);
+        assert_eq!(subscriber.take_from_all(&mut samples).unwrap(), 0);
+
+        let mut right = SampleBuffer::<Right>::new(2);
+        assert_eq!(subscriber.take_from_all(&mut right).unwrap(), 1);
     }
 }
@@ -0,0 +1,114 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Process wide counters of failures that do not surface as errors of an API
+//! call, for example samples that could not be deserialized or panics in listener
+//! callbacks. Production systems can poll [`stats`] and alert when a counter grows.
+//! # Example
+//! ```no_run
+//! let stats = cyclonedds_rs::dds_telemetry::stats();
+//! if stats.deserialize_failures > 0 {
+//!     eprintln!("{:?}", stats);
+//! }
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Counter {
+    DeserializeFailures = 0,
+    SerdesErrorsDiscarded,
+    RejectedLoans,
+    ListenerPanics,
+    FullBufferReads,
+    DropFailures,
+    ReentrantListenerCalls,
+}
+
+const COUNTERS_LEN: usize = 7;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static COUNTERS: [AtomicU64; COUNTERS_LEN] = [ZERO; COUNTERS_LEN];
+
+pub(crate) fn count(counter: Counter) {
+    COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+fn get(counter: Counter) -> u64 {
+    COUNTERS[counter as usize].load(Ordering::Relaxed)
+}
+
+/// A snapshot of the counters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Received samples that could not be deserialized and were dropped
+    pub deserialize_failures: u64,
+    /// Deserialization failures that were discarded because no reader collected
+    /// them with [`DdsReader::take_serdes_errors`](crate::DdsReader::take_serdes_errors)
+    pub serdes_errors_discarded: u64,
+    /// Loans refused by [`DdsWriter::loan`](crate::DdsWriter::loan)
+    pub rejected_loans: u64,
+    /// Panics in listener callbacks. The panic is caught as it must not unwind
+    /// into cyclone.
+    pub listener_panics: u64,
+    /// Reads and takes that filled the whole sample buffer. Samples beyond the
+    /// buffer stay in the reader history, where newer samples may replace them
+    /// before they are read.
+    pub full_buffer_reads: u64,
+    /// Entities that could not be deleted when they were dropped
+    pub drop_failures: u64,
+    /// Listener callbacks that were not called because the same callback was
+    /// running on the calling thread, see [`dds_listener`](crate::dds_listener)
+    pub reentrant_listener_calls: u64,
+}
+
+/// The current value of the counters
+pub fn stats() -> Stats {
+    Stats {
+        deserialize_failures: get(Counter::DeserializeFailures),
+        serdes_errors_discarded: get(Counter::SerdesErrorsDiscarded),
+        rejected_loans: get(Counter::RejectedLoans),
+        listener_panics: get(Counter::ListenerPanics),
+        full_buffer_reads: get(Counter::FullBufferReads),
+        drop_failures: get(Counter::DropFailures),
+        reentrant_listener_calls: get(Counter::ReentrantListenerCalls),
+    }
+}
+
+/// Set all counters to zero
+pub fn reset_stats() {
+    for counter in COUNTERS.iter() {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        let before = stats();
+        count(Counter::RejectedLoans);
+        count(Counter::ListenerPanics);
+        count(Counter::ListenerPanics);
+        let after = stats();
+        // other tests may count concurrently
+        assert!(after.rejected_loans >= before.rejected_loans + 1);
+        assert!(after.listener_panics >= before.listener_panics + 2);
+    }
+}
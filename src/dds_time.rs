@@ -0,0 +1,157 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Time as cyclone sees it: nanoseconds since the UNIX epoch. [`DdsTime`]
+//! converts to and from `SystemTime`, so timestamps of samples can be used
+//! without handling nanoseconds.
+//! # Example
+//! ```no_run
+//! use cyclonedds_rs::*;
+//! use std::time::{Duration, SystemTime};
+//! # fn example<T: TopicType>(writer: &mut DdsWriter<T>, sample: std::sync::Arc<T>) {
+//! // the sample was measured a moment ago
+//! writer.write_ts(sample, SystemTime::now() - Duration::from_millis(5)).unwrap();
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::ops::{Add, Sub};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use cyclonedds_sys::{dds_duration_t, dds_time_t};
+
+/// Convert a timeout into a dds duration, saturating at DDS_INFINITY
+pub(crate) fn duration_to_dds(timeout: Duration) -> dds_duration_t {
+    dds_duration_t::try_from(timeout.as_nanos()).unwrap_or(dds_duration_t::MAX)
+}
+
+/// A point in time in nanoseconds since the UNIX epoch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DdsTime(dds_time_t);
+
+impl DdsTime {
+    /// The end of time, for example of a lease that never expires
+    pub const NEVER: DdsTime = DdsTime(i64::MAX);
+    /// No time, for example the timestamp of a sample without valid data
+    pub const INVALID: DdsTime = DdsTime(i64::MIN);
+
+    /// The current time of the cyclone clock
+    pub fn now() -> Self {
+        DdsTime(unsafe { cyclonedds_sys::dds_time() })
+    }
+
+    pub fn from_nanos(nanos: dds_time_t) -> Self {
+        DdsTime(nanos)
+    }
+
+    pub fn as_nanos(&self) -> dds_time_t {
+        self.0
+    }
+
+    pub fn is_valid(&self) -> bool {
+        *self != Self::INVALID && *self != Self::NEVER
+    }
+
+    /// The time as `SystemTime`, `None` for `NEVER` and `INVALID`
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        if !self.is_valid() {
+            None
+        } else if self.0 >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_nanos(self.0 as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_nanos(self.0.unsigned_abs()))
+        }
+    }
+
+    /// The time elapsed since `earlier`, `None` if `earlier` is later
+    pub fn duration_since(&self, earlier: DdsTime) -> Option<Duration> {
+        self.0
+            .checked_sub(earlier.0)
+            .filter(|d| *d >= 0)
+            .map(|d| Duration::from_nanos(d as u64))
+    }
+}
+
+impl From<SystemTime> for DdsTime {
+    /// Times that do not fit are clamped
+    fn from(time: SystemTime) -> Self {
+        let nanos = match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => i64::try_from(after.as_nanos()).unwrap_or(i64::MAX - 1),
+            Err(e) => i64::try_from(e.duration().as_nanos())
+                .map(|n| -n)
+                .unwrap_or(i64::MIN + 1),
+        };
+        DdsTime(nanos)
+    }
+}
+
+impl From<DdsTime> for dds_time_t {
+    fn from(time: DdsTime) -> Self {
+        time.0
+    }
+}
+
+impl Add<Duration> for DdsTime {
+    type Output = DdsTime;
+
+    /// Saturates at `NEVER`
+    fn add(self, duration: Duration) -> DdsTime {
+        let nanos = i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX);
+        DdsTime(self.0.saturating_add(nanos))
+    }
+}
+
+impl Sub<Duration> for DdsTime {
+    type Output = DdsTime;
+
+    fn sub(self, duration: Duration) -> DdsTime {
+        let nanos = i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX);
+        DdsTime(self.0.saturating_sub(nanos).max(i64::MIN + 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_system_time() {
+        let now = SystemTime::now();
+        assert_eq!(DdsTime::from(now).to_system_time(), Some(now));
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(3);
+        assert_eq!(DdsTime::from(before_epoch).as_nanos(), -3_000_000_000);
+        assert_eq!(DdsTime::from(before_epoch).to_system_time(), Some(before_epoch));
+        assert_eq!(DdsTime::NEVER.to_system_time(), None);
+        assert_eq!(DdsTime::INVALID.to_system_time(), None);
+    }
+
+    #[test]
+    fn test_duration_to_dds() {
+        assert_eq!(duration_to_dds(Duration::from_millis(1)), 1_000_000);
+        // u64::MAX seconds are beyond i64 nanoseconds, this wrapped before
+        assert_eq!(duration_to_dds(Duration::from_secs(u64::MAX)), dds_duration_t::MAX);
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let t = DdsTime::from_nanos(1_000);
+        assert_eq!((t + Duration::from_nanos(500)).as_nanos(), 1_500);
+        assert_eq!((t + Duration::from_nanos(500)).duration_since(t), Some(Duration::from_nanos(500)));
+        assert_eq!(t.duration_since(t + Duration::from_nanos(1)), None);
+        assert_eq!(DdsTime::NEVER + Duration::from_secs(1), DdsTime::NEVER);
+        assert!(DdsTime::now().is_valid());
+    }
+}
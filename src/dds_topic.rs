@@ -17,17 +17,20 @@
 use crate::{dds_listener::DdsListener, dds_participant::DdsParticipant, dds_qos::DdsQos, Entity};
 
 use std::convert::From;
-use std::ffi::CString;
+use std::ffi::CStr;
 use std::marker::PhantomData;
 
-use crate::serdes::{SerType, TopicType};
-pub use cyclonedds_sys::{ddsi_sertype, DDSError, DdsEntity};
+use crate::common::{EntityWrapper, Uses};
+use crate::serdes::{cached_typename, BorrowedTopicType, SerType, TopicType};
+pub use cyclonedds_sys::{ddsi_sertype, DdsEntity};
+pub use crate::error::DDSError;
 
 pub struct TopicBuilder<T: TopicType> {
     maybe_qos: Option<DdsQos>,
     maybe_listener: Option<DdsListener>,
     topic_name: String,
-    phantom: PhantomData<T>,
+    type_name: Option<String>,
+    new_sertype: fn(&CStr) -> Box<SerType<T>>,
 }
 
 impl<T> TopicBuilder<T>
@@ -38,8 +41,9 @@ where
         Self {
             maybe_qos: None,
             maybe_listener: None,
-            topic_name: T::topic_name(None),
-            phantom: PhantomData,
+            topic_name: T::default_topic_name().to_owned(),
+            type_name: None,
+            new_sertype: SerType::<T>::named,
         }
     }
 
@@ -54,6 +58,14 @@ where
         self
     }
 
+    /// Report `type_name` instead of the type name of T, e.g. the IDL name
+    /// `HelloWorldData::Msg` to match participants built from the same IDL.
+    /// See also [`set_type_name_mode`](crate::serdes::set_type_name_mode).
+    pub fn with_type_name(mut self, type_name: &str) -> Self {
+        self.type_name = Some(type_name.to_owned());
+        self
+    }
+
     pub fn with_qos(mut self, qos: DdsQos) -> Self {
         self.maybe_qos = Some(qos);
         self
@@ -65,33 +77,66 @@ where
     }
 
     pub fn create(self, participant: &DdsParticipant) -> Result<DdsTopic<T>, DDSError> {
-        DdsTopic::<T>::create(
+        let sertype = match &self.type_name {
+            Some(type_name) => (self.new_sertype)(&crate::common::type_name(type_name)?),
+            None => (self.new_sertype)(cached_typename::<T>()),
+        };
+        DdsTopic::<T>::create_with_sertype(
             participant,
             self.topic_name.as_str(),
+            sertype,
             self.maybe_qos,
             self.maybe_listener,
         )
     }
 }
 
-pub struct DdsTopic<T: Sized + TopicType>(DdsEntity, PhantomData<T>, Option<DdsListener>);
+impl<T> TopicBuilder<T>
+where
+    T: BorrowedTopicType,
+{
+    /// Keep received samples as CDR instead of deserializing them to `T`. The
+    /// samples are read with [`Sample::borrowed`](crate::Sample::borrowed), which
+    /// borrows the strings and byte sequences of `T::Borrowed` from the CDR;
+    /// `Sample::try_deref` returns `None` for them.
+    pub fn with_borrowed_deserialization(mut self) -> Self {
+        self.new_sertype = SerType::<T>::named_borrowed;
+        self
+    }
+}
+
+/// A topic. Clones share the topic entity, which is deleted when the last
+/// clone is dropped. Readers and writers keep a clone of their topic, the
+/// topic keeps its participant alive.
+pub struct DdsTopic<T: Sized + TopicType>(EntityWrapper, PhantomData<T>);
 
 impl<T> DdsTopic<T>
 where
     T: std::marker::Sized + TopicType,
 {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(participant, maybe_qos, maybe_listener), err))]
     pub fn create(
         participant: &DdsParticipant,
         name: &str,
         maybe_qos: Option<DdsQos>,
         maybe_listener: Option<DdsListener>,
     ) -> Result<Self, DDSError> {
-        let t = SerType::<T>::new();
-        let mut t = SerType::into_sertype(t);
+        Self::create_with_sertype(participant, name, SerType::<T>::new(), maybe_qos, maybe_listener)
+    }
+
+    fn create_with_sertype(
+        participant: &DdsParticipant,
+        name: &str,
+        sertype: Box<SerType<T>>,
+        maybe_qos: Option<DdsQos>,
+        maybe_listener: Option<DdsListener>,
+    ) -> Result<Self, DDSError> {
+        Entity::check_valid(participant)?;
+        let strname = crate::common::topic_name(name)?;
+        let mut t = SerType::into_sertype(sertype);
         let tt = &mut t as *mut *mut ddsi_sertype;
 
         unsafe {
-            let strname = CString::new(name).expect("CString::new failed");
             let topic = cyclonedds_sys::dds_create_topic_sertype(
                 participant.entity().entity(),
                 strname.as_ptr(),
@@ -104,20 +149,198 @@ where
             );
 
             if topic >= 0 {
-                Ok(DdsTopic(DdsEntity::new(topic), PhantomData, maybe_listener))
+                Ok(DdsTopic(
+                    EntityWrapper::new(
+                        "topic",
+                        DdsEntity::new(topic),
+                        Some(participant.keep_alive()),
+                        maybe_listener,
+                        Uses::Nothing,
+                    ),
+                    PhantomData,
+                ))
             } else {
-                Err(DDSError::from(topic))
+                Err(DDSError::from_retcode("dds_create_topic_sertype", topic).with_entity_kind("topic"))
             }
         }
     }
 }
 
+impl<T> DdsTopic<T>
+where
+    T: std::marker::Sized + TopicType,
+{
+    /// Find an existing topic, which may also be a topic discovered from a
+    /// remote participant, and create a local handle for it with the same Qos.
+    /// Returns `Ok(None)` if the topic was not found within `timeout` and
+    /// `DDSError::PreconditionNotMet` if the type name of the topic is not
+    /// the type name of T.
+    pub fn find(
+        participant: &DdsParticipant,
+        name: &str,
+        timeout: std::time::Duration,
+    ) -> Result<Option<Self>, DDSError> {
+        let found = match DdsFoundTopic::find(participant, name, FindScope::Global, timeout)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        if found.type_name()?.as_bytes() != cached_typename::<T>().to_bytes() {
+            return Err(DDSError::PreconditionNotMet);
+        }
+
+        // The found topic may use a sertype created by cyclone, create our own
+        // so that samples are serialized by T.
+        let qos = found.qos()?;
+        Self::create(participant, name, Some(qos), None).map(Some)
+    }
+
+    /// Held by the readers and writers of the topic, the topic is deleted
+    /// after them
+    pub(crate) fn keep_alive(&self) -> EntityWrapper {
+        self.0.clone()
+    }
+
+    /// The name the topic was created with
+    pub fn name(&self) -> Result<String, DDSError> {
+        self.check_valid()?;
+        topic_name_of(self.0.entity())
+    }
+
+    /// The type name registered for the topic
+    pub fn type_name(&self) -> Result<String, DDSError> {
+        self.check_valid()?;
+        topic_type_name_of(self.0.entity())
+    }
+}
+
+pub(crate) fn topic_name_of(entity: &DdsEntity) -> Result<String, DDSError> {
+    name_with("dds_get_name", |buf, size| unsafe { cyclonedds_sys::dds_get_name(entity.entity(), buf, size) })
+}
+
+fn topic_type_name_of(entity: &DdsEntity) -> Result<String, DDSError> {
+    name_with("dds_get_type_name", |buf, size| unsafe { cyclonedds_sys::dds_get_type_name(entity.entity(), buf, size) })
+}
+
+/// Get a string from cyclone into a buffer. Cyclone truncates the string if the
+/// buffer is too small, so retry with a larger buffer if the buffer was filled.
+fn name_with<F>(operation: &'static str, f: F) -> Result<String, DDSError>
+where
+    F: Fn(*mut std::os::raw::c_char, cyclonedds_sys::size_t) -> cyclonedds_sys::dds_return_t,
+{
+    let mut size = 256;
+    loop {
+        let mut buf = vec![0 as std::os::raw::c_char; size];
+        let ret = f(buf.as_mut_ptr(), size as cyclonedds_sys::size_t);
+        if ret < 0 {
+            return Err(DDSError::from_retcode(operation, ret).with_entity_kind("topic"));
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+        if name.to_bytes().len() + 1 < size {
+            return Ok(name.to_string_lossy().into_owned());
+        }
+        size *= 2;
+    }
+}
+
+/// Scope of the search in `DdsFoundTopic::find`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FindScope {
+    /// Topics known anywhere in the domain, including remote topics if
+    /// cyclone is built with topic discovery.
+    Global,
+    /// Topics created by any participant in this process on the same domain
+    LocalDomain,
+    /// Topics created by this participant
+    Participant,
+}
+
+impl From<FindScope> for cyclonedds_sys::dds_find_scope {
+    fn from(scope: FindScope) -> Self {
+        match scope {
+            FindScope::Global => cyclonedds_sys::dds_find_scope::DDS_FIND_SCOPE_GLOBAL,
+            FindScope::LocalDomain => cyclonedds_sys::dds_find_scope::DDS_FIND_SCOPE_LOCAL_DOMAIN,
+            FindScope::Participant => cyclonedds_sys::dds_find_scope::DDS_FIND_SCOPE_PARTICIPANT,
+        }
+    }
+}
+
+/// A topic found by name. The type of the topic is not known, so this can
+/// only be used to inspect the topic. It keeps its participant alive and is
+/// deleted when dropped.
+pub struct DdsFoundTopic(EntityWrapper);
+
+impl DdsFoundTopic {
+    /// Find a topic by name. Waits up to `timeout` for the topic to appear.
+    /// Returns `Ok(None)` if the topic was not found.
+    pub fn find(
+        participant: &DdsParticipant,
+        name: &str,
+        scope: FindScope,
+        timeout: std::time::Duration,
+    ) -> Result<Option<Self>, DDSError> {
+        Entity::check_valid(participant)?;
+        let strname = crate::common::topic_name(name)?;
+        unsafe {
+            let topic = cyclonedds_sys::dds_find_topic_scoped(
+                scope.into(),
+                participant.entity().entity(),
+                strname.as_ptr(),
+                std::ptr::null(),
+                crate::dds_time::duration_to_dds(timeout),
+            );
+            if topic > 0 {
+                Ok(Some(DdsFoundTopic(EntityWrapper::new(
+                    "topic",
+                    DdsEntity::new(topic),
+                    Some(participant.keep_alive()),
+                    None,
+                    Uses::Nothing,
+                ))))
+            } else if topic == 0 {
+                Ok(None)
+            } else {
+                Err(DDSError::from_retcode("dds_find_topic_scoped", topic).with_entity_kind("topic"))
+            }
+        }
+    }
+
+    pub fn name(&self) -> Result<String, DDSError> {
+        self.0.check_valid()?;
+        topic_name_of(self.0.entity())
+    }
+
+    pub fn type_name(&self) -> Result<String, DDSError> {
+        self.0.check_valid()?;
+        topic_type_name_of(self.0.entity())
+    }
+
+    pub fn qos(&self) -> Result<DdsQos, DDSError> {
+        self.0.check_valid()?;
+        DdsQos::of_entity(self.0.entity())
+    }
+}
+
+impl Entity for DdsFoundTopic {
+    fn entity(&self) -> &DdsEntity {
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
+    }
+}
+
 impl<T> Entity for DdsTopic<T>
 where
     T: std::marker::Sized + TopicType,
 {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.0.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
     }
 }
 
@@ -126,7 +349,7 @@ where
     T: std::marker::Sized + TopicType,
 {
     fn clone(&self) -> Self {
-        Self(self.0.clone(), PhantomData, self.2.clone())
+        Self(self.0.clone(), PhantomData)
     }
 }
 
@@ -157,6 +380,8 @@ mod test {
             MyTopic::topic_name(Some("prefix")),
             String::from("prefix/dds_topic/test/test_topic_creation/MyTopic")
         );
+        // the name is built once per type
+        assert!(std::ptr::eq(MyTopic::default_topic_name(), MyTopic::default_topic_name()));
 
         let participant = DdsParticipant::create(None, None, None).unwrap();
         let topic = MyTopic::create_topic(&participant, None, None, None).unwrap();
@@ -175,4 +400,228 @@ mod test {
 
         writer.write(data).unwrap();
     }
+
+    #[test]
+    fn test_topic_deleted_with_last_clone() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct Shared {
+            #[topic_key]
+            a: u32,
+        }
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = Shared::create_topic(&participant, Some("shared_topic"), None, None).unwrap();
+        let handle = unsafe { topic.entity().entity() };
+        let alive = || unsafe { cyclonedds_sys::dds_get_parent(handle) } > 0;
+
+        let clone = topic.clone();
+        drop(topic);
+        assert!(alive());
+
+        // the writer keeps its topic
+        let writer = DdsWriter::create(&participant, clone, None, None).unwrap();
+        assert!(alive());
+        drop(writer);
+        assert!(!alive());
+    }
+
+    #[test]
+    fn test_invalid_topic_names() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct Named {
+            a: u32,
+        }
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        for name in ["", "with\0nul", "wild*card", "tab\there"] {
+            let err = Named::create_topic_with_name(&participant, name, None, None).unwrap_err();
+            assert_eq!(err, DDSError::BadParameter);
+            assert!(err.to_string().contains("invalid topic name"), "{}", err);
+        }
+        assert!(Named::create_topic_with_name(&participant, "valid/name", None, None).is_ok());
+    }
+
+    #[test]
+    fn test_borrowed_deserialization() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct Frame {
+            #[topic_key]
+            id: u32,
+            label: String,
+            payload: Vec<u8>,
+        }
+
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct FrameRef<'a> {
+            id: u32,
+            label: &'a str,
+            payload: &'a [u8],
+        }
+
+        impl BorrowedTopicType for Frame {
+            type Borrowed<'a> = FrameRef<'a>;
+
+            fn borrowed_key_cdr(sample: &FrameRef<'_>) -> Vec<u8> {
+                cdr::serialize::<_, _, cdr::CdrBe>(&sample.id, cdr::Infinite).unwrap()
+            }
+        }
+
+        let domain = crate::test_support::LoopbackDomain::create().unwrap();
+        let topic = domain.topic::<Frame>("frames").unwrap();
+        let mut writer = domain.writer(topic).unwrap();
+        let borrowed_topic = TopicBuilder::<Frame>::new()
+            .with_name("frames".to_owned())
+            .with_borrowed_deserialization()
+            .create(domain.participant())
+            .unwrap();
+        let reader = domain.reader(borrowed_topic).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let frame = Frame { id: 4, label: "depth".to_owned(), payload: vec![7; 1000] };
+        assert_eq!(Frame::borrowed_key_cdr(&FrameRef { id: 4, label: "", payload: &[] }), frame.key_cdr());
+        writer.write(Arc::new(frame)).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let mut samples = SampleBuffer::<Frame>::new(2);
+        assert_eq!(reader.take_now(&mut samples).unwrap(), 1);
+        let views: Vec<_> = samples.iter_borrowed().collect();
+        assert_eq!(views, vec![&FrameRef { id: 4, label: "depth", payload: &[7; 1000] }]);
+    }
+
+    #[test]
+    fn test_find_typed_topic() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct TypedFind {
+            #[topic_key]
+            a: u32,
+        }
+
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct OtherType {
+            #[topic_key]
+            a: u32,
+        }
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let name = TypedFind::topic_name(None);
+        let timeout = std::time::Duration::from_millis(0);
+        assert!(DdsTopic::<TypedFind>::find(&participant, &name, timeout)
+            .unwrap()
+            .is_none());
+
+        let _topic = TypedFind::create_topic(&participant, None, None, None).unwrap();
+        let found = DdsTopic::<TypedFind>::find(&participant, &name, timeout)
+            .unwrap()
+            .expect("topic not found");
+        assert_eq!(found.name().unwrap(), name);
+
+        assert_eq!(
+            DdsTopic::<OtherType>::find(&participant, &name, timeout).err(),
+            Some(DDSError::PreconditionNotMet)
+        );
+    }
+
+    #[test]
+    fn test_topic_names() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct NamedTopic {
+            #[topic_key]
+            a: u32,
+        }
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let long_prefix = "x".repeat(300);
+        let topic = NamedTopic::create_topic(&participant, Some(&long_prefix), None, None).unwrap();
+        assert_eq!(topic.name().unwrap(), NamedTopic::topic_name(Some(&long_prefix)));
+        assert_eq!(
+            topic.type_name().unwrap(),
+            NamedTopic::typename().to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_topic_type_name() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct Msg {
+            #[topic_key]
+            a: u32,
+        }
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let idl = TopicBuilder::<Msg>::new()
+            .with_name("/test/idl_type_name".to_owned())
+            .with_type_name("HelloWorldData::Msg")
+            .create(&participant)
+            .unwrap();
+        assert_eq!(idl.type_name().unwrap(), "HelloWorldData::Msg");
+
+        // without a type name, the type name of T
+        let rust = TopicBuilder::<Msg>::new().create(&participant).unwrap();
+        assert_eq!(rust.type_name().unwrap(), Msg::typename().to_str().unwrap());
+
+        assert_eq!(
+            TopicBuilder::<Msg>::new().with_type_name("").create(&participant).err(),
+            Some(DDSError::BadParameter)
+        );
+    }
+
+    #[test]
+    fn test_find_topic() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct FindMe {
+            #[topic_key]
+            a: u32,
+        }
+
+        let mut participant = DdsParticipant::create(None, None, None).unwrap();
+        let _topic = FindMe::create_topic(&participant, None, None, None).unwrap();
+
+        let found = DdsFoundTopic::find(
+            &participant,
+            &FindMe::topic_name(None),
+            FindScope::Participant,
+            std::time::Duration::from_millis(0),
+        )
+        .unwrap()
+        .expect("topic not found");
+        assert_eq!(found.name().unwrap(), FindMe::topic_name(None));
+        assert_eq!(
+            found.type_name().unwrap(),
+            FindMe::typename().to_str().unwrap()
+        );
+
+        let not_found = DdsFoundTopic::find(
+            &participant,
+            "/does/not/exist",
+            FindScope::Participant,
+            std::time::Duration::from_millis(0),
+        )
+        .unwrap();
+        assert!(not_found.is_none());
+
+        // the found topic goes with its participant
+        participant.close().unwrap();
+        assert!(matches!(found.name(), Err(DDSError::UseAfterClose)));
+    }
+
+    #[test]
+    fn test_topics_share_sertype() {
+        #[derive(Default, Deserialize, Serialize, Topic)]
+        struct Shared {
+            #[topic_key]
+            a: u32,
+        }
+
+        fn sertype<T: TopicType>(topic: &DdsTopic<T>) -> *const ddsi_sertype {
+            let mut sertype = std::ptr::null();
+            let ret = unsafe { cyclonedds_sys::dds_get_entity_sertype(topic.entity().entity(), &mut sertype) };
+            assert!(ret >= 0);
+            sertype
+        }
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let first = DdsTopic::<Shared>::create(&participant, "shared_a", None, None).unwrap();
+        let second = DdsTopic::<Shared>::create(&participant, "shared_b", None, None).unwrap();
+        assert_eq!(sertype(&first), sertype(&second));
+    }
 }
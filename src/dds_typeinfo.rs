@@ -0,0 +1,618 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! The XTypes type information of topic types. Participants announce the
+//! type information of their topics in discovery, tools like dds-spy and the
+//! admin consoles of other DDS implementations look up the type objects it
+//! refers to for the fields of the type.
+//!
+//! With the `type-discovery` feature, which needs a cyclone built with type
+//! discovery, the topics of Rust types publish the type information built
+//! from [`DynamicType::of`]. It describes the type as it is serialized: a
+//! final struct of plain CDR with unbounded strings and sequences.
+//!
+//! The encoding is XCDR2 little endian without encapsulation header, like
+//! the `TYPE_INFO_CDR` and `TYPE_MAP_CDR` that idlc generates.
+
+use crate::dds_dynamic::{DynamicKind, DynamicType, DynamicTypeError};
+use crate::serdes::{md5, TopicType};
+
+// EquivalenceKind, the hashed type identifiers
+const EK_MINIMAL: u8 = 0xf1;
+const EK_COMPLETE: u8 = 0xf2;
+const EK_BOTH: u8 = 0xf3;
+
+// TypeKind
+const TK_NONE: u8 = 0x00;
+const TK_BOOLEAN: u8 = 0x01;
+const TK_BYTE: u8 = 0x02;
+const TK_INT16: u8 = 0x03;
+const TK_INT32: u8 = 0x04;
+const TK_INT64: u8 = 0x05;
+const TK_UINT16: u8 = 0x06;
+const TK_UINT32: u8 = 0x07;
+const TK_UINT64: u8 = 0x08;
+const TK_FLOAT32: u8 = 0x09;
+const TK_FLOAT64: u8 = 0x0a;
+const TK_INT8: u8 = 0x0c;
+const TK_CHAR8: u8 = 0x10;
+const TK_STRUCTURE: u8 = 0x51;
+
+// TypeIdentifier discriminators of the fully descriptive collections
+const TI_STRING8_SMALL: u8 = 0x70;
+const TI_PLAIN_SEQUENCE_SMALL: u8 = 0x80;
+const TI_PLAIN_ARRAY_SMALL: u8 = 0x90;
+const TI_PLAIN_ARRAY_LARGE: u8 = 0x91;
+
+// MemberFlag and TypeFlag
+const TRY_CONSTRUCT1: u16 = 1 << 0;
+const IS_MUST_UNDERSTAND: u16 = 1 << 4;
+const IS_KEY: u16 = 1 << 5;
+const IS_FINAL: u16 = 1 << 0;
+
+// The member ids of TypeInformation
+const MINIMAL_ID: u32 = 0x1001;
+const COMPLETE_ID: u32 = 0x1002;
+
+// The length of the hash in a TypeIdentifier
+const HASH_LEN: usize = 14;
+
+/// The type information of a type with the type objects it refers to
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeInformation {
+    type_information: Vec<u8>,
+    type_mapping: Vec<u8>,
+    minimal_hash: [u8; HASH_LEN],
+    complete_hash: [u8; HASH_LEN],
+}
+
+impl TypeInformation {
+    /// The type information of a struct. The complete type object names the
+    /// type with the name of `ty`.
+    pub fn new(ty: &DynamicType) -> Self {
+        let mut registry = Registry::default();
+        let root = registry.add(ty);
+        Self {
+            type_information: registry.type_information(root),
+            type_mapping: registry.type_mapping(),
+            minimal_hash: registry.types[root].1.minimal.hash,
+            complete_hash: registry.types[root].1.complete.hash,
+        }
+    }
+
+    /// The type information of a Rust topic type, see [`DynamicType::of`]
+    pub fn of<T: TopicType>() -> Result<Self, DynamicTypeError> {
+        Ok(Self::new(&DynamicType::of::<T>()?))
+    }
+
+    /// The serialized `TypeInformation`, announced in discovery
+    pub fn type_information(&self) -> &[u8] {
+        &self.type_information
+    }
+
+    /// The serialized type objects of the type and the types it uses, with
+    /// their identifiers
+    pub fn type_mapping(&self) -> &[u8] {
+        &self.type_mapping
+    }
+
+    /// The hash identifying the minimal type object, which remote readers and
+    /// writers are matched on
+    pub fn minimal_hash(&self) -> [u8; HASH_LEN] {
+        self.minimal_hash
+    }
+
+    /// The hash identifying the complete type object, with type and member names
+    pub fn complete_hash(&self) -> [u8; HASH_LEN] {
+        self.complete_hash
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Equivalence {
+    Minimal,
+    Complete,
+}
+
+impl Equivalence {
+    fn kind(self) -> u8 {
+        match self {
+            Equivalence::Minimal => EK_MINIMAL,
+            Equivalence::Complete => EK_COMPLETE,
+        }
+    }
+}
+
+// XCDR2 little endian. Values are aligned to their size, up to four bytes,
+// relative to the start of the buffer.
+#[derive(Default)]
+struct Xcdr2 {
+    buf: Vec<u8>,
+}
+
+impl Xcdr2 {
+    fn align(&mut self, alignment: usize) {
+        while self.buf.len() % alignment != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.align(2);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    fn string(&mut self, v: &str) {
+        self.u32(v.len() as u32 + 1);
+        self.bytes(v.as_bytes());
+        self.u8(0);
+    }
+
+    // A DHEADER, `end` sets it to the size of what follows it
+    fn begin(&mut self) -> usize {
+        self.u32(0);
+        self.buf.len()
+    }
+
+    fn end(&mut self, start: usize) {
+        let size = (self.buf.len() - start) as u32;
+        self.buf[start - 4..start].copy_from_slice(&size.to_le_bytes());
+    }
+
+    // A member of a mutable struct, with its size in the NEXTINT after the
+    // EMHEADER (length code 4)
+    fn begin_member(&mut self, id: u32) -> usize {
+        self.u32(4 << 28 | id);
+        self.begin()
+    }
+}
+
+struct TypeObject {
+    bytes: Vec<u8>,
+    hash: [u8; HASH_LEN],
+}
+
+impl TypeObject {
+    fn new(bytes: Vec<u8>) -> Self {
+        let mut hash = [0; HASH_LEN];
+        hash.copy_from_slice(&md5(&bytes)[..HASH_LEN]);
+        Self { bytes, hash }
+    }
+}
+
+struct TypeObjects {
+    minimal: TypeObject,
+    complete: TypeObject,
+}
+
+impl TypeObjects {
+    fn get(&self, equivalence: Equivalence) -> &TypeObject {
+        match equivalence {
+            Equivalence::Minimal => &self.minimal,
+            Equivalence::Complete => &self.complete,
+        }
+    }
+}
+
+// The structs of a type, each after the structs it uses
+#[derive(Default)]
+struct Registry {
+    types: Vec<(DynamicType, TypeObjects)>,
+}
+
+impl Registry {
+    fn add(&mut self, ty: &DynamicType) -> usize {
+        if let Some(index) = self.index(ty) {
+            return index;
+        }
+        for field in ty.fields() {
+            self.add_kind(&field.kind);
+        }
+        let objects = TypeObjects {
+            minimal: TypeObject::new(self.type_object(ty, Equivalence::Minimal)),
+            complete: TypeObject::new(self.type_object(ty, Equivalence::Complete)),
+        };
+        self.types.push((ty.clone(), objects));
+        self.types.len() - 1
+    }
+
+    fn add_kind(&mut self, kind: &DynamicKind) {
+        match kind {
+            DynamicKind::Struct(ty) => {
+                self.add(ty);
+            }
+            DynamicKind::Sequence(element) | DynamicKind::Array(element, _) => self.add_kind(element),
+            _ => {}
+        }
+    }
+
+    fn index(&self, ty: &DynamicType) -> Option<usize> {
+        self.types.iter().position(|(known, _)| known == ty)
+    }
+
+    fn objects(&self, ty: &DynamicType) -> &TypeObjects {
+        // structs are added before the types using them
+        &self.types[self.index(ty).unwrap()].1
+    }
+
+    fn type_object(&self, ty: &DynamicType, equivalence: Equivalence) -> Vec<u8> {
+        let mut w = Xcdr2::default();
+        // TypeObject, an appendable union of a final union of the struct
+        let object = w.begin();
+        w.u8(equivalence.kind());
+        w.u8(TK_STRUCTURE);
+        w.u16(IS_FINAL);
+        let header = w.begin();
+        // no base type
+        w.u8(TK_NONE);
+        if equivalence == Equivalence::Complete {
+            // no builtin or custom annotations
+            w.u8(0);
+            w.u8(0);
+            w.string(ty.name());
+        }
+        w.end(header);
+        let members = w.begin();
+        w.u32(ty.fields().len() as u32);
+        for (id, field) in ty.fields().iter().enumerate() {
+            let member = w.begin();
+            w.u32(id as u32);
+            w.u16(if field.key {
+                TRY_CONSTRUCT1 | IS_MUST_UNDERSTAND | IS_KEY
+            } else {
+                TRY_CONSTRUCT1
+            });
+            self.type_identifier(&mut w, &field.kind, equivalence);
+            match equivalence {
+                Equivalence::Minimal => w.bytes(&md5(field.name.as_bytes())[..4]),
+                Equivalence::Complete => {
+                    w.string(&field.name);
+                    w.u8(0);
+                    w.u8(0);
+                }
+            }
+            w.end(member);
+        }
+        w.end(members);
+        w.end(object);
+        w.buf
+    }
+
+    fn type_identifier(&self, w: &mut Xcdr2, kind: &DynamicKind, equivalence: Equivalence) {
+        match kind {
+            DynamicKind::Bool => w.u8(TK_BOOLEAN),
+            DynamicKind::Char => w.u8(TK_CHAR8),
+            DynamicKind::Int8 => w.u8(TK_INT8),
+            DynamicKind::Int16 => w.u8(TK_INT16),
+            DynamicKind::Int32 => w.u8(TK_INT32),
+            DynamicKind::Int64 => w.u8(TK_INT64),
+            // octet in IDL
+            DynamicKind::UInt8 => w.u8(TK_BYTE),
+            DynamicKind::UInt16 => w.u8(TK_UINT16),
+            DynamicKind::UInt32 => w.u8(TK_UINT32),
+            DynamicKind::UInt64 => w.u8(TK_UINT64),
+            DynamicKind::Float32 => w.u8(TK_FLOAT32),
+            DynamicKind::Float64 => w.u8(TK_FLOAT64),
+            DynamicKind::String => {
+                w.u8(TI_STRING8_SMALL);
+                // unbounded
+                w.u8(0);
+            }
+            DynamicKind::Sequence(element) => {
+                w.u8(TI_PLAIN_SEQUENCE_SMALL);
+                self.collection_header(w, element, equivalence);
+                w.u8(0);
+                self.type_identifier(w, element, equivalence);
+            }
+            DynamicKind::Array(..) => {
+                // nested arrays are one array with several dimensions
+                let mut dims = Vec::new();
+                let mut element = kind;
+                while let DynamicKind::Array(inner, len) = element {
+                    dims.push(*len as u32);
+                    element = inner;
+                }
+                let small = dims.iter().all(|dim| *dim < 256);
+                w.u8(if small { TI_PLAIN_ARRAY_SMALL } else { TI_PLAIN_ARRAY_LARGE });
+                self.collection_header(w, element, equivalence);
+                w.u32(dims.len() as u32);
+                for dim in dims {
+                    if small {
+                        w.u8(dim as u8);
+                    } else {
+                        w.u32(dim);
+                    }
+                }
+                self.type_identifier(w, element, equivalence);
+            }
+            DynamicKind::Struct(ty) => {
+                w.u8(equivalence.kind());
+                w.bytes(&self.objects(ty).get(equivalence).hash);
+            }
+        }
+    }
+
+    fn collection_header(&self, w: &mut Xcdr2, element: &DynamicKind, equivalence: Equivalence) {
+        w.u8(if fully_descriptive(element) {
+            EK_BOTH
+        } else {
+            equivalence.kind()
+        });
+        w.u16(TRY_CONSTRUCT1);
+    }
+
+    fn identifier_with_size(&self, w: &mut Xcdr2, index: usize, equivalence: Equivalence) {
+        let object = self.types[index].1.get(equivalence);
+        let with_size = w.begin();
+        w.u8(equivalence.kind());
+        w.bytes(&object.hash);
+        w.u32(object.bytes.len() as u32);
+        w.end(with_size);
+    }
+
+    // TypeInformation, a mutable struct of the minimal and the complete
+    // identifiers, each with the identifiers of the structs the type uses
+    fn type_information(&self, root: usize) -> Vec<u8> {
+        let dependencies: Vec<usize> = (0..self.types.len()).filter(|index| *index != root).collect();
+        let mut w = Xcdr2::default();
+        let information = w.begin();
+        for (id, equivalence) in [(MINIMAL_ID, Equivalence::Minimal), (COMPLETE_ID, Equivalence::Complete)] {
+            let member = w.begin_member(id);
+            let with_dependencies = w.begin();
+            self.identifier_with_size(&mut w, root, equivalence);
+            w.u32(dependencies.len() as u32);
+            let sequence = w.begin();
+            w.u32(dependencies.len() as u32);
+            for index in &dependencies {
+                self.identifier_with_size(&mut w, *index, equivalence);
+            }
+            w.end(sequence);
+            w.end(with_dependencies);
+            w.end(member);
+        }
+        w.end(information);
+        w.buf
+    }
+
+    // TypeMapping: the minimal and the complete type objects with their
+    // identifiers, and the pairs of complete and minimal identifiers
+    fn type_mapping(&self) -> Vec<u8> {
+        let mut w = Xcdr2::default();
+        for equivalence in [Equivalence::Minimal, Equivalence::Complete] {
+            let sequence = w.begin();
+            w.u32(self.types.len() as u32);
+            for (_, objects) in &self.types {
+                let object = objects.get(equivalence);
+                w.u8(equivalence.kind());
+                w.bytes(&object.hash);
+                // the type object starts with its DHEADER
+                w.align(4);
+                w.bytes(&object.bytes);
+            }
+            w.end(sequence);
+        }
+        let sequence = w.begin();
+        w.u32(self.types.len() as u32);
+        for (_, objects) in &self.types {
+            w.u8(EK_COMPLETE);
+            w.bytes(&objects.complete.hash);
+            w.u8(EK_MINIMAL);
+            w.bytes(&objects.minimal.hash);
+        }
+        w.end(sequence);
+        w.buf
+    }
+}
+
+// Types identified without a hash
+fn fully_descriptive(kind: &DynamicKind) -> bool {
+    match kind {
+        DynamicKind::Struct(_) => false,
+        DynamicKind::Sequence(element) | DynamicKind::Array(element, _) => fully_descriptive(element),
+        _ => true,
+    }
+}
+
+#[cfg(cyclone_type_discovery)]
+pub(crate) use discovery::set_type_discovery_ops;
+
+// The sertype operations cyclone calls for the type information of a topic
+#[cfg(cyclone_type_discovery)]
+mod discovery {
+    use cyclonedds_sys::*;
+    use std::os::raw::c_void;
+
+    use crate::serdes::{SerType, TopicType};
+
+    pub(crate) fn set_type_discovery_ops<T: TopicType>(ops: &mut ddsi_sertype_ops) {
+        ops.type_id = Some(type_id::<T>);
+        ops.type_map = Some(type_map::<T>);
+        ops.type_info = Some(type_info::<T>);
+    }
+
+    fn cdr_data(bytes: &[u8]) -> ddsi_sertype_cdr_data {
+        ddsi_sertype_cdr_data {
+            sz: bytes.len() as u32,
+            data: bytes.as_ptr() as *mut u8,
+        }
+    }
+
+    unsafe extern "C" fn type_info<T: TopicType>(sertype: *const ddsi_sertype) -> *mut ddsi_typeinfo_t {
+        match SerType::<T>::ref_from_sertype(sertype).and_then(|s| s.type_information()) {
+            Some(info) => ddsi_typeinfo_deser(&cdr_data(info.type_information())),
+            None => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe extern "C" fn type_map<T: TopicType>(sertype: *const ddsi_sertype) -> *mut ddsi_typemap_t {
+        match SerType::<T>::ref_from_sertype(sertype).and_then(|s| s.type_information()) {
+            Some(info) => ddsi_typemap_deser(&cdr_data(info.type_mapping())),
+            None => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe extern "C" fn type_id<T: TopicType>(
+        sertype: *const ddsi_sertype,
+        kind: ddsi_typeid_kind_t,
+    ) -> *mut ddsi_typeid_t {
+        let info = type_info::<T>(sertype);
+        if info.is_null() {
+            return std::ptr::null_mut();
+        }
+        let id = ddsi_typeinfo_typeid(info, kind);
+        ddsi_typeinfo_fini(info);
+        dds_free(info as *mut c_void);
+        id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn msg() -> DynamicType {
+        DynamicType::new("HelloWorldData::Msg")
+            .with_key_field("id", DynamicKind::Int32)
+            .with_field("message", DynamicKind::String)
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_minimal_type_object() {
+        let registry = {
+            let mut registry = Registry::default();
+            registry.add(&msg());
+            registry
+        };
+        let minimal = &registry.types[0].1.minimal;
+        #[rustfmt::skip]
+        let expected = [
+            0x34, 0, 0, 0, EK_MINIMAL, TK_STRUCTURE, 0x01, 0x00,
+            // header: no base type
+            0x01, 0, 0, 0, TK_NONE, 0, 0, 0,
+            // two members
+            0x24, 0, 0, 0, 0x02, 0, 0, 0,
+            0x0b, 0, 0, 0, 0, 0, 0, 0, 0x31, 0x00, TK_INT32, 0xb8, 0x0b, 0xb7, 0x74, 0,
+            0x0c, 0, 0, 0, 1, 0, 0, 0, 0x01, 0x00, TI_STRING8_SMALL, 0, 0x78, 0xe7, 0x31, 0x02,
+        ];
+        assert_eq!(minimal.bytes, expected);
+        assert_eq!(minimal.hash[..], md5(&expected)[..HASH_LEN]);
+
+        // the names are only in the complete type object
+        let complete = &registry.types[0].1.complete;
+        assert_ne!(complete.hash, minimal.hash);
+        let name = b"HelloWorldData::Msg\0";
+        assert!(complete.bytes.windows(name.len()).any(|w| w == name));
+    }
+
+    #[test]
+    fn test_type_information() {
+        let info = TypeInformation::new(&msg());
+        let bytes = info.type_information();
+        assert_eq!(bytes.len(), 0x64);
+        assert_eq!(u32_at(bytes, 0), 0x60);
+        // minimal: EMHEADER, NEXTINT, TypeIdentifierWithDependencies
+        assert_eq!(u32_at(bytes, 4), 0x4000_1001);
+        assert_eq!(u32_at(bytes, 8), 0x28);
+        assert_eq!(u32_at(bytes, 12), 0x24);
+        assert_eq!(u32_at(bytes, 16), 0x14);
+        assert_eq!(bytes[20], EK_MINIMAL);
+        assert_eq!(bytes[21..35], info.minimal_hash());
+        assert_eq!(u32_at(bytes, 36), 56);
+        // no dependencies
+        assert_eq!(u32_at(bytes, 40), 0);
+        assert_eq!(u32_at(bytes, 44), 4);
+        assert_eq!(u32_at(bytes, 48), 0);
+        assert_eq!(u32_at(bytes, 52), 0x4000_1002);
+        assert_eq!(bytes[68], EK_COMPLETE);
+        assert_eq!(bytes[69..83], info.complete_hash());
+
+        // one pair in each sequence of the mapping
+        let mapping = info.type_mapping();
+        assert_eq!(u32_at(mapping, 4), 1);
+        assert_eq!(mapping[8], EK_MINIMAL);
+        assert_eq!(mapping[9..23], info.minimal_hash());
+        assert_eq!(u32_at(mapping, 24), 0x34);
+    }
+
+    #[test]
+    fn test_nested_types() {
+        let point = DynamicType::new("Point")
+            .with_field("x", DynamicKind::Float64)
+            .with_field("y", DynamicKind::Float64);
+        let track = DynamicType::new("Track")
+            .with_key_field("id", DynamicKind::UInt32)
+            .with_field("at", DynamicKind::Struct(point.clone()))
+            .with_field("path", DynamicKind::Sequence(Box::new(DynamicKind::Struct(point.clone()))))
+            .with_field("grid", DynamicKind::Array(Box::new(DynamicKind::Array(Box::new(DynamicKind::UInt8), 300)), 2));
+
+        let mut registry = Registry::default();
+        let root = registry.add(&track);
+        // the point is added once, before the track
+        assert_eq!(root, 1);
+        assert!(registry.types[0].0 == point);
+
+        let point_hash = registry.types[0].1.minimal.hash;
+        let track_object = &registry.types[1].1.minimal.bytes;
+        let references = track_object.windows(HASH_LEN).filter(|w| *w == point_hash).count();
+        assert_eq!(references, 2);
+        // a sequence of structs refers to the minimal point, an array of octets is fully described
+        assert!(track_object.windows(4).any(|w| w == [TI_PLAIN_SEQUENCE_SMALL, EK_MINIMAL, 0x01, 0x00]));
+        assert!(track_object.windows(4).any(|w| w == [TI_PLAIN_ARRAY_LARGE, EK_BOTH, 0x01, 0x00]));
+
+        let info = TypeInformation::new(&track);
+        let bytes = info.type_information();
+        // one dependency for each of minimal and complete
+        assert_eq!(u32_at(bytes, 40), 1);
+        assert_eq!(u32_at(bytes, 48), 1);
+        assert_eq!(bytes[56], EK_MINIMAL);
+        assert_eq!(bytes[57..71], point_hash);
+    }
+
+    #[test]
+    fn test_topic_type_information() {
+        use cdds_derive::Topic;
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Msg {
+            #[topic_key]
+            id: i32,
+            message: String,
+        }
+
+        let mut ty = DynamicType::of::<Msg>().unwrap();
+        ty.set_name("HelloWorldData::Msg");
+        assert_eq!(TypeInformation::new(&ty), TypeInformation::new(&msg()));
+    }
+}
@@ -14,103 +14,801 @@
     limitations under the License.
 */
 
-use crate::{DdsParticipant, Entity};
-use cyclonedds_sys::size_t;
-pub use cyclonedds_sys::{DDSError, DdsDomainId, DdsEntity};
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::common::{EntityWrapper, Uses};
+use crate::{DdsParticipant, DdsReader, Entity};
+use cyclonedds_sys::{dds_attach_t, dds_time_t, size_t, StateMask};
+use crate::dds_time::duration_to_dds;
+pub use cyclonedds_sys::{DdsDomainId, DdsEntity};
+pub use crate::error::DDSError;
+use std::collections::HashMap;
 use std::convert::From;
-use std::marker::PhantomData;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-pub struct DdsWaitset<T>(DdsEntity, PhantomData<T>);
+/// A token identifying an entity attached to a waitset. The token
+/// is returned by [`DdsWaitset::attach`] and handed back by
+/// [`DdsWaitset::wait`] for every attachment that triggered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WaitsetToken(dds_attach_t);
 
-impl<'a, T> DdsWaitset<T> {
+#[derive(Default)]
+struct Attachments {
+    next_token: dds_attach_t,
+    attached: HashMap<WaitsetToken, DdsEntity>,
+    // read conditions created by attach_reader. These are owned by the waitset.
+    read_conditions: HashMap<WaitsetToken, DdsEntity>,
+}
+
+/// A waitset. Entities (conditions, readers, writers etc.) are attached
+/// to the waitset and a token is returned for each attachment. Waiting on
+/// the waitset returns the tokens of the attachments that triggered.
+/// All attached entities are detached when the waitset is dropped.
+///
+/// The waitset is `Send` and `Sync`. One thread can block in [`DdsWaitset::wait`]
+/// while other threads attach entities or wake it up through a
+/// [`DdsGuardCondition`](crate::DdsGuardCondition) attached to the waitset.
+pub struct DdsWaitset {
+    // the waitset with the participant it belongs to
+    entity: EntityWrapper,
+    attachments: Mutex<Attachments>,
+}
+
+impl DdsWaitset {
     pub fn create(participant: &DdsParticipant) -> Result<Self, DDSError> {
+        Entity::check_valid(participant)?;
         unsafe {
             let p = cyclonedds_sys::dds_create_waitset(participant.entity().entity());
             if p >= 0 {
-                Ok(DdsWaitset(DdsEntity::new(p), PhantomData))
+                Ok(DdsWaitset {
+                    entity: EntityWrapper::new(
+                        "waitset",
+                        DdsEntity::new(p),
+                        Some(participant.keep_alive()),
+                        None,
+                        Uses::Nothing,
+                    ),
+                    attachments: Mutex::new(Attachments {
+                        next_token: 1,
+                        ..Default::default()
+                    }),
+                })
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_create_waitset", p).with_entity_kind("waitset"))
             }
         }
     }
 
-    pub fn attach(&mut self, entity: &dyn Entity, x: &'a T) -> Result<(), DDSError> {
+    /// Attach an entity to the waitset. The returned token identifies
+    /// this attachment in the result of [`DdsWaitset::wait`].
+    pub fn attach(&self, entity: &dyn Entity) -> Result<WaitsetToken, DDSError> {
+        self.entity.check_valid()?;
+        entity.check_valid()?;
+        let mut attachments = self.attachments.lock().unwrap();
+        let token = WaitsetToken(attachments.next_token);
         unsafe {
             let p = cyclonedds_sys::dds_waitset_attach(
-                self.0.entity(),
+                self.entity.entity().entity(),
                 entity.entity().entity(),
-                x as *const T as isize,
+                token.0,
             );
             if p == 0 {
-                Ok(())
+                attachments.next_token += 1;
+                attachments.attached.insert(token, entity.entity().clone());
+                Ok(token)
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_waitset_attach", p).with_entity_kind("waitset"))
             }
         }
     }
-    pub fn detach(&mut self, entity: &dyn Entity) -> Result<(), DDSError> {
+
+    /// Attach a reader to the waitset. A read condition for the sample, view and
+    /// instance states in `mask` is created internally and deleted when the reader is
+    /// detached. Use [`DdsWaitset::take`] or [`DdsWaitset::read`] with the returned
+    /// token to get the samples matching the condition.
+    pub fn attach_reader<T>(
+        &self,
+        reader: &DdsReader<T>,
+        mask: StateMask,
+    ) -> Result<WaitsetToken, DDSError>
+    where
+        T: TopicType,
+    {
+        self.entity.check_valid()?;
+        reader.check_valid()?;
+        let condition = unsafe {
+            let mask: u32 = *mask;
+            let p = cyclonedds_sys::dds_create_readcondition(reader.entity().entity(), mask);
+            if p > 0 {
+                DdsEntity::new(p)
+            } else {
+                return Err(DDSError::from_retcode("dds_create_readcondition", p).with_entity_kind("waitset"));
+            }
+        };
+
+        let mut attachments = self.attachments.lock().unwrap();
+        let token = WaitsetToken(attachments.next_token);
         unsafe {
-            let p = cyclonedds_sys::dds_waitset_detach(self.0.entity(), entity.entity().entity());
+            let p = cyclonedds_sys::dds_waitset_attach(
+                self.entity.entity().entity(),
+                condition.entity(),
+                token.0,
+            );
             if p == 0 {
-                Ok(())
+                attachments.next_token += 1;
+                attachments.attached.insert(token, condition.clone());
+                attachments.read_conditions.insert(token, condition);
+                Ok(token)
             } else {
-                Err(DDSError::from(p))
+                cyclonedds_sys::dds_delete(condition.entity());
+                Err(DDSError::from_retcode("dds_waitset_attach", p).with_entity_kind("waitset"))
+            }
+        }
+    }
+
+    /// Take the samples matching the read condition of a reader attached with
+    /// [`DdsWaitset::attach_reader`]. Returns the number of samples taken.
+    pub fn take<T>(&self, token: WaitsetToken, buf: &mut SampleBuffer<T>) -> Result<usize, DDSError>
+    where
+        T: TopicType,
+    {
+        let condition = self.read_condition(token)?;
+        DdsReader::<T>::readn_from_entity_now(&condition, buf, true)
+    }
+
+    /// Read the samples matching the read condition of a reader attached with
+    /// [`DdsWaitset::attach_reader`]. Returns the number of samples read.
+    pub fn read<T>(&self, token: WaitsetToken, buf: &mut SampleBuffer<T>) -> Result<usize, DDSError>
+    where
+        T: TopicType,
+    {
+        let condition = self.read_condition(token)?;
+        DdsReader::<T>::readn_from_entity_now(&condition, buf, false)
+    }
+
+    fn read_condition(&self, token: WaitsetToken) -> Result<DdsEntity, DDSError> {
+        self.entity.check_valid()?;
+        self.attachments
+            .lock()
+            .unwrap()
+            .read_conditions
+            .get(&token)
+            .cloned()
+            .ok_or(DDSError::BadParameter)
+    }
+
+    /// Detach the entity identified by the token.
+    pub fn detach(&self, token: WaitsetToken) -> Result<(), DDSError> {
+        self.entity.check_valid()?;
+        let mut attachments = self.attachments.lock().unwrap();
+        if let Some(entity) = attachments.attached.get(&token) {
+            unsafe {
+                let p = cyclonedds_sys::dds_waitset_detach(self.entity.entity().entity(), entity.entity());
+                if p == 0 {
+                    attachments.attached.remove(&token);
+                    if let Some(condition) = attachments.read_conditions.remove(&token) {
+                        cyclonedds_sys::dds_delete(condition.entity());
+                    }
+                    Ok(())
+                } else {
+                    Err(DDSError::from_retcode("dds_waitset_detach", p).with_entity_kind("waitset"))
+                }
             }
+        } else {
+            Err(DDSError::PreconditionNotMet)
         }
     }
-    pub fn set_trigger(&mut self, trigger: bool) -> Result<(), DDSError> {
+
+    pub fn set_trigger(&self, trigger: bool) -> Result<(), DDSError> {
+        self.entity.check_valid()?;
         unsafe {
-            let p = cyclonedds_sys::dds_waitset_set_trigger(self.0.entity(), trigger);
+            let p = cyclonedds_sys::dds_waitset_set_trigger(self.entity.entity().entity(), trigger);
             if p == 0 {
                 Ok(())
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_waitset_set_trigger", p).with_entity_kind("waitset"))
             }
         }
     }
-    pub fn wait<'b>(
-        &mut self,
-        xs: &'b mut Vec<&'b T>,
-        timeout_us: i64,
-    ) -> Result<&'b [&'b T], DDSError> {
-        let capacity = xs.capacity();
+
+    /// Wait for at most `timeout` for any of the attachments to trigger. The tokens
+    /// of the triggered attachments are returned. `DDSError::Timeout` is returned if
+    /// nothing triggered within the timeout.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
+    pub fn wait(&self, timeout: Duration) -> Result<Vec<WaitsetToken>, DDSError> {
+        let reltimeout = duration_to_dds(timeout);
+        self.wait_with(|ws, xs, nxs| unsafe {
+            cyclonedds_sys::dds_waitset_wait(ws, xs, nxs, reltimeout)
+        })
+    }
+
+    /// Wait until the absolute `deadline` for any of the attachments to trigger.
+    /// Use this instead of [`DdsWaitset::wait`] in periodic loops so that the
+    /// period does not drift by the time spent processing the triggers.
+    /// `DDSError::Timeout` is returned if nothing triggered before the deadline.
+    pub fn wait_until(&self, deadline: Instant) -> Result<Vec<WaitsetToken>, DDSError> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let abstimeout = unsafe { cyclonedds_sys::dds_time() }.saturating_add(duration_to_dds(remaining));
+        self.wait_until_time(abstimeout)
+    }
+
+    /// Wait until the absolute time `abstimeout` (as returned by `dds_time()`)
+    /// for any of the attachments to trigger.
+    pub fn wait_until_time(&self, abstimeout: dds_time_t) -> Result<Vec<WaitsetToken>, DDSError> {
+        self.wait_with(|ws, xs, nxs| unsafe {
+            cyclonedds_sys::dds_waitset_wait_until(ws, xs, nxs, abstimeout)
+        })
+    }
+
+    /// The number of entities attached to this waitset
+    pub fn attached_count(&self) -> Result<usize, DDSError> {
+        self.entity.check_valid()?;
         unsafe {
-            let p = cyclonedds_sys::dds_waitset_wait(
-                self.0.entity(),
-                xs.as_mut_ptr() as *mut isize,
-                capacity as size_t,
-                timeout_us,
+            let n = cyclonedds_sys::dds_waitset_get_entities(
+                self.entity.entity().entity(),
+                std::ptr::null_mut(),
+                0,
             );
-            if p == 0 {
-                // timeout, empty slice back
-                Ok(&xs[0..0])
-            } else if p > 0 {
-                let p = p as usize;
-                xs.set_len(p);
-                Ok(&xs[0..p])
+            if n >= 0 {
+                Ok(n as usize)
             } else {
-                Err(DDSError::from(p))
+                Err(DDSError::from_retcode("dds_waitset_get_entities", n).with_entity_kind("waitset"))
+            }
+        }
+    }
+
+    fn wait_with<F>(&self, wait_fn: F) -> Result<Vec<WaitsetToken>, DDSError>
+    where
+        F: FnOnce(cyclonedds_sys::dds_entity_t, *mut dds_attach_t, size_t) -> cyclonedds_sys::dds_return_t,
+    {
+        // one slot per attachment as known by cyclone. The attachments lock is not held
+        // while blocked so other threads can attach, cyclone then returns more triggers
+        // than there are slots.
+        let mut xs: Vec<dds_attach_t> = vec![0; std::cmp::max(self.attached_count()?, 1)];
+        let mut p = wait_fn(unsafe { self.entity.entity().entity() }, xs.as_mut_ptr(), xs.len() as size_t);
+        while p > 0 && p as usize > xs.len() {
+            // the triggers are still set, collect all of them without blocking
+            let first = xs.clone();
+            xs.resize(p as usize, 0);
+            p = unsafe {
+                cyclonedds_sys::dds_waitset_wait(self.entity.entity().entity(), xs.as_mut_ptr(), xs.len() as size_t, 0)
+            };
+            if p <= 0 {
+                // reset meanwhile, the slots of the first wait are all there is
+                return Ok(first.into_iter().map(WaitsetToken).collect());
             }
         }
+        if p > 0 {
+            Ok(xs[..p as usize].iter().map(|x| WaitsetToken(*x)).collect())
+        } else if p == 0 {
+            Err(DDSError::Timeout)
+        } else {
+            Err(DDSError::from_retcode("dds_waitset_wait", p).with_entity_kind("waitset"))
+        }
     }
 }
 
-impl<T> Entity for DdsWaitset<T> {
+impl Entity for DdsWaitset {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.entity.entity()
+    }
+
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.entity.check_valid()
     }
 }
 
-impl<T> Drop for DdsWaitset<T> {
+impl DdsWaitset {
+    /// Detach all attachments and delete the waitset. Dropping the waitset does the
+    /// same but can only log a failure.
+    pub fn close(mut self) -> Result<(), DDSError> {
+        self.release_attachments();
+        // deleting the waitset detaches the attachments
+        self.entity.close()
+    }
+
+    // Forget the attachments and delete the read conditions, which belong to
+    // their readers and are not deleted with the waitset
+    fn release_attachments(&mut self) {
+        let attachments = match self.attachments.get_mut() {
+            Ok(attachments) => attachments,
+            // a thread panicked while attaching, the attachments are still valid
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        attachments.attached.clear();
+        for (_token, condition) in attachments.read_conditions.drain() {
+            // deleted with the reader, the reader may be gone already
+            let _ = unsafe { cyclonedds_sys::dds_delete(condition.entity()) };
+        }
+    }
+}
+
+impl Drop for DdsWaitset {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
     fn drop(&mut self) {
-        unsafe {
-            let _ret: DDSError = cyclonedds_sys::dds_delete(self.0.entity()).into();
-            //if DDSError::DdsOk != ret {
-            //    //we ignore the error here as the waitset may be deleted by cyclone
-            //    //panic!("cannot delete DdsWaitset: {}", ret);
-            //} else {
-            //
-            //}
+        // the waitset itself is deleted with the last reference to its entity
+        self.release_attachments();
+    }
+}
+
+/// A waitset where each attachment has a closure associated with it. Calling
+/// [`WaitsetDispatcher::dispatch`] waits on the waitset and invokes the closures
+/// of the attachments that triggered. The closures run on the thread calling
+/// `dispatch`, never on a middleware thread.
+/// # Example
+/// ```no_run
+/// # use cyclonedds_rs::{DdsParticipant, DdsWaitset, WaitsetDispatcher, Entity};
+/// # fn example(participant: &DdsParticipant, reader: &dyn Entity) {
+/// let mut dispatcher = WaitsetDispatcher::new(DdsWaitset::create(participant).unwrap());
+/// dispatcher.attach(reader, |_entity| println!("reader triggered")).unwrap();
+/// loop {
+///     dispatcher.dispatch(std::time::Duration::from_secs(1)).unwrap();
+/// }
+/// # }
+/// ```
+pub struct WaitsetDispatcher {
+    waitset: DdsWaitset,
+    handlers: HashMap<WaitsetToken, (DdsEntity, Box<dyn FnMut(&DdsEntity) + 'static>)>,
+}
+
+impl WaitsetDispatcher {
+    pub fn new(waitset: DdsWaitset) -> Self {
+        Self {
+            waitset,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Attach an entity with the closure to be called when the entity triggers.
+    pub fn attach<F>(&mut self, entity: &dyn Entity, handler: F) -> Result<WaitsetToken, DDSError>
+    where
+        F: FnMut(&DdsEntity) + 'static,
+    {
+        let token = self.waitset.attach(entity)?;
+        self.handlers
+            .insert(token, (entity.entity().clone(), Box::new(handler)));
+        Ok(token)
+    }
+
+    /// Detach the entity and drop its closure.
+    pub fn detach(&mut self, token: WaitsetToken) -> Result<(), DDSError> {
+        self.waitset.detach(token)?;
+        self.handlers.remove(&token);
+        Ok(())
+    }
+
+    /// Wait for at most `timeout` and call the closures of the triggered
+    /// attachments. Returns the number of closures that were called, which is
+    /// zero if the timeout expired.
+    pub fn dispatch(&mut self, timeout: Duration) -> Result<usize, DDSError> {
+        match self.waitset.wait(timeout) {
+            Ok(triggered) => Ok(self.call_handlers(&triggered)),
+            Err(DDSError::Timeout) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Wait until `deadline` and call the closures of the triggered attachments.
+    /// Returns the number of closures that were called, which is zero if the
+    /// deadline passed.
+    pub fn dispatch_until(&mut self, deadline: Instant) -> Result<usize, DDSError> {
+        match self.waitset.wait_until(deadline) {
+            Ok(triggered) => Ok(self.call_handlers(&triggered)),
+            Err(DDSError::Timeout) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn call_handlers(&mut self, triggered: &[WaitsetToken]) -> usize {
+        let mut called = 0;
+        for token in triggered {
+            if let Some((entity, handler)) = self.handlers.get_mut(token) {
+                handler(entity);
+                called += 1;
+            }
+        }
+        called
+    }
+
+    /// Get the underlying waitset
+    pub fn waitset(&self) -> &DdsWaitset {
+        &self.waitset
+    }
+}
+
+/// How long the background thread of an [`AsyncWaitset`] blocks in the
+/// waitset before checking whether it has been asked to stop.
+const ASYNC_WAITSET_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct AsyncWaitsetState {
+    // set by the future when a task is waiting for triggers
+    requested: bool,
+    // counts the calls to wait, results of an earlier wait are dropped
+    generation: u64,
+    result: Option<Result<Vec<WaitsetToken>, DDSError>>,
+    waker: Option<Waker>,
+    stop: bool,
+}
+
+/// Runs a [`DdsWaitset`] on a background thread so that waitset based designs
+/// can be used from async code without blocking the executor's worker threads.
+/// The waitset is only waited upon while a task is awaiting [`AsyncWaitset::wait`],
+/// so level triggered conditions do not make the background thread spin.
+/// # Example
+/// ```no_run
+/// # use cyclonedds_rs::{AsyncWaitset, DdsParticipant, DdsWaitset};
+/// # async fn example(participant: &DdsParticipant) {
+/// let waitset = DdsWaitset::create(participant).unwrap();
+/// // attach entities here
+/// let mut waitset = AsyncWaitset::new(waitset);
+/// let triggered = waitset.wait().await.unwrap();
+/// # }
+/// ```
+pub struct AsyncWaitset {
+    shared: Arc<(Mutex<AsyncWaitsetState>, Condvar)>,
+    thread: Option<JoinHandle<DdsWaitset>>,
+}
+
+impl AsyncWaitset {
+    /// Take ownership of the waitset and start the background thread. Attach the entities
+    /// before handing over the waitset.
+    pub fn new(waitset: DdsWaitset) -> Self {
+        let shared = Arc::new((Mutex::new(AsyncWaitsetState::default()), Condvar::new()));
+        let thread_shared = shared.clone();
+
+        let thread = std::thread::spawn(move || {
+            let (lock, cvar) = &*thread_shared;
+            loop {
+                let generation = {
+                    let mut state = lock.lock().unwrap();
+                    while !state.requested && !state.stop {
+                        state = cvar.wait(state).unwrap();
+                    }
+                    if state.stop {
+                        break;
+                    }
+                    state.generation
+                };
+
+                match waitset.wait(ASYNC_WAITSET_POLL) {
+                    Err(DDSError::Timeout) => continue,
+                    result => {
+                        let mut state = lock.lock().unwrap();
+                        // the future that asked was dropped and another wait
+                        // started, which the triggers are waited for again
+                        if state.generation != generation {
+                            continue;
+                        }
+                        state.requested = false;
+                        state.result = Some(result);
+                        if let Some(waker) = state.waker.take() {
+                            waker.wake();
+                        }
+                    }
+                }
+            }
+            waitset
+        });
+
+        Self {
+            shared,
+            thread: Some(thread),
         }
     }
+
+    /// Wait asynchronously for any of the attachments to trigger and return their tokens.
+    pub async fn wait(&mut self) -> Result<Vec<WaitsetToken>, DDSError> {
+        {
+            // a result left by a dropped wait is stale
+            let mut state = self.shared.0.lock().unwrap();
+            state.generation = state.generation.wrapping_add(1);
+            state.result = None;
+        }
+        WaitsetFuture {
+            shared: self.shared.clone(),
+        }
+        .await
+    }
+
+    /// Stop the background thread and get back the waitset.
+    pub fn into_inner(mut self) -> DdsWaitset {
+        self.stop().expect("Waitset thread panicked")
+    }
+
+    fn stop(&mut self) -> Option<DdsWaitset> {
+        {
+            let (lock, cvar) = &*self.shared;
+            lock.lock().unwrap().stop = true;
+            cvar.notify_all();
+        }
+        self.thread.take().and_then(|thread| thread.join().ok())
+    }
+}
+
+impl Drop for AsyncWaitset {
+    fn drop(&mut self) {
+        let _waitset = self.stop();
+    }
+}
+
+struct WaitsetFuture {
+    shared: Arc<(Mutex<AsyncWaitsetState>, Condvar)>,
+}
+
+impl Future for WaitsetFuture {
+    type Output = Result<Vec<WaitsetToken>, DDSError>;
+
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        let (lock, cvar) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+
+        if let Some(result) = state.result.take() {
+            Poll::Ready(result)
+        } else {
+            let _ = state.waker.replace(ctx.waker().clone());
+            if !state.requested {
+                state.requested = true;
+                cvar.notify_one();
+            }
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for WaitsetFuture {
+    fn drop(&mut self) {
+        let mut state = self.shared.0.lock().unwrap();
+        state.waker = None;
+        state.result = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dds_api::DdsStatus;
+    use crate::{DdsPublisher, DdsReader, DdsSubscriber, DdsWriter};
+    use crate::SampleBuffer;
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize, Topic, Debug, PartialEq, Default)]
+    struct WaitsetTopic {
+        #[topic_key]
+        id: u32,
+        value: u32,
+    }
+
+    #[test]
+    fn test_close_waitset() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        waitset.attach(&publisher).unwrap();
+        assert!(waitset.close().is_ok());
+        assert!(publisher.close().is_ok());
+        assert!(publisher.close().is_err());
+    }
+
+    #[test]
+    fn test_waitset_tokens() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = WaitsetTopic::create_topic(&participant, Some("waitset"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+        reader
+            .set_status_mask(DdsStatus::DATA_AVAILABLE)
+            .unwrap();
+
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let token = waitset.attach(&reader).unwrap();
+
+        writer.write(Arc::new(WaitsetTopic { id: 1, value: 42 })).unwrap();
+
+        let triggered = waitset.wait(Duration::from_secs(1)).unwrap();
+        assert_eq!(triggered, vec![token]);
+
+        waitset.detach(token).unwrap();
+        assert!(waitset.detach(token).is_err());
+    }
+
+    #[test]
+    fn test_waitset_holds_the_participant() {
+        let alive = |entity: cyclonedds_sys::dds_entity_t| unsafe { cyclonedds_sys::dds_get_parent(entity) } > 0;
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let handle = unsafe { participant.entity().entity() };
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let guard = crate::DdsGuardCondition::create(&participant).unwrap();
+        waitset.attach(&guard).unwrap();
+
+        drop(participant);
+        assert!(alive(handle));
+        guard.set(true).unwrap();
+        assert_eq!(waitset.wait(Duration::from_millis(10)).unwrap().len(), 1);
+
+        drop(waitset);
+        drop(guard);
+        assert!(!alive(handle));
+    }
+
+    #[test]
+    fn test_waitset_after_participant_close() {
+        let mut participant = DdsParticipant::create(None, None, None).unwrap();
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let guard = crate::DdsGuardCondition::create(&participant).unwrap();
+        participant.close().unwrap();
+
+        assert_eq!(guard.set(true), Err(DDSError::UseAfterClose));
+        assert_eq!(waitset.attach(&guard), Err(DDSError::UseAfterClose));
+        assert_eq!(waitset.wait(Duration::from_millis(10)), Err(DDSError::UseAfterClose));
+    }
+
+    #[test]
+    fn test_wait_collects_triggers_past_the_buffer() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let guards: Vec<_> = (0..3)
+            .map(|_| crate::DdsGuardCondition::create(&participant).unwrap())
+            .collect();
+        let mut tokens: Vec<_> = guards.iter().map(|g| waitset.attach(g).unwrap()).collect();
+        for guard in &guards {
+            guard.set(true).unwrap();
+        }
+
+        // as if the guards were attached while waiting with one slot
+        let mut triggered = waitset
+            .wait_with(|ws, xs, _| unsafe { cyclonedds_sys::dds_waitset_wait(ws, xs, 1, 0) })
+            .unwrap();
+        triggered.sort_by_key(|token| token.0);
+        tokens.sort_by_key(|token| token.0);
+        assert_eq!(triggered, tokens);
+    }
+
+    #[test]
+    fn test_wait_until_times_out() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert_eq!(waitset.wait_until(deadline), Err(DDSError::Timeout));
+        assert_eq!(waitset.wait(Duration::from_millis(10)), Err(DDSError::Timeout));
+    }
+
+    #[test]
+    fn test_async_waitset() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = WaitsetTopic::create_topic(&participant, Some("async_waitset"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+        reader
+            .set_status_mask(DdsStatus::DATA_AVAILABLE)
+            .unwrap();
+
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let token = waitset.attach(&reader).unwrap();
+        let mut waitset = AsyncWaitset::new(waitset);
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let waiter = tokio::spawn(async move {
+                let triggered = waitset.wait().await.unwrap();
+                assert_eq!(triggered, vec![token]);
+            });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            writer.write(Arc::new(WaitsetTopic { id: 1, value: 42 })).unwrap();
+            waiter.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_async_waitset_dropped_wait() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = WaitsetTopic::create_topic(&participant, Some("async_waitset_dropped"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        reader.set_status_mask(DdsStatus::DATA_AVAILABLE).unwrap();
+
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let token = waitset.attach(&reader).unwrap();
+        let mut waitset = AsyncWaitset::new(waitset);
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            // triggers while nobody waits any more
+            let abandoned = tokio::time::timeout(Duration::from_millis(50), waitset.wait()).await;
+            assert!(abandoned.is_err());
+            writer.write(Arc::new(WaitsetTopic { id: 1, value: 42 })).unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let mut samples = SampleBuffer::<WaitsetTopic>::new(1);
+            assert_eq!(reader.take_now(&mut samples), Ok(1));
+
+            // the next wait does not return the trigger of the abandoned one
+            let next = tokio::time::timeout(Duration::from_millis(300), waitset.wait()).await;
+            assert!(next.is_err());
+            writer.write(Arc::new(WaitsetTopic { id: 2, value: 43 })).unwrap();
+            assert_eq!(waitset.wait().await.unwrap(), vec![token]);
+        });
+    }
+
+    #[test]
+    fn test_waitset_dispatcher() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = WaitsetTopic::create_topic(&participant, Some("dispatcher"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+        reader
+            .set_status_mask(DdsStatus::DATA_AVAILABLE)
+            .unwrap();
+
+        let mut dispatcher = WaitsetDispatcher::new(DdsWaitset::create(&participant).unwrap());
+        let count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let handler_count = count.clone();
+        dispatcher
+            .attach(&reader, move |_entity| handler_count.set(handler_count.get() + 1))
+            .unwrap();
+
+        writer.write(Arc::new(WaitsetTopic { id: 1, value: 42 })).unwrap();
+
+        assert_eq!(dispatcher.dispatch(Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn test_attach_reader_and_take() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = WaitsetTopic::create_topic(&participant, Some("attach_reader"), None, None).unwrap();
+        let publisher = DdsPublisher::create(&participant, None, None).unwrap();
+        let mut writer = DdsWriter::create(&publisher, topic.clone(), None, None).unwrap();
+        let subscriber = DdsSubscriber::create(&participant, None, None).unwrap();
+        let reader = DdsReader::create(&subscriber, topic, None, None).unwrap();
+
+        let waitset = DdsWaitset::create(&participant).unwrap();
+        let token = waitset
+            .attach_reader(&reader, StateMask::from(cyclonedds_sys::DDS_ANY_STATE))
+            .unwrap();
+
+        writer.write(Arc::new(WaitsetTopic { id: 7, value: 42 })).unwrap();
+
+        assert_eq!(waitset.wait(Duration::from_secs(1)).unwrap(), vec![token]);
+        let mut buf = SampleBuffer::<WaitsetTopic>::new(1);
+        assert_eq!(waitset.take(token, &mut buf).unwrap(), 1);
+        assert_eq!(buf.iter().next().unwrap().id, 7);
+
+        waitset.detach(token).unwrap();
+    }
+
+    #[test]
+    fn test_waitset_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DdsWaitset>();
+    }
+
+    #[test]
+    fn test_cross_thread_wakeup() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let waitset = Arc::new(DdsWaitset::create(&participant).unwrap());
+        let guard = crate::DdsGuardCondition::create(&participant).unwrap();
+        let token = waitset.attach(&guard).unwrap();
+
+        let waiter = waitset.clone();
+        let handle = std::thread::spawn(move || waiter.wait(Duration::from_secs(5)));
+
+        std::thread::sleep(Duration::from_millis(50));
+        guard.set(true).unwrap();
+
+        assert_eq!(handle.join().unwrap().unwrap(), vec![token]);
+        assert!(guard.take().unwrap());
+    }
 }
@@ -15,16 +15,23 @@
 */
 
 use cyclonedds_sys::*;
+use crate::error::DDSError;
 use std::convert::From;
 use std::ffi::c_void;
+#[cfg(cyclone_shm)]
 use std::ptr::NonNull;
 
 pub use cyclonedds_sys::{ DdsEntity};
 use std::marker::PhantomData;
+use std::sync::Arc;
 use crate::SampleBuffer;
 
 use crate::{dds_listener::DdsListener, dds_qos::DdsQos, dds_topic::DdsTopic, DdsWritable, Entity};
-use crate::serdes::{Sample, TopicType};
+use crate::common::{EntityWrapper, Uses};
+use crate::dds_coherent::CoherentSet;
+use crate::dds_telemetry::{count, Counter};
+use crate::dds_time::DdsTime;
+use crate::serdes::{take_keyhash_violation, with_write_scratch, Sample, TopicType, WriteScratch};
 
 pub struct WriterBuilder<T: TopicType> {
     maybe_qos: Option<DdsQos>,
@@ -58,16 +65,20 @@ impl <T>WriterBuilder<T> where T: TopicType {
         }
 }
 
+// Loans come from iceoryx, they only exist with the shm feature
+#[cfg(cyclone_shm)]
 pub enum LoanedInner<T: Sized + TopicType> {
     Uninitialized(NonNull<T>, DdsEntity),
     Initialized(NonNull<T>, DdsEntity),
     Empty,
 }
 
+#[cfg(cyclone_shm)]
 pub struct Loaned<T: Sized + TopicType> {
     inner : LoanedInner<T>
 }
 
+#[cfg(cyclone_shm)]
 impl <T> Loaned<T> 
 where T: Sized + TopicType {
     pub fn as_mut_ptr(&mut self) -> Option<*mut T> {
@@ -78,6 +89,40 @@ where T: Sized + TopicType {
         }
     }
 
+    /// Set the value of the loaned sample. The sample is published when the
+    /// loan is returned with [`DdsWriter::return_loan`]. Fails with
+    /// `DDSError::PreconditionNotMet` when there is no loaned sample to write.
+    pub fn write(&mut self, value: T) -> Result<(), DDSError> {
+        self.inner = match std::mem::replace(&mut self.inner, LoanedInner::Empty) {
+            LoanedInner::Uninitialized(p, e) => {
+                unsafe { p.as_ptr().write(value) };
+                LoanedInner::Initialized(p, e)
+            }
+            LoanedInner::Initialized(p, e) => {
+                unsafe { *p.as_ptr() = value };
+                LoanedInner::Initialized(p, e)
+            }
+            LoanedInner::Empty => return Err(DDSError::PreconditionNotMet),
+        };
+        Ok(())
+    }
+
+    /// The value of the sample, once it is written
+    pub fn get(&self) -> Option<&T> {
+        match &self.inner {
+            LoanedInner::Initialized(p, _) => Some(unsafe { p.as_ref() }),
+            _ => None,
+        }
+    }
+
+    /// The value of the sample for changing it in place, once it is written
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        match &mut self.inner {
+            LoanedInner::Initialized(p, _) => Some(unsafe { p.as_mut() }),
+            _ => None,
+        }
+    }
+
     pub fn assume_init(mut self) -> Self {
         match &mut self.inner {
             LoanedInner::Uninitialized(p, e) => Self{inner : LoanedInner::Initialized(*p, e.clone())},
@@ -87,6 +132,7 @@ where T: Sized + TopicType {
     }
 }
 
+#[cfg(cyclone_shm)]
 impl<T> Drop for Loaned<T> 
 where T : Sized + TopicType {
     fn drop(&mut self) {
@@ -106,8 +152,9 @@ where T : Sized + TopicType {
 
 #[derive(Clone)]
 pub struct DdsWriter<T: Sized + TopicType>(
-    DdsEntity,
-    Option<DdsListener>,
+    // the writer with its topic and its listener
+    EntityWrapper,
+    Arc<WriteScratch>,
     PhantomData<T>,
 );
 
@@ -115,12 +162,15 @@ impl<'a, T> DdsWriter<T>
 where
     T: Sized + TopicType,
 {
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, err))]
     pub fn create(
         entity: &dyn DdsWritable,
         topic: DdsTopic<T>,
         maybe_qos: Option<DdsQos>,
         maybe_listener: Option<DdsListener>,
     ) -> Result<Self, DDSError> {
+        entity.check_valid()?;
+        Entity::check_valid(&topic)?;
         unsafe {
             let w = dds_create_writer(
                 entity.entity().entity(),
@@ -133,16 +183,23 @@ where
 
             if w >= 0 {
                 Ok(DdsWriter(
-                    DdsEntity::new(w),
-                    maybe_listener,
+                    EntityWrapper::new(
+                        "writer",
+                        DdsEntity::new(w),
+                        entity.keep_alive(),
+                        maybe_listener,
+                        Uses::Topic(topic.keep_alive()),
+                    ),
+                    Arc::default(),
                     PhantomData,
                 ))
             } else {
-                Err(DDSError::from(w))
+                Err(DDSError::from_retcode("dds_create_writer", w).with_entity_kind("writer"))
             }
         }
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, err))]
     pub fn write_to_entity(entity: &DdsEntity, msg: std::sync::Arc<T>) -> Result<(), DDSError> {
         unsafe {
             let sample = Sample::<T>::from(msg);
@@ -152,21 +209,86 @@ where
             if ret >= 0 {
                 Ok(())
             } else {
-                Err(DDSError::from(ret))
+                Err(DDSError::from_retcode("dds_write", ret).with_entity_kind("writer"))
             }
         }
     }
 
     pub fn write(&mut self, msg: std::sync::Arc<T>) -> Result<(), DDSError> {
-        Self::write_to_entity(&self.0, msg)
+        self.sample_op("dds_write", msg, |entity, sample| unsafe { dds_write(entity, sample) })
+    }
+
+    /// Write with the given source timestamp instead of the current time
+    pub fn write_ts<S: Into<DdsTime>>(&mut self, msg: std::sync::Arc<T>, timestamp: S) -> Result<(), DDSError> {
+        let timestamp = timestamp.into().as_nanos();
+        self.sample_op("dds_write_ts", msg, |entity, sample| unsafe {
+            dds_write_ts(entity, sample, timestamp)
+        })
+    }
 
+    /// Dispose the instance with the key of `msg`
+    pub fn dispose(&mut self, msg: std::sync::Arc<T>) -> Result<(), DDSError> {
+        self.sample_op("dds_dispose", msg, |entity, sample| unsafe { dds_dispose(entity, sample) })
+    }
+
+    /// Dispose the instance with the key of `msg` with the given source timestamp
+    pub fn dispose_ts<S: Into<DdsTime>>(&mut self, msg: std::sync::Arc<T>, timestamp: S) -> Result<(), DDSError> {
+        let timestamp = timestamp.into().as_nanos();
+        self.sample_op("dds_dispose_ts", msg, |entity, sample| unsafe {
+            dds_dispose_ts(entity, sample, timestamp)
+        })
+    }
+
+    /// Start a coherent set of the writes of this writer
+    pub fn begin_coherent(&self) -> Result<CoherentSet, DDSError> {
+        CoherentSet::begin(self, "writer")
+    }
+
+    /// The instance handle of the instance with the key of `msg`, `None` if the
+    /// writer does not know the instance
+    pub fn lookup_instance(&self, msg: std::sync::Arc<T>) -> Option<dds_instance_handle_t> {
+        self.0.check_valid().ok()?;
+        let sample = Sample::<T>::from(msg);
+        let handle = unsafe {
+            dds_lookup_instance(self.0.entity().entity(), &sample as *const Sample<T> as *const c_void)
+        };
+        if handle == 0 {
+            None
+        } else {
+            Some(handle)
+        }
+    }
+
+    fn sample_op<F>(&self, operation: &'static str, msg: std::sync::Arc<T>, op: F) -> Result<(), DDSError>
+    where
+        F: FnOnce(dds_entity_t, *const c_void) -> dds_return_t,
+    {
+        self.0.check_valid()?;
+        let sample = Sample::<T>::from(msg);
+        // left by an operation that did not go through here
+        take_keyhash_violation();
+        let ret = with_write_scratch(&self.1, || {
+            op(unsafe { self.0.entity().entity() }, &sample as *const Sample<T> as *const c_void)
+        });
+        if ret >= 0 {
+            Ok(())
+        } else {
+            let error = DDSError::from_retcode(operation, ret).with_entity_kind("writer");
+            Err(match take_keyhash_violation() {
+                Some(violation) => error.with_source(violation),
+                None => error,
+            })
+        }
     }
 
     // Loan memory buffers for zero copy operation. Only supported for fixed size types
+    #[cfg(cyclone_shm)]
     pub fn loan(&mut self) -> Result<Loaned<T>, DDSError> {
+        self.0.check_valid()?;
 
         if !T::is_fixed_size() {
             // Loaning is not supported for types that are not fixed size
+            count(Counter::RejectedLoans);
             return Err(DDSError::Unsupported)
         }
 
@@ -174,17 +296,39 @@ where
         let voidpp:*mut *mut T= &mut p_sample;
         let voidpp = voidpp as *mut *mut c_void;
         let res = unsafe {
-            dds_loan_sample(self.0.entity(), voidpp)
+            dds_loan_sample(self.0.entity().entity(), voidpp)
         };
         if res == 0 {
+            // the chunk is used as a T in place, hand it back if it does not fit
+            if let Err(e) = self.check_loan(p_sample) {
+                let voidpp = &mut p_sample as *mut *mut T as *mut *mut c_void;
+                unsafe { dds_return_loan(self.0.entity().entity(), voidpp, 1) };
+                count(Counter::RejectedLoans);
+                return Err(e);
+            }
             Ok(Loaned { inner: LoanedInner::Uninitialized( NonNull::new(p_sample).unwrap(),  self.entity().clone()) })   
         } else {
-            Err(DDSError::from(res))
+            count(Counter::RejectedLoans);
+            Err(DDSError::from_retcode("dds_loan_sample", res).with_entity_kind("writer"))
         } 
     }
 
+    #[cfg(cyclone_shm)]
+    fn check_loan(&self, p_sample: *mut T) -> Result<(), DDSError> {
+        let mut sertype: *const ddsi_sertype = std::ptr::null();
+        let ret = unsafe { dds_get_entity_sertype(self.0.entity().entity(), &mut sertype) };
+        if ret < 0 {
+            return Err(DDSError::from_retcode("dds_get_entity_sertype", ret).with_entity_kind("writer"));
+        }
+        let chunk_size = unsafe { crate::sys_compat::iox_size(&*sertype) } as usize;
+        crate::serdes::check_loan_layout::<T>(chunk_size, p_sample as *const c_void)?;
+        Ok(())
+    }
+
      // Return the loaned buffer.  If the buffer was initialized, then write the data to be published
+     #[cfg(cyclone_shm)]
      pub fn return_loan(&mut self, mut buffer: Loaned<T>) -> Result<(),DDSError> {
+        self.0.check_valid()?;
         let res = match &mut buffer.inner {
             
             LoanedInner::Uninitialized(p,entity) => {
@@ -203,22 +347,14 @@ where
         if res == 0 {
             Ok(())        
         } else {
-            Err(DDSError::from(res))
+            Err(DDSError::from_retcode("dds_return_loan", res).with_entity_kind("writer"))
         } 
         
     }
 
+    /// Replace the listener of the writer, for all its clones
     pub fn set_listener(&mut self, listener: DdsListener) -> Result<(), DDSError> {
-        unsafe {
-            let refl = &listener;
-            let rc = dds_set_listener(self.0.entity(), refl.into());
-            if rc == 0 {
-                self.1 = Some(listener);
-                Ok(())
-            } else {
-                Err(DDSError::from(rc))
-            }
-        }
+        self.0.set_listener(listener)
     }
 }
 
@@ -227,21 +363,11 @@ where
     T: std::marker::Sized + TopicType,
 {
     fn entity(&self) -> &DdsEntity {
-        &self.0
+        self.0.entity()
     }
-}
 
-impl<'a, T> Drop for DdsWriter<T>
-where
-    T: std::marker::Sized + TopicType,
-{
-    fn drop(&mut self) {
-        unsafe {
-            let ret: DDSError = cyclonedds_sys::dds_delete(self.0.entity()).into();
-            if DDSError::DdsOk != ret && DDSError::AlreadyDeleted != ret {
-                //panic!("cannot delete Writer: {}", ret);
-            }
-        }
+    fn check_valid(&self) -> Result<(), DDSError> {
+        self.0.check_valid()
     }
 }
 
@@ -258,19 +384,6 @@ mod test {
     use serde_derive::{Deserialize, Serialize};
     use tokio::runtime::Runtime;
 
-    const cyclone_shm_config : &str = r###"<?xml version="1.0" encoding="UTF-8" ?>
-    <CycloneDDS xmlns="https://cdds.io/config"
-                xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
-                xsi:schemaLocation="https://cdds.io/config https://raw.githubusercontent.com/eclipse-cyclonedds/cyclonedds/iceoryx/etc/cyclonedds.xsd">
-        <Domain id="any">
-            <SharedMemory>
-                <Enable>true</Enable>
-                <LogLevel>info</LogLevel>
-            </SharedMemory>
-        </Domain>
-    </CycloneDDS>"###;
-
-
     #[repr(C)]
     #[derive(Serialize,Deserialize,Debug, PartialEq, Clone)]
     enum Position {
@@ -284,12 +397,13 @@ mod test {
         }
     }
     
+    #[repr(C)]
     #[derive(Serialize,Deserialize,TopicFixedSize, Debug, PartialEq)]
     struct TestTopic {
         a : u32,
         b : u16,
         c: [u8;10],
-        d : [u8;15],
+        d : [u8;16],
         #[topic_key]
         e : u32,
         #[topic_key_enum]
@@ -302,7 +416,7 @@ mod test {
                 a : 10,
                 b : 20,
                 c : [0,0,0,0,0,0,0,0,0,0],
-                d : [1,2,3,4,5,6,7,8,9,0,1,2,3,4,5],
+                d : [1,2,3,4,5,6,7,8,9,0,1,2,3,4,5,6],
                 e : 0,
                 pos : Position::default(),
             }
@@ -333,9 +447,13 @@ mod test {
     }
 
    //#[test]
+    #[cfg(cyclone_shm)]
     fn test_loan() {
         // Make sure iox-roudi is running
-        std::env::set_var("CYCLONEDDS_URI", cyclone_shm_config);
+        crate::CycloneConfig::new()
+            .with_shared_memory(true)
+            .with_shared_memory_log_level("info")
+            .set_env();
 
         let participant = DdsParticipant::create(None, None, None).unwrap();
 
@@ -382,11 +500,9 @@ mod test {
 
              let mut loaned = writer.loan().unwrap(); 
 
-             let ptr = loaned.as_mut_ptr().unwrap();
-             let topic = TestTopic::default();
-            
-             unsafe {ptr.write(topic)};
-             let loaned = loaned.assume_init();
+             assert!(loaned.get().is_none());
+             loaned.write(TestTopic::default()).unwrap();
+             assert!(loaned.get().is_some());
              writer.return_loan(loaned).unwrap();
 
             tokio::time::sleep(std::time::Duration::from_millis(300)).await;
@@ -395,6 +511,73 @@ mod test {
 
     }
 
-    
+    #[test]
+    fn test_write_ts_and_dispose() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = AnotherTopic::create_topic(&participant, Some("write_ts"), None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let timestamp = std::time::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        writer.write_ts(Arc::new(AnotherTopic::default()), timestamp).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let mut samples = SampleBuffer::<AnotherTopic>::new(4);
+        assert_eq!(reader.take_now(&mut samples).unwrap(), 1);
+        assert_eq!(samples.source_timestamp(0), Some(timestamp));
+
+        writer.dispose(Arc::new(AnotherTopic::default())).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let mut samples = SampleBuffer::<AnotherTopic>::new(4);
+        // the first sample is the dispose, which is not valid data
+        assert!(reader.take_now(&mut samples).is_err());
+        assert!(!samples.is_valid_sample(0));
+    }
 
+    #[test]
+    fn test_writer_clones_share_the_writer() {
+        let alive = |entity: dds_entity_t| unsafe { dds_get_parent(entity) } > 0;
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let participant_handle = unsafe { participant.entity().entity() };
+        let topic = AnotherTopic::create_topic(&participant, Some("writer_clones"), None, None).unwrap();
+        let writer = DdsWriter::create(&participant, topic, None, None).unwrap();
+        let handle = unsafe { writer.entity().entity() };
+
+        // the writer holds the topic and the participant
+        drop(participant);
+        let mut clone = writer.clone();
+        drop(writer);
+        assert!(alive(handle));
+        clone.write(Arc::new(AnotherTopic::default())).unwrap();
+
+        drop(clone);
+        assert!(!alive(handle));
+        assert!(!alive(participant_handle));
+    }
+
+    #[test]
+    fn test_listener_outlives_the_clone_that_set_it() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = AnotherTopic::create_topic(&participant, Some("writer_set_listener"), None, None).unwrap();
+        let writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+
+        let matched = Arc::new(AtomicUsize::new(0));
+        let counter = matched.clone();
+        let listener = DdsListener::new()
+            .on_publication_matched(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .hook();
+        let mut clone = writer.clone();
+        clone.set_listener(listener).unwrap();
+        drop(clone);
+
+        let _reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(matched.load(Ordering::SeqCst) > 0);
+        drop(writer);
+    }
 }
@@ -14,14 +14,507 @@
     limitations under the License.
 */
 
+//! The error type of the crate. All functions return `DDSError`. Errors
+//! can carry context (the entity involved and the underlying cause) in the
+//! `Context` variant. Use `kind()` to get the plain variant; comparisons
+//! between errors compare only the kind, so
+//! `err == DDSError::Timeout` works with and without context.
+//!
+//! Errors from cyclone calls record the failing operation and the return
+//! code, for example
+//! `Bad parameter in dds_create_reader (reader): dds retcode -3 "Bad Parameter"`.
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use cyclonedds_sys::{dds_entity_t, dds_return_t, DdsEntity};
 use thiserror::Error;
 
 #[derive(Error, Debug, Clone)]
-pub enum ReaderError {
+pub enum DDSError {
+    #[error("OK")]
+    DdsOk,
+    #[error("Error")]
+    Error,
+    #[error("Unsupported")]
+    Unsupported,
+    #[error("Bad parameter")]
+    BadParameter,
+    #[error("Precondition not met")]
+    PreconditionNotMet,
+    #[error("Out of resources")]
+    OutOfResources,
+    #[error("Not enabled")]
+    NotEnabled,
+    #[error("Immutable policy")]
+    ImmutablePolicy,
+    #[error("Inconsistent policy")]
+    InconsistentPolicy,
+    #[error("Already deleted")]
+    AlreadyDeleted,
+    #[error("Timeout")]
+    Timeout,
+    #[error("No data")]
+    NoData,
+    #[error("Illegal operation")]
+    IllegalOperation,
+    #[error("Not allowed by security")]
+    NotAllowedBySecurity,
     #[error("Missed a requested deadline")]
-    RequestedDeadLineMissed,
+    RequestedDeadlineMissed,
     #[error("Reader is not async type")]
     ReaderNotAsync,
-    #[error("DDS Binding error")]
-    DdsError(#[from] crate::DDSError )
-}
\ No newline at end of file
+    /// The entity was closed and the handle must not be used any more. Cyclone
+    /// may have given the id of the entity to a new one.
+    #[error("Use of a closed entity")]
+    UseAfterClose,
+    /// A received sample could not be deserialized
+    #[error(transparent)]
+    Serdes(Box<SerdesError>),
+    /// An error with context
+    #[error(transparent)]
+    Context(Box<ErrorContext>),
+}
+
+/// Kept for compatibility, the reader now returns DDSError
+pub type ReaderError = DDSError;
+
+/// The context of an error
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    kind: DDSError,
+    operation: Option<&'static str>,
+    entity_kind: Option<&'static str>,
+    retcode: Option<dds_return_t>,
+    entity: Option<dds_entity_t>,
+    source: Option<Arc<dyn std::error::Error + Send + Sync + 'static>>,
+}
+
+impl ErrorContext {
+    pub fn kind(&self) -> &DDSError {
+        &self.kind
+    }
+
+    pub fn entity(&self) -> Option<dds_entity_t> {
+        self.entity
+    }
+
+    /// The cyclone function that failed
+    pub fn operation(&self) -> Option<&'static str> {
+        self.operation
+    }
+
+    /// The kind of entity involved, for example "reader"
+    pub fn entity_kind(&self) -> Option<&'static str> {
+        self.entity_kind
+    }
+
+    /// The return code of the cyclone function
+    pub fn retcode(&self) -> Option<dds_return_t> {
+        self.retcode
+    }
+}
+
+/// The text cyclone has for a return code
+pub fn retcode_text(ret: dds_return_t) -> String {
+    unsafe {
+        let text = cyclonedds_sys::dds_strretcode(ret);
+        if text.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(text).to_string_lossy().into_owned()
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(operation) = self.operation {
+            write!(f, " in {}", operation)?;
+        }
+        match (self.entity_kind, self.entity) {
+            (Some(kind), Some(entity)) => write!(f, " ({} {})", kind, entity)?,
+            (Some(kind), None) => write!(f, " ({})", kind)?,
+            (None, Some(entity)) => write!(f, " (entity {})", entity)?,
+            (None, None) => {}
+        }
+        if let Some(ret) = self.retcode {
+            write!(f, ": dds retcode {} \"{}\"", ret, retcode_text(ret))?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ErrorContext {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|s| s.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl DDSError {
+    /// The error without context
+    pub fn kind(&self) -> &DDSError {
+        match self {
+            DDSError::Context(context) => context.kind(),
+            kind => kind,
+        }
+    }
+
+    /// The entity involved in the error, if known
+    pub fn entity(&self) -> Option<dds_entity_t> {
+        match self {
+            DDSError::Context(context) => context.entity,
+            _ => None,
+        }
+    }
+
+    fn into_context(self) -> ErrorContext {
+        match self {
+            DDSError::Context(context) => *context,
+            kind => ErrorContext {
+                kind,
+                operation: None,
+                entity_kind: None,
+                retcode: None,
+                entity: None,
+                source: None,
+            },
+        }
+    }
+
+    /// The error for a failed cyclone call
+    pub fn from_retcode(operation: &'static str, ret: dds_return_t) -> Self {
+        let mut context = DDSError::from(ret).into_context();
+        context.operation = Some(operation);
+        context.retcode = Some(ret);
+        DDSError::Context(Box::new(context))
+    }
+
+    /// Record the kind of entity involved, for example "reader"
+    pub fn with_entity_kind(self, entity_kind: &'static str) -> Self {
+        let mut context = self.into_context();
+        context.entity_kind = Some(entity_kind);
+        DDSError::Context(Box::new(context))
+    }
+
+    /// The context of the error, if any
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            DDSError::Context(context) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Attach the entity involved to the error
+    pub fn with_entity(self, entity: &DdsEntity) -> Self {
+        let mut context = self.into_context();
+        context.entity = Some(unsafe { entity.entity() });
+        DDSError::Context(Box::new(context))
+    }
+
+    /// Attach the underlying cause to the error
+    pub fn with_source<E>(self, source: E) -> Self
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut context = self.into_context();
+        context.source = Some(Arc::new(source));
+        DDSError::Context(Box::new(context))
+    }
+}
+
+impl PartialEq for DDSError {
+    fn eq(&self, other: &Self) -> bool {
+        std::mem::discriminant(self.kind()) == std::mem::discriminant(other.kind())
+    }
+}
+
+impl Eq for DDSError {}
+
+/// A sample that could not be deserialized. Cyclone drops such samples, they
+/// are reported to the readers of the topic with
+/// [`DdsReader::take_serdes_errors`](crate::DdsReader::take_serdes_errors).
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("cannot deserialize {type_name} sample of {size} bytes{}: {cause}", .topic.as_ref().map(|t| format!(" on topic {}", t)).unwrap_or_default())]
+pub struct SerdesError {
+    type_name: String,
+    topic: Option<String>,
+    size: usize,
+    cause: String,
+}
+
+impl SerdesError {
+    pub(crate) fn new(type_name: String, size: usize, cause: String) -> Self {
+        Self {
+            type_name,
+            topic: None,
+            size,
+            cause,
+        }
+    }
+
+    pub(crate) fn with_topic(mut self, topic: String) -> Self {
+        self.topic = Some(topic);
+        self
+    }
+
+    /// The type name of the topic
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    /// The topic the sample was received on, if known
+    pub fn topic(&self) -> Option<&str> {
+        self.topic.as_deref()
+    }
+
+    /// The size of the serialized sample in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Why deserialization failed
+    pub fn cause(&self) -> &str {
+        &self.cause
+    }
+}
+
+/// A topic or partition name that cyclone would not accept, the source of the
+/// `DDSError::BadParameter` returned for it
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("invalid {kind} name {name:?}: {problem}")]
+pub struct InvalidName {
+    kind: &'static str,
+    name: String,
+    problem: NameProblem,
+}
+
+/// What is wrong with an [`InvalidName`]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameProblem {
+    #[error("the name is empty")]
+    Empty,
+    #[error("the name is longer than {0} bytes")]
+    TooLong(usize),
+    #[error("NUL byte at {0}")]
+    Nul(usize),
+    #[error("{0:?} at {1} is not a printable ASCII character")]
+    NotPrintable(char, usize),
+    #[error("wildcard {0:?} at {1}, wildcards are only allowed in partitions")]
+    Wildcard(char, usize),
+}
+
+impl InvalidName {
+    pub(crate) fn new(kind: &'static str, name: &str, problem: NameProblem) -> Self {
+        Self {
+            kind,
+            name: name.to_owned(),
+            problem,
+        }
+    }
+
+    /// "topic" or "partition"
+    pub fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn problem(&self) -> NameProblem {
+        self.problem
+    }
+}
+
+impl From<InvalidName> for DDSError {
+    fn from(e: InvalidName) -> Self {
+        DDSError::BadParameter.with_source(e)
+    }
+}
+
+/// The memory of a loaned sample does not fit the Rust type, the source of the
+/// `DDSError::PreconditionNotMet` returned for the loan
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("loaned memory does not fit {type_name}: {problem}")]
+pub struct LayoutMismatch {
+    type_name: &'static str,
+    problem: LayoutProblem,
+}
+
+/// What is wrong with a [`LayoutMismatch`]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutProblem {
+    #[error("the type is {size} bytes, the chunk is {chunk_size} bytes")]
+    Size { size: usize, chunk_size: usize },
+    #[error("the chunk at {address:#x} is not aligned to {align} bytes")]
+    Align { address: usize, align: usize },
+}
+
+impl LayoutMismatch {
+    pub(crate) fn new(type_name: &'static str, problem: LayoutProblem) -> Self {
+        Self { type_name, problem }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn problem(&self) -> LayoutProblem {
+        self.problem
+    }
+}
+
+impl From<LayoutMismatch> for DDSError {
+    fn from(e: LayoutMismatch) -> Self {
+        DDSError::PreconditionNotMet.with_source(e)
+    }
+}
+
+/// A key that breaks the rules of the keyhash, found in strict keyhash mode
+/// (see [`set_strict_keyhash`](crate::set_strict_keyhash)). The sample is not
+/// written.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("the key of {type_name} breaks the keyhash rules: {problem}")]
+pub struct KeyhashViolation {
+    type_name: String,
+    problem: KeyhashProblem,
+}
+
+/// What is wrong with the key of a [`KeyhashViolation`]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyhashProblem {
+    #[error("the key CDR has encapsulation {0:#06x} instead of big endian CDR")]
+    Encapsulation(u16),
+    #[error("the key CDR is {0} bytes, shorter than the encapsulation header")]
+    Truncated(usize),
+    #[error("the key is {size} bytes after {first} bytes, variable size keys must force the md5 keyhash")]
+    VariableSize { first: usize, size: usize },
+    #[error("the size of the key depends on its value, so it can be longer than 16 bytes and must force the md5 keyhash")]
+    Md5NotForced,
+}
+
+impl KeyhashViolation {
+    pub(crate) fn new(type_name: String, problem: KeyhashProblem) -> Self {
+        Self { type_name, problem }
+    }
+
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    pub fn problem(&self) -> KeyhashProblem {
+        self.problem
+    }
+}
+
+impl From<KeyhashViolation> for DDSError {
+    fn from(e: KeyhashViolation) -> Self {
+        DDSError::BadParameter.with_source(e)
+    }
+}
+
+impl From<SerdesError> for DDSError {
+    fn from(e: SerdesError) -> Self {
+        DDSError::Serdes(Box::new(e))
+    }
+}
+
+/// Convert a cyclonedds return code
+impl From<i32> for DDSError {
+    fn from(ret: i32) -> Self {
+        match ret {
+            0 => DDSError::DdsOk,
+            -2 => DDSError::Unsupported,
+            -3 => DDSError::BadParameter,
+            -4 => DDSError::PreconditionNotMet,
+            -5 => DDSError::OutOfResources,
+            -6 => DDSError::NotEnabled,
+            -7 => DDSError::ImmutablePolicy,
+            -8 => DDSError::InconsistentPolicy,
+            -9 => DDSError::AlreadyDeleted,
+            -10 => DDSError::Timeout,
+            -11 => DDSError::NoData,
+            -12 => DDSError::IllegalOperation,
+            -13 => DDSError::NotAllowedBySecurity,
+            _ => DDSError::Error,
+        }
+    }
+}
+
+impl From<cyclonedds_sys::DDSError> for DDSError {
+    fn from(e: cyclonedds_sys::DDSError) -> Self {
+        use cyclonedds_sys::DDSError as SysError;
+        match e {
+            SysError::DdsOk => DDSError::DdsOk,
+            SysError::Error => DDSError::Error,
+            SysError::Unsupported => DDSError::Unsupported,
+            SysError::BadParameter => DDSError::BadParameter,
+            SysError::PreconditionNotMet => DDSError::PreconditionNotMet,
+            SysError::OutOfResources => DDSError::OutOfResources,
+            SysError::NotEnabled => DDSError::NotEnabled,
+            SysError::ImmutablePolicy => DDSError::ImmutablePolicy,
+            SysError::InconsistentPolicy => DDSError::InconsistentPolicy,
+            SysError::AlreadyDeleted => DDSError::AlreadyDeleted,
+            SysError::Timeout => DDSError::Timeout,
+            SysError::NoData => DDSError::NoData,
+            SysError::IllegalOperation => DDSError::IllegalOperation,
+            SysError::NotAllowedBySecurity => DDSError::NotAllowedBySecurity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_retcode() {
+        assert_eq!(DDSError::from(0), DDSError::DdsOk);
+        assert_eq!(DDSError::from(-9), DDSError::AlreadyDeleted);
+        assert_eq!(DDSError::from(-100), DDSError::Error);
+    }
+
+    #[test]
+    fn test_context() {
+        let entity = unsafe { DdsEntity::new(42) };
+        let err = DDSError::Timeout
+            .with_entity(&entity)
+            .with_source(std::io::Error::new(std::io::ErrorKind::Other, "io"));
+        assert_eq!(err, DDSError::Timeout);
+        assert_ne!(err, DDSError::NoData);
+        assert_eq!(err.entity(), Some(42));
+        assert_eq!(err.to_string(), "Timeout (entity 42): io");
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn test_serdes_error() {
+        let err: DDSError = SerdesError::new("Foo".to_owned(), 12, "unexpected end of input".to_owned())
+            .with_topic("/foo".to_owned())
+            .into();
+        assert!(matches!(err, DDSError::Serdes(_)));
+        assert_ne!(err, DDSError::Error);
+        assert_eq!(
+            err.to_string(),
+            "cannot deserialize Foo sample of 12 bytes on topic /foo: unexpected end of input"
+        );
+    }
+
+    #[test]
+    fn test_retcode_context() {
+        let err = DDSError::from_retcode("dds_create_reader", -3).with_entity_kind("reader");
+        assert_eq!(err, DDSError::BadParameter);
+        let context = err.context().unwrap();
+        assert_eq!(context.operation(), Some("dds_create_reader"));
+        assert_eq!(context.retcode(), Some(-3));
+        let text = err.to_string();
+        assert!(text.starts_with("Bad parameter in dds_create_reader (reader): dds retcode -3"));
+    }
+}
@@ -0,0 +1,244 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Entry points for the fuzz targets in `fuzz/` and the serdes benchmark,
+//! enabled with the `fuzzing` feature. A [`FuzzSertype`] hands arbitrary bytes
+//! to the serdata operations that cyclone calls for samples from the network,
+//! without a domain.
+//!
+//! The input is split into fragments the way cyclone passes a fragmented
+//! sample, so the scatter-gather reader used for both `from_ser` and
+//! `from_ser_iov` is covered. Accepted samples are read back and serialized
+//! again.
+
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+
+use cyclonedds_sys::{
+    ddsi_keyhash, ddsi_serdata, ddsi_serdata_kind_SDK_DATA, ddsi_serdata_kind_SDK_KEY,
+    ddsi_serdata_ops, ddsi_serdata_removeref, ddsi_sertype, iovec,
+};
+
+use crate::common::c_len;
+use crate::serdes::{BorrowedTopicType, Sample, SampleBuffer, SerType, TopicType};
+
+/// A sertype of `T` that is not registered with cyclone
+pub struct FuzzSertype<T: TopicType> {
+    sertype: *mut ddsi_sertype,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: TopicType> FuzzSertype<T> {
+    pub fn new() -> Self {
+        Self {
+            sertype: SerType::into_sertype(SerType::<T>::new()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// A sertype that keeps received samples as CDR, see [`BorrowedTopicType`]
+    pub fn new_borrowed() -> Self
+    where
+        T: BorrowedTopicType,
+    {
+        Self {
+            sertype: SerType::into_sertype(SerType::<T>::new_borrowed()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Deserialize `input` as a sample and as a key. The first byte sets the
+    /// length of the fragments of the rest. Returns whether the sample was
+    /// accepted.
+    pub fn run(&self, input: &[u8]) -> bool {
+        let (fragment, data) = match input.split_first() {
+            Some((fragment, data)) => (*fragment as usize + 1, data),
+            None => return false,
+        };
+        self.deserialize(ddsi_serdata_kind_SDK_KEY, data, fragment);
+        self.deserialize(ddsi_serdata_kind_SDK_DATA, data, fragment)
+    }
+
+    /// Serialize a sample the way a writer does, into a serdata that is then
+    /// written out as CDR. `None` if the sertype refuses the sample.
+    pub fn serialize(&self, sample: &Sample<T>) -> Option<Vec<u8>> {
+        let ops = self.ops();
+        unsafe {
+            let serdata = ops.from_sample.unwrap()(
+                self.sertype,
+                ddsi_serdata_kind_SDK_DATA,
+                sample as *const Sample<T> as *const c_void,
+            );
+            if serdata.is_null() {
+                return None;
+            }
+            let size = ops.get_size.unwrap()(serdata);
+            let mut cdr = vec![0u8; size as usize];
+            ops.to_ser.unwrap()(serdata, 0, c_len(cdr.len()), cdr.as_mut_ptr() as *mut c_void);
+            ddsi_serdata_removeref(serdata);
+            Some(cdr)
+        }
+    }
+
+    /// Receive an unfragmented sample the way a reader does, and read it into
+    /// the first sample of `buffer`. Returns whether the sample was accepted.
+    pub fn receive(&self, cdr: &[u8], buffer: &mut SampleBuffer<T>) -> bool {
+        let ops = self.ops();
+        let iov = iovec {
+            iov_base: cdr.as_ptr() as *mut c_void,
+            iov_len: c_len(cdr.len()),
+        };
+        unsafe {
+            let serdata = ops.from_ser_iov.unwrap()(
+                self.sertype,
+                ddsi_serdata_kind_SDK_DATA,
+                1,
+                &iov,
+                c_len(cdr.len()),
+            );
+            if serdata.is_null() {
+                return false;
+            }
+            let (samples, _) = buffer.as_mut_ptr();
+            ops.to_sample.unwrap()(
+                serdata,
+                *samples as *mut c_void,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            );
+            ddsi_serdata_removeref(serdata);
+        }
+        true
+    }
+
+    fn ops(&self) -> &ddsi_serdata_ops {
+        unsafe { &*(*self.sertype).serdata_ops }
+    }
+
+    fn deserialize(&self, kind: u32, data: &[u8], fragment: usize) -> bool {
+        let iovs: Vec<iovec> = data
+            .chunks(fragment)
+            .map(|chunk| iovec {
+                iov_base: chunk.as_ptr() as *mut c_void,
+                iov_len: c_len(chunk.len()),
+            })
+            .collect();
+        let from_ser_iov = self.ops().from_ser_iov.unwrap();
+        let serdata = unsafe {
+            from_ser_iov(
+                self.sertype,
+                kind,
+                c_len(iovs.len()),
+                iovs.as_ptr(),
+                c_len(data.len()),
+            )
+        };
+        if serdata.is_null() {
+            return false;
+        }
+        unsafe {
+            self.read_back(serdata);
+            ddsi_serdata_removeref(serdata);
+        }
+        true
+    }
+
+    // Exercise what a reader and a forwarding writer do with a received serdata
+    unsafe fn read_back(&self, serdata: *mut ddsi_serdata) {
+        let ops = self.ops();
+
+        let mut keyhash: ddsi_keyhash = std::mem::zeroed();
+        ops.get_keyhash.unwrap()(serdata, &mut keyhash, false);
+        assert!(ops.eqkey.unwrap()(serdata, serdata));
+
+        let size = ops.get_size.unwrap()(serdata);
+        let mut buf = vec![0u8; size as usize];
+        ops.to_ser.unwrap()(serdata, 0, c_len(buf.len()), buf.as_mut_ptr() as *mut c_void);
+
+        let mut buffer = SampleBuffer::<T>::new(1);
+        let (samples, _) = buffer.as_mut_ptr();
+        ops.to_sample.unwrap()(
+            serdata,
+            *samples as *mut c_void,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+    }
+}
+
+impl<T: TopicType> Default for FuzzSertype<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: TopicType> Drop for FuzzSertype<T> {
+    fn drop(&mut self) {
+        let _it = SerType::<T>::try_from_sertype(self.sertype);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Topic, Default)]
+    struct Packet {
+        #[topic_key]
+        id: u32,
+        label: String,
+        payload: Vec<u8>,
+    }
+
+    #[test]
+    fn test_fuzz_entry() {
+        let target = FuzzSertype::<Packet>::new();
+        let sample = Packet {
+            id: 5,
+            label: "label".to_owned(),
+            payload: vec![1, 2, 3],
+        };
+        let cdr = cdr::serialize::<_, _, cdr::CdrBe>(&sample, cdr::Infinite).unwrap();
+        for fragment in [0u8, 3, 255] {
+            let input: Vec<u8> = std::iter::once(fragment).chain(cdr.iter().copied()).collect();
+            assert!(target.run(&input));
+        }
+
+        // truncated, and a sequence longer than the input
+        assert!(!target.run(&[0, 0, 0, 0, 0, 0, 0, 0, 5]));
+        assert!(!target.run(&[7, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 1, b'a', 0, 0xff, 0xff, 0xff, 0xff]));
+        assert!(!target.run(&[]));
+    }
+
+    #[test]
+    fn test_serialize_and_receive() {
+        let target = FuzzSertype::<Packet>::new();
+        let sample = Packet {
+            id: 5,
+            label: "label".to_owned(),
+            payload: vec![1, 2, 3],
+        };
+        let cdr = target.serialize(&Sample::from(std::sync::Arc::new(sample))).unwrap();
+
+        let mut buffer = SampleBuffer::<Packet>::new(1);
+        assert!(target.receive(&cdr, &mut buffer));
+        let received = buffer.get(0).try_deref().unwrap();
+        assert_eq!((received.id, received.label.as_str()), (5, "label"));
+        assert!(!target.receive(&cdr[..6], &mut buffer));
+    }
+}
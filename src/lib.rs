@@ -17,39 +17,120 @@
 
 //! Safe Rust bindings to cyclonedds
 //! 
+//! With the `tracing` feature, entity creation and deletion, reads, takes and
+//! writes run inside tracing spans and samples that fail to deserialize are
+//! reported as warning events.
 //! 
 
+// the paths emitted by the Topic derive resolve inside this crate as well
+extern crate self as cyclonedds_rs;
+
 pub mod alloc;
+mod atomic_waker;
 mod common;
 pub mod dds_api;
+pub mod dds_bridge;
+pub mod dds_builtin;
+pub mod dds_cache;
+pub mod dds_channel;
+pub mod dds_coherent;
+pub mod dds_config;
+pub mod dds_deadline;
 pub mod dds_domain;
+pub mod dds_dynamic;
+pub mod dds_executor;
+pub mod dds_gateway;
+pub mod dds_graph;
+pub mod dds_guardcondition;
+pub mod dds_idlc;
+#[cfg(feature = "serde_json")]
+pub mod dds_json;
 pub mod dds_listener;
+pub mod dds_liveliness;
+pub mod dds_log;
+pub mod dds_metrics;
+pub mod dds_node;
+pub mod dds_param;
 pub mod dds_participant;
 pub mod dds_publisher;
 pub mod dds_qos;
 pub mod dds_reader;
+pub mod dds_rpc;
+pub mod dds_scope;
+pub mod dds_security;
+pub mod dds_statistics;
+pub mod dds_statuscondition;
 pub mod dds_subscriber;
+pub mod dds_telemetry;
+pub mod dds_time;
 pub mod dds_topic;
+pub mod dds_typeinfo;
 mod dds_waitset;
 pub mod dds_writer;
 pub mod error;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz_support;
 pub mod serdes;
+mod serdes_borrowed;
+mod serdes_raw;
+mod serdes_reflect;
+mod sys_compat;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 pub mod topic_type_methods;
 
-pub use common::{DdsReadable, DdsWritable, Entity};
+pub use common::{DdsReadable, DdsWritable, Entity, Guid};
 pub use dds_api::*;
+pub use dds_bridge::{BridgeStats, BridgedTopic, DomainBridge, DomainBridgeBuilder};
+pub use dds_builtin::{
+    BuiltinSample, BuiltinTopicData, DdsBuiltinReader, EndpointBuiltinTopicData, EndpointKind,
+    ParticipantBuiltinTopicData, PublicationBuiltinTopicData, SubscriptionBuiltinTopicData,
+    TopicBuiltinTopicData,
+};
+pub use dds_cache::{CacheEvent, KeyedCache};
+pub use dds_channel::{channel_reader, sample_channel, sink_reader, SampleReceiver, SampleSender, SampleSink};
+pub use dds_coherent::CoherentSet;
+pub use dds_config::{CycloneConfig, EffectiveConfig, TraceVerbosity};
+pub use dds_deadline::{DeadlineEvent, DeadlineWatchdog, NextDeadlineEvent};
+pub use dds_domain::{DdsDomain, DomainManager, DOMAIN_DEFAULT};
+pub use dds_dynamic::{
+    DynamicData, DynamicField, DynamicKind, DynamicReader, DynamicSample, DynamicTopic,
+    DynamicType, DynamicTypeError, DynamicWriter,
+};
+pub use dds_executor::WaitsetExecutor;
+pub use dds_gateway::{DomainFactory, Gateway};
+pub use dds_graph::{DomainGraph, EndpointInfo, ParticipantInfo, TopicInfo};
+pub use dds_guardcondition::DdsGuardCondition;
+pub use dds_idlc::{Idlc, IdlcType, IdlcTypeError, IdlcValue};
 pub use dds_listener::{DdsListener,DdsListenerBuilder};
-pub use dds_participant::{DdsParticipant, ParticipantBuilder};
+pub use dds_liveliness::{LivelinessEvent, LivelinessMonitor, NextLivelinessEvent, WriterLiveliness};
+pub use dds_log::TraceCategories;
+pub use dds_metrics::{Metric, MetricKind, MetricsCollector};
+pub use dds_node::{Node, NodeBuilder};
+pub use dds_param::ParamStore;
+pub use dds_participant::{DdsParticipant, ParticipantBuilder, SharedParticipant};
 pub use dds_publisher::{DdsPublisher,PublisherBuilder};
 pub use dds_qos::*;
-pub use dds_reader::{DdsReadCondition, DdsReader, ReaderBuilder};
+pub use dds_reader::{BusyPoll, DdsReadCondition, DdsReader, LoanedSamples, ReaderBuilder};
+pub use dds_scope::{Scope, ScopeBuilder};
+pub use dds_security::{SecurityConfig, SecurityConfigError};
+pub use dds_statistics::{DdsStatistics, StatisticKey, StatisticValue};
+pub use dds_statuscondition::DdsStatusCondition;
 pub use dds_subscriber::{DdsSubscriber,SubscriberBuilder};
-pub use dds_topic::{DdsTopic,TopicBuilder};
-pub use dds_waitset::DdsWaitset;
+pub use dds_time::DdsTime;
+pub use dds_topic::{DdsFoundTopic, DdsTopic, FindScope, TopicBuilder};
+pub use dds_typeinfo::TypeInformation;
+pub use dds_waitset::{AsyncWaitset, DdsWaitset, WaitsetDispatcher, WaitsetToken};
 pub use dds_writer::{DdsWriter,WriterBuilder};
-pub use serdes::{TopicType, SampleBuffer, Sample};
+pub use serdes::{
+    check_key_cdr, set_strict_keyhash, set_type_name_mode, BorrowedTopicType, TopicType, SampleBuffer, SampleInfo,
+    SamplePool, Sample, TypeNameMode,
+};
 
 pub use cdr;
-pub use cyclonedds_sys::dds_error::DDSError;
+pub use error::{
+    retcode_text, DDSError, ErrorContext, InvalidName, KeyhashProblem, KeyhashViolation, LayoutMismatch, LayoutProblem,
+    NameProblem, SerdesError,
+};
 
 pub use serde_derive::{Deserialize, Serialize};
@@ -20,74 +20,248 @@
 use cdr::{Bounded, CdrBe, Infinite};
 
 
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::io::prelude::*;
 
 use std::ptr::NonNull;
 
 use std::{
+    any::TypeId,
+    collections::{HashMap, VecDeque},
     ffi::{c_void, CStr},
     marker::PhantomData,
+    mem::MaybeUninit,
     ops::Deref,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
 };
 
+use crate::common::{c_len, rust_len};
+use crate::dds_telemetry::{count, Counter};
+use crate::dds_time::DdsTime;
+use crate::error::{DDSError, KeyhashProblem, KeyhashViolation, LayoutMismatch, LayoutProblem, SerdesError};
+use crate::sys_compat;
+#[cfg(cyclone_type_discovery)]
+use crate::{dds_dynamic::DynamicType, dds_typeinfo::TypeInformation};
+
 use cyclonedds_sys::*;
-//use fasthash::{murmur3::Hasher32, FastHasher};
-use murmur3::murmur3_32;
-use std::io::Cursor;
 
 #[repr(C)]
 pub struct SerType<T> {
     sertype: ddsi_sertype,
+    serdes_errors: SerdesErrors,
+    // keyhashes of the instances written with this sertype
+    keyhashes: KeyHashCache,
+    // set if received samples are kept as CDR for borrowed reads
+    borrowed: Option<BorrowedDecode>,
+    // the storage of received samples that are not kept inline
+    arcs: ArcPool<T>,
+    // returned by the hash op, see sertype_hash
+    hash: u32,
+    // built on the first request of cyclone, None if T cannot be described
+    #[cfg(cyclone_type_discovery)]
+    type_information: OnceLock<Option<TypeInformation>>,
     _phantom: PhantomData<T>,
 }
 
-pub trait TopicType: Serialize + DeserializeOwned {
+// Instances whose keyhash is kept. The cache is emptied when it is full, so
+// a writer with more instances than this hashes like without a cache.
+const MAX_CACHED_KEYHASHES: usize = 4096;
+
+/// The keyhash and the instance hash of the instances written with a sertype,
+/// keyed by the CDR of the key fields. Steady-state writes of an instance look
+/// up the hashes instead of computing the md5 and the murmur hash again.
+#[derive(Default)]
+struct KeyHashCache {
+    hashes: Mutex<HashMap<Vec<u8>, (KeyHash, u32)>>,
+    // the size of the first key plus one, checked in strict keyhash mode
+    key_size: AtomicUsize,
+    // whether the key of the type has a fixed size, None if the type cannot
+    // be described. Found once in strict keyhash mode.
+    fixed_key: OnceLock<Option<bool>>,
+}
+
+impl KeyHashCache {
+    fn get_or_insert<T: TopicType>(&self, sample: &T, basehash: u32) -> Result<(KeyHash, u32), KeyhashViolation> {
+        let key_cdr = sample.key_cdr();
+        let mut cache = self.hashes.lock().unwrap();
+        if let Some(hashes) = cache.get(&key_cdr) {
+            return Ok(hashes.clone());
+        }
+        if STRICT_KEYHASH.load(Ordering::Relaxed) {
+            check_key_cdr::<T>(&key_cdr)?;
+            self.check_md5::<T>()?;
+            self.check_key_size::<T>(key_cdr.len() - 4)?;
+        }
+        // skip the four byte header
+        let hashes = (cdr_key_hash::<T>(&key_cdr[4..]), sample.hash(basehash));
+        if cache.len() == MAX_CACHED_KEYHASHES {
+            cache.clear();
+        }
+        cache.insert(key_cdr, hashes.clone());
+        Ok(hashes)
+    }
+
+    // The spec hashes a key with md5 if its largest size is more than 16 bytes.
+    // Keys longer than 16 bytes are hashed with md5 anyway, so a key of fixed
+    // size gets the keyhash of the spec without forcing it, a key whose size
+    // depends on its value must force it.
+    fn check_md5<T: TopicType>(&self) -> Result<(), KeyhashViolation> {
+        if T::force_md5_keyhash() {
+            return Ok(());
+        }
+        let fixed_key = self.fixed_key.get_or_init(|| {
+            crate::dds_dynamic::DynamicType::of::<T>()
+                .ok()
+                .map(|ty| ty.key_size().is_some())
+        });
+        match fixed_key {
+            Some(false) => Err(KeyhashViolation::new(
+                cached_typename::<T>().to_string_lossy().into_owned(),
+                KeyhashProblem::Md5NotForced,
+            )),
+            // the sizes of the keys written are compared instead
+            _ => Ok(()),
+        }
+    }
+
+    // Keys that are not hashed with md5 must all have the same size, a key
+    // that is shorter for some samples would switch to md5 for longer ones.
+    fn check_key_size<T: TopicType>(&self, size: usize) -> Result<(), KeyhashViolation> {
+        if T::force_md5_keyhash() {
+            return Ok(());
+        }
+        match self.key_size.compare_exchange(0, size + 1, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => Ok(()),
+            Err(first) if first == size + 1 => Ok(()),
+            Err(first) => Err(KeyhashViolation::new(
+                cached_typename::<T>().to_string_lossy().into_owned(),
+                KeyhashProblem::VariableSize { first: first - 1, size },
+            )),
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.hashes.lock().unwrap().len()
+    }
+}
+
+static STRICT_KEYHASH: AtomicBool = AtomicBool::new(false);
+
+/// Check the keys of written samples against the rules of the keyhash in the
+/// DDSI-RTPS spec, which other DDS implementations rely on to match
+/// instances. A sample whose key breaks them is not written, the write fails
+/// with a [`KeyhashViolation`] as the source of the error. Keys are checked
+/// the first time they are written. This is meant for tests of hand written
+/// [`TopicType`] implementations.
+pub fn set_strict_keyhash(strict: bool) {
+    STRICT_KEYHASH.store(strict, Ordering::Relaxed);
+}
+
+/// Check the key CDR returned by [`TopicType::key_cdr`]. It must start with
+/// the encapsulation header of big endian CDR, as the keyhash is computed from
+/// the big endian serialization of the key.
+pub fn check_key_cdr<T: TopicType>(key_cdr: &[u8]) -> Result<(), KeyhashViolation> {
+    let problem = match key_cdr {
+        [0, 0, _, _, ..] => return Ok(()),
+        [a, b, _, _, ..] => KeyhashProblem::Encapsulation(u16::from_be_bytes([*a, *b])),
+        _ => KeyhashProblem::Truncated(key_cdr.len()),
+    };
+    Err(KeyhashViolation::new(cached_typename::<T>().to_string_lossy().into_owned(), problem))
+}
+
+thread_local! {
+    // why the last sample created on this thread was refused
+    static KEYHASH_VIOLATION: std::cell::RefCell<Option<KeyhashViolation>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Why the last write on this thread was refused in strict keyhash mode
+pub(crate) fn take_keyhash_violation() -> Option<KeyhashViolation> {
+    KEYHASH_VIOLATION.with(|violation| violation.borrow_mut().take())
+}
+
+// Only this many deserialization failures are kept until a reader collects them.
+// Older failures are discarded first.
+const MAX_PENDING_SERDES_ERRORS: usize = 64;
+
+/// Deserialization failures of a sertype, kept until they are collected by a reader.
+#[derive(Default)]
+pub(crate) struct SerdesErrors(Mutex<VecDeque<SerdesError>>);
+
+impl SerdesErrors {
+    pub(crate) fn push(&self, error: SerdesError) {
+        if let Ok(mut errors) = self.0.lock() {
+            if errors.len() == MAX_PENDING_SERDES_ERRORS {
+                errors.pop_front();
+                count(Counter::SerdesErrorsDiscarded);
+            }
+            errors.push_back(error);
+        }
+    }
+
+    pub(crate) fn take(&self) -> Vec<SerdesError> {
+        self.0
+            .lock()
+            .map(|mut errors| errors.drain(..).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Received samples of types up to this size are kept inline by default, see
+/// [`TopicType::store_inline`]
+pub const INLINE_SAMPLE_SIZE: usize = 64;
+
+/// A type that can be published on a topic. Types are told apart by their
+/// `TypeId`, so they must be `'static`.
+pub trait TopicType: Serialize + DeserializeOwned + 'static {
     // generate a non-cryptographic hash of the key values to be used internally
     // in cyclonedds
     fn hash(&self, basehash : u32) -> u32 {
-        let cdr = self.key_cdr();
-        let mut cursor = Cursor::new(cdr.as_slice());
-        murmur3_32(&mut cursor, 0).unwrap() ^ basehash
+        murmur3_32_slices(&[&self.key_cdr()], 0) ^ basehash
     }
 
     fn is_fixed_size() -> bool {
         false
     }
-    /// The type name for this topic
+
+    /// Whether received samples are kept inline instead of behind an `Arc`,
+    /// saving an allocation per sample. By default types of up to
+    /// [`INLINE_SAMPLE_SIZE`] bytes are kept inline, override this to change
+    /// the threshold of a type.
+    fn store_inline() -> bool {
+        std::mem::size_of::<Self>() <= INLINE_SAMPLE_SIZE
+    }
+
+    /// The type name for this topic. The default implementation uses the
+    /// path of the type without the crate, or the IDL form selected with
+    /// [`set_type_name_mode`].
     fn typename() -> std::ffi::CString {
-        let ty_name_parts: String = std::any::type_name::<Self>()
-            .split("::")
-            .skip(1)
-            .collect::<Vec<_>>()
-            .join("::");
-
-        
-        //println!("Typename:{:?}", &typename);
+        let ty_name_parts = TYPE_NAME_MODE.lock().unwrap().type_name(std::any::type_name::<Self>());
+
         std::ffi::CString::new(ty_name_parts).expect("Unable to create CString for type name")
     }
 
-    /// The default topic_name to use when creating a topic of this type. The default
-    /// implementation uses '/' instead of '::' to form a unix like path.
-    /// A prefix can optionally be added
-    fn topic_name(maybe_prefix: Option<&str>) -> String {
-        let topic_name_parts: String = format!(
-            "/{}",
-            std::any::type_name::<Self>()
-                .to_string()
-                .split("::")
-                .skip(1)
-                .collect::<Vec<_>>()
-                .join("/")
-        );
+    /// The default topic name without a prefix. The default implementation
+    /// uses '/' instead of '::' to form a unix like path, it is built once per
+    /// type.
+    fn default_topic_name() -> &'static str {
+        static TOPIC_NAMES: Mutex<Option<HashMap<TypeId, &'static str>>> = Mutex::new(None);
+        let mut names = TOPIC_NAMES.lock().unwrap();
+        names
+            .get_or_insert_with(HashMap::new)
+            .entry(TypeId::of::<Self>())
+            .or_insert_with(|| Box::leak(path_topic_name(std::any::type_name::<Self>()).into_boxed_str()))
+    }
 
-        if let Some(prefix) = maybe_prefix {
-            let mut path = String::from(prefix);
-            path.push_str(&topic_name_parts);
-            path
-        } else {
-            topic_name_parts
+    /// The default topic_name to use when creating a topic of this type, see
+    /// [`TopicType::default_topic_name`]. A prefix can optionally be added
+    fn topic_name(maybe_prefix: Option<&str>) -> String {
+        match maybe_prefix {
+            Some(prefix) => format!("{}{}", prefix, Self::default_topic_name()),
+            None => Self::default_topic_name().to_owned(),
         }
     }
 
@@ -102,10 +276,93 @@ pub trait TopicType: Serialize + DeserializeOwned {
     // force the use of md5 even if the serialized size is less than 16
     // as per the standard, we need to check the potential field size and not the actual.
     fn force_md5_keyhash() -> bool;
+
+    /// The names of the key fields in the order of [`TopicType::key_cdr`], a
+    /// field of a nested struct as `outer.inner`. Used to describe the type to
+    /// other applications, see [`DynamicType::of`](crate::DynamicType::of).
+    fn key_fields() -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// A topic type with a view that borrows its string and byte fields from the
+/// received CDR. Topics created with
+/// [`TopicBuilder::with_borrowed_deserialization`](crate::TopicBuilder::with_borrowed_deserialization)
+/// keep received samples as CDR, read them with [`Sample::borrowed`].
+pub trait BorrowedTopicType: TopicType {
+    /// The type with `&'a str` and `&'a [u8]` fields in place of `String` and
+    /// `Vec<u8>`. The fields must be in the same order as in `Self`.
+    type Borrowed<'a>: Deserialize<'a>;
+
+    /// The key of a borrowed sample, as [`TopicType::key_cdr`] returns it for
+    /// the same sample. Keyed types must implement it.
+    fn borrowed_key_cdr(_sample: &Self::Borrowed<'_>) -> Vec<u8> {
+        vec![0, 0, 0, 0]
+    }
+}
+
+// Decodes the borrowed view of a sample kept as CDR, with its keyhash and
+// instance hash. The view borrows from `cdr`, which must outlive it.
+type BorrowedDecode = unsafe fn(&[u8], u32) -> Result<(KeyHash, u32, BorrowedView), cdr::Error>;
+
+unsafe fn decode_borrowed<T: BorrowedTopicType>(
+    cdr: &[u8],
+    basehash: u32,
+) -> Result<(KeyHash, u32, BorrowedView), cdr::Error> {
+    let cdr: &'static [u8] = std::slice::from_raw_parts(cdr.as_ptr(), cdr.len());
+    let sample = crate::serdes_borrowed::from_cdr::<T::Borrowed<'static>>(cdr)?;
+    let (key_hash, hash) = if T::has_key() {
+        let key_cdr = T::borrowed_key_cdr(&sample);
+        // skip the four byte header
+        let key_hash = cdr_key_hash::<T>(&key_cdr[4..]);
+        (key_hash, murmur3_32_slices(&[&key_cdr], 0) ^ basehash)
+    } else {
+        (KeyHash::None, keyless_hash(basehash))
+    };
+    Ok((key_hash, hash, BorrowedView::new(sample)))
+}
+
+// The decoded borrowed view of the CDR of a serdata, kept so that a received
+// sample is decoded once. It is dropped before the CDR it borrows from.
+struct BorrowedView {
+    view: NonNull<()>,
+    drop: unsafe fn(NonNull<()>),
+}
+
+impl BorrowedView {
+    fn new<V>(view: V) -> Self {
+        unsafe fn drop_view<V>(view: NonNull<()>) {
+            drop(Box::from_raw(view.as_ptr() as *mut V));
+        }
+        Self {
+            view: NonNull::from(Box::leak(Box::new(view))).cast(),
+            drop: drop_view::<V>,
+        }
+    }
+
+    // V must be the type the view was created with, borrowing from data that
+    // lives for 'a
+    unsafe fn get<'a, V>(&'a self) -> &'a V {
+        &*(self.view.as_ptr() as *const V)
+    }
+}
+
+impl Drop for BorrowedView {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.view) }
+    }
 }
 
 impl<'a, T> SerType<T> {
     pub fn new() -> Box<SerType<T>>
+    where
+        T: DeserializeOwned + Serialize + TopicType,
+    {
+        Self::named(cached_typename::<T>())
+    }
+
+    /// A sertype that reports `type_name` instead of the type name of T
+    pub(crate) fn named(type_name: &CStr) -> Box<SerType<T>>
     where
         T: DeserializeOwned + Serialize + TopicType,
     {
@@ -113,24 +370,49 @@ impl<'a, T> SerType<T> {
             sertype: {
                 let mut sertype = std::mem::MaybeUninit::uninit();
                 unsafe {
-                    let type_name = T::typename();
+                    let (sertype_ops, serdata_ops) = shared_ops::<T>();
                     ddsi_sertype_init(
                         sertype.as_mut_ptr(),
                         type_name.as_ptr(),
-                        Box::into_raw(create_sertype_ops::<T>()),
-                        Box::into_raw(create_serdata_ops::<T>()),
+                        sertype_ops,
+                        serdata_ops,
                         !T::has_key(),
                     );
                     let mut sertype = sertype.assume_init();
                     sertype.set_fixed_size(if T::is_fixed_size() { 1 } else { 0 });
-                    sertype.iox_size = std::mem::size_of::<T>() as u32;
+                    sys_compat::set_iox_size(&mut sertype, std::mem::size_of::<T>() as u32);
                     sertype
                 }
             },
+            serdes_errors: SerdesErrors::default(),
+            keyhashes: KeyHashCache::default(),
+            borrowed: None,
+            arcs: ArcPool::default(),
+            hash: sertype_hash::<T>(type_name),
+            #[cfg(cyclone_type_discovery)]
+            type_information: OnceLock::new(),
             _phantom: PhantomData,
         })
     }
 
+    /// A sertype that keeps received samples as CDR instead of deserializing
+    /// them, see [`BorrowedTopicType`]
+    pub fn new_borrowed() -> Box<SerType<T>>
+    where
+        T: BorrowedTopicType,
+    {
+        Self::named_borrowed(cached_typename::<T>())
+    }
+
+    pub(crate) fn named_borrowed(type_name: &CStr) -> Box<SerType<T>>
+    where
+        T: BorrowedTopicType,
+    {
+        let mut sertype = Self::named(type_name);
+        sertype.borrowed = Some(decode_borrowed::<T>);
+        sertype
+    }
+
     // cast into cyclone dds sertype.  Rust relinquishes ownership here.
     // Cyclone DDS will free this. But if you need to free this pointer
     // before handing it over to cyclone, make sure you explicitly free it
@@ -146,12 +428,65 @@ impl<'a, T> SerType<T> {
             None
         }
     }
+
+    // Borrow a sertype owned by cyclone. The sertype must have been created by
+    // SerType::<T>::new.
+    pub(crate) unsafe fn ref_from_sertype(sertype: *const ddsi_sertype) -> Option<&'a SerType<T>> {
+        (sertype as *const SerType<T>).as_ref()
+    }
+
+    /// Deserialization failures since the last call
+    pub(crate) fn take_serdes_errors(&self) -> Vec<SerdesError> {
+        self.serdes_errors.take()
+    }
+
+    /// The type information published for the topics of this sertype
+    #[cfg(cyclone_type_discovery)]
+    pub(crate) fn type_information(&self) -> Option<&TypeInformation>
+    where
+        T: TopicType,
+    {
+        self.type_information
+            .get_or_init(|| {
+                let mut ty = DynamicType::of::<T>().ok()?;
+                let name = unsafe { CStr::from_ptr(self.sertype.type_name) };
+                ty.set_name(&name.to_string_lossy());
+                Some(TypeInformation::new(&ty))
+            })
+            .as_ref()
+    }
 }
 
+/// The value of a sample. A loaned value is released when the last clone of
+/// the storage is dropped, the sample it came from may be dropped or reused
+/// before that.
 #[derive(Clone)]
 pub enum SampleStorage<T> {
     Owned(Arc<T>),
-    Loaned(Arc<NonNull<T>>),
+    Loaned(Arc<LoanedPtr<T>>),
+}
+
+/// A sample in memory loaned from cyclone, either received in an iceoryx
+/// chunk or set with [`Sample::set_loaned`]. The sample is only read through
+/// the pointer.
+pub struct LoanedPtr<T> {
+    ptr: NonNull<T>,
+    // the serdata holding the chunk of a received sample. The chunk goes
+    // back to iceoryx when cyclone frees the serdata.
+    _serdata: Option<SerdataRef<T>>,
+}
+
+// shared like a &T, and the serdata reference like an Arc<T>
+unsafe impl<T: Send + Sync> Send for LoanedPtr<T> {}
+unsafe impl<T: Send + Sync> Sync for LoanedPtr<T> {}
+
+impl<T> Deref for LoanedPtr<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: valid while the serdata is, or as promised to Sample::set_loaned
+        unsafe { self.ptr.as_ref() }
+    }
 }
 
 impl<T> Deref for SampleStorage<T> {
@@ -160,28 +495,47 @@ impl<T> Deref for SampleStorage<T> {
     fn deref(&self) -> &Self::Target {
         match self {
             SampleStorage::Owned(t) => t.deref(),
-            SampleStorage::Loaned(t) => unsafe { t.as_ref().as_ref() },
+            SampleStorage::Loaned(t) => t.deref(),
         }
     }
 }
 
-impl<T> Drop for SampleStorage<T> {
-    fn drop(&mut self) {
-        match self {
-            SampleStorage::Loaned(_t) => {
-            }
-            _ => {
+// A reference to a serdata of a SerData<T>, counted by cyclone. The count is
+// atomic and the sample of a serdata does not change once it is handed to a
+// reader, so the reference moves and is shared like an Arc<T>.
+struct SerdataRef<T>(NonNull<ddsi_serdata>, PhantomData<Arc<T>>);
 
-            }
-        }
+unsafe impl<T: Send + Sync> Send for SerdataRef<T> {}
+unsafe impl<T: Send + Sync> Sync for SerdataRef<T> {}
+
+impl<T> SerdataRef<T> {
+    // Adds a reference to the serdata
+    unsafe fn new(serdata: *mut ddsi_serdata) -> Self {
+        ddsi_serdata_addref(serdata);
+        Self(NonNull::new_unchecked(serdata), PhantomData)
+    }
+
+    fn as_ptr(&self) -> *mut ddsi_serdata {
+        self.0.as_ptr()
+    }
+}
+
+impl<T> Clone for SerdataRef<T> {
+    fn clone(&self) -> Self {
+        unsafe { Self::new(self.as_ptr()) }
     }
 }
 
+impl<T> Drop for SerdataRef<T> {
+    fn drop(&mut self) {
+        unsafe { ddsi_serdata_removeref(self.0.as_ptr()) };
+    }
+}
 
 pub struct Sample<T> {
     //Serdata is used for incoming samples. We hold a reference to the ddsi_serdata which contains 
     // the sample
-    serdata: Option<*mut ddsi_serdata>,
+    serdata: Option<SerdataRef<T>>,
     // sample is used for outgoing samples.
     sample: Option<SampleStorage<T>>,
 }
@@ -191,13 +545,16 @@ where
     T: TopicType
 {
     pub fn try_deref<>(&self) -> Option<&T> {       
-            if let Some(serdata) = self.serdata {
-                let serdata = SerData::<T>::mut_ref_from_serdata(serdata);
+            if let Some(serdata) = &self.serdata {
+                let serdata = SerData::<T>::const_ref_from_serdata(serdata.as_ptr());
                 match &serdata.sample {
                     SampleData::Uninitialized => None,
                     SampleData::SDKKey => None,
                     SampleData::SDKData(it) => Some(it.as_ref()),
+                    SampleData::Inline(it) => Some(it),
+                    #[cfg(cyclone_shm)]
                     SampleData::SHMData(it) => unsafe { Some(it.as_ref())},
+                    SampleData::SDKCdr => None,
                 }
             } else {
                 None
@@ -231,10 +588,15 @@ where
         }
     }
 
+    // The sample keeps a reference to the serdata, cyclone drops its own on
+    // a take. Reading the same serdata into the sample again keeps the one
+    // reference it has.
     pub(crate) fn set_serdata(&mut self,serdata:*mut ddsi_serdata) {
-        // Increment the reference count
-        unsafe {ddsi_serdata_addref(serdata);}
-        self.serdata = Some(serdata)
+        if self.serdata.as_ref().map(SerdataRef::as_ptr) == Some(serdata) {
+            return;
+        }
+        // a reused sample drops the reference to the serdata of the previous read
+        self.serdata = Some(unsafe { SerdataRef::new(serdata) });
     }
 
     pub fn set(&mut self, t: Arc<T>) {
@@ -242,9 +604,28 @@ where
         self.sample.replace(SampleStorage::Owned(t));
     }
 
-    pub fn set_loaned(&mut self, t: NonNull<T>) {
+    /// # Safety
+    ///
+    /// `t` must point to a valid sample that is not written to and outlives
+    /// the sample and all storage returned by [`Sample::get_sample`].
+    pub unsafe fn set_loaned(&mut self, t: NonNull<T>) {
         //let mut sample = self.sample.write().unwrap();
-        self.sample.replace(SampleStorage::Loaned(Arc::new(t)));
+        self.sample.replace(SampleStorage::Loaned(Arc::new(LoanedPtr {
+            ptr: t,
+            _serdata: None,
+        })));
+    }
+
+    // Loan the value of the serdata of the sample, which lives in the chunk
+    // of the serdata. The loan keeps its own reference to the serdata.
+    #[cfg(cyclone_shm)]
+    fn set_received_loan(&mut self, t: NonNull<T>) {
+        let serdata = self.serdata.clone();
+        debug_assert!(serdata.is_some());
+        self.sample.replace(SampleStorage::Loaned(Arc::new(LoanedPtr {
+            ptr: t,
+            _serdata: serdata,
+        })));
     }
 
     pub fn clear(&mut self) {
@@ -266,6 +647,31 @@ where
     }
 }
 
+impl<T> Sample<T> {
+    // Drop the value and the serdata so the sample can be reused
+    fn release(&mut self) {
+        self.sample = None;
+        self.serdata = None;
+    }
+}
+
+impl<T: BorrowedTopicType> Sample<T> {
+    /// A view of a received sample that borrows its strings and byte sequences
+    /// from the CDR of the sample. Received samples are decoded once, samples
+    /// of a local writer are serialized and decoded on the first call. `None`
+    /// for invalid samples.
+    pub fn borrowed(&self) -> Option<&T::Borrowed<'_>> {
+        let serdata: &SerData<T> = SerData::const_ref_from_serdata(self.serdata.as_ref()?.as_ptr());
+        if serdata.view.get().is_none() {
+            let cdr = serdata.cdr()?;
+            let (_, _, view) = unsafe { decode_borrowed::<T>(cdr, 0) }.ok()?;
+            let _ = serdata.view.set(view);
+        }
+        // the view borrows from the CDR of the serdata, which the sample holds on to
+        serdata.view.get().map(|view| unsafe { view.get::<T::Borrowed<'_>>() })
+    }
+}
+
 impl<T> Default for Sample<T> {
     fn default() -> Self {
         Self {
@@ -275,48 +681,278 @@ impl<T> Default for Sample<T> {
     }
 }
 
-impl<T> Drop for Sample<T> {
-    fn drop(&mut self) {
-        if let Some(serdata) = self.serdata {
-            unsafe {ddsi_serdata_removeref(serdata)};
+// Idle samples kept by a pool, more are freed
+const MAX_POOLED_SAMPLES: usize = 1024;
+
+/// Recycles the `Sample<T>` allocations of sample buffers. Each reader has a
+/// pool, see [`DdsReader::sample_buffer`](crate::DdsReader::sample_buffer).
+pub struct SamplePool<T> {
+    idle: Mutex<Vec<Box<Sample<T>>>>,
+}
+
+// idle samples hold neither a value nor a serdata
+unsafe impl<T: Send> Send for SamplePool<T> {}
+unsafe impl<T: Send + Sync> Sync for SamplePool<T> {}
+
+impl<T> Default for SamplePool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SamplePool<T> {
+    pub fn new() -> Self {
+        Self {
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The number of samples ready for reuse
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Allocate samples up front, so that the first reads into buffers of
+    /// up to `count` samples do not allocate
+    pub fn preallocate(&self, count: usize) {
+        let mut idle = self.idle.lock().unwrap();
+        let count = count.min(MAX_POOLED_SAMPLES);
+        while idle.len() < count {
+            idle.push(Box::default());
+        }
+    }
+
+    fn get(&self) -> *mut Sample<T> {
+        let sample = self.idle.lock().unwrap().pop().unwrap_or_default();
+        Box::into_raw(sample)
+    }
+
+    // The sample must have been allocated as a Box and is not used afterwards
+    unsafe fn put(&self, sample: *mut Sample<T>) {
+        let mut sample = Box::from_raw(sample);
+        sample.release();
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_POOLED_SAMPLES {
+            idle.push(sample);
+        }
+    }
+}
+
+// Idle Arcs kept by a sertype, more are freed
+const MAX_POOLED_ARCS: usize = 1024;
+
+/// Recycles the `Arc` allocations of received samples. An `Arc` is given back
+/// when its serdata is freed and nobody else holds the sample. The sample is
+/// dropped then, the next received sample of the sertype is moved into the
+/// empty allocation.
+struct ArcPool<T>(Mutex<Vec<Arc<MaybeUninit<T>>>>);
+
+impl<T> Default for ArcPool<T> {
+    fn default() -> Self {
+        Self(Mutex::new(Vec::new()))
+    }
+}
+
+impl<T> ArcPool<T> {
+    fn wrap(&self, value: T) -> Arc<T> {
+        let idle = self.0.lock().ok().and_then(|mut idle| idle.pop());
+        match idle {
+            Some(mut arc) => {
+                // pooled Arcs are not shared
+                if let Some(slot) = Arc::get_mut(&mut arc) {
+                    slot.write(value);
+                    // SAFETY: MaybeUninit<T> has the layout of T and the slot is initialized
+                    return unsafe { Arc::from_raw(Arc::into_raw(arc) as *const T) };
+                }
+                Arc::new(value)
+            }
+            None => Arc::new(value),
+        }
+    }
+
+    fn give_back(&self, mut arc: Arc<T>) {
+        if Arc::get_mut(&mut arc).is_none() {
+            return;
+        }
+        // SAFETY: MaybeUninit<T> has the layout of T. Nobody else holds the Arc,
+        // the value is dropped once and the slot is empty afterwards.
+        let mut empty = unsafe { Arc::from_raw(Arc::into_raw(arc) as *const MaybeUninit<T>) };
+        if let Some(slot) = Arc::get_mut(&mut empty) {
+            unsafe { slot.assume_init_drop() };
+        }
+        if let Ok(mut idle) = self.0.lock() {
+            if idle.len() < MAX_POOLED_ARCS {
+                idle.push(empty);
+            }
         }
     }
 }
 
+/// The metadata of a sample read into a [`SampleBuffer`], see
+/// [`SampleBuffer::infos`]
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct SampleInfo(dds_sample_info);
+
+impl SampleInfo {
+    /// Whether the sample carries data, otherwise only the instance state changed
+    pub fn valid_data(&self) -> bool {
+        self.0.valid_data
+    }
+
+    pub fn sample_state(&self) -> dds_sample_state_t {
+        self.0.sample_state
+    }
+
+    pub fn view_state(&self) -> dds_view_state_t {
+        self.0.view_state
+    }
+
+    pub fn instance_state(&self) -> dds_instance_state_t {
+        self.0.instance_state
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.0.instance_state == dds_instance_state_DDS_IST_ALIVE
+    }
+
+    /// The time the sample was written, `None` without a valid timestamp
+    pub fn source_timestamp(&self) -> Option<std::time::SystemTime> {
+        DdsTime::from_nanos(self.0.source_timestamp).to_system_time()
+    }
+
+    pub fn instance_handle(&self) -> dds_instance_handle_t {
+        self.0.instance_handle
+    }
+
+    /// The instance handle of the writer of the sample
+    pub fn publication_handle(&self) -> dds_instance_handle_t {
+        self.0.publication_handle
+    }
+
+    pub fn disposed_generation_count(&self) -> u32 {
+        self.0.disposed_generation_count
+    }
+
+    pub fn no_writers_generation_count(&self) -> u32 {
+        self.0.no_writers_generation_count
+    }
+
+    pub fn sample_rank(&self) -> u32 {
+        self.0.sample_rank
+    }
+
+    pub fn generation_rank(&self) -> u32 {
+        self.0.generation_rank
+    }
 
+    pub fn absolute_generation_rank(&self) -> u32 {
+        self.0.absolute_generation_rank
+    }
 
+    pub fn as_raw(&self) -> &dds_sample_info {
+        &self.0
+    }
+}
 
-///
-/// TODO: UNSAFE WARNING Review needed. Forcing SampleBuffer<T> to be Send
-/// DDS read API uses an array of void* pointers. The SampleBuffer<T> structure
-/// is used to create the sample array in the necessary format.
-/// We allocate the Sample<T> structure and set it to deallocated here.
-/// Cyclone does not allocate the sample, it only sets the value of the Arc<T>
-/// inside the Sample<T>::Value<Arc<T>>.
-/// So this structure always points to a valid sample memory, but the serdes callbacks
-/// can change the value of the sample under us.
-/// To be absolutely sure, I think we must put each sample into an RwLock<Arc<T>> instead of
-/// an Arc<T>, I guess this is the cost we pay for zero copy.
+impl std::fmt::Debug for SampleInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SampleInfo")
+            .field("valid_data", &self.valid_data())
+            .field("sample_state", &self.sample_state())
+            .field("view_state", &self.view_state())
+            .field("instance_state", &self.instance_state())
+            .field("instance_handle", &self.instance_handle())
+            .field("publication_handle", &self.publication_handle())
+            .finish()
+    }
+}
 
-unsafe impl<T> Send for SampleBuffer<T> {}
+/// The samples of a read or take. DDS read API uses an array of void*
+/// pointers, `buffer` points to the samples the buffer owns, either in `slots`
+/// or boxed by the pool. Cyclone only fills the samples through
+/// [`SampleBuffer::as_mut_ptr`], which borrows the buffer mutably, so the buffer
+/// is as Send and Sync as the samples it owns.
 pub struct SampleBuffer<T> {
-    /// This is !Send. This is the only way to punch through the Cyclone API as we need an array of pointers
+    /// This is the only way to punch through the Cyclone API as we need an array of pointers
     pub(crate) buffer: Vec<*mut Sample<T>>,
     pub(crate) sample_info: Vec<cyclonedds_sys::dds_sample_info>,
+    // where the samples come from and go back to, if pooled
+    pool: Option<Arc<SamplePool<T>>>,
+    // the samples of a buffer without a pool. Only the first `initialized`
+    // are valid, the rest are initialized when the buffer is first handed
+    // to cyclone.
+    slots: Vec<MaybeUninit<Sample<T>>>,
+    initialized: usize,
+    // stands in for the samples that are not initialized yet
+    empty: Sample<T>,
 }
 
+// the pointers are owned like a Vec<Box<Sample<T>>>
+unsafe impl<T> Send for SampleBuffer<T> where Sample<T>: Send {}
+unsafe impl<T> Sync for SampleBuffer<T> where Sample<T>: Sync {}
+
 impl<'a, T:TopicType> SampleBuffer<T> {
     pub fn new(len: usize) -> Self {
         let mut buf = Self {
             buffer: Vec::new(),
             sample_info: vec![cyclonedds_sys::dds_sample_info::default(); len],
+            pool: None,
+            slots: Vec::new(),
+            initialized: 0,
+            empty: Sample::default(),
         };
+        buf.grow_slots(len);
+        buf
+    }
+
+    /// A buffer with samples from `pool`. The samples go back to the pool when
+    /// the buffer is dropped.
+    pub fn from_pool(len: usize, pool: &Arc<SamplePool<T>>) -> Self {
+        Self {
+            buffer: (0..len).map(|_| pool.get()).collect(),
+            sample_info: vec![cyclonedds_sys::dds_sample_info::default(); len],
+            pool: Some(pool.clone()),
+            slots: Vec::new(),
+            initialized: 0,
+            empty: Sample::default(),
+        }
+    }
+
+    /// The pool the samples of the buffer come from, if any
+    pub(crate) fn pool(&self) -> Option<&Arc<SamplePool<T>>> {
+        self.pool.as_ref()
+    }
 
-        for _i in 0..len {
-            let p = Box::into_raw(Box::default());
-            buf.buffer.push(p);
+    /// Add room for `additional` samples, from the pool of the buffer if it has one
+    pub(crate) fn grow(&mut self, additional: usize) {
+        match &self.pool {
+            Some(pool) => {
+                for _i in 0..additional {
+                    self.buffer.push(pool.get());
+                }
+            }
+            None => self.grow_slots(additional),
+        }
+        self.sample_info
+            .resize(self.buffer.len(), cyclonedds_sys::dds_sample_info::default());
+    }
+
+    // The slots may move, the samples in them do not refer to themselves
+    fn grow_slots(&mut self, additional: usize) {
+        let len = self.slots.len() + additional;
+        self.slots.reserve_exact(additional);
+        // SAFETY: uninitialized MaybeUninit slots are valid
+        unsafe { self.slots.set_len(len) };
+        self.buffer = self.slots.iter_mut().map(|slot| slot.as_mut_ptr()).collect();
+    }
+
+    /// The number of samples that are actually allocated and initialized
+    pub(crate) fn initialized(&self) -> usize {
+        match self.pool {
+            Some(_) => self.buffer.len(),
+            None => self.initialized,
         }
-        buf
     }
 
     /// Check if sample is valid. Will panic if out of
@@ -325,12 +961,26 @@ impl<'a, T:TopicType> SampleBuffer<T> {
         self.sample_info[index].valid_data
     }
 
+    /// The sample infos of the last read or take, one for each sample of the
+    /// buffer. Only the first `n` are meaningful after reading `n` samples.
+    pub fn infos(&self) -> &[SampleInfo] {
+        let infos = self.sample_info.as_slice();
+        // SAFETY: SampleInfo is a transparent wrapper of dds_sample_info
+        unsafe { std::slice::from_raw_parts(infos.as_ptr() as *const SampleInfo, infos.len()) }
+    }
+
+    /// The time the sample was written, `None` for samples without a valid
+    /// timestamp. Will panic if out of bounds.
+    pub fn source_timestamp(&self, index: usize) -> Option<std::time::SystemTime> {
+        DdsTime::from_nanos(self.sample_info[index].source_timestamp).to_system_time()
+    }
+
     pub fn len(&self) -> usize {
         self.buffer.len()
     }
 
     pub fn iter(&'a self) -> impl Iterator<Item = &T> {
-        let p = self.buffer.iter().filter_map(|p| {
+        let p = self.buffer[..self.initialized()].iter().filter_map(|p| {
             let sample = unsafe { &*(*p) };
             sample.try_deref()
             
@@ -341,6 +991,9 @@ impl<'a, T:TopicType> SampleBuffer<T> {
     /// Get a sample
     pub fn get(&self, index: usize) -> &Sample<T> {
         let p_sample = self.buffer[index];
+        if index >= self.initialized() {
+            return &self.empty;
+        }
         unsafe { &*p_sample }
     }
 
@@ -348,15 +1001,36 @@ impl<'a, T:TopicType> SampleBuffer<T> {
     /// to be used in unsafe code that calls the CycloneDDS
     /// API
     pub unsafe fn as_mut_ptr(&mut self) -> (*mut *mut Sample<T>, *mut dds_sample_info) {
+        // cyclone may fill any sample
+        for slot in &mut self.slots[self.initialized..] {
+            slot.write(Sample::default());
+        }
+        self.initialized = self.slots.len();
         (self.buffer.as_mut_ptr(), self.sample_info.as_mut_ptr())
     }
 }
 
+impl<T: BorrowedTopicType> SampleBuffer<T> {
+    /// The borrowed views of the valid samples, see [`Sample::borrowed`]
+    pub fn iter_borrowed(&self) -> impl Iterator<Item = &T::Borrowed<'_>> {
+        self.buffer[..self.initialized()]
+            .iter()
+            .filter_map(|p| unsafe { &**p }.borrowed())
+    }
+}
+
 impl<'a, T> Drop for SampleBuffer<T> {
     fn drop(&mut self) {
-        for p in &self.buffer {
-            unsafe {
-                let _it = Box::from_raw(*p);
+        match &self.pool {
+            Some(pool) => {
+                for p in &self.buffer {
+                    unsafe { pool.put(*p) }
+                }
+            }
+            None => {
+                for slot in &mut self.slots[..self.initialized] {
+                    unsafe { slot.assume_init_drop() }
+                }
             }
         }
     }
@@ -370,15 +1044,21 @@ impl <'a,T>Index<usize> for SampleBuffer<T> {
 }
 */
 
-#[allow(dead_code)]
+// Loaned samples, see DdsReader::read_loan. Like the samples of the default
+// sertype of cyclone, a loan is a single block of samples and the first
+// sample pointer is the start of the block.
 unsafe extern "C" fn zero_samples<T>(
     _sertype: *const ddsi_sertype,
-    _ptr: *mut std::ffi::c_void,
-    _len: size_t,
+    ptr: *mut std::ffi::c_void,
+    len: size_t,
 ) {
-} // empty implementation
+    if !ptr.is_null() {
+        for sample in std::slice::from_raw_parts_mut(ptr as *mut Sample<T>, rust_len(len)) {
+            sample.release();
+        }
+    }
+}
 
-#[allow(dead_code)]
 extern "C" fn realloc_samples<T>(
     ptrs: *mut *mut std::ffi::c_void,
     _sertype: *const ddsi_sertype,
@@ -386,65 +1066,41 @@ extern "C" fn realloc_samples<T>(
     old_count: size_t,
     new_count: size_t,
 ) {
-    //println!("realloc");
-    let old = unsafe {
-        Vec::<*mut Sample<T>>::from_raw_parts(
-            old as *mut *mut Sample<T>,
-            old_count as usize,
-            old_count as usize,
-        )
-    };
-    let mut new = Vec::<*mut Sample<T>>::with_capacity(new_count as usize);
-
-    if new_count >= old_count {
-        for entry in old {
-            new.push(entry);
-        }
-
-        for _i in 0..(new_count - old_count) {
-            new.push(Box::into_raw(Box::default()));
-        }
+    let mut block = if old.is_null() {
+        Vec::new()
     } else {
-        for e in old.into_iter().take(new_count as usize) {
-            new.push(e)
+        unsafe {
+            Vec::<Sample<T>>::from_raw_parts(old as *mut Sample<T>, rust_len(old_count), rust_len(old_count))
         }
-    }
-
-    let leaked = new.leak();
+    };
+    block.resize_with(rust_len(new_count), Sample::default);
+    let block = block.into_boxed_slice();
+    let block = Box::leak(block);
 
-    let (raw, _length) = (leaked.as_ptr(), leaked.len());
-    // if the length and allocated length are not equal, we messed up above.
-    //assert_eq!(length, allocated_length);
-    unsafe {
-        *ptrs = raw as *mut std::ffi::c_void;
+    let ptrs = unsafe { std::slice::from_raw_parts_mut(ptrs as *mut *mut Sample<T>, rust_len(new_count)) };
+    for (ptr, sample) in ptrs.iter_mut().zip(block.iter_mut()) {
+        *ptr = sample;
     }
 }
 
-#[allow(dead_code)]
 extern "C" fn free_samples<T>(
     _sertype: *const ddsi_sertype,
     ptrs: *mut *mut std::ffi::c_void,
     len: size_t,
     op: dds_free_op_t,
-) where
-    T: TopicType,
-{
-    let ptrs_v: *mut *mut Sample<T> = ptrs as *mut *mut Sample<T>;
-
+) {
+    let block = unsafe { *(ptrs as *mut *mut Sample<T>) };
+    if block.is_null() || len == 0 {
+        return;
+    }
     if (op & DDS_FREE_ALL_BIT) != 0 {
-        let _samples =
-            unsafe { Vec::<Sample<T>>::from_raw_parts(*ptrs_v, len as usize, len as usize) };
         // all samples will get freed when samples goes out of scope
+        let _samples = unsafe { Vec::<Sample<T>>::from_raw_parts(block, rust_len(len), rust_len(len)) };
     } else {
         assert_ne!(op & DDS_FREE_CONTENTS_BIT, 0);
-        let mut samples =
-            unsafe { Vec::<Sample<T>>::from_raw_parts(*ptrs_v, len as usize, len as usize) };
-        for sample in samples.iter_mut() {
-            //let _old_sample = std::mem::take(sample);
-            sample.clear()
-            //_old_sample goes out of scope and the content is freed. The pointer is replaced with a default constructed sample
+        for sample in unsafe { std::slice::from_raw_parts_mut(block, rust_len(len)) } {
+            sample.release();
         }
-        let _intentional_leak = samples.leak();
     }
 }
 
@@ -452,9 +1108,7 @@ extern "C" fn free_samples<T>(
 unsafe extern "C" fn free_sertype<T>(sertype: *mut cyclonedds_sys::ddsi_sertype) {
     ddsi_sertype_fini(sertype);
 
-    let _sertype_ops = Box::<ddsi_sertype_ops>::from_raw((*sertype).ops as *mut ddsi_sertype_ops);
-    let _serdata_ops =
-        Box::<ddsi_serdata_ops>::from_raw((*sertype).serdata_ops as *mut ddsi_serdata_ops);
+    // the ops are shared by all sertypes of T, see shared_ops
     // this sertype is always constructed in Rust. During destruction,
     // the Box takes over the pointer and frees it when it goes out
     // of scope.
@@ -467,54 +1121,43 @@ unsafe extern "C" fn free_sertype<T>(sertype: *mut cyclonedds_sys::ddsi_sertype)
 unsafe extern "C" fn serdata_from_fragchain<T>(
     sertype: *const ddsi_sertype,
     kind: u32,
-    mut fragchain: *const nn_rdata,
+    fragchain: *const nn_rdata,
     size: size_t,
 ) -> *mut ddsi_serdata
 where
     T: DeserializeOwned + TopicType,
 {
     //println!("serdata_from_fragchain");
-    let mut off: u32 = 0;
-    let size = size as usize;
-    let fragchain_ref = &*fragchain;
+    let size = rust_len(size);
 
     let mut serdata = SerData::<T>::new(sertype, kind);
 
-    assert_eq!(fragchain_ref.min, 0);
-    assert!(fragchain_ref.maxp1 >= off);
-
     // The scatter gather list
-    let mut sg_list = Vec::new();
+    let sg_list = fragchain_slices(fragchain, size);
 
-    while !fragchain.is_null() {
-        let fragchain_ref = &*fragchain;
-        if fragchain_ref.maxp1 > off {
-            let payload =
-                nn_rmsg_payload_offset(fragchain_ref.rmsg, nn_rdata_payload_offset(fragchain));
-            let src = payload.add((off - fragchain_ref.min) as usize);
-            let n_bytes = fragchain_ref.maxp1 - off;
-            sg_list.push(std::slice::from_raw_parts(src, n_bytes as usize));
-            off = fragchain_ref.maxp1;
-            assert!(off as usize <= size);
-        }
-        fragchain = fragchain_ref.nextfrag;
+    if let Some(borrowed) = SerType::<T>::ref_from_sertype(sertype).and_then(|s| s.borrowed) {
+        return serdata_from_cdr(serdata, &sg_list, borrowed);
     }
+
     // make a reader out of the sg_list
     let reader = SGReader::new(&sg_list);
-    if let Ok(decoded) = cdr::deserialize_from::<_, T, _>(reader, Bounded(size as u64)) {
+    let decoded = cdr::deserialize_from::<_, T, _>(reader, Bounded(size as u64))
+        .map_err(|e| deserialize_failed::<T>(sertype, size, e));
+    if let Ok(decoded) = decoded {
         if T::has_key() {
             // compute the 16byte key hash
             let key_cdr = decoded.key_cdr();
             // skip the four byte header
             let key_cdr = &key_cdr[4..];
             compute_key_hash(key_cdr, &mut serdata);
+            serdata.serdata.hash = decoded.hash((*sertype).serdata_basehash);
+        } else {
+            serdata.serdata.hash = keyless_hash((*sertype).serdata_basehash);
         }
-        serdata.serdata.hash = decoded.hash((*sertype).serdata_basehash);
-        let sample = std::sync::Arc::new(decoded);
         //store the deserialized sample in the serdata. We don't need to deserialize again
-        serdata.sample = SampleData::SDKData(sample);
+        serdata.sample = SampleData::received(sertype, decoded);
+        serdata.received = true;
     } else {
-        println!("Deserialization error!");
         return std::ptr::null_mut();
     }
 
@@ -526,6 +1169,68 @@ where
     ptr as *mut ddsi_serdata
 }
 
+// Keep a received sample as CDR for borrowed reads. A sample that arrived in
+// one piece is copied once, a fragmented sample is gathered. The view decoded
+// for the hashes is kept for the reads.
+unsafe fn serdata_from_cdr<T: TopicType>(
+    mut serdata: Box<SerData<T>>,
+    sg_list: &[&[u8]],
+    borrowed: BorrowedDecode,
+) -> *mut ddsi_serdata {
+    let sertype = serdata.serdata.type_;
+    let size = sg_list.iter().map(|slice| slice.len()).sum();
+    let _ = serdata.cdr.set(sg_list.concat());
+    let cdr = serdata.cdr.get().map_or(&[][..], |cdr| cdr.as_slice());
+    match borrowed(cdr, (*sertype).serdata_basehash) {
+        Ok((key_hash, hash, view)) => {
+            serdata.key_hash = key_hash;
+            serdata.serdata.hash = hash;
+            serdata.sample = SampleData::SDKCdr;
+            let _ = serdata.view.set(view);
+            Box::into_raw(serdata) as *mut ddsi_serdata
+        }
+        Err(e) => {
+            deserialize_failed::<T>(sertype, size, e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// The payload of a fragchain as a list of slices
+pub(crate) unsafe fn fragchain_slices<'a>(mut fragchain: *const nn_rdata, size: usize) -> Vec<&'a [u8]> {
+    let mut off: u32 = 0;
+    let fragchain_ref = &*fragchain;
+
+    assert_eq!(fragchain_ref.min, 0);
+    assert!(fragchain_ref.maxp1 >= off);
+
+    let mut sg_list = Vec::new();
+
+    while !fragchain.is_null() {
+        let fragchain_ref = &*fragchain;
+        if fragchain_ref.maxp1 > off {
+            let payload =
+                nn_rmsg_payload_offset(fragchain_ref.rmsg, nn_rdata_payload_offset(fragchain));
+            let src = payload.add((off - fragchain_ref.min) as usize);
+            let n_bytes = fragchain_ref.maxp1 - off;
+            sg_list.push(std::slice::from_raw_parts(src, n_bytes as usize));
+            off = fragchain_ref.maxp1;
+            assert!(off as usize <= size);
+        }
+        fragchain = fragchain_ref.nextfrag;
+    }
+    sg_list
+}
+
+// The buffers of an iovec array as a list of slices
+pub(crate) unsafe fn iov_slices<'a>(iov: *const iovec, niov: usize) -> Vec<&'a [u8]> {
+    let iovs = std::slice::from_raw_parts(iov as *const cyclonedds_sys::iovec, niov);
+
+    iovs.iter()
+        .map(|iov| std::slice::from_raw_parts(iov.iov_base as *const u8, rust_len(iov.iov_len)))
+        .collect()
+}
+
 fn copy_raw_key_hash<T>(key: &[u8], serdata: &mut Box<SerData<T>>) {
     let mut raw_key = [0u8; 16];
     for (i, data) in key.iter().enumerate() {
@@ -538,22 +1243,39 @@ fn compute_key_hash<T>(key_cdr: &[u8], serdata: &mut SerData<T>)
 where
     T: TopicType,
 {
+    serdata.key_hash = cdr_key_hash::<T>(key_cdr)
+}
+
+// The keyhash follows the encapsulation header, as in serdata_from_keyhash
+fn cdr_key_hash<T: TopicType>(key_cdr: &[u8]) -> KeyHash {
     let mut cdr_key = [0u8; 20];
+    cdr_key[4..].copy_from_slice(&spec_keyhash(key_cdr, T::force_md5_keyhash()));
+    KeyHash::CdrKey(cdr_key)
+}
 
-    if T::force_md5_keyhash() || key_cdr.len() > 16 {
-        let mut md5st = ddsrt_md5_state_t::default();
-        let md5set = &mut md5st as *mut ddsrt_md5_state_s;
-        unsafe {
-            ddsrt_md5_init(md5set);
-            ddsrt_md5_append(md5set, key_cdr.as_ptr(), key_cdr.len() as u32);
-            ddsrt_md5_finish(md5set, cdr_key.as_mut_ptr());
-        }
+/// The keyhash of the DDSI-RTPS spec (9.6.3.8) of the big endian CDR of a key
+/// without the encapsulation header: the key padded with zeros if it can never
+/// be longer than 16 bytes, its md5 otherwise.
+pub(crate) fn spec_keyhash(key: &[u8], force_md5: bool) -> [u8; 16] {
+    if force_md5 || key.len() > 16 {
+        md5(key)
     } else {
-        for (i, data) in key_cdr.iter().enumerate() {
-            cdr_key[i] = *data;
-        }
+        let mut keyhash = [0u8; 16];
+        keyhash[..key.len()].copy_from_slice(key);
+        keyhash
+    }
+}
+
+pub(crate) fn md5(data: &[u8]) -> [u8; 16] {
+    let mut digest = [0u8; 16];
+    let mut md5st = ddsrt_md5_state_t::default();
+    let md5set = &mut md5st as *mut ddsrt_md5_state_s;
+    unsafe {
+        ddsrt_md5_init(md5set);
+        ddsrt_md5_append(md5set, data.as_ptr(), data.len() as u32);
+        ddsrt_md5_finish(md5set, digest.as_mut_ptr());
     }
-    serdata.key_hash = KeyHash::CdrKey(cdr_key)
+    digest
 }
 
 #[allow(dead_code)]
@@ -608,8 +1330,23 @@ where
         #[allow(non_upper_case_globals)]
         ddsi_serdata_kind_SDK_DATA => {
             let sample = sample.get().unwrap();
-            serdata.serdata.hash = sample.hash((*sertype).serdata_basehash);
+            let basehash = (*sertype).serdata_basehash;
+            match SerType::<T>::ref_from_sertype(sertype) {
+                Some(ser_type) if T::has_key() => match ser_type.keyhashes.get_or_insert(sample.deref(), basehash) {
+                    Ok((key_hash, hash)) => {
+                        serdata.key_hash = key_hash;
+                        serdata.serdata.hash = hash;
+                    }
+                    Err(violation) => {
+                        KEYHASH_VIOLATION.with(|last| *last.borrow_mut() = Some(violation));
+                        return std::ptr::null_mut();
+                    }
+                },
+                _ if !T::has_key() => serdata.serdata.hash = keyless_hash(basehash),
+                _ => serdata.serdata.hash = sample.hash(basehash),
+            }
             serdata.sample = SampleData::SDKData(sample);
+            serdata.scratch = WRITE_SCRATCH.with(|scratch| scratch.borrow().clone());
         }
         ddsi_serdata_kind_SDK_KEY => {
             panic!("Don't know how to create serdata from sample for SDK_KEY");
@@ -633,40 +1370,38 @@ unsafe extern "C" fn serdata_from_iov<T>(
 where
     T: DeserializeOwned + TopicType,
 {
-    let size = size as usize;
-    let niov = niov as usize;
+    let size = rust_len(size);
+    let niov = rust_len(niov);
     //println!("serdata_from_iov");
 
     let mut serdata = SerData::<T>::new(sertype, kind);
 
-    let iovs = std::slice::from_raw_parts(iov as *const cyclonedds_sys::iovec, niov);
-
-    let iov_slices: Vec<&[u8]> = iovs
-        .iter()
-        .map(|iov| {
-            let iov = iov;
+    let iov_slices = iov_slices(iov, niov);
 
-            std::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len as usize)
-        })
-        .collect();
+    if let Some(borrowed) = SerType::<T>::ref_from_sertype(sertype).and_then(|s| s.borrowed) {
+        return serdata_from_cdr(serdata, &iov_slices, borrowed);
+    }
 
     // make a reader out of the sg_list
     let reader = SGReader::new(&iov_slices);
 
-    if let Ok(decoded) = cdr::deserialize_from::<_, T, _>(reader, Bounded(size as u64)) {
+    let decoded = cdr::deserialize_from::<_, T, _>(reader, Bounded(size as u64))
+        .map_err(|e| deserialize_failed::<T>(sertype, size, e));
+    if let Ok(decoded) = decoded {
         if T::has_key() {
             // compute the 16byte key hash
             let key_cdr = decoded.key_cdr();
             // skip the four byte header
             let key_cdr = &key_cdr[4..];
             compute_key_hash(key_cdr, &mut serdata);
+            serdata.serdata.hash = decoded.hash((*sertype).serdata_basehash);
+        } else {
+            serdata.serdata.hash = keyless_hash((*sertype).serdata_basehash);
         }
-        serdata.serdata.hash = decoded.hash((*sertype).serdata_basehash);
-        let sample = std::sync::Arc::new(decoded);
         //store the deserialized sample in the serdata. We don't need to deserialize again
-        serdata.sample = SampleData::SDKData(sample);
+        serdata.sample = SampleData::received(sertype, decoded);
+        serdata.received = true;
     } else {
-        //println!("Deserialization error!");
         return std::ptr::null_mut();
     }
 
@@ -682,18 +1417,38 @@ unsafe extern "C" fn free_serdata<T>(serdata: *mut ddsi_serdata) {
     // the pointer is really a *mut SerData
     let ptr = serdata as *mut SerData<T>;
 
-    let serdata = &mut *ptr;
+    #[cfg(cyclone_shm)]
+    {
+        let serdata = &mut *ptr;
+        let iox_subscriber = sys_compat::iox_subscriber(&serdata.serdata) as *mut iox_sub_t;
+        if !iox_subscriber.is_null() {
+            //println!("Free iox chunk");
+            release_iox_chunk(iox_subscriber, sys_compat::iox_chunk_mut(&mut serdata.serdata));
+        }
+    }
 
-    if !serdata.serdata.iox_subscriber.is_null() {
-        let iox_subscriber: *mut iox_sub_t = serdata.serdata.iox_subscriber as *mut iox_sub_t;
-        let chunk = &mut serdata.serdata.iox_chunk;
-        let chunk = chunk as *mut *mut c_void;
-        //println!("Free iox chunk");
-        free_iox_chunk(iox_subscriber, chunk);
+    let mut data = Box::from_raw(ptr);
+    // the view borrows from the CDR
+    data.view.take();
+    if let (Some(scratch), Some(cdr)) = (data.scratch.take(), data.cdr.take()) {
+        scratch.give_back(cdr);
+    }
+    if let (SampleData::SDKData(sample), true) = (std::mem::take(&mut data.sample), data.received) {
+        if let Some(sertype) = SerType::<T>::ref_from_sertype(data.serdata.type_) {
+            sertype.arcs.give_back(sample);
+        }
     }
+    // data goes out of scope and frees the SerData. Nothing more to do here.
+}
 
-    let _data = Box::from_raw(ptr);
-    // _data goes out of scope and frees the SerData. Nothing more to do here.
+#[cfg(all(cyclone_shm, not(test)))]
+use free_iox_chunk as release_iox_chunk;
+
+// counts the chunks handed back, for the tests of the loans
+#[cfg(all(cyclone_shm, test))]
+unsafe fn release_iox_chunk(iox_subscriber: *mut iox_sub_t, chunk: *mut *mut c_void) {
+    free_iox_chunk(iox_subscriber, chunk);
+    test::RELEASED_CHUNKS.fetch_add(1, Ordering::SeqCst);
 }
 
 #[allow(dead_code)]
@@ -701,16 +1456,19 @@ unsafe extern "C" fn get_size<T>(serdata: *const ddsi_serdata) -> u32
 where
     T: Serialize + TopicType,
 {
-    let serdata = SerData::<T>::mut_ref_from_serdata(serdata);
+    let serdata = SerData::<T>::const_ref_from_serdata(serdata);
     let size = match &serdata.sample {
         SampleData::Uninitialized => 0,
         SampleData::SDKKey => serdata.key_hash.key_length() as u32,
         // This function asks for the serialized size so we do this even for SHM Data
-        SampleData::SDKData(sample) => {
-            serdata.serialized_size =
-                Some((cdr::calc_serialized_size::<T>(sample.deref())) as u32);
-            *serdata.serialized_size.as_ref().unwrap()
-        }
+        SampleData::SDKData(sample) => *serdata
+            .serialized_size
+            .get_or_init(|| cdr::calc_serialized_size::<T>(sample.deref()) as u32),
+        SampleData::Inline(sample) => *serdata
+            .serialized_size
+            .get_or_init(|| cdr::calc_serialized_size::<T>(sample) as u32),
+        SampleData::SDKCdr => serdata.cdr.get().map_or(0, |cdr| cdr.len() as u32),
+        #[cfg(cyclone_shm)]
         SampleData::SHMData(_sample) => {
             // we refuse to serialize SHM data so return 0
             0
@@ -724,64 +1482,74 @@ where
 }
 
 #[allow(dead_code)]
-unsafe extern "C" fn eqkey<T>(
+unsafe extern "C" fn eqkey<T: TopicType>(
     serdata_a: *const ddsi_serdata,
     serdata_b: *const ddsi_serdata,
 ) -> bool {
+    // all samples of a type without a key are of the same instance
+    if !T::has_key() {
+        return true;
+    }
     let a = SerData::<T>::mut_ref_from_serdata(serdata_a);
     let b = SerData::<T>::mut_ref_from_serdata(serdata_b);
     a.key_hash == b.key_hash
 }
 
+// The instance hash of the samples of a type without a key, the hash of the
+// empty key CDR that is only the encapsulation header
+fn keyless_hash(basehash: u32) -> u32 {
+    murmur3_32_slices(&[&[0u8; 4]], 0) ^ basehash
+}
+
 #[allow(dead_code)]
 unsafe extern "C" fn serdata_to_ser<T>(
     serdata: *const ddsi_serdata,
-    size: size_t,
     offset: size_t,
+    size: size_t,
     buf: *mut c_void,
 ) where
     T: Serialize + TopicType,
 {
     //println!("serdata_to_ser");
     let serdata = SerData::<T>::const_ref_from_serdata(serdata);
-    let buf = buf as *mut u8;
-    let buf = buf.add(offset as usize);
+    let (offset, size) = (rust_len(offset), rust_len(size));
 
     if size == 0 {
         return;
     }
+    let buf = std::slice::from_raw_parts_mut(buf as *mut u8, size);
 
-    match &serdata.sample {
+    let serialized: &[u8] = match &serdata.sample {
         SampleData::Uninitialized => {
             panic!("Attempt to serialize uninitialized serdata")
         }
         SampleData::SDKKey => match &serdata.key_hash {
-            KeyHash::None => {}
-            KeyHash::CdrKey(k) => std::ptr::copy_nonoverlapping(k.as_ptr(), buf, size as usize),
-            KeyHash::RawKey(k) => std::ptr::copy_nonoverlapping(k.as_ptr(), buf, size as usize),
+            KeyHash::None => &[],
+            KeyHash::CdrKey(k) => &k[..],
+            KeyHash::RawKey(k) => &k[..],
         },
-        // We may serialize both SDK data as well as SHM Data
-        SampleData::SDKData(serdata) => {
-            let buf_slice = std::slice::from_raw_parts_mut(buf, size as usize);
-            if let Err(e) = cdr::serialize_into::<_, T, _, CdrBe>(
-                buf_slice,
-                serdata.deref(),
-                Bounded(size),
-            ) {
-                panic!("Unable to serialize type {:?} due to {}", T::typename(), e);
+        // We may serialize both SDK data as well as SHM Data. Unless the sample
+        // was serialized before, it is serialized straight into the buffer.
+        _ => {
+            if let (0, None, Some(sample)) = (offset, serdata.cdr.get(), serdata.value()) {
+                let mut rest = &mut buf[..];
+                if cdr::serialize_into::<_, T, _, CdrBe>(&mut rest, sample, Infinite).is_ok() {
+                    // cdds rounds the size up to a multiple of four
+                    rest.fill(0);
+                    return;
+                }
             }
-        }
-        SampleData::SHMData(serdata) => {
-            let buf_slice = std::slice::from_raw_parts_mut(buf, size as usize);
-            if let Err(e) = cdr::serialize_into::<_, T, _, CdrBe>(
-                buf_slice,
-                serdata.as_ref(),
-                Bounded(size),
-            ) {
-                panic!("Unable to serialize type {:?} due to {}", T::typename(), e);
+            match serdata.cdr() {
+                Some(cdr) => cdr,
+                None => panic!("Unable to serialize type {:?}", cached_typename::<T>()),
             }
         }
-    }
+    };
+
+    let end = (offset + size).min(serialized.len());
+    let src = &serialized[offset.min(end)..end];
+    buf[..src.len()].copy_from_slice(src);
+    buf[src.len()..].fill(0);
 }
 
 #[allow(dead_code)]
@@ -808,41 +1576,21 @@ where
             };
 
             iov.iov_base = p as *mut c_void;
-            iov.iov_len = len as size_t;
-        }
-        SampleData::SDKData(sample) => {
-            if serdata.cdr.is_none() {
-                serdata.cdr = serialize_type::<T>(sample, serdata.serialized_size).ok();
-            }
-            if let Some(cdr) = &serdata.cdr {
-                let offset = offset as usize;
-                let mut last = offset + size as usize;
-                if last > cdr.len() - 1 {
-                    last = cdr.len() - 1;
-                }
-                let cdr = &cdr[offset..last];
-                // cdds rounds up the length into multiple of 4. We mirror that by allocating extra in the
-                // ``serialize_type`` function.
-                iov.iov_base = cdr.as_ptr() as *mut c_void;
-                iov.iov_len = size; //cdr.len() as size_t;
-            } else {
-                println!("Serialization error!");
-                return std::ptr::null_mut();
-            }
+            iov.iov_len = c_len(len);
         }
-
-        SampleData::SHMData(sample) => {
-            if serdata.cdr.is_none() {
-                serdata.cdr = serialize_type::<T>(sample.as_ref(), serdata.serialized_size).ok();
-            }
-            if let Some(cdr) = &serdata.cdr {
-                let offset = offset as usize;
-                let last = offset + size as usize;
-                let cdr = &cdr[offset..last];
+        // every reference is to the same serialization of the sample
+        _ => {
+            if let Some(cdr) = serdata.cdr() {
+                // the serialization is padded to the multiple of four cdds asks for
+                let end = (rust_len(offset) + rust_len(size)).min(cdr.len());
+                let cdr = &cdr[rust_len(offset).min(end)..end];
                 iov.iov_base = cdr.as_ptr() as *mut c_void;
-                iov.iov_len = cdr.len() as size_t;
+                iov.iov_len = c_len(cdr.len());
             } else {
-                println!("Serialization error (SHM)!");
+                crate::dds_log::warn_failed(
+                    format_args!("cannot serialize {}", cached_typename::<T>().to_string_lossy()),
+                    &"no serialized sample",
+                );
                 return std::ptr::null_mut();
             }
         }
@@ -851,20 +1599,61 @@ where
 }
 
 fn serialize_type<T: Serialize>(sample: &T, maybe_size: Option<u32>) -> Result<Vec<u8>, ()> {
-    if let Some(size) = maybe_size {
-        // Round up allocation to multiple of four
-        let size = (size + 3) & !3u32;
-        let mut buffer = Vec::<u8>::with_capacity(size as usize);
-        if let Ok(()) = cdr::serialize_into::<_, T, _, CdrBe>(&mut buffer, sample, Infinite) {
-            Ok(buffer)
-        } else {
-            Err(())
+    let mut buffer = Vec::new();
+    serialize_type_into(sample, maybe_size, &mut buffer)?;
+    Ok(buffer)
+}
+
+// Serialize into `buffer`, replacing its contents but keeping its allocation
+fn serialize_type_into<T: Serialize>(sample: &T, maybe_size: Option<u32>, buffer: &mut Vec<u8>) -> Result<(), ()> {
+    let size = maybe_size.map_or_else(|| cdr::calc_serialized_size(sample) as usize, |size| size as usize);
+    // Round up to multiple of four, cdds asks for the padded length
+    let padded = (size + 3) & !3;
+    buffer.clear();
+    buffer.reserve_exact(padded);
+    cdr::serialize_into::<_, T, _, CdrBe>(&mut *buffer, sample, Infinite).map_err(|_| ())?;
+    let padded = (buffer.len() + 3) & !3;
+    buffer.resize(padded, 0);
+    Ok(())
+}
+
+/// The serialization buffer of a writer. A sample written by the writer is
+/// serialized into the buffer and the buffer is handed back when cyclone frees
+/// the sample, so a writer that is not holding on to samples serializes
+/// without allocating.
+#[derive(Default)]
+pub(crate) struct WriteScratch(Mutex<Vec<u8>>);
+
+impl WriteScratch {
+    // An empty buffer if the buffer is still in use by another sample
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    fn give_back(&self, buffer: Vec<u8>) {
+        let mut scratch = self.0.lock().unwrap();
+        if buffer.capacity() > scratch.capacity() {
+            *scratch = buffer;
         }
-    } else if let Ok(data) = cdr::serialize::<T, _, CdrBe>(sample, Infinite) {
-        Ok(data)
-    } else {
-        Err(())
     }
+
+    #[cfg(test)]
+    pub(crate) fn capacity(&self) -> usize {
+        self.0.lock().unwrap().capacity()
+    }
+}
+
+thread_local! {
+    // the scratch buffer of the writer that is writing on this thread
+    static WRITE_SCRATCH: std::cell::RefCell<Option<Arc<WriteScratch>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Run `f` with the samples created on this thread serialized into `scratch`
+pub(crate) fn with_write_scratch<R>(scratch: &Arc<WriteScratch>, f: impl FnOnce() -> R) -> R {
+    let previous = WRITE_SCRATCH.with(|current| current.replace(Some(scratch.clone())));
+    let result = f();
+    WRITE_SCRATCH.with(|current| *current.borrow_mut() = previous);
+    result
 }
 
 #[allow(dead_code)]
@@ -874,11 +1663,41 @@ unsafe extern "C" fn serdata_to_ser_unref<T>(serdata: *mut ddsi_serdata, _iov: *
     ddsi_serdata_removeref(&mut serdata.serdata)
 }
 
-fn deserialize_type<T>(data:&[u8]) -> Result<Arc<T>,()> 
+fn deserialize_type<T>(data:&[u8]) -> Result<Arc<T>,cdr::Error> 
     where
     T: DeserializeOwned {
-        cdr::deserialize::<Box<T>>(data).map(Arc::from).map_err(|_e|())
+        cdr::deserialize::<Box<T>>(data).map(Arc::from)
+    }
+
+// Report a sample that could not be deserialized. Cyclone only sees a null serdata,
+// so the failure is queued on the sertype for the readers to collect.
+fn deserialize_failed<T: TopicType>(
+    sertype: *const ddsi_sertype,
+    size: usize,
+    e: cdr::Error,
+) -> cdr::Error {
+    count(Counter::DeserializeFailures);
+    let error = SerdesError::new(cached_typename::<T>().to_string_lossy().into_owned(), size, e.to_string());
+    crate::dds_log::warn_failed(format_args!("deserialization failed"), &error);
+    queue_serdes_error::<T>(sertype, error);
+    e
+}
+
+// Report a loaned sample that does not fit T, it is dropped like a sample
+// that cannot be deserialized.
+#[cfg(cyclone_shm)]
+fn loan_layout_failed<T: TopicType>(sertype: *const ddsi_sertype, size: usize, e: LayoutMismatch) {
+    count(Counter::DeserializeFailures);
+    let error = SerdesError::new(cached_typename::<T>().to_string_lossy().into_owned(), size, e.to_string());
+    crate::dds_log::warn_failed(format_args!("loaned sample rejected"), &error);
+    queue_serdes_error::<T>(sertype, error);
+}
+
+fn queue_serdes_error<T: TopicType>(sertype: *const ddsi_sertype, error: SerdesError) {
+    if let Some(ser_type) = unsafe { SerType::<T>::ref_from_sertype(sertype) } {
+        ser_type.serdes_errors.push(error);
     }
+}
 
 #[allow(dead_code)]
 unsafe extern "C" fn serdata_to_sample<T>(
@@ -894,25 +1713,72 @@ where
     //    "serdata to sample serdata:{:?} sample:{:?} bufptr:{:?} buflim:{:?}",
     //    serdata, sample, _bufptr, _buflim
     //);
-    let mut serdata = SerData::<T>::mut_ref_from_serdata(serdata_ptr);
-    let mut s = Box::<Sample<T>>::from_raw(sample as *mut Sample<T>);
-    assert!(!sample.is_null());
+    if sample.is_null() {
+        return false;
+    }
+    let serdata = SerData::<T>::mut_ref_from_serdata(serdata_ptr);
+    // the sample belongs to the caller and is not necessarily a Box, e.g. a
+    // Sample<T> on the stack
+    let s = &mut *(sample as *mut Sample<T>);
+
+    #[cfg(cyclone_shm)]
+    let ret = sample_from_iox_chunk(serdata, s);
+    #[cfg(not(cyclone_shm))]
+    let ret: Result<(), ()> = Ok(());
+
+    if let Ok(()) = ret {
+        match &serdata.sample {
+            // a reused sample must not show the data of its previous read
+            SampleData::Uninitialized | SampleData::SDKKey => {
+                s.release();
+                true
+            }
+            SampleData::SDKData(_) | SampleData::Inline(_) => {
+                s.set_serdata(serdata_ptr as *mut ddsi_serdata);
+                //s.set(data.clone());
+                false
+            }
+            #[cfg(cyclone_shm)]
+            SampleData::SHMData(data) => {
+                let data = *data;
+                s.set_serdata(serdata_ptr as *mut ddsi_serdata);
+                s.set_received_loan(data);
+                false
+            }
+            SampleData::SDKCdr => {
+                s.set_serdata(serdata_ptr as *mut ddsi_serdata);
+                false
+            }
+        }
+    } else {
+        true
+    }
+}
 
-    //#[cfg(shm)]
-    let ret = if !serdata.serdata.iox_chunk.is_null() {
+// Take the data of a sample received through iceoryx. Serialized data is
+// deserialized now, otherwise the sample refers to the chunk.
+#[cfg(cyclone_shm)]
+unsafe fn sample_from_iox_chunk<T>(serdata: &mut SerData<T>, s: &mut Sample<T>) -> Result<(), ()>
+where
+    T: DeserializeOwned + TopicType,
+{
+    let chunk = sys_compat::iox_chunk(&serdata.serdata);
+    if !chunk.is_null() {
         // We got data from Iceoryx, deal with it
-        let hdr = iceoryx_header_from_chunk(serdata.serdata.iox_chunk);
+        let hdr = iceoryx_header_from_chunk(chunk);
         if (*hdr).shm_data_state == iox_shm_data_state_t_IOX_CHUNK_CONTAINS_SERIALIZED_DATA {
             // we have to deserialize the data now
             let reader = std::slice::from_raw_parts(
-                serdata.serdata.iox_chunk as *const u8,
+                chunk as *const u8,
                 (*hdr).data_size as usize,
             );
             if serdata.serdata.kind == ddsi_serdata_kind_SDK_KEY {
                 compute_key_hash(reader, serdata);
                 serdata.sample = SampleData::SDKKey;
                 Ok(())
-            } else if let Ok(decoded) = deserialize_type::<T>(reader) {
+            } else if let Ok(decoded) = deserialize_type::<T>(reader)
+                .map_err(|e| deserialize_failed::<T>(serdata.serdata.type_, reader.len(), e))
+            {
                 if T::has_key() {
                     // compute the 16byte key hash
                     let key_cdr = decoded.key_cdr();
@@ -927,49 +1793,26 @@ where
 
                 Ok(())
             } else {
-                println!("Deserialization error!");
                 Err(())
             }
         } else {
-            // Not serialized data, we make a sample out of the data and store it in our sample
-            assert_eq!((*hdr).data_size as usize, std::mem::size_of::<T>());
-            if std::mem::size_of::<T>() == (*hdr).data_size as usize {
-                // Pay Attention here
-                //
-                //
-                let p: *mut T = serdata.serdata.iox_chunk as *mut T;
-                serdata.sample = SampleData::SHMData(NonNull::new_unchecked(p));
-                Ok(())
-            } else {
-                Err(())
+            // Not serialized data, the sample refers to the chunk
+            let size = (*hdr).data_size as usize;
+            match check_loan_layout::<T>(size, chunk) {
+                Ok(()) => {
+                    let p: *mut T = chunk as *mut T;
+                    serdata.sample = SampleData::SHMData(NonNull::new_unchecked(p));
+                    Ok(())
+                }
+                Err(e) => {
+                    loan_layout_failed::<T>(serdata.serdata.type_, size, e);
+                    Err(())
+                }
             }
         }
     } else {
         Ok(())
-    };
-
-    let ret = if let Ok(()) = ret {
-        match &serdata.sample {
-            SampleData::Uninitialized => true,
-            SampleData::SDKKey => true,
-            SampleData::SDKData(_data) => {
-                s.set_serdata(serdata_ptr as *mut ddsi_serdata);
-                //s.set(data.clone());
-                false
-            }
-            SampleData::SHMData(_data) => {
-                s.set_serdata(serdata_ptr as *mut ddsi_serdata);
-                //s.set_loaned(data.clone());
-                false
-            }
-        }
-    } else {
-        true
-    };
-
-    // leak the sample intentionally so it doesn't get deallocated here
-    let _intentional_leak = Box::into_raw(s);
-    ret
+    }
 }
 
 #[allow(dead_code)]
@@ -1009,12 +1852,10 @@ where
 {
     //println!("untyped to sample!");
     if !sample.is_null() {
-        let mut sample = Box::<Sample<T>>::from_raw(sample as *mut Sample<T>);
+        let sample = &mut *(sample as *mut Sample<T>);
         // hmm. We don't store serialized data in serdata. I'm not really sure how
         // to implement this. For now, invalidate the sample.
         sample.clear();
-        // leak this as we don't want to deallocate it.
-        let _leaked = Box::<Sample<T>>::into_raw(sample);
         true
     } else {
         false
@@ -1022,13 +1863,17 @@ where
 }
 
 #[allow(dead_code)]
-unsafe extern "C" fn get_keyhash<T>(
+unsafe extern "C" fn get_keyhash<T: TopicType>(
     serdata: *const ddsi_serdata,
     keyhash: *mut ddsi_keyhash,
     _force_md5: bool,
 ) {
-    let serdata = SerData::<T>::mut_ref_from_serdata(serdata);
     let keyhash = &mut *keyhash;
+    if !T::has_key() {
+        keyhash.value = [0; 16];
+        return;
+    }
+    let serdata = SerData::<T>::mut_ref_from_serdata(serdata);
 
     let src = match &serdata.key_hash {
         KeyHash::None => &[],
@@ -1052,11 +1897,110 @@ unsafe extern "C" fn print<T>(
     0
 }
 
+// The default topic name of a type with the given std::any::type_name: the
+// path of the type without the crate, with '/' instead of '::'
+fn path_topic_name(type_name: &str) -> String {
+    let mut name = String::with_capacity(type_name.len());
+    for part in type_name.split("::").skip(1) {
+        name.push('/');
+        name.push_str(part);
+    }
+    name
+}
+
+// type names and sertype ops by TypeId, created once per type. The name of a
+// type from std::any::type_name is not unique, e.g. for types of two versions
+// of a crate. Cyclone only reuses a registered sertype for a new topic if the
+// ops of both sertypes are the same, so they are shared by every sertype of a
+// type. The ops are stored as addresses as they contain raw pointers.
+static TYPENAMES: Mutex<Option<HashMap<TypeId, &'static CStr>>> = Mutex::new(None);
+static SERTYPE_OPS: Mutex<Option<HashMap<TypeId, (usize, usize)>>> = Mutex::new(None);
+
+/// How the default [`TopicType::typename`] reports the type name of a topic
+/// type during discovery. Remote participants only match topics of the same
+/// type name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypeNameMode {
+    /// The path of the type without the crate, e.g. `idl::HelloWorldData::Msg`
+    /// for `app::idl::HelloWorldData::Msg`. This is the default.
+    RustPath,
+    /// The IDL form for types generated from IDL into the module `root`,
+    /// given as a full path including the crate. `app::idl` reports
+    /// `app::idl::HelloWorldData::Msg` as `HelloWorldData::Msg`, which is the
+    /// name used by C, C++ and Python participants built from the same IDL.
+    /// Types outside of `root` keep the `RustPath` form.
+    Idl { root: String },
+}
+
+impl TypeNameMode {
+    fn type_name(&self, rust_name: &str) -> String {
+        if let TypeNameMode::Idl { root } = self {
+            let root = root.trim_end_matches("::");
+            if let Some(name) = rust_name.strip_prefix(root).and_then(|name| name.strip_prefix("::")) {
+                return name.to_owned();
+            }
+        }
+        rust_name.split("::").skip(1).collect::<Vec<_>>().join("::")
+    }
+}
+
+static TYPE_NAME_MODE: Mutex<TypeNameMode> = Mutex::new(TypeNameMode::RustPath);
+
+/// Select how type names are reported, see [`TypeNameMode`]. The type name of
+/// a type is fixed once a topic of any type was created, the mode must be set
+/// before that or `DDSError::PreconditionNotMet` is returned. Individual
+/// topics can use another type name with
+/// [`TopicBuilder::with_type_name`](crate::TopicBuilder::with_type_name).
+pub fn set_type_name_mode(mode: TypeNameMode) -> Result<(), DDSError> {
+    let typenames = TYPENAMES.lock().unwrap();
+    if typenames.as_ref().map_or(false, |names| !names.is_empty()) {
+        return Err(DDSError::PreconditionNotMet);
+    }
+    *TYPE_NAME_MODE.lock().unwrap() = mode;
+    Ok(())
+}
+
+/// The type name of T, built once and kept for the life of the process
+pub(crate) fn cached_typename<T: TopicType>() -> &'static CStr {
+    let mut typenames = TYPENAMES.lock().unwrap();
+    *typenames
+        .get_or_insert_with(HashMap::new)
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| Box::leak(T::typename().into_boxed_c_str()))
+}
+
+fn shared_ops<T>() -> (*mut ddsi_sertype_ops, *mut ddsi_serdata_ops)
+where
+    T: DeserializeOwned + TopicType + Serialize,
+{
+    let mut ops = SERTYPE_OPS.lock().unwrap();
+    let (sertype_ops, serdata_ops) = *ops
+        .get_or_insert_with(HashMap::new)
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| {
+            (
+                Box::into_raw(create_sertype_ops::<T>()) as usize,
+                Box::into_raw(create_serdata_ops::<T>()) as usize,
+            )
+        });
+    (sertype_ops as *mut ddsi_sertype_ops, serdata_ops as *mut ddsi_serdata_ops)
+}
+
+/// true if `sertype` was created for T, the samples of its readers are a T
+pub(crate) fn is_sertype_of<T>(sertype: *const ddsi_sertype) -> bool
+where
+    T: DeserializeOwned + TopicType + Serialize,
+{
+    // the ops are shared by the sertypes of a type and nothing else. They are
+    // keyed by the TypeId, so types with the same type name are told apart.
+    !sertype.is_null() && unsafe { (*sertype).ops } == shared_ops::<T>().0 as *const ddsi_sertype_ops
+}
+
 fn create_sertype_ops<T>() -> Box<ddsi_sertype_ops>
 where
     T: TopicType,
 {
-    Box::new(ddsi_sertype_ops {
+    let mut ops = Box::new(ddsi_sertype_ops {
         version: Some(ddsi_sertype_v0),
         arg: std::ptr::null_mut(),
         free: Some(free_sertype::<T>),
@@ -1066,17 +2010,35 @@ where
         equal: Some(equal::<T>),
         hash: Some(hash::<T>),
         ..Default::default()
-    })
+    });
+    sys_compat::set_type_discovery_ops::<T>(&mut ops);
+    ops
+}
+
+/// Check that a loaned chunk of `chunk_size` bytes at `chunk` can be used as a
+/// `T` in place. A mismatch would corrupt the sample, the writer and the reader
+/// may have been built from different definitions of the type.
+#[cfg_attr(not(cyclone_shm), allow(dead_code))]
+pub(crate) fn check_loan_layout<T>(chunk_size: usize, chunk: *const c_void) -> Result<(), LayoutMismatch> {
+    let size = std::mem::size_of::<T>();
+    let align = std::mem::align_of::<T>();
+    let problem = if size != chunk_size {
+        LayoutProblem::Size { size, chunk_size }
+    } else if chunk as usize % align != 0 {
+        LayoutProblem::Align { address: chunk as usize, align }
+    } else {
+        return Ok(());
+    };
+    Err(LayoutMismatch::new(std::any::type_name::<T>(), problem))
 }
 
-#[cfg(feature = "shm")]
+#[cfg(cyclone_shm)]
 #[allow(dead_code)]
 unsafe extern "C" fn get_sample_size(serdata: *const ddsi_serdata) -> u32 {
-    let serdata = *serdata;
-    (*serdata.type_).iox_size
+    sys_compat::iox_size(&*(*serdata).type_)
 }
 
-#[cfg(feature = "shm")]
+#[cfg(cyclone_shm)]
 #[allow(dead_code)]
 unsafe extern "C" fn from_iox_buffer<T>(
     sertype: *const ddsi_sertype,
@@ -1084,23 +2046,42 @@ unsafe extern "C" fn from_iox_buffer<T>(
     /*_deserialize_hint : bool,*/
     sub: *mut ::std::os::raw::c_void,
     buffer: *mut ::std::os::raw::c_void,
-) -> *mut ddsi_serdata {
+) -> *mut ddsi_serdata
+where
+    T: TopicType,
+{
     //println!("from_iox_buffer");
 
     if sertype.is_null() {
         return std::ptr::null::<ddsi_serdata>() as *mut ddsi_serdata;
     }
 
+    // a loan of the writer has the size of the sertype, a received chunk
+    // records its size unless it holds serialized data
+    let chunk_size = if sub.is_null() {
+        Some(sys_compat::iox_size(&*sertype) as usize)
+    } else {
+        let hdr = iceoryx_header_from_chunk(buffer);
+        if (*hdr).shm_data_state == iox_shm_data_state_t_IOX_CHUNK_CONTAINS_SERIALIZED_DATA {
+            None
+        } else {
+            Some((*hdr).data_size as usize)
+        }
+    };
+    if let Some(size) = chunk_size {
+        if let Err(e) = check_loan_layout::<T>(size, buffer) {
+            loan_layout_failed::<T>(sertype, size, e);
+            return std::ptr::null_mut();
+        }
+    }
+
     let mut d = SerData::<T>::new(sertype, kind);
 
     // from loaned sample, just take the pointer
-    if sub.is_null() {
-        d.serdata.iox_chunk = buffer;
-    } else {
+    sys_compat::set_iox_chunk(&mut d.serdata, buffer, sub);
+    if !sub.is_null() {
         //println!("from_iox_buffer: take pointer {:?}from iox", buffer);
         // from iox buffer
-        d.serdata.iox_chunk = buffer;
-        d.serdata.iox_subscriber = sub;
         let hdr = iceoryx_header_from_chunk(buffer);
         // Copy the key hash (TODO: Check this)
         copy_raw_key_hash(&(*hdr).keyhash.value, &mut d);
@@ -1134,9 +2115,9 @@ where
         free: Some(free_serdata::<T>),
         print: Some(print::<T>),
         get_keyhash: Some(get_keyhash::<T>),
-        #[cfg(feature = "shm")]
+        #[cfg(cyclone_shm)]
         get_sample_size: Some(get_sample_size),
-        #[cfg(feature = "shm")]
+        #[cfg(cyclone_shm)]
         from_iox_buffer: Some(from_iox_buffer::<T>),
         ..Default::default()
     })
@@ -1159,29 +2140,23 @@ where
 //  * works nice and quickly.
 //  */
 
-unsafe extern "C" fn hash<T: TopicType>(tp: *const ddsi_sertype) -> u32  
-{
-    if let Some(ser_type) = SerType::<T>::try_from_sertype(tp) {
-        let type_name =  CStr::from_ptr(ser_type.sertype.type_name);
-        let type_name_bytes = type_name.to_bytes();
-        let type_size = core::mem::size_of::<T>().to_ne_bytes();
-        let sg_list = [type_name_bytes,&type_size];
-        let mut sg_buffer = SGReader::new(&sg_list);
-
-        let hash = murmur3_32(&mut sg_buffer, 0);
-        
-        let _intentional_leak = SerType::<T>::into_sertype(ser_type);
-        hash.unwrap_or(0)
+unsafe extern "C" fn hash<T: TopicType>(tp: *const ddsi_sertype) -> u32 {
+    SerType::<T>::ref_from_sertype(tp).map_or(0, |ser_type| ser_type.hash)
+}
 
-    } else {
-        0
-    }
+// The hash of the type name and the size of T, computed once per sertype
+fn sertype_hash<T: TopicType>(type_name: &CStr) -> u32 {
+    let type_name_bytes = type_name.to_bytes();
+    let type_size = core::mem::size_of::<T>().to_ne_bytes();
+    murmur3_32_slices(&[type_name_bytes, &type_size], 0)
 }
 
 unsafe extern "C" fn equal<T>(acmn: *const ddsi_sertype, bcmn: *const ddsi_sertype) -> bool {
+    let borrowed = |sertype: *const ddsi_sertype| SerType::<T>::ref_from_sertype(sertype).map(|s| s.borrowed.is_some());
+    let same_mode = borrowed(acmn) == borrowed(bcmn);
     let acmn = CStr::from_ptr((*acmn).type_name as *mut std::os::raw::c_char);
     let bcmn = CStr::from_ptr((*bcmn).type_name as *mut std::os::raw::c_char);
-    acmn == bcmn
+    acmn == bcmn && same_mode
 }
 
 #[derive(Clone)]
@@ -1189,7 +2164,13 @@ enum SampleData<T> {
     Uninitialized,
     SDKKey,
     SDKData(std::sync::Arc<T>),
+    // received sample of a small type, see TopicType::store_inline
+    Inline(T),
+    // sample in an iceoryx chunk
+    #[cfg(cyclone_shm)]
     SHMData(NonNull<T>),
+    // received sample kept as CDR, see BorrowedTopicType
+    SDKCdr,
 }
 
 impl<T> Default for SampleData<T> {
@@ -1198,6 +2179,21 @@ impl<T> Default for SampleData<T> {
     }
 }
 
+impl<T: TopicType> SampleData<T> {
+    // A received sample, without an allocation if the type is small. Larger
+    // samples reuse an Arc of the sertype.
+    unsafe fn received(sertype: *const ddsi_sertype, sample: T) -> Self {
+        if T::store_inline() {
+            Self::Inline(sample)
+        } else {
+            match SerType::<T>::ref_from_sertype(sertype) {
+                Some(sertype) => Self::SDKData(sertype.arcs.wrap(sample)),
+                None => Self::SDKData(Arc::new(sample)),
+            }
+        }
+    }
+}
+
 
 #[derive(PartialEq, Clone)]
 enum KeyHash {
@@ -1234,15 +2230,23 @@ impl KeyHash {
 pub (crate)struct SerData<T> {
     serdata: ddsi_serdata,
     sample: SampleData<T>,
-    //data in CDR format. This is put into an option as we only create
-    //the serialized version when we need it
-    cdr: Option<Vec<u8>>,
+    // the borrowed view of cdr, declared first so it is dropped first
+    view: OnceLock<BorrowedView>,
+    //data in CDR format. Set once, as we only create the serialized
+    //version when we need it
+    cdr: OnceLock<Vec<u8>>,
     //key_hash: ddsi_keyhash,
     // include 4 bytes of CDR encapsulation header
     //key_hash: [u8; 20],
     key_hash: KeyHash,
-    // We store the serialized size here if available
-    serialized_size: Option<u32>,
+    // We store the serialized size here if available. Set through a shared
+    // serdata, like cdr.
+    serialized_size: OnceLock<u32>,
+    // the buffer of the writer that wrote the sample, see WriteScratch
+    scratch: Option<Arc<WriteScratch>>,
+    // the sample was received, its Arc is recycled by the ArcPool of the
+    // sertype. The Arcs of written samples belong to the application.
+    received: bool,
 }
 
 impl<'a, T> SerData<T> {
@@ -1256,9 +2260,12 @@ impl<'a, T> SerData<T> {
                 }
             },
             sample: SampleData::default(),
-            cdr: None,
+            view: OnceLock::new(),
+            cdr: OnceLock::new(),
             key_hash: KeyHash::default(),
-            serialized_size: None,
+            serialized_size: OnceLock::new(),
+            scratch: None,
+            received: false,
         })
     }
 
@@ -1273,19 +2280,50 @@ impl<'a, T> SerData<T> {
     }
 }
 
-impl <T>Clone for SerData<T> {
+impl<T: Serialize> SerData<T> {
+    fn value(&self) -> Option<&T> {
+        match &self.sample {
+            SampleData::SDKData(sample) => Some(sample.deref()),
+            SampleData::Inline(sample) => Some(sample),
+            #[cfg(cyclone_shm)]
+            SampleData::SHMData(sample) => Some(unsafe { sample.as_ref() }),
+            _ => None,
+        }
+    }
+
+    // The sample as CDR, serialized on first use
+    fn cdr(&self) -> Option<&Vec<u8>> {
+        if self.cdr.get().is_none() {
+            if let Some(value) = self.value() {
+                let mut cdr = self.scratch.as_ref().map(|scratch| scratch.take()).unwrap_or_default();
+                if serialize_type_into::<T>(value, self.serialized_size.get().copied(), &mut cdr).is_ok() {
+                    let _ = self.cdr.set(cdr);
+                }
+            }
+        }
+        self.cdr.get()
+    }
+}
+
+impl <T: Clone>Clone for SerData<T> {
     fn clone(&self) -> Self {
         Self { 
                 serdata: {
+                    // a new serdata with a single reference, the references
+                    // of the original stay with it
                     let mut newdata = self.serdata;
-                    unsafe {ddsi_serdata_addref(&mut newdata)};
+                    newdata.refc = ddsrt_atomic_uint32_t { v: 1 };
                     newdata
                 }, sample:  match &self.sample {
                         SampleData::Uninitialized => SampleData::Uninitialized,
                         SampleData::SDKKey => SampleData::SDKKey,
                         SampleData::SDKData(d) => SampleData::SDKData(d.clone()),
+                        SampleData::Inline(d) => SampleData::Inline(d.clone()),
+                        #[cfg(cyclone_shm)]
                         SampleData::SHMData(d) => SampleData::SHMData(*d),
-                    }, cdr: self.cdr.clone(), key_hash: self.key_hash.clone(), serialized_size: self.serialized_size }
+                        SampleData::SDKCdr => SampleData::SDKCdr,
+                    }, view: OnceLock::new(), cdr: self.cdr.clone(), key_hash: self.key_hash.clone(), serialized_size: self.serialized_size.clone(),
+                    scratch: None }
     }
 } 
 
@@ -1308,6 +2346,61 @@ fn nn_rmsg_payload_offset(rmsg: *const nn_rmsg, offset: usize) -> *const u8 {
     unsafe { nn_rmsg_payload(rmsg).add(offset) }
 }
 
+/// MurmurHash3 (x86, 32 bit) of the concatenation of `parts`, without going
+/// through a reader
+pub(crate) fn murmur3_32_slices(parts: &[&[u8]], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    fn scramble(k: u32) -> u32 {
+        k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2)
+    }
+    fn mix(h: u32, k: u32) -> u32 {
+        (h ^ scramble(k)).rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64)
+    }
+
+    let mut h = seed;
+    let mut len = 0;
+    // a block that continues in the next part
+    let mut tail = [0u8; 4];
+    let mut tail_len = 0;
+    for part in parts {
+        len += part.len();
+        let mut part = *part;
+        if tail_len > 0 {
+            let n = (4 - tail_len).min(part.len());
+            tail[tail_len..tail_len + n].copy_from_slice(&part[..n]);
+            tail_len += n;
+            part = &part[n..];
+            if tail_len < 4 {
+                continue;
+            }
+            h = mix(h, u32::from_le_bytes(tail));
+            tail_len = 0;
+        }
+        let mut blocks = part.chunks_exact(4);
+        for block in &mut blocks {
+            h = mix(h, u32::from_le_bytes([block[0], block[1], block[2], block[3]]));
+        }
+        let rest = blocks.remainder();
+        tail[..rest.len()].copy_from_slice(rest);
+        tail_len = rest.len();
+    }
+    if tail_len > 0 {
+        let k = tail[..tail_len]
+            .iter()
+            .rev()
+            .fold(0u32, |k, b| (k << 8) | *b as u32);
+        h ^= scramble(k);
+    }
+
+    h ^= len as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
 /// A reader for a list of scatter gather buffers
 struct SGReader<'a> {
     sc_list: Option<  &'a[&'a [u8]]>,
@@ -1366,11 +2459,16 @@ impl<'a> Read for SGReader<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{DdsListener, DdsParticipant, DdsQos, DdsTopic};
+    use crate::{DdsParticipant, DdsTopic};
     use cdds_derive::Topic;
     use serde_derive::{Deserialize, Serialize};
     use std::ffi::CString;
 
+    // iceoryx chunks handed back by free_serdata
+    #[cfg(cyclone_shm)]
+    pub(super) static RELEASED_CHUNKS: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+
     #[test]
     fn scatter_gather() {
         let a = vec![1, 2, 3, 4, 5, 6];
@@ -1400,6 +2498,51 @@ mod test {
         }
     }
 
+    #[test]
+    fn arc_pool_reuses_unshared_arcs() {
+        let pool = ArcPool::<Vec<u8>>::default();
+        let first = pool.wrap(vec![1, 2, 3]);
+        let ptr = Arc::as_ptr(&first);
+        pool.give_back(first);
+        let second = pool.wrap(vec![4]);
+        assert_eq!(Arc::as_ptr(&second), ptr);
+        assert_eq!(*second, vec![4]);
+
+        // an Arc that is still held elsewhere is not pooled
+        let held = second.clone();
+        pool.give_back(second);
+        assert_ne!(Arc::as_ptr(&pool.wrap(vec![5])), Arc::as_ptr(&held));
+    }
+
+    #[test]
+    fn arc_pool_drops_the_values_it_keeps() {
+        let pool = ArcPool::<Arc<()>>::default();
+        let value = Arc::new(());
+        pool.give_back(pool.wrap(value.clone()));
+        // the allocation is pooled, the sample it held is gone
+        assert_eq!(Arc::strong_count(&value), 1);
+        let reused = pool.wrap(value.clone());
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(reused);
+        drop(pool);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn murmur3_of_slices() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        for len in [0, 1, 3, 4, 5, 17, 1000] {
+            let data = &data[..len];
+            let expected = murmur3::murmur3_32(&mut std::io::Cursor::new(data), 7).unwrap();
+            assert_eq!(murmur3_32_slices(&[data], 7), expected);
+            for split in 0..len.min(9) {
+                let (a, b) = data.split_at(split);
+                let (b, c) = b.split_at(b.len() / 2);
+                assert_eq!(murmur3_32_slices(&[a, &[], b, c], 7), expected);
+            }
+        }
+    }
+
     #[test]
     fn keyhash_basic() {
         #[derive(Serialize, Deserialize, Topic, Default)]
@@ -1417,6 +2560,754 @@ mod test {
         let key_cdr = foo.key_cdr();
         assert_eq!(key_cdr, vec![0, 0, 0, 0, 0x12u8, 0x34u8, 0x56u8, 0x78u8]);
     }
+    #[test]
+    fn serdes_errors_are_queued() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Foo {
+            #[topic_key]
+            id: i32,
+            s: String,
+        }
+
+        let sertype = SerType::into_sertype(SerType::<Foo>::new());
+        for _ in 0..MAX_PENDING_SERDES_ERRORS + 1 {
+            let e = cdr::deserialize::<Foo>(&[0, 1, 0]).err().unwrap();
+            deserialize_failed::<Foo>(sertype, 3, e);
+        }
+
+        let ser_type = unsafe { SerType::<Foo>::ref_from_sertype(sertype) }.unwrap();
+        let errors = ser_type.take_serdes_errors();
+        assert_eq!(errors.len(), MAX_PENDING_SERDES_ERRORS);
+        assert_eq!(errors[0].size(), 3);
+        assert_eq!(errors[0].type_name(), "serdes::test::serdes_errors_are_queued::Foo");
+        assert!(ser_type.take_serdes_errors().is_empty());
+
+        let _it = SerType::<Foo>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn sertype_of_a_type_with_the_same_name() {
+        #[derive(Serialize, Deserialize, Default)]
+        struct First {
+            id: u32,
+        }
+
+        #[derive(Serialize, Deserialize, Default)]
+        struct Second {
+            id: u32,
+        }
+
+        // cyclone sees the same type
+        macro_rules! same_name {
+            ($t:ty) => {
+                impl TopicType for $t {
+                    fn typename() -> CString {
+                        CString::new("Same").unwrap()
+                    }
+                    fn has_key() -> bool {
+                        false
+                    }
+                    fn key_cdr(&self) -> Vec<u8> {
+                        Vec::new()
+                    }
+                    fn force_md5_keyhash() -> bool {
+                        false
+                    }
+                }
+            };
+        }
+        same_name!(First);
+        same_name!(Second);
+
+        let sertype = SerType::into_sertype(SerType::<First>::new());
+        assert!(is_sertype_of::<First>(sertype));
+        assert!(!is_sertype_of::<Second>(sertype));
+        assert!(!is_sertype_of::<First>(std::ptr::null()));
+
+        let _it = SerType::<First>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn serialize_into_cyclone_buffers() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Foo {
+            #[topic_key]
+            id: i32,
+            s: String,
+        }
+
+        let sertype = SerType::into_sertype(SerType::<Foo>::new());
+        let foo = Arc::new(Foo { id: 3, s: String::from("hello") });
+        let expected = cdr::serialize::<_, _, CdrBe>(foo.deref(), Infinite).unwrap();
+        let sample = Sample::from(foo);
+        unsafe {
+            let serdata = serdata_from_sample::<Foo>(
+                sertype,
+                ddsi_serdata_kind_SDK_DATA,
+                &sample as *const Sample<Foo> as *const c_void,
+            );
+            let size = get_size::<Foo>(serdata) as usize;
+            assert_eq!(size, expected.len());
+
+            // the whole sample is serialized into the buffer, nothing is kept
+            let mut buf = vec![0xffu8; (size + 3) & !3];
+            serdata_to_ser::<Foo>(serdata, 0, buf.len() as size_t, buf.as_mut_ptr() as *mut c_void);
+            assert_eq!(&buf[..size], &expected[..]);
+            assert!(buf[size..].iter().all(|b| *b == 0));
+            assert!(SerData::<Foo>::const_ref_from_serdata(serdata).cdr.get().is_none());
+
+            // references point into a single serialization
+            let mut whole: iovec = std::mem::zeroed();
+            let mut tail: iovec = std::mem::zeroed();
+            let first = serdata_to_ser_ref::<Foo>(serdata, 0, size as size_t, &mut whole);
+            let second = serdata_to_ser_ref::<Foo>(serdata, 4, (size - 4) as size_t, &mut tail);
+            let bytes = std::slice::from_raw_parts(whole.iov_base as *const u8, whole.iov_len as usize);
+            assert_eq!(bytes, &expected[..]);
+            assert_eq!(tail.iov_base as *const u8, bytes.as_ptr().add(4));
+            serdata_to_ser_unref::<Foo>(first, &whole);
+            serdata_to_ser_unref::<Foo>(second, &tail);
+
+            // a part is copied from the serialization
+            let mut part = [0u8; 4];
+            serdata_to_ser::<Foo>(serdata, 4, 4, part.as_mut_ptr() as *mut c_void);
+            assert_eq!(&part[..], &expected[4..8]);
+            ddsi_serdata_removeref(serdata);
+        }
+        let _it = SerType::<Foo>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn writes_reuse_the_scratch_buffer() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Foo {
+            #[topic_key]
+            id: i32,
+            s: String,
+        }
+
+        let sertype = SerType::into_sertype(SerType::<Foo>::new());
+        let scratch = Arc::new(WriteScratch::default());
+        let write = |id| unsafe {
+            let sample = Sample::from(Arc::new(Foo { id, s: String::from("hello") }));
+            let serdata = with_write_scratch(&scratch, || {
+                serdata_from_sample::<Foo>(
+                    sertype,
+                    ddsi_serdata_kind_SDK_DATA,
+                    &sample as *const Sample<Foo> as *const c_void,
+                )
+            });
+            let mut iov: iovec = std::mem::zeroed();
+            let reference = serdata_to_ser_ref::<Foo>(serdata, 0, get_size::<Foo>(serdata) as size_t, &mut iov);
+            serdata_to_ser_unref::<Foo>(reference, &iov);
+            let buffer = iov.iov_base;
+            ddsi_serdata_removeref(serdata);
+            buffer
+        };
+
+        let first = write(1);
+        assert!(scratch.capacity() > 0);
+        assert_eq!(write(2), first);
+        let _it = SerType::<Foo>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn sertypes_share_typename_and_ops() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Named {
+            #[topic_key]
+            id: u32,
+        }
+
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Other {
+            #[topic_key]
+            id: u32,
+        }
+
+        assert!(std::ptr::eq(cached_typename::<Named>(), cached_typename::<Named>()));
+        assert_eq!(cached_typename::<Named>(), Named::typename().as_c_str());
+
+        let a = SerType::<Named>::new();
+        let b = SerType::<Named>::new();
+        assert_eq!(a.sertype.ops, b.sertype.ops);
+        assert_eq!(a.sertype.serdata_ops, b.sertype.serdata_ops);
+        assert_ne!(a.sertype.ops, SerType::<Other>::new().sertype.ops);
+    }
+
+    #[test]
+    fn types_of_the_same_name_do_not_share_ops() {
+        let first = {
+            #[derive(Serialize, Deserialize, Topic, Default)]
+            struct Same {
+                id: u32,
+            }
+            (std::any::type_name::<Same>(), SerType::<Same>::new().sertype.ops)
+        };
+        let second = {
+            #[derive(Serialize, Deserialize, Topic, Default)]
+            struct Same {
+                id: String,
+            }
+            (std::any::type_name::<Same>(), SerType::<Same>::new().sertype.ops)
+        };
+        assert_eq!(first.0, second.0);
+        assert_ne!(first.1, second.1);
+    }
+
+    #[test]
+    fn sertype_hash_is_precomputed() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Hashed {
+            #[topic_key]
+            id: u32,
+        }
+
+        let sertype = SerType::into_sertype(SerType::<Hashed>::new());
+        let name = Hashed::typename();
+        let size = std::mem::size_of::<Hashed>().to_ne_bytes();
+        let expected = murmur3_32_slices(&[name.to_bytes(), &size], 0);
+        unsafe {
+            assert_eq!(hash::<Hashed>(sertype), expected);
+            assert_eq!(hash::<Hashed>(sertype), expected);
+        }
+        let _it = SerType::<Hashed>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn type_name_modes() {
+        let rust = TypeNameMode::RustPath;
+        assert_eq!(rust.type_name("app::idl::HelloWorldData::Msg"), "idl::HelloWorldData::Msg");
+
+        let idl = TypeNameMode::Idl { root: "app::idl".to_owned() };
+        assert_eq!(idl.type_name("app::idl::HelloWorldData::Msg"), "HelloWorldData::Msg");
+        assert_eq!(idl.type_name("app::idl::Msg"), "Msg");
+        // a trailing separator in the root is accepted
+        let idl = TypeNameMode::Idl { root: "app::idl::".to_owned() };
+        assert_eq!(idl.type_name("app::idl::HelloWorldData::Msg"), "HelloWorldData::Msg");
+        // outside of the root, and a module that only shares a prefix
+        assert_eq!(idl.type_name("app::other::Msg"), "other::Msg");
+        assert_eq!(idl.type_name("app::idlx::Msg"), "idlx::Msg");
+    }
+
+    #[test]
+    fn named_sertype() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Renamed {
+            #[topic_key]
+            id: u32,
+        }
+
+        let name = CString::new("HelloWorldData::Msg").unwrap();
+        let named = SerType::into_sertype(SerType::<Renamed>::named(&name));
+        let default = SerType::into_sertype(SerType::<Renamed>::new());
+        unsafe {
+            assert_eq!(CStr::from_ptr((*named).type_name), name.as_c_str());
+            assert_eq!(CStr::from_ptr((*default).type_name), cached_typename::<Renamed>());
+            assert!(!equal::<Renamed>(named, default));
+            assert_ne!(hash::<Renamed>(named), hash::<Renamed>(default));
+        }
+        let _it = SerType::<Renamed>::try_from_sertype(named);
+        let _it = SerType::<Renamed>::try_from_sertype(default);
+
+        // names are fixed once a type name is cached
+        assert_eq!(set_type_name_mode(TypeNameMode::RustPath), Err(DDSError::PreconditionNotMet));
+    }
+
+    #[test]
+    fn keyless_samples_share_an_instance() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Telemetry {
+            value: f64,
+        }
+
+        let sertype = SerType::into_sertype(SerType::<Telemetry>::new());
+        let basehash = unsafe { (*sertype).serdata_basehash };
+        // the same hash as hashing the key CDR
+        assert_eq!(keyless_hash(basehash), Telemetry::default().hash(basehash));
+        unsafe {
+            let write = |value| {
+                let sample = Sample::from(Arc::new(Telemetry { value }));
+                serdata_from_sample::<Telemetry>(
+                    sertype,
+                    ddsi_serdata_kind_SDK_DATA,
+                    &sample as *const Sample<Telemetry> as *const c_void,
+                )
+            };
+            let (a, b) = (write(1.0), write(2.0));
+            assert!(eqkey::<Telemetry>(a, b));
+            assert_eq!((*a).hash, keyless_hash(basehash));
+            let mut keyhash = ddsi_keyhash { value: [0xff; 16] };
+            get_keyhash::<Telemetry>(a, &mut keyhash, false);
+            assert_eq!(keyhash.value, [0; 16]);
+            ddsi_serdata_removeref(a);
+            ddsi_serdata_removeref(b);
+        }
+        let _it = SerType::<Telemetry>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn samples_hold_one_serdata_reference() {
+        #[derive(Serialize, Deserialize, Topic, Default, Clone)]
+        struct Counted {
+            #[topic_key]
+            id: u32,
+        }
+
+        fn refs(serdata: *mut ddsi_serdata) -> u32 {
+            unsafe { (*serdata).refc.v }
+        }
+
+        let sertype = SerType::into_sertype(SerType::<Counted>::new());
+        unsafe {
+            let written = Sample::from(Arc::new(Counted { id: 1 }));
+            let serdata = serdata_from_sample::<Counted>(
+                sertype,
+                ddsi_serdata_kind_SDK_DATA,
+                &written as *const Sample<Counted> as *const c_void,
+            );
+            assert_eq!(refs(serdata), 1);
+
+            let mut sample = Box::new(Sample::<Counted>::default());
+            let p = &mut *sample as *mut Sample<Counted> as *mut c_void;
+            assert!(!serdata_to_sample::<Counted>(serdata, p, std::ptr::null_mut(), std::ptr::null_mut()));
+            assert_eq!(refs(serdata), 2);
+            // reading the same serdata again does not take another reference
+            assert!(!serdata_to_sample::<Counted>(serdata, p, std::ptr::null_mut(), std::ptr::null_mut()));
+            assert_eq!(refs(serdata), 2);
+
+            let copy = SerData::<Counted>::const_ref_from_serdata(serdata).clone();
+            assert_eq!(copy.serdata.refc.v, 1);
+            assert_eq!(refs(serdata), 2);
+            drop(copy);
+
+            drop(sample);
+            assert_eq!(refs(serdata), 1);
+            ddsi_serdata_removeref(serdata);
+        }
+        let _it = SerType::<Counted>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn samples_move_and_share_across_threads() {
+        #[derive(Serialize, Deserialize, Topic, Default, Clone)]
+        struct Shared {
+            #[topic_key]
+            id: u32,
+        }
+
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<SampleStorage<Shared>>();
+        assert_send_sync::<Sample<Shared>>();
+        assert_send_sync::<SampleBuffer<Shared>>();
+
+        let sertype = SerType::into_sertype(SerType::<Shared>::new());
+        let mut buffer = SampleBuffer::<Shared>::new(1);
+        let serdata = unsafe {
+            let written = Sample::from(Arc::new(Shared { id: 7 }));
+            let serdata = serdata_from_sample::<Shared>(
+                sertype,
+                ddsi_serdata_kind_SDK_DATA,
+                &written as *const Sample<Shared> as *const c_void,
+            );
+            let (samples, _) = buffer.as_mut_ptr();
+            assert!(!serdata_to_sample::<Shared>(serdata, *samples as *mut c_void, std::ptr::null_mut(), std::ptr::null_mut()));
+            serdata
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| assert_eq!(buffer.get(0).try_deref().map(|s| s.id), Some(7)));
+            }
+        });
+
+        // the reference of the buffer is dropped on another thread
+        std::thread::spawn(move || drop(buffer)).join().unwrap();
+        unsafe {
+            assert_eq!((*serdata).refc.v, 1);
+            ddsi_serdata_removeref(serdata);
+        }
+        let _it = SerType::<Shared>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn loan_layout() {
+        #[repr(C)]
+        struct Fixed {
+            id: u64,
+            values: [u16; 3],
+        }
+        let chunk = [0u64; 4];
+        let aligned = chunk.as_ptr() as *const c_void;
+        assert!(check_loan_layout::<Fixed>(16, aligned).is_ok());
+
+        let err = check_loan_layout::<Fixed>(24, aligned).unwrap_err();
+        assert_eq!(err.problem(), LayoutProblem::Size { size: 16, chunk_size: 24 });
+        let err = check_loan_layout::<Fixed>(16, unsafe { aligned.add(4) }).unwrap_err();
+        assert_eq!(err.problem(), LayoutProblem::Align { address: aligned as usize + 4, align: 8 });
+        assert!(err.type_name().ends_with("Fixed"));
+
+        let err: DDSError = err.into();
+        assert_eq!(err, DDSError::PreconditionNotMet);
+        assert!(err.to_string().contains("not aligned to 8 bytes"));
+    }
+
+    #[test]
+    #[cfg(cyclone_shm)]
+    fn loan_outlives_sample_buffer() {
+        #[derive(Serialize, Deserialize, Topic, Default, Clone, Copy)]
+        struct Fixed {
+            #[topic_key]
+            id: u32,
+        }
+
+        let sertype = SerType::into_sertype(SerType::<Fixed>::new());
+        // stands in for the chunk, the serdata has no subscriber to return it to
+        let chunk = Fixed { id: 3 };
+        let mut buffer = SampleBuffer::<Fixed>::new(1);
+        let serdata = unsafe {
+            let mut d = SerData::<Fixed>::new(sertype, ddsi_serdata_kind_SDK_DATA);
+            d.sample = SampleData::SHMData(NonNull::from(&chunk));
+            let serdata = Box::into_raw(d) as *mut ddsi_serdata;
+            let (samples, _) = buffer.as_mut_ptr();
+            assert!(!serdata_to_sample::<Fixed>(serdata, *samples as *mut c_void, std::ptr::null_mut(), std::ptr::null_mut()));
+            serdata
+        };
+        let refc = || unsafe { (*serdata).refc.v };
+        // ours, the sample and its loan
+        assert_eq!(refc(), 3);
+
+        let loan = buffer.get(0).get_sample().unwrap();
+        assert!(matches!(loan, SampleStorage::Loaned(_)));
+        let clone = loan.clone();
+        drop(buffer);
+        assert_eq!(refc(), 2);
+        drop(loan);
+        assert_eq!(clone.id, 3);
+        assert_eq!(refc(), 2);
+        drop(clone);
+        assert_eq!(refc(), 1);
+
+        unsafe { ddsi_serdata_removeref(serdata) };
+        let _it = SerType::<Fixed>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    #[cfg(cyclone_shm)]
+    #[ignore = "needs iox-roudi running"]
+    fn iox_chunk_returned_with_last_loan() {
+        use crate::{DdsReader, DdsWriter};
+        use std::sync::atomic::Ordering;
+
+        #[derive(Serialize, Deserialize, Topic, Default, Clone, Copy)]
+        struct Fixed {
+            #[topic_key]
+            id: u32,
+        }
+
+        crate::CycloneConfig::new().with_shared_memory(true).set_env();
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = Fixed::create_topic(&participant, None, None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+
+        let mut loaned = writer.loan().unwrap();
+        unsafe { loaned.as_mut_ptr().unwrap().write(Fixed { id: 9 }) };
+        writer.return_loan(unsafe { loaned.assume_init() }).unwrap();
+
+        let mut buffer = SampleBuffer::<Fixed>::new(1);
+        for _ in 0..100 {
+            if reader.take_now(&mut buffer).unwrap_or(0) == 1 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let loan = buffer.get(0).get_sample().expect("no sample through iceoryx");
+        assert!(matches!(loan, SampleStorage::Loaned(_)));
+
+        let released = RELEASED_CHUNKS.load(Ordering::SeqCst);
+        let clone = loan.clone();
+        drop(buffer);
+        drop(loan);
+        assert_eq!(RELEASED_CHUNKS.load(Ordering::SeqCst), released);
+        assert_eq!(clone.id, 9);
+        drop(clone);
+        assert_eq!(RELEASED_CHUNKS.load(Ordering::SeqCst), released + 1);
+    }
+
+    #[test]
+    fn small_samples_are_inline() {
+        #[derive(Serialize, Deserialize, Topic, Default, Debug, PartialEq)]
+        struct Small {
+            #[topic_key]
+            id: u32,
+            on: bool,
+        }
+
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Large {
+            #[topic_key]
+            id: u32,
+            values: [u64; 16],
+        }
+
+        assert!(Small::store_inline());
+        assert!(!Large::store_inline());
+
+        let small = Small { id: 1, on: true };
+        let cdr = cdr::serialize::<_, _, CdrBe>(&small, Infinite).unwrap();
+        let sertype = SerType::into_sertype(SerType::<Small>::new());
+        unsafe {
+            let iov = iovec {
+                iov_base: cdr.as_ptr() as *mut c_void,
+                iov_len: cdr.len() as size_t,
+            };
+            let serdata =
+                serdata_from_iov::<Small>(sertype, ddsi_serdata_kind_SDK_DATA, 1, &iov, cdr.len() as size_t);
+            let data = SerData::<Small>::const_ref_from_serdata(serdata);
+            assert!(matches!(&data.sample, SampleData::Inline(s) if *s == small));
+            ddsi_serdata_removeref(serdata);
+        }
+        let _it = SerType::<Small>::try_from_sertype(sertype);
+    }
+
+    #[test]
+    fn keyhashes_are_cached() {
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Foo {
+            #[topic_key]
+            id: i32,
+            #[topic_key]
+            s: String,
+            x: u32,
+        }
+
+        let cache = KeyHashCache::default();
+        let foo = Foo { id: 1, s: String::from("a long key that needs an md5 hash"), x: 1 };
+        let hashes = cache.get_or_insert(&foo, 7).unwrap();
+        assert!(hashes == (cdr_key_hash::<Foo>(&foo.key_cdr()[4..]), foo.hash(7)));
+
+        // a change of a non-key field reuses the hashes of the instance
+        let updated = Foo { x: 2, ..foo };
+        assert!(cache.get_or_insert(&updated, 7).unwrap() == hashes);
+        assert_eq!(cache.len(), 1);
+
+        let other = Foo { id: 2, ..updated };
+        assert!(cache.get_or_insert(&other, 7).unwrap() != hashes);
+        assert_eq!(cache.len(), 2);
+    }
+
+    // The keyhash sent on the wire for a sample written locally and for the
+    // same sample received as CDR
+    fn wire_keyhashes<T>(sample: T) -> ([u8; 16], [u8; 16])
+    where
+        T: TopicType + Serialize + DeserializeOwned,
+    {
+        let cdr = cdr::serialize::<_, _, CdrBe>(&sample, Infinite).unwrap();
+        let sertype = SerType::into_sertype(SerType::<T>::new());
+        let keyhash = |serdata: *mut ddsi_serdata| unsafe {
+            let mut keyhash = ddsi_keyhash { value: [0xff; 16] };
+            get_keyhash::<T>(serdata, &mut keyhash, false);
+            ddsi_serdata_removeref(serdata);
+            keyhash.value
+        };
+        let written = unsafe {
+            let sample = Sample::from(Arc::new(sample));
+            keyhash(serdata_from_sample::<T>(
+                sertype,
+                ddsi_serdata_kind_SDK_DATA,
+                &sample as *const Sample<T> as *const c_void,
+            ))
+        };
+        let received = unsafe {
+            let iov = iovec {
+                iov_base: cdr.as_ptr() as *mut c_void,
+                iov_len: c_len(cdr.len()),
+            };
+            keyhash(serdata_from_iov::<T>(sertype, ddsi_serdata_kind_SDK_DATA, 1, &iov, cdr.len() as size_t))
+        };
+        let _it = SerType::<T>::try_from_sertype(sertype);
+        (written, received)
+    }
+
+    // Golden keyhashes by the rules of DDSI-RTPS 9.6.3.8: the key members as
+    // big endian plain CDR, aligned from the start of the key, used as is when
+    // the key can never be longer than 16 bytes and hashed with md5 otherwise.
+    #[test]
+    fn keyhash_conformance() {
+        fn golden<T>(sample: T, expected: [u8; 16])
+        where
+            T: TopicType + Serialize + DeserializeOwned,
+        {
+            assert_eq!(wire_keyhashes(sample), (expected, expected), "{:?}", cached_typename::<T>());
+        }
+
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Id {
+            #[topic_key]
+            id: i32,
+            value: f64,
+        }
+        golden(Id { id: 0x12345678, value: 1.0 }, [0x12, 0x34, 0x56, 0x78, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // padded to the alignment of each member, filling exactly 16 bytes
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Aligned {
+            #[topic_key]
+            a: u8,
+            #[topic_key]
+            b: u32,
+            #[topic_key]
+            c: u64,
+        }
+        golden(Aligned { a: 1, b: 2, c: 3 }, [1, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 3]);
+
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Exact {
+            #[topic_key]
+            bytes: [u8; 16],
+        }
+        let bytes: [u8; 16] = std::array::from_fn(|i| i as u8 + 1);
+        golden(Exact { bytes }, bytes);
+
+        // longer than 16 bytes
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Wide {
+            #[topic_key]
+            bytes: [u8; 20],
+        }
+        golden(
+            Wide { bytes: std::array::from_fn(|i| i as u8) },
+            [0x15, 0x49, 0xd1, 0xaa, 0xe2, 0x02, 0x14, 0xe0, 0x65, 0xab, 0x4b, 0x76, 0xaa, 0xac, 0x89, 0xa8],
+        );
+
+        // a string may be longer than 16 bytes, short strings are hashed too
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Name {
+            #[topic_key]
+            name: String,
+        }
+        golden(
+            Name { name: "boo".to_owned() },
+            [0xe0, 0x8a, 0xb3, 0x93, 0x8c, 0x73, 0x57, 0x15, 0xc5, 0x2b, 0x8f, 0x32, 0xf8, 0x4d, 0x4b, 0x75],
+        );
+
+        // as do nested keys with a string
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Label {
+            #[topic_key]
+            label: String,
+            unused: u32,
+        }
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Outer {
+            #[topic_key]
+            id: u32,
+            #[topic_key]
+            inner: Label,
+        }
+        golden(
+            Outer { id: 1, inner: Label { label: "abc".to_owned(), unused: 9 } },
+            [0x5d, 0x93, 0xea, 0x3d, 0x05, 0xa4, 0xe6, 0xba, 0x97, 0x27, 0x10, 0xf2, 0x01, 0xa3, 0x2c, 0xdf],
+        );
+    }
+
+    #[test]
+    fn strict_keyhash_checks() {
+        #[derive(Serialize, Deserialize, Default)]
+        struct Handwritten {
+            name: String,
+        }
+
+        // a variable size key without the md5 keyhash, in little endian CDR
+        impl TopicType for Handwritten {
+            fn has_key() -> bool {
+                true
+            }
+
+            fn key_cdr(&self) -> Vec<u8> {
+                cdr::serialize::<_, _, cdr::CdrLe>(&self.name, Infinite).unwrap()
+            }
+
+            fn force_md5_keyhash() -> bool {
+                false
+            }
+        }
+
+        let sample = Handwritten { name: "a".to_owned() };
+        let violation = check_key_cdr::<Handwritten>(&sample.key_cdr()).unwrap_err();
+        assert_eq!(violation.problem(), KeyhashProblem::Encapsulation(1));
+        assert_eq!(check_key_cdr::<Handwritten>(&[0, 0]).unwrap_err().problem(), KeyhashProblem::Truncated(2));
+        assert!(check_key_cdr::<Handwritten>(&[0, 0, 0, 0, 1]).is_ok());
+
+        let cache = KeyHashCache::default();
+        assert!(cache.check_key_size::<Handwritten>(8).is_ok());
+        assert!(cache.check_key_size::<Handwritten>(8).is_ok());
+        assert_eq!(
+            cache.check_key_size::<Handwritten>(12).unwrap_err().problem(),
+            KeyhashProblem::VariableSize { first: 8, size: 12 }
+        );
+        // the key fields are not known, only the sizes of the keys are compared
+        assert!(cache.check_md5::<Handwritten>().is_ok());
+
+        // hashed with md5 for being longer than 16 bytes
+        #[derive(Serialize, Deserialize, Topic, Default)]
+        struct Wide {
+            #[topic_key]
+            id: [u8; 20],
+        }
+        assert!(!Wide::force_md5_keyhash());
+        assert!(KeyHashCache::default().check_md5::<Wide>().is_ok());
+    }
+
+    #[test]
+    fn strict_keyhash_refuses_write() {
+        #[derive(Serialize, Deserialize, Default)]
+        struct Named {
+            name: String,
+            value: u32,
+        }
+
+        // a variable size key without the md5 keyhash
+        impl TopicType for Named {
+            fn has_key() -> bool {
+                true
+            }
+
+            fn key_cdr(&self) -> Vec<u8> {
+                cdr::serialize::<_, _, CdrBe>(&self.name, Infinite).unwrap()
+            }
+
+            fn force_md5_keyhash() -> bool {
+                false
+            }
+
+            fn key_fields() -> Vec<String> {
+                vec!["name".to_owned()]
+            }
+        }
+
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic = DdsTopic::<Named>::create(&participant, "strict_keyhash", None, None).unwrap();
+        let mut writer = crate::DdsWriter::create(&participant, topic, None, None).unwrap();
+
+        set_strict_keyhash(true);
+        let refused = writer.write(Arc::new(Named { name: "a".to_owned(), value: 1 }));
+        set_strict_keyhash(false);
+
+        let err = refused.unwrap_err();
+        match &err {
+            DDSError::Context(context) => assert_eq!(context.operation(), Some("dds_write")),
+            other => panic!("no context in {:?}", other),
+        }
+        let violation = std::error::Error::source(&err)
+            .and_then(|e| e.downcast_ref::<KeyhashViolation>())
+            .expect("the violation is the source of the error");
+        assert_eq!(violation.problem(), KeyhashProblem::Md5NotForced);
+        // nothing is left for the next write
+        assert!(take_keyhash_violation().is_none());
+        assert!(writer.write(Arc::new(Named { name: "a".to_owned(), value: 2 })).is_ok());
+    }
+
     #[test]
     fn keyhash_simple() {
         #[derive(Serialize, Deserialize, Topic, Default)]
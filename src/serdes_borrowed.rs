@@ -0,0 +1,424 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+// A CDR deserializer over a contiguous buffer. Unlike the deserializer of the
+// cdr crate it hands out strings and byte sequences as slices of the buffer, so
+// types with &str and &[u8] fields are deserialized without copying them.
+//
+// The layout is the one written by the cdr crate: primitives aligned to their
+// size relative to the end of the encapsulation header, strings as a length
+// including the terminating nul, sequences and maps with a u32 length and enum
+// variants as a u32 index. Options and self-describing formats are not supported.
+
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::Deserialize;
+
+use cdr::Error;
+
+/// Deserialize `T` from CDR including the encapsulation header
+pub(crate) fn from_cdr<'de, T: Deserialize<'de>>(cdr: &'de [u8]) -> Result<T, Error> {
+    if cdr.len() < 4 || cdr[0] != 0 {
+        return Err(de::Error::custom("missing or unsupported CDR encapsulation header"));
+    }
+    let big_endian = match cdr[1] {
+        0 => true,
+        1 => false,
+        _ => return Err(de::Error::custom("unsupported CDR encapsulation")),
+    };
+    let mut deserializer = BorrowedDeserializer {
+        input: &cdr[4..],
+        pos: 0,
+        big_endian,
+    };
+    T::deserialize(&mut deserializer)
+}
+
+struct BorrowedDeserializer<'de> {
+    input: &'de [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+macro_rules! read_primitive {
+    ($name:ident, $ty:ty) => {
+        fn $name(&mut self) -> Result<$ty, Error> {
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            self.align(SIZE)?;
+            let mut bytes = [0u8; SIZE];
+            bytes.copy_from_slice(self.take(SIZE)?);
+            Ok(if self.big_endian {
+                <$ty>::from_be_bytes(bytes)
+            } else {
+                <$ty>::from_le_bytes(bytes)
+            })
+        }
+    };
+}
+
+impl<'de> BorrowedDeserializer<'de> {
+    fn take(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.input.len())
+            .ok_or_else(|| de::Error::custom("unexpected end of CDR data"))?;
+        let bytes = &self.input[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn align(&mut self, alignment: usize) -> Result<(), Error> {
+        let padding = (alignment - self.pos % alignment) % alignment;
+        self.take(padding).map(|_| ())
+    }
+
+    read_primitive!(read_u16, u16);
+    read_primitive!(read_u32, u32);
+    read_primitive!(read_u64, u64);
+    read_primitive!(read_i16, i16);
+    read_primitive!(read_i32, i32);
+    read_primitive!(read_i64, i64);
+    read_primitive!(read_f32, f32);
+    read_primitive!(read_f64, f64);
+
+    fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_len(&mut self) -> Result<usize, Error> {
+        self.read_u32().map(|len| len as usize)
+    }
+
+    fn read_str(&mut self) -> Result<&'de str, Error> {
+        let len = self.read_len()?;
+        let bytes = match self.take(len)?.split_last() {
+            Some((0, bytes)) => bytes,
+            Some(_) => return Err(de::Error::custom("CDR string is not nul terminated")),
+            None => &[],
+        };
+        std::str::from_utf8(bytes).map_err(de::Error::custom)
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut BorrowedDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom("CDR is not self-describing"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.read_u8()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            value => Err(de::Error::custom(format!("invalid CDR boolean {}", value))),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i8(self.read_u8()? as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i16(self.read_i16()?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i32(self.read_i32()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(self.read_i64()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u8(self.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u16(self.read_u16()?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u32(self.read_u32()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(self.read_u64()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f32(self.read_f32()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.read_f64()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_char(self.read_u8()? as char)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.read_str()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let len = self.read_len()?;
+        visitor.visit_borrowed_bytes(self.take(len)?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom("CDR has no optional values"))
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let len = self.read_len()?;
+        visitor.visit_seq(Elements { de: self, remaining: len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements { de: self, remaining: len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let len = self.read_len()?;
+        visitor.visit_map(Elements { de: self, remaining: len })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom("CDR values cannot be skipped"))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+// The elements of sequences, tuples, structs and maps
+struct Elements<'a, 'de> {
+    de: &'a mut BorrowedDeserializer<'de>,
+    remaining: usize,
+}
+
+impl<'a, 'de> SeqAccess<'de> for Elements<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>, Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'a, 'de> MapAccess<'de> for Elements<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>, Error> {
+        self.next_element_seed(seed)
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, Error> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+impl<'a, 'de> EnumAccess<'de> for &'a mut BorrowedDeserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), Error> {
+        let index: de::value::U32Deserializer<Error> = self.read_u32()?.into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'a, 'de> VariantAccess<'de> for &'a mut BorrowedDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Debug, PartialEq)]
+    enum Gear {
+        Drive(u8),
+    }
+
+    #[derive(Serialize)]
+    struct Frame {
+        id: u16,
+        stamp: f64,
+        name: String,
+        empty: String,
+        payload: Vec<u8>,
+        gear: Gear,
+        tags: Vec<String>,
+        fixed: [u32; 2],
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum GearRef {
+        Drive(u8),
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct FrameRef<'a> {
+        id: u16,
+        stamp: f64,
+        name: &'a str,
+        empty: &'a str,
+        payload: &'a [u8],
+        gear: GearRef,
+        #[serde(borrow)]
+        tags: Vec<&'a str>,
+        fixed: [u32; 2],
+    }
+
+    fn frame() -> Frame {
+        Frame {
+            id: 7,
+            stamp: 1.5,
+            name: "front camera".to_owned(),
+            empty: String::new(),
+            payload: vec![1, 2, 3, 4, 5],
+            gear: Gear::Drive(3),
+            tags: vec!["a".to_owned(), "bc".to_owned()],
+            fixed: [10, 20],
+        }
+    }
+
+    #[test]
+    fn test_borrowed_from_cdr() {
+        let expected = FrameRef {
+            id: 7,
+            stamp: 1.5,
+            name: "front camera",
+            empty: "",
+            payload: &[1, 2, 3, 4, 5],
+            gear: GearRef::Drive(3),
+            tags: vec!["a", "bc"],
+            fixed: [10, 20],
+        };
+        let be = cdr::serialize::<_, _, cdr::CdrBe>(&frame(), cdr::Infinite).unwrap();
+        let decoded = from_cdr::<FrameRef>(&be).unwrap();
+        assert_eq!(decoded, expected);
+        // the strings point into the buffer
+        let range = be.as_ptr_range();
+        assert!(range.contains(&decoded.name.as_ptr()));
+        assert!(range.contains(&decoded.payload.as_ptr()));
+
+        let le = cdr::serialize::<_, _, cdr::CdrLe>(&frame(), cdr::Infinite).unwrap();
+        assert_eq!(from_cdr::<FrameRef>(&le).unwrap(), expected);
+
+        assert!(from_cdr::<FrameRef>(&be[..be.len() - 3]).is_err());
+        assert!(from_cdr::<FrameRef>(&[]).is_err());
+    }
+}
@@ -0,0 +1,516 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+// A sertype for samples that stay in their serialized form. Unlike the sertypes
+// in serdes.rs there is no Rust type behind it: received samples are kept as CDR
+// and written samples are CDR provided by the caller. The type name is given at
+// runtime and the key of a sample is found by a KeyCodec.
+//
+// Samples passed to dds_write, dds_dispose and friends are a Vec<u8> holding the
+// CDR including the encapsulation header. Readers use dds_takecdr/dds_readcdr.
+
+use std::ffi::{c_void, CStr, CString};
+use std::sync::Arc;
+
+use cyclonedds_sys::*;
+
+use crate::common::{c_len, rust_len};
+use crate::dds_telemetry::{count, Counter};
+use crate::error::{DDSError, SerdesError};
+use crate::serdes::{fragchain_slices, iov_slices, murmur3_32_slices, spec_keyhash, SerdesErrors};
+use crate::{DdsListener, DdsQos};
+
+/// Encapsulation header of big endian plain CDR
+pub(crate) const CDR_BE_HEADER: [u8; 4] = [0, 0, 0, 0];
+
+/// Finds the key of serialized samples of a type only known at runtime
+pub(crate) trait KeyCodec: Send + Sync {
+    /// The key of a serialized sample, or of a serialized key if `key_only`, as
+    /// big endian CDR without encapsulation header. The keyhash is computed from it.
+    fn key_cdr(&self, cdr: &[u8], key_only: bool) -> Result<Vec<u8>, String>;
+
+    /// Whether the serialized key can be longer than 16 bytes. The keyhash is
+    /// then always the md5 of the key.
+    fn force_md5_keyhash(&self) -> bool;
+}
+
+#[repr(C)]
+pub(crate) struct RawSerType {
+    sertype: ddsi_sertype,
+    key_codec: Option<Arc<dyn KeyCodec>>,
+    serdes_errors: SerdesErrors,
+}
+
+impl RawSerType {
+    /// A sertype for a keyless type if `key_codec` is None
+    pub(crate) fn new(
+        type_name: &str,
+        key_codec: Option<Arc<dyn KeyCodec>>,
+    ) -> Result<Box<RawSerType>, DDSError> {
+        let type_name = CString::new(type_name).map_err(|_| DDSError::BadParameter)?;
+        Ok(Box::new(RawSerType {
+            sertype: {
+                let mut sertype = std::mem::MaybeUninit::uninit();
+                unsafe {
+                    ddsi_sertype_init(
+                        sertype.as_mut_ptr(),
+                        type_name.as_ptr(),
+                        Box::into_raw(create_sertype_ops()),
+                        Box::into_raw(create_serdata_ops()),
+                        key_codec.is_none(),
+                    );
+                    let mut sertype = sertype.assume_init();
+                    sertype.set_fixed_size(0);
+                    sertype
+                }
+            },
+            key_codec,
+            serdes_errors: SerdesErrors::default(),
+        }))
+    }
+
+    // cast into cyclone dds sertype. Cyclone DDS frees it.
+    pub(crate) fn into_sertype(sertype: Box<RawSerType>) -> *mut ddsi_sertype {
+        Box::into_raw(sertype) as *mut ddsi_sertype
+    }
+
+    // The sertype must have been created by RawSerType::new
+    pub(crate) unsafe fn from_sertype<'a>(sertype: *const ddsi_sertype) -> &'a RawSerType {
+        &*(sertype as *const RawSerType)
+    }
+
+    /// Received samples that were dropped because their key could not be found
+    pub(crate) fn serdes_errors(&self) -> &SerdesErrors {
+        &self.serdes_errors
+    }
+}
+
+/// Create a topic whose samples are handled as CDR
+pub(crate) fn create_topic(
+    participant: &DdsEntity,
+    name: &str,
+    type_name: &str,
+    key_codec: Option<Arc<dyn KeyCodec>>,
+    maybe_qos: Option<DdsQos>,
+    maybe_listener: Option<&DdsListener>,
+) -> Result<DdsEntity, DDSError> {
+    let strname = crate::common::topic_name(name)?;
+    let mut sertype = RawSerType::into_sertype(RawSerType::new(type_name, key_codec)?);
+
+    unsafe {
+        let topic = dds_create_topic_sertype(
+            participant.entity(),
+            strname.as_ptr(),
+            &mut sertype,
+            maybe_qos.map_or(std::ptr::null(), |q| q.into()),
+            maybe_listener.map_or(std::ptr::null(), |l| l.into()),
+            std::ptr::null_mut(),
+        );
+        if topic >= 0 {
+            Ok(DdsEntity::new(topic))
+        } else {
+            Err(DDSError::from_retcode("dds_create_topic_sertype", topic).with_entity_kind("topic"))
+        }
+    }
+}
+
+/// A serialized sample or key
+#[repr(C)]
+pub(crate) struct RawSerData {
+    serdata: ddsi_serdata,
+    // the serialized sample, or the serialized key for key serdata, including the
+    // encapsulation header. Padded to a multiple of four bytes as cyclone may
+    // ask for the padding.
+    cdr: Vec<u8>,
+    size: usize,
+    // big endian CDR of the key without header, empty for keyless types
+    key: Vec<u8>,
+    keyhash: [u8; 16],
+}
+
+impl RawSerData {
+    fn new(sertype: *const ddsi_sertype, kind: u32) -> Box<RawSerData> {
+        Box::new(RawSerData {
+            serdata: {
+                let mut data = std::mem::MaybeUninit::uninit();
+                unsafe {
+                    ddsi_serdata_init(data.as_mut_ptr(), sertype, kind);
+                    data.assume_init()
+                }
+            },
+            cdr: Vec::new(),
+            size: 0,
+            key: Vec::new(),
+            keyhash: [0; 16],
+        })
+    }
+
+    // A serdata for a serialized sample, or for a serialized key if kind is SDK_KEY
+    unsafe fn from_cdr(
+        sertype: *const ddsi_sertype,
+        kind: u32,
+        cdr: Vec<u8>,
+    ) -> Result<Box<RawSerData>, String> {
+        let mut serdata = Self::new(sertype, kind);
+        if let Some(codec) = &RawSerType::from_sertype(sertype).key_codec {
+            let key = codec.key_cdr(&cdr, kind == ddsi_serdata_kind_SDK_KEY)?;
+            serdata.set_key(key, codec.force_md5_keyhash());
+        }
+        serdata.serdata.hash = key_hash32(&serdata.key) ^ (*sertype).serdata_basehash;
+        serdata.set_cdr(cdr);
+        Ok(serdata)
+    }
+
+    fn set_cdr(&mut self, mut cdr: Vec<u8>) {
+        self.size = cdr.len();
+        cdr.resize((self.size + 3) & !3, 0);
+        self.cdr = cdr;
+    }
+
+    fn set_key(&mut self, key: Vec<u8>, force_md5: bool) {
+        self.keyhash = spec_keyhash(&key, force_md5);
+        self.key = key;
+    }
+
+    // Borrow a serdata of a RawSerType
+    pub(crate) unsafe fn from_serdata<'a>(serdata: *const ddsi_serdata) -> &'a RawSerData {
+        &*(serdata as *const RawSerData)
+    }
+
+    /// The serialized sample, or the serialized key for key serdata
+    pub(crate) fn cdr(&self) -> &[u8] {
+        &self.cdr[..self.size]
+    }
+
+    /// The key as big endian CDR without encapsulation header
+    pub(crate) fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// A copy of the serialized data for another RawSerType, with one reference.
+    /// Used to forward samples without deserializing them.
+    pub(crate) unsafe fn copy_for(&self, sertype: *const ddsi_sertype) -> *mut ddsi_serdata {
+        let mut copy = Self::new(sertype, self.serdata.kind);
+        copy.serdata.hash = key_hash32(&self.key) ^ (*sertype).serdata_basehash;
+        copy.serdata.statusinfo = self.serdata.statusinfo;
+        copy.serdata.timestamp = self.serdata.timestamp;
+        copy.cdr = self.cdr.clone();
+        copy.size = self.size;
+        copy.key = self.key.clone();
+        copy.keyhash = self.keyhash;
+        Box::into_raw(copy) as *mut ddsi_serdata
+    }
+}
+
+fn key_hash32(key: &[u8]) -> u32 {
+    murmur3_32_slices(&[key], 0)
+}
+
+// Report a received sample whose key could not be found. Cyclone only sees a
+// null serdata and drops the sample.
+unsafe fn key_failed(sertype: *const ddsi_sertype, size: usize, cause: &str) -> *mut ddsi_serdata {
+    count(Counter::DeserializeFailures);
+    let type_name = CStr::from_ptr((*sertype).type_name).to_string_lossy().into_owned();
+    let error = SerdesError::new(type_name, size, cause.to_owned());
+    crate::dds_log::warn_failed(format_args!("cannot find key of sample"), &error);
+    RawSerType::from_sertype(sertype).serdes_errors.push(error);
+    std::ptr::null_mut()
+}
+
+unsafe fn serdata_from_slices(
+    sertype: *const ddsi_sertype,
+    kind: u32,
+    slices: &[&[u8]],
+    size: usize,
+) -> *mut ddsi_serdata {
+    let mut cdr = Vec::with_capacity(size);
+    for slice in slices {
+        cdr.extend_from_slice(slice);
+    }
+    cdr.truncate(size);
+    match RawSerData::from_cdr(sertype, kind, cdr) {
+        Ok(serdata) => Box::into_raw(serdata) as *mut ddsi_serdata,
+        Err(cause) => key_failed(sertype, size, &cause),
+    }
+}
+
+unsafe extern "C" fn serdata_from_fragchain(
+    sertype: *const ddsi_sertype,
+    kind: u32,
+    fragchain: *const nn_rdata,
+    size: size_t,
+) -> *mut ddsi_serdata {
+    let size = rust_len(size);
+    serdata_from_slices(sertype, kind, &fragchain_slices(fragchain, size), size)
+}
+
+unsafe extern "C" fn serdata_from_iov(
+    sertype: *const ddsi_sertype,
+    kind: u32,
+    niov: size_t,
+    iov: *const iovec,
+    size: size_t,
+) -> *mut ddsi_serdata {
+    serdata_from_slices(sertype, kind, &iov_slices(iov, rust_len(niov)), rust_len(size))
+}
+
+unsafe extern "C" fn serdata_from_keyhash(
+    sertype: *const ddsi_sertype,
+    keyhash: *const ddsi_keyhash,
+) -> *mut ddsi_serdata {
+    // the key can only be recovered if the keyhash is not an md5
+    match &RawSerType::from_sertype(sertype).key_codec {
+        Some(codec) if !codec.force_md5_keyhash() => {
+            let key = (*keyhash).value.to_vec();
+            let mut serdata = RawSerData::new(sertype, ddsi_serdata_kind_SDK_KEY);
+            serdata.serdata.hash = key_hash32(&key) ^ (*sertype).serdata_basehash;
+            let mut cdr = CDR_BE_HEADER.to_vec();
+            cdr.extend_from_slice(&key);
+            serdata.set_cdr(cdr);
+            serdata.set_key(key, false);
+            Box::into_raw(serdata) as *mut ddsi_serdata
+        }
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[allow(non_upper_case_globals)]
+unsafe extern "C" fn serdata_from_sample(
+    sertype: *const ddsi_sertype,
+    kind: u32,
+    sample: *const c_void,
+) -> *mut ddsi_serdata {
+    let sample = &*(sample as *const Vec<u8>);
+    let serdata = match kind {
+        ddsi_serdata_kind_SDK_DATA => RawSerData::from_cdr(sertype, kind, sample.clone()),
+        ddsi_serdata_kind_SDK_KEY => {
+            // reduce the sample to its key
+            let mut serdata = RawSerData::new(sertype, kind);
+            match &RawSerType::from_sertype(sertype).key_codec {
+                Some(codec) => codec.key_cdr(sample, false).map(|key| {
+                    serdata.serdata.hash = key_hash32(&key) ^ (*sertype).serdata_basehash;
+                    let mut cdr = CDR_BE_HEADER.to_vec();
+                    cdr.extend_from_slice(&key);
+                    serdata.set_cdr(cdr);
+                    serdata.set_key(key, codec.force_md5_keyhash());
+                    serdata
+                }),
+                None => {
+                    serdata.serdata.hash = key_hash32(&[]) ^ (*sertype).serdata_basehash;
+                    serdata.set_cdr(CDR_BE_HEADER.to_vec());
+                    Ok(serdata)
+                }
+            }
+        }
+        _ => return std::ptr::null_mut(),
+    };
+    match serdata {
+        Ok(serdata) => Box::into_raw(serdata) as *mut ddsi_serdata,
+        Err(cause) => key_failed(sertype, sample.len(), &cause),
+    }
+}
+
+unsafe extern "C" fn serdata_to_ser(
+    serdata: *const ddsi_serdata,
+    offset: size_t,
+    size: size_t,
+    buf: *mut c_void,
+) {
+    let serdata = RawSerData::from_serdata(serdata);
+    let offset = rust_len(offset).min(serdata.cdr.len());
+    let size = rust_len(size).min(serdata.cdr.len() - offset);
+    std::ptr::copy_nonoverlapping(serdata.cdr[offset..].as_ptr(), buf as *mut u8, size);
+}
+
+unsafe extern "C" fn serdata_to_ser_ref(
+    serdata: *const ddsi_serdata,
+    offset: size_t,
+    size: size_t,
+    iov: *mut iovec,
+) -> *mut ddsi_serdata {
+    let raw = RawSerData::from_serdata(serdata);
+    let iov = &mut *iov;
+    let offset = rust_len(offset).min(raw.cdr.len());
+    let size = rust_len(size).min(raw.cdr.len() - offset);
+    iov.iov_base = raw.cdr[offset..].as_ptr() as *mut c_void;
+    iov.iov_len = c_len(size);
+    ddsi_serdata_addref(serdata)
+}
+
+unsafe extern "C" fn serdata_to_ser_unref(serdata: *mut ddsi_serdata, _iov: *const iovec) {
+    ddsi_serdata_removeref(serdata)
+}
+
+unsafe extern "C" fn serdata_to_sample(
+    serdata: *const ddsi_serdata,
+    sample: *mut c_void,
+    _bufptr: *mut *mut c_void,
+    _buflim: *mut c_void,
+) -> bool {
+    if sample.is_null() {
+        return false;
+    }
+    let serdata = RawSerData::from_serdata(serdata);
+    *(sample as *mut Vec<u8>) = serdata.cdr().to_vec();
+    true
+}
+
+unsafe extern "C" fn serdata_to_untyped(serdata: *const ddsi_serdata) -> *mut ddsi_serdata {
+    let raw = RawSerData::from_serdata(serdata);
+    let mut untyped = RawSerData::new((*serdata).type_, ddsi_serdata_kind_SDK_KEY);
+    untyped.serdata.type_ = std::ptr::null_mut();
+    untyped.serdata.hash = (*serdata).hash;
+    let mut cdr = CDR_BE_HEADER.to_vec();
+    cdr.extend_from_slice(&raw.key);
+    untyped.set_cdr(cdr);
+    untyped.key = raw.key.clone();
+    untyped.keyhash = raw.keyhash;
+    Box::into_raw(untyped) as *mut ddsi_serdata
+}
+
+unsafe extern "C" fn untyped_to_sample(
+    _sertype: *const ddsi_sertype,
+    serdata: *const ddsi_serdata,
+    sample: *mut c_void,
+    _buf: *mut *mut c_void,
+    _buflim: *mut c_void,
+) -> bool {
+    if sample.is_null() {
+        return false;
+    }
+    let serdata = RawSerData::from_serdata(serdata);
+    *(sample as *mut Vec<u8>) = serdata.cdr().to_vec();
+    true
+}
+
+unsafe extern "C" fn free_serdata(serdata: *mut ddsi_serdata) {
+    let _serdata = Box::from_raw(serdata as *mut RawSerData);
+}
+
+unsafe extern "C" fn get_size(serdata: *const ddsi_serdata) -> u32 {
+    RawSerData::from_serdata(serdata).size as u32
+}
+
+unsafe extern "C" fn eqkey(a: *const ddsi_serdata, b: *const ddsi_serdata) -> bool {
+    RawSerData::from_serdata(a).keyhash == RawSerData::from_serdata(b).keyhash
+}
+
+unsafe extern "C" fn get_keyhash(
+    serdata: *const ddsi_serdata,
+    keyhash: *mut ddsi_keyhash,
+    _force_md5: bool,
+) {
+    (*keyhash).value = RawSerData::from_serdata(serdata).keyhash;
+}
+
+unsafe extern "C" fn print(
+    _sertype: *const ddsi_sertype,
+    _serdata: *const ddsi_serdata,
+    _buf: *mut std::os::raw::c_char,
+    _bufsize: size_t,
+) -> size_t {
+    0
+}
+
+// Samples are never loaned, readers of a RawSerType use dds_takecdr.
+unsafe extern "C" fn zero_samples(_sertype: *const ddsi_sertype, _ptr: *mut c_void, _len: size_t) {}
+
+unsafe extern "C" fn realloc_samples(
+    ptrs: *mut *mut c_void,
+    _sertype: *const ddsi_sertype,
+    old: *mut c_void,
+    old_count: size_t,
+    new_count: size_t,
+) {
+    let mut samples = if old.is_null() {
+        Vec::new()
+    } else {
+        Vec::from_raw_parts(old as *mut Vec<u8>, rust_len(old_count), rust_len(old_count))
+    };
+    samples.resize(rust_len(new_count), Vec::new());
+    let samples = samples.into_boxed_slice();
+    *ptrs = Box::into_raw(samples) as *mut c_void;
+}
+
+unsafe extern "C" fn free_samples(
+    _sertype: *const ddsi_sertype,
+    ptrs: *mut *mut c_void,
+    len: size_t,
+    op: dds_free_op_t,
+) {
+    let samples = *ptrs as *mut Vec<u8>;
+    if samples.is_null() {
+        return;
+    }
+    if (op & DDS_FREE_ALL_BIT) != 0 {
+        let _samples = Vec::from_raw_parts(samples, rust_len(len), rust_len(len));
+    } else {
+        for i in 0..rust_len(len) {
+            *samples.add(i) = Vec::new();
+        }
+    }
+}
+
+unsafe extern "C" fn free_sertype(sertype: *mut ddsi_sertype) {
+    ddsi_sertype_fini(sertype);
+    let _sertype_ops = Box::from_raw((*sertype).ops as *mut ddsi_sertype_ops);
+    let _serdata_ops = Box::from_raw((*sertype).serdata_ops as *mut ddsi_serdata_ops);
+    let _sertype = Box::from_raw(sertype as *mut RawSerType);
+}
+
+unsafe extern "C" fn equal(a: *const ddsi_sertype, b: *const ddsi_sertype) -> bool {
+    CStr::from_ptr((*a).type_name) == CStr::from_ptr((*b).type_name)
+}
+
+// see the comment on serdes::hash
+unsafe extern "C" fn hash(tp: *const ddsi_sertype) -> u32 {
+    key_hash32(CStr::from_ptr((*tp).type_name).to_bytes())
+}
+
+fn create_sertype_ops() -> Box<ddsi_sertype_ops> {
+    Box::new(ddsi_sertype_ops {
+        version: Some(ddsi_sertype_v0),
+        arg: std::ptr::null_mut(),
+        free: Some(free_sertype),
+        zero_samples: Some(zero_samples),
+        realloc_samples: Some(realloc_samples),
+        free_samples: Some(free_samples),
+        equal: Some(equal),
+        hash: Some(hash),
+        ..Default::default()
+    })
+}
+
+fn create_serdata_ops() -> Box<ddsi_serdata_ops> {
+    Box::new(ddsi_serdata_ops {
+        eqkey: Some(eqkey),
+        get_size: Some(get_size),
+        from_ser: Some(serdata_from_fragchain),
+        from_ser_iov: Some(serdata_from_iov),
+        from_keyhash: Some(serdata_from_keyhash),
+        from_sample: Some(serdata_from_sample),
+        to_ser: Some(serdata_to_ser),
+        to_ser_ref: Some(serdata_to_ser_ref),
+        to_ser_unref: Some(serdata_to_ser_unref),
+        to_sample: Some(serdata_to_sample),
+        to_untyped: Some(serdata_to_untyped),
+        untyped_to_sample: Some(untyped_to_sample),
+        free: Some(free_serdata),
+        print: Some(print),
+        get_keyhash: Some(get_keyhash),
+        ..Default::default()
+    })
+}
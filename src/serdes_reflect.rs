@@ -0,0 +1,384 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+// Finds the structure of a type from its Deserialize implementation. The type
+// is deserialized from a deserializer that records what it is asked for: the
+// fields of structs, the primitive of every field and one element of every
+// sequence. The values handed to the visitors are zeros and empty strings.
+//
+// The structure is the one serialized by the cdr crate: newtypes are their
+// inner type, tuples of one type are arrays and unit enum variants are a u32
+// index. Options, maps, enums with data and 128 bit integers have no CDR form
+// and are reported as errors, as are custom Deserialize implementations that
+// refuse the zero values.
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, SeqAccess, VariantAccess, Visitor,
+};
+
+use crate::dds_dynamic::{DynamicKind, DynamicType};
+
+// Recursive types have no end
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+pub(crate) struct ReflectError(pub(crate) String);
+
+impl std::fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+impl de::Error for ReflectError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        ReflectError(msg.to_string())
+    }
+}
+
+/// The kind of `T` as it is serialized
+pub(crate) fn reflect<T: DeserializeOwned>() -> Result<DynamicKind, ReflectError> {
+    let mut kind = None;
+    T::deserialize(Tracer {
+        kind: &mut kind,
+        depth: 0,
+    })?;
+    kind.ok_or_else(|| ReflectError(format!("{} is not serialized", std::any::type_name::<T>())))
+}
+
+struct Tracer<'k> {
+    kind: &'k mut Option<DynamicKind>,
+    depth: usize,
+}
+
+impl<'k> Tracer<'k> {
+    fn unsupported<T>(what: &str) -> Result<T, ReflectError> {
+        Err(ReflectError(format!("{} cannot be serialized as CDR", what)))
+    }
+
+    // The kinds of the `len` elements that `visitor` deserializes
+    fn elements<'de, V: Visitor<'de>>(
+        depth: usize,
+        len: usize,
+        visitor: V,
+    ) -> Result<(Vec<DynamicKind>, V::Value), ReflectError> {
+        if depth == MAX_DEPTH {
+            return Err(ReflectError("the type is nested too deep".to_owned()));
+        }
+        let mut kinds = Vec::with_capacity(len);
+        let value = visitor.visit_seq(Elements {
+            kinds: &mut kinds,
+            remaining: len,
+            depth: depth + 1,
+        })?;
+        Ok((kinds, value))
+    }
+}
+
+macro_rules! trace_primitive {
+    ($deserialize:ident, $visit:ident, $kind:ident, $value:expr) => {
+        fn $deserialize<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReflectError> {
+            *self.kind = Some(DynamicKind::$kind);
+            visitor.$visit($value)
+        }
+    };
+}
+
+impl<'de, 'k> de::Deserializer<'de> for Tracer<'k> {
+    type Error = ReflectError;
+
+    trace_primitive!(deserialize_bool, visit_bool, Bool, false);
+    trace_primitive!(deserialize_i8, visit_i8, Int8, 0);
+    trace_primitive!(deserialize_i16, visit_i16, Int16, 0);
+    trace_primitive!(deserialize_i32, visit_i32, Int32, 0);
+    trace_primitive!(deserialize_i64, visit_i64, Int64, 0);
+    trace_primitive!(deserialize_u8, visit_u8, UInt8, 0);
+    trace_primitive!(deserialize_u16, visit_u16, UInt16, 0);
+    trace_primitive!(deserialize_u32, visit_u32, UInt32, 0);
+    trace_primitive!(deserialize_u64, visit_u64, UInt64, 0);
+    trace_primitive!(deserialize_f32, visit_f32, Float32, 0.0);
+    trace_primitive!(deserialize_f64, visit_f64, Float64, 0.0);
+    trace_primitive!(deserialize_char, visit_char, Char, '\0');
+    trace_primitive!(deserialize_str, visit_str, String, "");
+    trace_primitive!(deserialize_string, visit_string, String, String::new());
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReflectError> {
+        *self.kind = Some(DynamicKind::Sequence(Box::new(DynamicKind::UInt8)));
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReflectError> {
+        *self.kind = Some(DynamicKind::Sequence(Box::new(DynamicKind::UInt8)));
+        visitor.visit_byte_buf(Vec::new())
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReflectError> {
+        let (mut kinds, value) = Self::elements(self.depth, 1, visitor)?;
+        let element = kinds.pop().ok_or_else(|| ReflectError("a sequence without elements".to_owned()))?;
+        *self.kind = Some(DynamicKind::Sequence(Box::new(element)));
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, ReflectError> {
+        let (kinds, value) = Self::elements(self.depth, len, visitor)?;
+        match kinds.first() {
+            Some(first) if kinds.iter().all(|k| k == first) => {
+                *self.kind = Some(DynamicKind::Array(Box::new(first.clone()), len));
+                Ok(value)
+            }
+            _ => Self::unsupported("a tuple of different types"),
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ReflectError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ReflectError> {
+        let (kinds, value) = Self::elements(self.depth, fields.len(), visitor)?;
+        if kinds.len() != fields.len() {
+            return Err(ReflectError(format!("{} is not deserialized field by field", name)));
+        }
+        let ty = fields
+            .iter()
+            .zip(kinds)
+            .fold(DynamicType::new(name), |ty, (field, kind)| ty.with_field(field, kind));
+        *self.kind = Some(DynamicKind::Struct(ty));
+        Ok(value)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ReflectError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ReflectError> {
+        *self.kind = Some(DynamicKind::UInt32);
+        visitor.visit_enum(UnitVariant)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ReflectError> {
+        Self::unsupported("an Option")
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ReflectError> {
+        Self::unsupported("a map")
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ReflectError> {
+        Self::unsupported("an i128")
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ReflectError> {
+        Self::unsupported("a u128")
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ReflectError> {
+        Self::unsupported("a unit")
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value, ReflectError> {
+        Self::unsupported(name)
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ReflectError> {
+        Self::unsupported("a self describing type")
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReflectError> {
+        visitor.visit_u32(0)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ReflectError> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct Elements<'k> {
+    kinds: &'k mut Vec<DynamicKind>,
+    remaining: usize,
+    depth: usize,
+}
+
+impl<'de, 'k> SeqAccess<'de> for Elements<'k> {
+    type Error = ReflectError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>, ReflectError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let mut kind = None;
+        let value = seed.deserialize(Tracer {
+            kind: &mut kind,
+            depth: self.depth,
+        })?;
+        self.kinds
+            .push(kind.ok_or_else(|| ReflectError("an element that is not serialized".to_owned()))?);
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining)
+    }
+}
+
+// The first variant of an enum, which must not hold data
+struct UnitVariant;
+
+impl<'de> EnumAccess<'de> for UnitVariant {
+    type Error = ReflectError;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), ReflectError> {
+        let index: de::value::U32Deserializer<ReflectError> = 0u32.into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for UnitVariant {
+    type Error = ReflectError;
+
+    fn unit_variant(self) -> Result<(), ReflectError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, _seed: S) -> Result<S::Value, ReflectError> {
+        Tracer::unsupported("an enum with data")
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, ReflectError> {
+        Tracer::unsupported("an enum with data")
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, ReflectError> {
+        Tracer::unsupported("an enum with data")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_derive::Deserialize;
+
+    #[test]
+    fn reflect_struct() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        enum Mode {
+            Off,
+            On,
+        }
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Meters(f32);
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Point {
+            x: Meters,
+            y: Meters,
+        }
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Track {
+            id: u32,
+            name: String,
+            mode: Mode,
+            #[serde(skip)]
+            cached: u64,
+            #[serde(rename = "path")]
+            points: Vec<Point>,
+            hist: [[u16; 3]; 2],
+            flags: (bool, bool),
+        }
+
+        let point = DynamicType::new("Point")
+            .with_field("x", DynamicKind::Float32)
+            .with_field("y", DynamicKind::Float32);
+        let expected = DynamicType::new("Track")
+            .with_field("id", DynamicKind::UInt32)
+            .with_field("name", DynamicKind::String)
+            .with_field("mode", DynamicKind::UInt32)
+            .with_field("path", DynamicKind::Sequence(Box::new(DynamicKind::Struct(point))))
+            .with_field(
+                "hist",
+                DynamicKind::Array(Box::new(DynamicKind::Array(Box::new(DynamicKind::UInt16), 3)), 2),
+            )
+            .with_field("flags", DynamicKind::Array(Box::new(DynamicKind::Bool), 2));
+        assert_eq!(reflect::<Track>().unwrap(), DynamicKind::Struct(expected));
+    }
+
+    #[test]
+    fn reflect_unsupported() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Maybe {
+            value: Option<u32>,
+        }
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        enum Shape {
+            Circle(f64),
+        }
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Node {
+            children: Vec<Node>,
+        }
+
+        assert!(reflect::<Maybe>().is_err());
+        assert!(reflect::<Shape>().is_err());
+        assert!(reflect::<(u32, String)>().is_err());
+        assert!(reflect::<Node>().is_err());
+    }
+}
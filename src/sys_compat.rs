@@ -0,0 +1,118 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Accessors for the fields of the cyclonedds-sys structs that only exist
+//! in some builds of cyclone. The iceoryx fields of `ddsi_sertype` and
+//! `ddsi_serdata` are only generated when cyclone is built with shared
+//! memory. The build script looks for the installed cyclone (under
+//! `CYCLONEDDS_HOME`, `CMAKE_PREFIX_PATH` or the system include paths) and
+//! sets `cyclone_shm` only when the `shm` feature is enabled and
+//! `dds/features.h` defines `DDS_HAS_SHM`, or the cyclone version has
+//! iceoryx when there is no features.h. Without it, the chunk and subscriber
+//! read as null and the sample size is not recorded, and the paths that need
+//! iceoryx itself are compiled out. Likewise the type information operations
+//! of `ddsi_sertype_ops` are only set under `cyclone_type_discovery`. When
+//! no cyclone headers are found, the features are taken as given.
+
+use cyclonedds_sys::{ddsi_serdata, ddsi_sertype, ddsi_sertype_ops};
+use std::os::raw::c_void;
+
+/// Record the size of a sample for the iceoryx loans of the type
+#[cfg(cyclone_shm)]
+pub(crate) fn set_iox_size(sertype: &mut ddsi_sertype, size: u32) {
+    sertype.iox_size = size;
+}
+
+#[cfg(not(cyclone_shm))]
+pub(crate) fn set_iox_size(_sertype: &mut ddsi_sertype, _size: u32) {}
+
+/// The size of a sample as recorded by `set_iox_size`
+#[cfg(cyclone_shm)]
+#[allow(dead_code)]
+pub(crate) fn iox_size(sertype: &ddsi_sertype) -> u32 {
+    sertype.iox_size
+}
+
+#[cfg(not(cyclone_shm))]
+#[allow(dead_code)]
+pub(crate) fn iox_size(_sertype: &ddsi_sertype) -> u32 {
+    0
+}
+
+/// The iceoryx chunk holding the data of the serdata, or null
+#[cfg(cyclone_shm)]
+pub(crate) fn iox_chunk(serdata: &ddsi_serdata) -> *mut c_void {
+    serdata.iox_chunk
+}
+
+#[cfg(not(cyclone_shm))]
+#[allow(dead_code)]
+pub(crate) fn iox_chunk(_serdata: &ddsi_serdata) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+/// The address of the chunk pointer, for handing the chunk back to iceoryx
+#[cfg(cyclone_shm)]
+pub(crate) fn iox_chunk_mut(serdata: &mut ddsi_serdata) -> *mut *mut c_void {
+    &mut serdata.iox_chunk
+}
+
+/// The iceoryx subscriber the chunk was received from, or null
+#[cfg(cyclone_shm)]
+pub(crate) fn iox_subscriber(serdata: &ddsi_serdata) -> *mut c_void {
+    serdata.iox_subscriber
+}
+
+#[cfg(not(cyclone_shm))]
+#[allow(dead_code)]
+pub(crate) fn iox_subscriber(_serdata: &ddsi_serdata) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+/// Attach an iceoryx chunk, and the subscriber it came from if any
+#[cfg(cyclone_shm)]
+pub(crate) fn set_iox_chunk(serdata: &mut ddsi_serdata, chunk: *mut c_void, sub: *mut c_void) {
+    serdata.iox_chunk = chunk;
+    serdata.iox_subscriber = sub;
+}
+
+/// Publish the type information of T for the topics of a sertype
+#[cfg(cyclone_type_discovery)]
+pub(crate) fn set_type_discovery_ops<T: crate::serdes::TopicType>(ops: &mut ddsi_sertype_ops) {
+    crate::dds_typeinfo::set_type_discovery_ops::<T>(ops);
+}
+
+#[cfg(not(cyclone_type_discovery))]
+pub(crate) fn set_type_discovery_ops<T>(_ops: &mut ddsi_sertype_ops) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_iox_fields() {
+        let mut sertype: ddsi_sertype = unsafe { std::mem::zeroed() };
+        set_iox_size(&mut sertype, 24);
+        let serdata: ddsi_serdata = unsafe { std::mem::zeroed() };
+        assert!(iox_chunk(&serdata).is_null());
+        assert!(iox_subscriber(&serdata).is_null());
+        if cfg!(cyclone_shm) {
+            assert_eq!(iox_size(&sertype), 24);
+        } else {
+            assert_eq!(iox_size(&sertype), 0);
+        }
+    }
+}
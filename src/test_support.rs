@@ -0,0 +1,230 @@
+/*
+    Copyright 2021 Sojan James
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+//! Helpers for unit testing publish/subscribe logic without a network. Enable
+//! the `test-support` feature in the dev-dependencies to use them.
+//!
+//! A [`LoopbackDomain`] is a domain on the loopback interface with multicast and
+//! shared memory disabled. Every loopback domain created by a process gets its own
+//! domain id, so tests running in parallel do not see each other's samples.
+//! # Example
+//! ```ignore
+//! use cyclonedds_rs::test_support::LoopbackDomain;
+//! let domain = LoopbackDomain::create().unwrap();
+//! let topic = MyType::create_topic(domain.participant(), None, None, None).unwrap();
+//! let mut writer = domain.writer(topic.clone()).unwrap();
+//! let reader = domain.reader(topic).unwrap();
+//! domain.assert_received(&mut writer, &reader, MyType::default(), Duration::from_secs(1));
+//! ```
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::Thread;
+use std::time::{Duration, Instant};
+
+use cyclonedds_sys::DdsDomainId;
+
+use crate::error::DDSError;
+use crate::serdes::{SampleBuffer, TopicType};
+use crate::{CycloneConfig, DdsDomain, DdsParticipant, DdsReader, DdsTopic, DdsWriter};
+
+// Domain ids handed out to loopback domains. Ids above 232 cannot be mapped
+// to ports, so the range wraps.
+const FIRST_LOOPBACK_DOMAIN: DdsDomainId = 100;
+const LOOPBACK_DOMAINS: DdsDomainId = 130;
+static NEXT_LOOPBACK_DOMAIN: AtomicU32 = AtomicU32::new(0);
+
+// how often a reader is polled while waiting for a sample
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A network-less domain with a participant in it
+pub struct LoopbackDomain {
+    participant: DdsParticipant,
+    domain: DdsDomain,
+}
+
+impl LoopbackDomain {
+    pub fn create() -> Result<Self, DDSError> {
+        // ids of domains still alive in this process are skipped
+        let mut last_error = DDSError::PreconditionNotMet;
+        for _ in 0..LOOPBACK_DOMAINS {
+            let id = FIRST_LOOPBACK_DOMAIN
+                + NEXT_LOOPBACK_DOMAIN.fetch_add(1, Ordering::SeqCst) % LOOPBACK_DOMAINS;
+            match Self::config(id).create_domain() {
+                Ok(domain) => {
+                    let participant = domain.create_participant(None, None)?;
+                    return Ok(Self {
+                        participant,
+                        domain,
+                    });
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// The configuration used for a loopback domain
+    pub fn config(id: DdsDomainId) -> CycloneConfig {
+        CycloneConfig::new()
+            .with_domain_id(id)
+            .with_interface("127.0.0.1")
+            .with_multicast(false)
+            .with_shared_memory(false)
+    }
+
+    pub fn participant(&self) -> &DdsParticipant {
+        &self.participant
+    }
+
+    pub fn domain(&self) -> &DdsDomain {
+        &self.domain
+    }
+
+    pub fn domain_id(&self) -> DdsDomainId {
+        self.domain.id()
+    }
+
+    /// Create a topic of type T with the given name
+    pub fn topic<T: TopicType>(&self, name: &str) -> Result<DdsTopic<T>, DDSError> {
+        DdsTopic::create(&self.participant, name, None, None)
+    }
+
+    /// Create a writer with the default QoS directly on the participant
+    pub fn writer<T: TopicType>(&self, topic: DdsTopic<T>) -> Result<DdsWriter<T>, DDSError> {
+        DdsWriter::create(&self.participant, topic, None, None)
+    }
+
+    /// Create a reader with the default QoS directly on the participant
+    pub fn reader<T: TopicType>(&self, topic: DdsTopic<T>) -> Result<DdsReader<T>, DDSError> {
+        DdsReader::create(&self.participant, topic, None, None)
+    }
+
+    /// Write `sample` and panic unless the reader receives an equal sample within
+    /// `timeout`.
+    pub fn assert_received<T>(
+        &self,
+        writer: &mut DdsWriter<T>,
+        reader: &DdsReader<T>,
+        sample: T,
+        timeout: Duration,
+    ) where
+        T: TopicType + PartialEq + Clone + Debug,
+    {
+        let expected = sample.clone();
+        writer
+            .write(Arc::new(sample))
+            .expect("cannot write the sample");
+        if wait_for_sample(reader, timeout, |s| *s == expected).is_none() {
+            panic!("{:?} was not received within {:?}", expected, timeout);
+        }
+    }
+}
+
+/// Take samples from the reader until one matches `predicate` or `timeout`
+/// expires. Samples that do not match are discarded.
+pub fn wait_for_sample<T, F>(reader: &DdsReader<T>, timeout: Duration, mut predicate: F) -> Option<T>
+where
+    T: TopicType + Clone,
+    F: FnMut(&T) -> bool,
+{
+    let deadline = Instant::now() + timeout;
+    let mut buffer = SampleBuffer::<T>::new(16);
+    loop {
+        while let Ok(n) = reader.take_now(&mut buffer) {
+            for i in 0..n {
+                if let Some(sample) = buffer.get(i).try_deref() {
+                    if predicate(sample) {
+                        return Some(sample.clone());
+                    }
+                }
+            }
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Panic if the reader receives any sample within `duration`
+pub fn assert_nothing_received<T>(reader: &DdsReader<T>, duration: Duration)
+where
+    T: TopicType + Clone + Debug,
+{
+    if let Some(sample) = wait_for_sample(reader, duration, |_| true) {
+        panic!("unexpected sample {:?}", sample);
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread, without an async runtime.
+/// The futures of this crate are woken from cyclone threads and do not depend
+/// on any particular executor.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut ctx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut ctx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cdds_derive::Topic;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Topic, Clone, Debug, Default, PartialEq)]
+    struct Ping {
+        #[topic_key]
+        id: u32,
+        text: String,
+    }
+
+    #[test]
+    fn test_loopback() {
+        let a = LoopbackDomain::create().unwrap();
+        let b = LoopbackDomain::create().unwrap();
+        assert_ne!(a.domain_id(), b.domain_id());
+
+        let topic = a.topic::<Ping>("ping").unwrap();
+        let mut writer = a.writer(topic.clone()).unwrap();
+        let reader = a.reader(topic).unwrap();
+        let other_reader = b.reader(b.topic::<Ping>("ping").unwrap()).unwrap();
+
+        let ping = Ping {
+            id: 1,
+            text: "hello".to_owned(),
+        };
+        a.assert_received(&mut writer, &reader, ping, Duration::from_secs(1));
+        assert_nothing_received(&other_reader, Duration::from_millis(100));
+    }
+}
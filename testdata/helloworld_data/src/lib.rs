@@ -6,11 +6,57 @@ use std::env;
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+// the C type can be used with the typed readers and writers as Idlc<HelloWorldData_Msg>
+cyclonedds_rs::idlc_type!(HelloWorldData_Msg, HelloWorldData_Msg_desc);
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use cyclonedds_rs::{DdsParticipant, DdsReader, DdsWriter, Idlc, SampleBuffer};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn hello_world() {
+        let participant = DdsParticipant::create(None, None, None).unwrap();
+        let topic =
+            Idlc::<HelloWorldData_Msg>::create_topic(&participant, "HelloWorldData_Msg", None, None).unwrap();
+        let mut writer = DdsWriter::create(&participant, topic.clone(), None, None).unwrap();
+        let reader = DdsReader::create(&participant, topic, None, None).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut msg = Idlc::<HelloWorldData_Msg>::zeroed();
+        msg.set(|m| &m.userID, 1).unwrap();
+        msg.set_string(|m| &m.message, "Hello World").unwrap();
+        writer.write(Arc::new(msg)).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        let mut samples = SampleBuffer::new(1);
+        assert_eq!(reader.take_now(&mut samples).unwrap(), 1);
+        let received = samples.get(0).try_deref().unwrap();
+        assert_eq!(received.userID, 1);
+        assert_eq!(received.string(|m| &m.message), Some("Hello World"));
+    }
+
+    // idlc generated the type information of the descriptor from the same IDL
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
+    #[cfg(feature = "type-discovery")]
+    fn type_information_matches_idlc() {
+        use cyclonedds_rs::{DynamicType, TypeInformation};
+
+        let ty = DynamicType::from_idl(include_str!("../idl/HelloWorldData.idl")).unwrap();
+        assert_eq!(ty.name(), "HelloWorldData::Msg");
+        let info = TypeInformation::new(&ty);
+
+        let descriptor = unsafe { &HelloWorldData_Msg_desc };
+        let golden = |data: *const u8, sz: u32| unsafe { std::slice::from_raw_parts(data, sz as usize) };
+        assert_eq!(
+            info.type_information(),
+            golden(descriptor.type_information.data, descriptor.type_information.sz)
+        );
+        assert_eq!(
+            info.type_mapping(),
+            golden(descriptor.type_mapping.data, descriptor.type_mapping.sz)
+        );
     }
 }